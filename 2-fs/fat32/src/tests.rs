@@ -1,5 +1,6 @@
 extern crate rand;
 
+use std::cmp::min;
 use std::io::prelude::*;
 use std::io::Cursor;
 use std::path::Path;
@@ -354,3 +355,609 @@ fn shared_fs_is_sync_send_static() {
     fn f<T: Sync + Send + 'static>() {}
     f::<Shared<VFat>>();
 }
+
+/// A small FAT32 image built in memory: an MBR, a partition starting at sector
+/// 1 with two one-sector FATs, and single-sector clusters. The root directory
+/// is cluster 2.
+struct MockImage {
+    data: Vec<u8>,
+}
+
+impl MockImage {
+    const SECTOR_SIZE: usize = 512;
+    const FAT_SECTOR: usize = 3;
+    const DATA_SECTOR: usize = 5;
+    const CLUSTERS: usize = 100;
+
+    fn new() -> MockImage {
+        let mut data = vec![0u8; (MockImage::DATA_SECTOR + MockImage::CLUSTERS) * 512];
+        {
+            let mbr = &mut data[..512];
+            mbr[446 + 4] = 0xC;
+            mbr[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+            mbr[446 + 12..446 + 16].copy_from_slice(&104u32.to_le_bytes());
+            mbr[510..].copy_from_slice(&[0x55, 0xAA]);
+        }
+        {
+            let ebpb = &mut data[512..1024];
+            ebpb[11..13].copy_from_slice(&512u16.to_le_bytes());
+            ebpb[13] = 1;
+            ebpb[14..16].copy_from_slice(&2u16.to_le_bytes());
+            ebpb[16] = 2;
+            ebpb[32..36].copy_from_slice(&104u32.to_le_bytes());
+            ebpb[36..40].copy_from_slice(&1u32.to_le_bytes());
+            ebpb[44..48].copy_from_slice(&2u32.to_le_bytes());
+            ebpb[66] = 0x29;
+            ebpb[510..].copy_from_slice(&[0x55, 0xAA]);
+        }
+
+        let mut image = MockImage { data };
+        image.set_fat(0, 0x0FFF_FFF8);
+        image.set_fat(1, 0x0FFF_FFFF);
+        image.set_fat(2, 0x0FFF_FFFF);
+        image
+    }
+
+    fn set_fat(&mut self, cluster: u32, value: u32) {
+        for fat in 0..2 {
+            let offset = (MockImage::FAT_SECTOR + fat) * MockImage::SECTOR_SIZE + cluster as usize * 4;
+            self.data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn fat(&self, cluster: u32) -> u32 {
+        let offset = MockImage::FAT_SECTOR * MockImage::SECTOR_SIZE + cluster as usize * 4;
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&self.data[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn cluster_mut(&mut self, cluster: u32) -> &mut [u8] {
+        let start = (MockImage::DATA_SECTOR + cluster as usize - 2) * MockImage::SECTOR_SIZE;
+        &mut self.data[start..start + MockImage::SECTOR_SIZE]
+    }
+
+    /// Writes the raw 32-byte `entry` into the first unused slot of the
+    /// directory at cluster `dir` and returns its index.
+    fn push_entry(&mut self, dir: u32, entry: [u8; 32]) -> usize {
+        let cluster = self.cluster_mut(dir);
+        let index = (0..16).find(|i| cluster[i * 32] == 0).expect("directory full");
+        cluster[index * 32..index * 32 + 32].copy_from_slice(&entry);
+        index
+    }
+
+    /// Adds an entry with the padded 8.3 name `name` to the directory at
+    /// cluster `dir`, preceded by long file name entries for `long_name` if it
+    /// is non-empty. Returns the index of the 8.3 entry.
+    fn add_entry(&mut self, dir: u32, name: &[u8; 11], long_name: &str, attributes: u8,
+                 first: u32, size: u32) -> usize {
+        let checksum = name.iter().fold(0u8, |sum, &b| {
+            ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(b)
+        });
+        let mut chars: Vec<u16> = long_name.encode_utf16().collect();
+        if chars.len() % 13 != 0 {
+            chars.push(0);
+        }
+        while chars.len() % 13 != 0 {
+            chars.push(0xFFFF);
+        }
+        let parts = chars.len() / 13;
+        for part in (0..parts).rev() {
+            let mut entry = [0u8; 32];
+            entry[0] = (part + 1) as u8 | if part + 1 == parts { 0x40 } else { 0 };
+            entry[11] = 0x0F;
+            entry[13] = checksum;
+            let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+            for (c, &offset) in chars[part * 13..part * 13 + 13].iter().zip(offsets.iter()) {
+                entry[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+            }
+            self.push_entry(dir, entry);
+        }
+
+        let mut entry = [0u8; 32];
+        entry[..11].copy_from_slice(name);
+        entry[11] = attributes;
+        entry[20..22].copy_from_slice(&((first >> 16) as u16).to_le_bytes());
+        entry[26..28].copy_from_slice(&(first as u16).to_le_bytes());
+        entry[28..32].copy_from_slice(&size.to_le_bytes());
+        self.push_entry(dir, entry)
+    }
+
+    /// Adds a file named `name` whose contents `data` are stored in `clusters`.
+    fn add_file(&mut self, dir: u32, name: &[u8; 11], long_name: &str, clusters: &[u32],
+                data: &[u8]) -> usize {
+        for (i, &cluster) in clusters.iter().enumerate() {
+            let next = clusters.get(i + 1).cloned().unwrap_or(0x0FFF_FFFF);
+            self.set_fat(cluster, next);
+            let chunk = &data[min(data.len(), i * 512)..min(data.len(), (i + 1) * 512)];
+            self.cluster_mut(cluster)[..chunk.len()].copy_from_slice(chunk);
+        }
+        let first = clusters.first().cloned().unwrap_or(0);
+        self.add_entry(dir, name, long_name, 0x20, first, data.len() as u32)
+    }
+
+    /// Adds an empty directory named `name` stored in `cluster`, with its `.`
+    /// and `..` entries.
+    fn add_dir(&mut self, dir: u32, name: &[u8; 11], long_name: &str, cluster: u32) -> usize {
+        self.set_fat(cluster, 0x0FFF_FFFF);
+        self.add_entry(cluster, b".          ", "", 0x10, cluster, 0);
+        self.add_entry(cluster, b"..         ", "", 0x10, if dir == 2 { 0 } else { dir }, 0);
+        self.add_entry(dir, name, long_name, 0x10, cluster, 0)
+    }
+
+    /// Deletes the entry at `index` of the directory at cluster `dir` (and
+    /// its long file name entries) the way most FAT drivers do: the first
+    /// byte of every entry is set to 0xE5 and the cluster chain is freed.
+    fn delete(&mut self, dir: u32, index: usize) {
+        let (first, is_dir) = {
+            let entries = self.cluster_mut(dir);
+            let entry = &entries[index * 32..index * 32 + 32];
+            let first = (entry[20] as u32) << 16 | (entry[21] as u32) << 24
+                | entry[26] as u32 | (entry[27] as u32) << 8;
+            (first, entry[11] & 0x10 != 0)
+        };
+        let mut i = index;
+        loop {
+            self.cluster_mut(dir)[i * 32] = 0xE5;
+            if i == 0 || self.cluster_mut(dir)[(i - 1) * 32 + 11] != 0x0F {
+                break;
+            }
+            i -= 1;
+        }
+        let mut cluster = first;
+        while cluster >= 2 && cluster < 0x0FFF_FFF8 {
+            let next = if is_dir { 0x0FFF_FFFF } else { self.fat(cluster) };
+            self.set_fat(cluster, 0);
+            cluster = next;
+        }
+    }
+
+    fn vfat(self) -> Shared<VFat> {
+        VFat::from(Cursor::new(self.data)).expect("failed to initialize VFAT from mock image")
+    }
+}
+
+fn mock_data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+fn read_file<T: File>(mut file: T) -> Vec<u8> {
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).expect("read file");
+    data
+}
+
+#[test]
+fn test_deleted_entries() {
+    let mut image = MockImage::new();
+    image.add_file(2, b"KEEP    TXT", "", &[3], &mock_data(10));
+    let lost = image.add_file(2, b"HELLOW~1TXT", "hello world.txt", &[4, 5], &mock_data(700));
+    let reused = image.add_file(2, b"REUSED  BIN", "", &[6], &mock_data(100));
+    image.delete(2, lost);
+    image.delete(2, reused);
+    image.add_file(2, b"NEW     BIN", "", &[6], &mock_data(100));
+
+    let vfat = image.vfat();
+    let deleted = vfat.deleted_entries("/").expect("deleted entries");
+    assert_eq!(deleted.len(), 2);
+
+    assert_eq!(deleted[0].name(), "hello world.txt");
+    assert_eq!(deleted[0].size(), 700);
+    assert_eq!(deleted[0].first_cluster(), 4);
+    assert!(!deleted[0].is_dir());
+    assert!(deleted[0].clusters_free());
+
+    assert_eq!(deleted[1].name(), "_EUSED.BIN");
+    assert_eq!(deleted[1].first_cluster(), 6);
+    assert!(!deleted[1].clusters_free());
+}
+
+#[test]
+fn test_undelete() {
+    let mut image = MockImage::new();
+    image.add_file(2, b"KEEP    TXT", "", &[3], &mock_data(10));
+    let lost = image.add_file(2, b"HELLOW~1TXT", "hello world.txt", &[4, 5], &mock_data(700));
+    image.delete(2, lost);
+
+    let vfat = image.vfat();
+    let deleted = vfat.deleted_entries("/").expect("deleted entries");
+
+    let e = vfat.undelete(&deleted[0], "not a valid name").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+    let e = vfat.undelete(&deleted[0], "keep.txt").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::AlreadyExists);
+
+    vfat.undelete(&deleted[0], "hello.txt").expect("undelete");
+    let file = vfat.open_file("/HELLO.TXT").expect("recovered file");
+    assert_eq!(file.size(), 700);
+    assert_eq!(read_file(file), mock_data(700));
+    assert!(vfat.deleted_entries("/").expect("deleted entries").is_empty());
+
+    let e = vfat.undelete(&deleted[0], "again.txt").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::Other);
+}
+
+#[test]
+fn test_dentry_cache_invalidation() {
+    let mut image = MockImage::new();
+    image.add_file(2, b"KERNEL  IMG", "", &[3, 4, 8, 9, 12], &mock_data(2300));
+    let lost = image.add_file(2, b"HELLO   TXT", "", &[5], &mock_data(40));
+    image.delete(2, lost);
+
+    let vfat = image.vfat();
+    for _ in 0..2 {
+        let e = vfat.open("/hello.txt").unwrap_err();
+        assert_eq!(e.kind(), ::std::io::ErrorKind::NotFound);
+    }
+    let deleted = vfat.deleted_entries("/").expect("deleted entries");
+    vfat.undelete(&deleted[0], "hello.txt").expect("undelete");
+    assert_eq!(read_file(vfat.open_file("/hello.txt").expect("recovered file")), mock_data(40));
+
+    assert_eq!(read_file(vfat.open_file("/kernel.img").unwrap()), mock_data(2300));
+    assert!(vfat.defragment("/kernel.img").expect("defragment"));
+    assert_eq!(vfat.extents("/kernel.img").expect("extents").len(), 1);
+    assert_eq!(read_file(vfat.open_file("/KERNEL.IMG").unwrap()), mock_data(2300));
+}
+
+#[test]
+fn test_extents_and_fragmentation() {
+    let mut image = MockImage::new();
+    image.add_file(2, b"KERNEL  IMG", "", &[3, 4, 8, 9, 12], &mock_data(2300));
+    image.add_file(2, b"CONFIG  TXT", "", &[5], &mock_data(40));
+    image.add_file(2, b"EMPTY   TXT", "", &[], &[]);
+
+    let vfat = image.vfat();
+    let extents = vfat.extents("/kernel.img").expect("extents");
+    let runs: Vec<_> = extents.iter().map(|e| (e.start(), e.len())).collect();
+    assert_eq!(runs, vec![(3, 2), (8, 2), (12, 1)]);
+    assert!(vfat.extents("/empty.txt").expect("extents").is_empty());
+
+    let report = vfat.fragmentation().expect("fragmentation report");
+    assert_eq!(report.files, 2);
+    assert_eq!(report.fragmented_files, 1);
+    assert_eq!(report.file_extents, 4);
+    assert_eq!(report.used_clusters, 7);
+    assert_eq!(report.free_clusters, 93);
+    assert_eq!(report.free_extents, 3);
+    assert_eq!(report.largest_free_extent, 89);
+}
+
+#[test]
+fn test_defragment() {
+    let mut image = MockImage::new();
+    image.add_file(2, b"KERNEL  IMG", "", &[3, 4, 8, 9, 12], &mock_data(2300));
+    image.add_file(2, b"CONFIG  TXT", "", &[5], &mock_data(40));
+
+    let vfat = image.vfat();
+    assert_eq!(vfat.defragment("/config.txt").expect("defragment"), false);
+    assert_eq!(vfat.defragment("/kernel.img").expect("defragment"), true);
+
+    let extents = vfat.extents("/kernel.img").expect("extents");
+    assert_eq!(extents.len(), 1);
+    assert_eq!(extents[0].len(), 5);
+    assert_eq!(read_file(vfat.open_file("/kernel.img").unwrap()), mock_data(2300));
+    assert_eq!(read_file(vfat.open_file("/config.txt").unwrap()), mock_data(40));
+
+    let report = vfat.fragmentation().expect("fragmentation report");
+    assert_eq!(report.fragmented_files, 0);
+    assert_eq!(report.used_clusters, 7);
+    assert_eq!(vfat.defragment_all().expect("defragment all"), 0);
+}
+
+#[test]
+fn test_defragment_without_free_run() {
+    use vfat::Error;
+
+    let mut image = MockImage::new();
+    image.add_file(2, b"KERNEL  IMG", "", &[3, 4, 8, 9, 12], &mock_data(2300));
+    for cluster in (13..MockImage::CLUSTERS as u32).filter(|c| c % 2 == 1) {
+        image.set_fat(cluster, 0x0FFF_FFF7);
+    }
+
+    let vfat = image.vfat();
+    let e = vfat.defragment("/kernel.img").unwrap_err();
+    expect_variant!(Error::from_io(&e), Some(&Error::NoContiguousRun { clusters: 5 }));
+    assert_eq!(vfat.defragment_all().expect("defragment all"), 0);
+    assert_eq!(vfat.extents("/kernel.img").expect("extents").len(), 3);
+}
+
+#[test]
+fn test_vfat_init_errors() {
+    use vfat::Error;
+
+    let mut image = MockImage::new();
+    image.data[446 + 4] = 0x83;
+    let e = VFat::from(Cursor::new(image.data)).unwrap_err();
+    expect_variant!(e, Error::NoVfatPartition);
+
+    let mut image = MockImage::new();
+    image.data[512 + 11..512 + 13].copy_from_slice(&256u16.to_le_bytes());
+    let e = VFat::from(Cursor::new(image.data)).unwrap_err();
+    expect_variant!(e, Error::UnsupportedSectorSize(256));
+
+    let mut image = MockImage::new();
+    image.data[512 + 13] = 3;
+    let e = VFat::from(Cursor::new(image.data)).unwrap_err();
+    expect_variant!(e, Error::BadBpbField { field: "sectors_per_cluster", value: 3 });
+
+    let mut image = MockImage::new();
+    image.data[512 + 44..512 + 48].copy_from_slice(&500u32.to_le_bytes());
+    let e = VFat::from(Cursor::new(image.data)).unwrap_err();
+    expect_variant!(e, Error::BadBpbField { field: "root_directory_cluster", value: 500 });
+}
+
+#[test]
+fn test_vfat_chain_errors() {
+    use std::io::ErrorKind;
+    use vfat::Error;
+
+    let mut image = MockImage::new();
+    image.add_file(2, b"KEEP    TXT", "", &[3], &mock_data(10));
+    image.add_entry(2, b"LOOP       ", "", 0x10, 4, 0);
+    image.set_fat(4, 5);
+    image.set_fat(5, 4);
+    image.add_entry(2, b"BROKEN     ", "", 0x10, 6, 0);
+    image.set_fat(6, 0);
+    image.add_entry(2, b"OUTSIDE    ", "", 0x10, 7, 0);
+    image.set_fat(7, 1000);
+
+    let vfat = image.vfat();
+    let e = vfat.open_dir("/loop").unwrap().entries().err().expect("chain error");
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    expect_variant!(Error::from_io(&e), Some(&Error::ClusterChainCycle { start: 4 }));
+
+    let e = vfat.open_dir("/broken").unwrap().entries().err().expect("chain error");
+    expect_variant!(Error::from_io(&e), Some(&Error::BrokenChain { cluster: 6 }));
+
+    let e = vfat.open_dir("/outside").unwrap().entries().err().expect("chain error");
+    expect_variant!(Error::from_io(&e), Some(&Error::InvalidCluster(1000)));
+
+    let e = vfat.open("/keep.txt/anything").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    expect_variant!(Error::from_io(&e), Some(&Error::NotADirectory));
+}
+
+#[test]
+fn test_concurrent_handles_follow_relocation() {
+    let mut image = MockImage::new();
+    image.add_file(2, b"KERNEL  IMG", "", &[3, 4, 8, 9, 12], &mock_data(2300));
+
+    let vfat = image.vfat();
+    let mut first = vfat.open_file("/kernel.img").unwrap();
+    let second = vfat.open_file("/kernel.img").unwrap();
+    let mut head = vec![0u8; 1024];
+    first.read_exact(&mut head).expect("read head");
+
+    assert!(vfat.defragment("/kernel.img").expect("defragment"));
+    let mut tail = Vec::new();
+    first.read_to_end(&mut tail).expect("read tail");
+    head.extend(tail);
+    assert_eq!(head, mock_data(2300));
+    assert_eq!(second.size(), 2300);
+    assert_eq!(read_file(second), mock_data(2300));
+}
+
+#[test]
+fn test_set_len() {
+    use std::io::ErrorKind;
+
+    let mut image = MockImage::new();
+    image.add_file(2, b"KERNEL  IMG", "", &[3, 4, 8, 9, 12], &mock_data(2300));
+
+    let vfat = image.vfat();
+    let mut file = vfat.open_file("/kernel.img").unwrap();
+    let other = vfat.open_file("/kernel.img").unwrap();
+    file.set_len(1000).expect("truncate");
+    assert_eq!(other.size(), 1000);
+    assert_eq!(read_file(other), mock_data(1000));
+    assert_eq!(read_file(vfat.open_file("/kernel.img").unwrap()), mock_data(1000));
+    let runs: Vec<_> = vfat.extents("/kernel.img").expect("extents").iter()
+        .map(|e| (e.start(), e.len()))
+        .collect();
+    assert_eq!(runs, vec![(3, 2)]);
+    assert_eq!(vfat.fragmentation().expect("fragmentation report").used_clusters, 3);
+    assert_eq!(file.set_len(1001).unwrap_err().kind(), ErrorKind::InvalidInput);
+
+    file.set_len(0).expect("truncate to nothing");
+    assert_eq!(file.size(), 0);
+    assert!(vfat.extents("/kernel.img").expect("extents").is_empty());
+    assert_eq!(vfat.fragmentation().expect("fragmentation report").used_clusters, 1);
+    assert_eq!(read_file(file), Vec::<u8>::new());
+    assert_eq!(vfat.open_file("/kernel.img").unwrap().size(), 0);
+}
+
+#[test]
+fn test_remove() {
+    use std::io::ErrorKind;
+
+    let mut image = MockImage::new();
+    image.add_file(2, b"HELLOW~1TXT", "hello world.txt", &[3, 4], &mock_data(700));
+    image.add_dir(2, b"DIR        ", "", 5);
+    image.add_file(5, b"INNER   TXT", "", &[6], &mock_data(10));
+
+    let vfat = image.vfat();
+    let mut handle = vfat.open_file("/hello world.txt").unwrap();
+    vfat.remove("/hello world.txt", false).expect("remove file");
+    assert_eq!(vfat.open("/hello world.txt").unwrap_err().kind(), ErrorKind::NotFound);
+    let e = handle.read(&mut [0u8; 16]).unwrap_err();
+    expect_variant!(::vfat::Error::from_io(&e), Some(&::vfat::Error::FileRemoved));
+
+    let deleted = vfat.deleted_entries("/").expect("deleted entries");
+    assert_eq!(deleted.len(), 1);
+    assert_eq!(deleted[0].name(), "hello world.txt");
+    assert!(deleted[0].clusters_free());
+
+    assert_eq!(vfat.remove("/dir", false).unwrap_err().kind(), ErrorKind::Other);
+    assert_eq!(vfat.remove("/", true).unwrap_err().kind(), ErrorKind::InvalidInput);
+    vfat.remove("/dir", true).expect("remove directory");
+    assert_eq!(vfat.open("/dir/inner.txt").unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(vfat.fragmentation().expect("fragmentation report").used_clusters, 1);
+}
+
+#[test]
+fn test_shared_across_threads() {
+    let mut image = MockImage::new();
+    image.add_file(2, b"KERNEL  IMG", "", &[3, 4, 8, 9, 12], &mock_data(2300));
+    image.add_file(2, b"CONFIG  TXT", "", &[5], &mock_data(40));
+
+    let vfat = image.vfat();
+    let threads: Vec<_> = (0..4).map(|i| {
+        let vfat = vfat.clone();
+        ::std::thread::spawn(move || {
+            for _ in 0..20 {
+                let (path, len) = if i % 2 == 0 { ("/kernel.img", 2300) } else { ("/config.txt", 40) };
+                assert_eq!(read_file(vfat.open_file(path).unwrap()), mock_data(len));
+            }
+        })
+    }).collect();
+    for thread in threads {
+        thread.join().expect("reader thread panicked");
+    }
+}
+
+#[test]
+fn test_remove_while_opening() {
+    use std::io::ErrorKind;
+
+    for _ in 0..50 {
+        let mut image = MockImage::new();
+        image.add_file(2, b"HELLO   TXT", "", &[3, 4], &mock_data(700));
+
+        let vfat = image.vfat();
+        let threads: Vec<_> = (0..4).map(|_| {
+            let vfat = vfat.clone();
+            ::std::thread::spawn(move || {
+                // A handle opened before the removal reads the file or fails
+                // as removed, never anything else.
+                while let Ok(mut file) = vfat.open_file("/hello.txt") {
+                    let mut data = Vec::new();
+                    match file.read_to_end(&mut data) {
+                        Ok(_) => assert_eq!(data, mock_data(700)),
+                        Err(e) => expect_variant!(
+                            ::vfat::Error::from_io(&e),
+                            Some(&::vfat::Error::FileRemoved)
+                        ),
+                    }
+                }
+            })
+        }).collect();
+        vfat.remove("/hello.txt", false).expect("remove file");
+        for thread in threads {
+            thread.join().expect("opening thread panicked");
+        }
+        assert_eq!(vfat.open("/hello.txt").unwrap_err().kind(), ErrorKind::NotFound);
+    }
+}
+
+/// An image holding `/boot/{config.txt,kernel.bin}`,
+/// `/data/{sub/config.txt,a.bin}` and `/readme.txt`.
+fn tree_image() -> MockImage {
+    let mut image = MockImage::new();
+    image.add_dir(2, b"BOOT       ", "boot", 3);
+    image.add_file(3, b"CONFIG  TXT", "config.txt", &[4], &mock_data(10));
+    image.add_file(3, b"KERNEL  BIN", "kernel.bin", &[5], &mock_data(10));
+    image.add_dir(2, b"DATA       ", "data", 6);
+    image.add_dir(6, b"SUB        ", "sub", 7);
+    image.add_file(7, b"CONFIG  TXT", "config.txt", &[8], &mock_data(10));
+    image.add_file(6, b"A       BIN", "a.bin", &[9], &mock_data(10));
+    image.add_file(2, b"README  TXT", "readme.txt", &[10], &mock_data(10));
+    image
+}
+
+fn walk_paths<I, E>(walk: I) -> Vec<String>
+where
+    I: Iterator<Item = ::std::io::Result<WalkEntry<E>>>,
+{
+    walk.map(|entry| entry.expect("walk entry").path.to_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn test_walk() {
+    let vfat = tree_image().vfat();
+
+    assert_eq!(walk_paths(walk(&vfat, "/").unwrap()), vec![
+        "/boot", "/boot/config.txt", "/boot/kernel.bin", "/data", "/data/sub",
+        "/data/sub/config.txt", "/data/a.bin", "/readme.txt",
+    ]);
+    assert_eq!(walk_paths(walk(&vfat, "/").unwrap().order(Order::BreadthFirst)), vec![
+        "/boot", "/data", "/readme.txt", "/boot/config.txt", "/boot/kernel.bin",
+        "/data/sub", "/data/a.bin", "/data/sub/config.txt",
+    ]);
+    assert_eq!(walk_paths(walk(&vfat, "/data").unwrap().max_depth(1)), vec![
+        "/data/sub", "/data/a.bin",
+    ]);
+
+    let depths: Vec<_> = walk(&vfat, "/").unwrap().map(|e| e.unwrap().depth).collect();
+    assert_eq!(depths, vec![1, 2, 2, 1, 2, 3, 2, 1]);
+
+    let walk = walk(&vfat, "/").unwrap().filter(|e| e.entry.name() != "data");
+    assert_eq!(walk_paths(walk), vec![
+        "/boot", "/boot/config.txt", "/boot/kernel.bin", "/readme.txt",
+    ]);
+}
+
+#[test]
+fn test_walk_errors() {
+    let mut image = tree_image();
+    image.add_entry(2, b"BROKEN     ", "broken", 0x10, 11, 0);
+
+    let vfat = image.vfat();
+    let results: Vec<_> = walk(&vfat, "/").unwrap().collect();
+    assert_eq!(results.len(), 9);
+    let e = results[8].as_ref().unwrap_err();
+    expect_variant!(::vfat::Error::from_io(e), Some(&::vfat::Error::BrokenChain { cluster: 11 }));
+
+    let paths = walk_paths(walk(&vfat, "/").unwrap().on_error(OnError::Skip));
+    assert_eq!(paths.len(), 9);
+    assert_eq!(paths[8], "/broken");
+}
+
+#[test]
+fn test_pattern() {
+    let pattern = Pattern::new("*.bin").unwrap();
+    assert!(pattern.matches_path("kernel.bin"));
+    assert!(pattern.matches_path(".bin"));
+    assert!(!pattern.matches_path("kernel.img"));
+    assert!(!pattern.matches_path("boot/kernel.bin"));
+    assert!(!pattern.matches_path("KERNEL.BIN"));
+    assert!(pattern.clone().case_insensitive().matches_path("KERNEL.BIN"));
+
+    let pattern = Pattern::new("**/config.txt").unwrap();
+    assert!(pattern.matches_path("config.txt"));
+    assert!(pattern.matches_path("a/b/c/config.txt"));
+    assert!(!pattern.matches_path("a/config.txt/b"));
+
+    let pattern = Pattern::new("kernel?.[a-c0-9]*").unwrap();
+    assert!(pattern.matches_path("kernel8.bin"));
+    assert!(pattern.matches_path("kernel8.0"));
+    assert!(!pattern.matches_path("kernel.bin"));
+    assert!(!pattern.matches_path("kernel8.img"));
+
+    let pattern = Pattern::new("[!.]*").unwrap();
+    assert!(pattern.matches_path("boot"));
+    assert!(!pattern.matches_path(".hidden"));
+
+    let e = Pattern::new("kernel[0-9").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_glob() {
+    let vfat = tree_image().vfat();
+    let matches = |pattern: &str, cwd: &str| {
+        let pattern = Pattern::new(pattern).unwrap();
+        walk_paths(glob(&vfat, cwd, &pattern).expect("glob"))
+    };
+
+    assert_eq!(matches("**/config.txt", "/"), vec!["/boot/config.txt", "/data/sub/config.txt"]);
+    assert_eq!(matches("**/*.bin", "/"), vec!["/boot/kernel.bin", "/data/a.bin"]);
+    assert_eq!(matches("/boot/*.bin", "/data"), vec!["/boot/kernel.bin"]);
+    assert_eq!(matches("*.bin", "/data"), vec!["/data/a.bin"]);
+    assert_eq!(matches("*/config.txt", "/"), vec!["/boot/config.txt"]);
+    assert_eq!(matches("data/*", "/"), vec!["/data/sub", "/data/a.bin"]);
+    assert!(matches("missing/*.bin", "/").is_empty());
+
+    let pattern = Pattern::new("/BOOT/*.TXT").unwrap().case_insensitive();
+    assert_eq!(walk_paths(glob(&vfat, "/", &pattern).unwrap()), vec!["/boot/config.txt"]);
+}
@@ -21,6 +21,7 @@ impl io::Seek for Dummy {
 impl File for Dummy {
     fn sync(&mut self) -> io::Result<()> { panic!("Dummy") }
     fn size(&self) -> u64 { panic!("Dummy") }
+    fn set_len(&mut self, _size: u64) -> io::Result<()> { panic!("Dummy") }
 }
 
 /// Trait implemented by directories in a file system.
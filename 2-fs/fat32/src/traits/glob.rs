@@ -0,0 +1,295 @@
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use traits::walk::{walk, OnError, Walk, WalkEntry};
+use traits::FileSystem;
+
+/// One `/`-separated component of a `Pattern`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// `**`: any number of components, including none.
+    AnyPath,
+    /// A component matched character by character.
+    Name(Vec<Token>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// A character that matches itself.
+    Char(char),
+    /// `?`: any one character.
+    AnyChar,
+    /// `*`: any run of characters, including none.
+    AnyRun,
+    /// `[...]` or `[!...]`: one character in (or, if negated, not in) the
+    /// given ranges.
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+/// A glob pattern over paths, such as `*.bin`, `boot/kernel?.img` or
+/// `**/config.txt`.
+///
+/// Within a component, `?` matches any one character, `*` matches any run of
+/// characters, and `[a-z]` or `[!a-z]` match one character in or not in a
+/// set of characters and ranges. A component that is exactly `**` matches any
+/// number of components. Wildcards never match a `/`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    absolute: bool,
+    segments: Vec<Segment>,
+    case_sensitive: bool,
+}
+
+impl Pattern {
+    /// Parses `pattern`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `pattern` contains a `[` without
+    /// a matching `]`.
+    pub fn new(pattern: &str) -> io::Result<Pattern> {
+        let mut segments = Vec::new();
+        for component in pattern.split('/').filter(|c| !c.is_empty() && *c != ".") {
+            if component == "**" {
+                if segments.last() != Some(&Segment::AnyPath) {
+                    segments.push(Segment::AnyPath);
+                }
+            } else {
+                segments.push(Segment::Name(Pattern::parse_segment(component)?));
+            }
+        }
+        Ok(Pattern {
+            absolute: pattern.starts_with('/'),
+            segments,
+            case_sensitive: true,
+        })
+    }
+
+    fn parse_segment(segment: &str) -> io::Result<Vec<Token>> {
+        let mut tokens = Vec::new();
+        let mut chars = segment.chars();
+        while let Some(c) = chars.next() {
+            let token = match c {
+                '?' => Token::AnyChar,
+                '*' => {
+                    if tokens.last() == Some(&Token::AnyRun) {
+                        continue;
+                    }
+                    Token::AnyRun
+                }
+                '[' => {
+                    let mut class = chars.clone();
+                    let negated = class.as_str().starts_with('!');
+                    if negated {
+                        class.next();
+                    }
+                    let mut ranges = Vec::new();
+                    let mut closed = false;
+                    while let Some(c) = class.next() {
+                        // A `]` right after the `[` is part of the set.
+                        if c == ']' && !ranges.is_empty() {
+                            closed = true;
+                            break;
+                        }
+                        let rest = class.as_str();
+                        if rest.starts_with('-') && rest.len() > 1 && !rest[1..].starts_with(']') {
+                            class.next();
+                            let end = class.next().unwrap();
+                            ranges.push((c, end));
+                        } else {
+                            ranges.push((c, c));
+                        }
+                    }
+                    if !closed {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "unterminated character class in pattern",
+                        ));
+                    }
+                    chars = class;
+                    Token::Class { negated, ranges }
+                }
+                c => Token::Char(c),
+            };
+            tokens.push(token);
+        }
+        Ok(tokens)
+    }
+
+    /// Makes the pattern compare characters without regard to ASCII case, as
+    /// FAT does.
+    pub fn case_insensitive(mut self) -> Pattern {
+        self.case_sensitive = false;
+        self
+    }
+
+    /// Returns `true` if `path`, which is relative to the directory the
+    /// pattern is matched from, matches the pattern.
+    pub fn matches_path<P: AsRef<Path>>(&self, path: P) -> bool {
+        let names = components(path.as_ref());
+        self.matches_from(&self.segments, &names, false)
+    }
+
+    /// Returns `true` if some path starting with the components `names`
+    /// could match the pattern.
+    fn could_match(&self, names: &[&str]) -> bool {
+        self.matches_from(&self.segments, names, true)
+    }
+
+    fn matches_from(&self, segments: &[Segment], names: &[&str], prefix: bool) -> bool {
+        match segments.first() {
+            None => names.is_empty(),
+            Some(&Segment::AnyPath) => {
+                prefix || (0..names.len() + 1).any(|skip| {
+                    self.matches_from(&segments[1..], &names[skip..], prefix)
+                })
+            }
+            Some(&Segment::Name(ref tokens)) => match names.first() {
+                None => prefix,
+                Some(name) => {
+                    let name: Vec<char> = name.chars().collect();
+                    self.matches_name(tokens, &name)
+                        && self.matches_from(&segments[1..], &names[1..], prefix)
+                }
+            },
+        }
+    }
+
+    fn matches_name(&self, tokens: &[Token], name: &[char]) -> bool {
+        match tokens.first() {
+            None => name.is_empty(),
+            Some(&Token::AnyRun) => {
+                (0..name.len() + 1).any(|skip| self.matches_name(&tokens[1..], &name[skip..]))
+            }
+            Some(token) => match name.first() {
+                None => false,
+                Some(&c) => self.matches_char(token, c) && self.matches_name(&tokens[1..], &name[1..]),
+            },
+        }
+    }
+
+    fn matches_char(&self, token: &Token, c: char) -> bool {
+        let fold = |c: char| if self.case_sensitive { c } else { c.to_ascii_lowercase() };
+        match *token {
+            Token::Char(expected) => fold(expected) == fold(c),
+            Token::AnyChar | Token::AnyRun => true,
+            Token::Class { negated, ref ranges } => {
+                let within = |c: char| ranges.iter().any(|&(start, end)| start <= c && c <= end);
+                let found = within(c)
+                    || (!self.case_sensitive
+                        && (within(c.to_ascii_lowercase()) || within(c.to_ascii_uppercase())));
+                found != negated
+            }
+        }
+    }
+
+    /// Returns the leading components of the pattern that contain no
+    /// wildcards.
+    fn literal_prefix(&self) -> Vec<String> {
+        let mut prefix = Vec::new();
+        // The last segment is always matched against the entries of a
+        // directory, so that it is checked for existence.
+        for segment in &self.segments[..self.segments.len().saturating_sub(1)] {
+            match *segment {
+                Segment::Name(ref tokens) if self.case_sensitive => {
+                    let literal: Option<String> = tokens.iter().map(|token| match *token {
+                        Token::Char(c) => Some(c),
+                        _ => None,
+                    }).collect();
+                    match literal {
+                        Some(name) => prefix.push(name),
+                        None => break,
+                    }
+                }
+                _ => break,
+            }
+        }
+        prefix
+    }
+}
+
+/// Returns the names of the normal components of `path`.
+fn components(path: &Path) -> Vec<&str> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// An iterator over the entries matching a `Pattern`, returned by `glob()`.
+pub struct Glob<F: FileSystem> {
+    walk: Option<Walk<F>>,
+    base: PathBuf,
+    pattern: Pattern,
+}
+
+/// Returns an iterator over the entries of `fs` whose paths match `pattern`.
+///
+/// A relative `pattern` is matched from the directory `cwd`, and an absolute
+/// one from the root. Directories that cannot be read are skipped.
+///
+/// # Errors
+///
+/// Returns the errors of `open_dir()` for the deepest directory named by the
+/// pattern without wildcards. `NotFound` errors are not reported; the
+/// iterator is then empty.
+pub fn glob<F, P>(fs: F, cwd: P, pattern: &Pattern) -> io::Result<Glob<F>>
+where
+    F: FileSystem,
+    P: AsRef<Path>,
+{
+    let mut base = if pattern.absolute { PathBuf::from("/") } else { cwd.as_ref().to_path_buf() };
+    let prefix = pattern.literal_prefix();
+    for name in &prefix {
+        base.push(name);
+    }
+    let remaining = Pattern {
+        absolute: false,
+        segments: pattern.segments[prefix.len()..].to_vec(),
+        case_sensitive: pattern.case_sensitive,
+    };
+
+    let mut walk = match walk(fs, &base) {
+        Ok(walk) => walk.on_error(OnError::Skip),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(Glob { walk: None, base, pattern: remaining });
+        }
+        Err(err) => return Err(err),
+    };
+    if !remaining.segments.contains(&Segment::AnyPath) {
+        walk = walk.max_depth(remaining.segments.len());
+    }
+
+    let filter_base = base.clone();
+    let filter_pattern = remaining.clone();
+    let walk = walk.filter(move |entry: &WalkEntry<F::Entry>| {
+        let relative = entry.path.strip_prefix(&filter_base).unwrap_or(&entry.path);
+        filter_pattern.could_match(&components(relative))
+    });
+    Ok(Glob { walk: Some(walk), base, pattern: remaining })
+}
+
+impl<F: FileSystem> Iterator for Glob<F> {
+    type Item = io::Result<WalkEntry<F::Entry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let walk = self.walk.as_mut()?;
+        while let Some(entry) = walk.next() {
+            match entry {
+                Ok(entry) => {
+                    let matches = {
+                        let relative = entry.path.strip_prefix(&self.base).unwrap_or(&entry.path);
+                        self.pattern.matches_path(relative)
+                    };
+                    if matches {
+                        return Some(Ok(entry));
+                    }
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        None
+    }
+}
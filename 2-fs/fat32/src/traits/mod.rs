@@ -2,8 +2,12 @@ mod fs;
 mod block_device;
 mod metadata;
 mod dummy;
+pub mod walk;
+pub mod glob;
 
 pub use self::fs::{Dir, Entry, File, FileSystem};
 pub use self::metadata::{Metadata, Timestamp};
 pub use self::block_device::BlockDevice;
 pub use self::dummy::Dummy;
+pub use self::walk::{walk, OnError, Order, Walk, WalkEntry};
+pub use self::glob::{glob, Glob, Pattern};
@@ -0,0 +1,177 @@
+use std::collections::VecDeque;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use traits::{Dir, Entry, FileSystem};
+
+/// The order in which a `Walk` visits entries.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Order {
+    /// Every directory is followed by its contents, before its siblings.
+    DepthFirst,
+    /// All entries at one depth are visited before any entry deeper down.
+    BreadthFirst,
+}
+
+/// What a `Walk` does when a directory's entries cannot be read.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OnError {
+    /// Yield the error and end the walk.
+    Fail,
+    /// Leave the directory's contents out and carry on.
+    Skip,
+}
+
+/// An entry visited by a `Walk`.
+#[derive(Debug)]
+pub struct WalkEntry<E> {
+    /// The absolute path of the entry.
+    pub path: PathBuf,
+    /// The depth of the entry below the directory the walk started at, whose
+    /// own entries have depth 1.
+    pub depth: usize,
+    /// The entry itself.
+    pub entry: E,
+}
+
+/// A directory whose entries are being visited.
+struct Pending<I> {
+    path: PathBuf,
+    depth: usize,
+    entries: I,
+}
+
+/// An iterator over every entry below a directory, returned by `walk()`.
+///
+/// The entries `.` and `..` are never visited.
+pub struct Walk<F: FileSystem> {
+    pending: VecDeque<Pending<<F::Dir as Dir>::Iter>>,
+    order: Order,
+    on_error: OnError,
+    max_depth: Option<usize>,
+    filter: Option<Box<FnMut(&WalkEntry<F::Entry>) -> bool>>,
+    done: bool,
+}
+
+/// Returns a `Walk` over every entry below the directory at `path` of `fs`.
+///
+/// By default, the walk is depth-first, unbounded in depth, and fails on the
+/// first directory that cannot be read.
+///
+/// # Errors
+///
+/// Returns the errors of `open_dir()` for `path`, or of reading its entries.
+pub fn walk<F: FileSystem, P: AsRef<Path>>(fs: F, path: P) -> io::Result<Walk<F>> {
+    let path = path.as_ref().to_path_buf();
+    let entries = fs.open_dir(&path)?.entries()?;
+    let mut pending = VecDeque::new();
+    pending.push_back(Pending { path, depth: 0, entries });
+    Ok(Walk {
+        pending,
+        order: Order::DepthFirst,
+        on_error: OnError::Fail,
+        max_depth: None,
+        filter: None,
+        done: false,
+    })
+}
+
+impl<F: FileSystem> Walk<F> {
+    /// Visits entries in `order`.
+    pub fn order(mut self, order: Order) -> Walk<F> {
+        self.order = order;
+        self
+    }
+
+    /// Handles unreadable directories as `on_error` says.
+    pub fn on_error(mut self, on_error: OnError) -> Walk<F> {
+        self.on_error = on_error;
+        self
+    }
+
+    /// Visits no entries deeper than `max_depth`. A `max_depth` of 1 visits
+    /// only the entries of the starting directory.
+    pub fn max_depth(mut self, max_depth: usize) -> Walk<F> {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Visits only the entries for which `filter` returns `true`. The walk
+    /// does not descend into directories that are filtered out.
+    pub fn filter<P>(mut self, filter: P) -> Walk<F>
+    where
+        P: FnMut(&WalkEntry<F::Entry>) -> bool + 'static,
+    {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Returns the next entry of the directory the walk is in, leaving
+    /// finished directories behind.
+    fn next_entry(&mut self) -> Option<WalkEntry<F::Entry>> {
+        loop {
+            let next = {
+                let pending = match self.order {
+                    Order::DepthFirst => self.pending.back_mut()?,
+                    Order::BreadthFirst => self.pending.front_mut()?,
+                };
+                pending.entries.next().map(|entry| WalkEntry {
+                    path: pending.path.join(entry.name()),
+                    depth: pending.depth + 1,
+                    entry,
+                })
+            };
+            match next {
+                Some(entry) => return Some(entry),
+                None => match self.order {
+                    Order::DepthFirst => self.pending.pop_back(),
+                    Order::BreadthFirst => self.pending.pop_front(),
+                },
+            };
+        }
+    }
+}
+
+impl<F: FileSystem> Iterator for Walk<F> {
+    type Item = io::Result<WalkEntry<F::Entry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        while let Some(entry) = self.next_entry() {
+            if entry.entry.name() == "." || entry.entry.name() == ".." {
+                continue;
+            }
+            if let Some(ref mut filter) = self.filter {
+                if !filter(&entry) {
+                    continue;
+                }
+            }
+
+            let descend = self.max_depth.map_or(true, |max| entry.depth < max);
+            if descend {
+                if let Some(dir) = entry.entry.as_dir() {
+                    match dir.entries() {
+                        Ok(entries) => self.pending.push_back(Pending {
+                            path: entry.path.clone(),
+                            depth: entry.depth,
+                            entries,
+                        }),
+                        Err(err) => match self.on_error {
+                            OnError::Fail => {
+                                self.done = true;
+                                return Some(Err(err));
+                            }
+                            OnError::Skip => {}
+                        },
+                    }
+                }
+            }
+            return Some(Ok(entry));
+        }
+
+        None
+    }
+}
@@ -1,5 +1,6 @@
 use std::{fmt, io};
 use std::io::Read;
+use std::cmp::min;
 use std::collections::HashMap;
 
 use traits::BlockDevice;
@@ -112,6 +113,36 @@ impl CachedDevice {
         }
         Ok(&self.cache[&sector].data[..])
     }
+
+    /// Writes every dirty cached sector back to the underlying device and
+    /// marks it clean.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error writing a sector to the disk.
+    /// Sectors that were not written remain dirty.
+    pub fn sync(&mut self) -> io::Result<()> {
+        let mut dirty: Vec<u64> = self.cache
+            .iter()
+            .filter(|&(_, entry)| entry.dirty)
+            .map(|(&sector, _)| sector)
+            .collect();
+        dirty.sort();
+
+        let physical_sector_size = self.device.sector_size();
+        for sector in dirty {
+            let (physical_sector, physical_sector_num) = self.virtual_to_physical(sector);
+            let entry = self.cache.get_mut(&sector).unwrap();
+            for i in 0..physical_sector_num {
+                self.device.write_sector(
+                    physical_sector + i,
+                    &entry.data[(i * physical_sector_size) as usize..],
+                )?;
+            }
+            entry.dirty = false;
+        }
+        Ok(())
+    }
 }
 
 // FIXME: Implement `BlockDevice` for `CacheDevice`. The `read_sector` and
@@ -130,8 +161,15 @@ impl BlockDevice for CachedDevice {
         }
     }
 
-    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!();
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size() as usize;
+        if buf.len() < sector_size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "buffer smaller than sector"));
+        }
+        let data = self.get_mut(n)?;
+        let size = min(data.len(), buf.len());
+        data[..size].copy_from_slice(&buf[..size]);
+        Ok(size)
     }
 }
 
@@ -0,0 +1,100 @@
+use std::collections::{HashMap, VecDeque};
+
+use vfat::dir::EntryLocation;
+use vfat::{Cluster, Dir, Entry, File, Metadata, Shared, VFat};
+
+/// The maximum number of names a `DentryCache` remembers.
+const CAPACITY: usize = 512;
+
+/// Everything needed to rebuild an `Entry` without re-reading its directory.
+#[derive(Debug, Clone)]
+pub(super) struct Dentry {
+    pub(super) short_name: String,
+    pub(super) long_name: String,
+    pub(super) metadata: Metadata,
+    pub(super) file_size: u32,
+    pub(super) location: EntryLocation,
+}
+
+impl Dentry {
+    /// The name the entry is looked up by: its long name if it has one and its
+    /// 8.3 name otherwise.
+    pub(super) fn name(&self) -> &str {
+        if !self.long_name.is_empty() {
+            &self.long_name
+        } else {
+            &self.short_name
+        }
+    }
+
+    /// Builds the `Entry` this dentry describes. `vfat` is a borrow of `fs`,
+    /// under which a file is registered as open.
+    pub(super) fn into_entry(self, fs: &Shared<VFat>, vfat: &mut VFat) -> Entry {
+        let cluster = Cluster::from(self.metadata.first_cluster());
+        if self.metadata.attributes.directory() {
+            Entry::Dir(Dir {
+                cluster,
+                fs: fs.clone(),
+                short_name: self.short_name,
+                long_name: self.long_name,
+                metadata: self.metadata,
+                location: Some(self.location),
+            })
+        } else {
+            let node = vfat.open_files.open(self.location, cluster, self.file_size);
+            Entry::File(File {
+                cluster,
+                fs: fs.clone(),
+                short_name: self.short_name,
+                long_name: self.long_name,
+                metadata: self.metadata,
+                file_size: self.file_size,
+                current_offset: 0,
+                current_cluster: Some(cluster),
+                bytes_per_cluster: vfat.bytes_per_cluster() as u32,
+                location: self.location,
+                node,
+            })
+        }
+    }
+}
+
+/// A bounded cache of name lookups, keyed by the first cluster of the
+/// directory searched and the lower-cased name searched for.
+///
+/// A cached `None` records that the directory has no entry with that name.
+/// When full, the oldest lookup is forgotten first.
+#[derive(Debug, Default)]
+pub(super) struct DentryCache {
+    entries: HashMap<(Cluster, String), Option<Dentry>>,
+    order: VecDeque<(Cluster, String)>,
+}
+
+impl DentryCache {
+    /// Returns the cached result of looking up `name` in the directory at
+    /// `dir`, or `None` if the lookup is not cached.
+    pub(super) fn get(&self, dir: Cluster, name: &str) -> Option<Option<Dentry>> {
+        self.entries.get(&(dir, name.to_string())).cloned()
+    }
+
+    /// Caches `dentry` as the result of looking up `name` in the directory at
+    /// `dir`.
+    pub(super) fn insert(&mut self, dir: Cluster, name: String, dentry: Option<Dentry>) {
+        let key = (dir, name);
+        if self.entries.insert(key.clone(), dentry).is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    /// Forgets every cached lookup in the directory at `dir`. Must be called
+    /// whenever an entry of that directory is modified.
+    pub(super) fn invalidate(&mut self, dir: Cluster) {
+        self.entries.retain(|&(cluster, _), _| cluster != dir);
+        self.order.retain(|&(cluster, _)| cluster != dir);
+    }
+}
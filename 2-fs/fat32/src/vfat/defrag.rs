@@ -0,0 +1,243 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use traits::{walk, Entry as EntryTrait, FileSystem};
+use vfat::dir::VFatDirEntry;
+use vfat::{Cluster, Entry, Error, Shared, VFat};
+
+/// The FAT value marking the last cluster of a chain.
+const END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+
+/// A run of contiguous clusters.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Extent {
+    start: u32,
+    len: u32,
+}
+
+impl Extent {
+    /// The first cluster of the run.
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    /// The number of clusters in the run.
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+}
+
+/// Splits `clusters` into runs of contiguous clusters.
+fn extents_of<I: IntoIterator<Item = Cluster>>(clusters: I) -> Vec<Extent> {
+    let mut extents: Vec<Extent> = Vec::new();
+    for cluster in clusters {
+        let num = cluster.cluster_num();
+        if let Some(last) = extents.last_mut() {
+            if last.start + last.len == num {
+                last.len += 1;
+                continue;
+            }
+        }
+        extents.push(Extent { start: num, len: 1 });
+    }
+    extents
+}
+
+/// Returns `true` if `err` reports that a file could not be defragmented for
+/// lack of free space.
+fn is_no_contiguous_run(err: &io::Error) -> bool {
+    match Error::from_io(err) {
+        Some(&Error::NoContiguousRun { .. }) => true,
+        _ => false,
+    }
+}
+
+/// A volume-wide summary of fragmentation.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FragmentationReport {
+    /// The number of non-empty files on the volume.
+    pub files: usize,
+    /// The number of files stored in more than one extent.
+    pub fragmented_files: usize,
+    /// The total number of extents over all files.
+    pub file_extents: usize,
+    /// The number of clusters in use.
+    pub used_clusters: u32,
+    /// The number of free clusters.
+    pub free_clusters: u32,
+    /// The number of runs of free clusters.
+    pub free_extents: usize,
+    /// The length of the longest run of free clusters.
+    pub largest_free_extent: u32,
+}
+
+impl VFat {
+    /// Returns the runs of free clusters on the volume in ascending order.
+    fn free_extents(&mut self) -> Result<Vec<Extent>, Error> {
+        let mut free = Vec::new();
+        for num in 2..self.cluster_count() {
+            let cluster = Cluster::from(num);
+            if self.is_free(cluster)? {
+                free.push(cluster);
+            }
+        }
+        Ok(extents_of(free))
+    }
+
+    /// Returns the clusters of the chain starting at `first`, or an empty list
+    /// if `first` is not a data cluster (as for an empty file).
+    fn chain_of(&mut self, first: Cluster) -> Result<Vec<Cluster>, Error> {
+        if first.is_valid() {
+            self.chain(first)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+}
+
+impl Shared<VFat> {
+    /// Returns the runs of contiguous clusters holding the file or directory
+    /// at `path`, in file order. An empty file has no extents.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `open()` for `path`, or any I/O error that occurs
+    /// while reading the FAT.
+    pub fn extents<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<Extent>> {
+        let first = match self.open(path)? {
+            Entry::File(file) => file.cluster,
+            Entry::Dir(dir) => dir.cluster,
+        };
+        let chain = self.borrow_mut().chain_of(first)?;
+        Ok(extents_of(chain))
+    }
+
+    /// Returns the paths of every file on the volume.
+    fn files(&self) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in walk(self, "/")? {
+            let entry = entry?;
+            if entry.entry.is_file() {
+                files.push(entry.path);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Returns a summary of how fragmented the files and free space of the
+    /// volume are.
+    ///
+    /// # Errors
+    ///
+    /// Returns any I/O error that occurs while reading directories or the FAT.
+    pub fn fragmentation(&self) -> io::Result<FragmentationReport> {
+        let mut report = FragmentationReport::default();
+        for path in self.files()? {
+            let extents = self.extents(path)?;
+            if extents.is_empty() {
+                continue;
+            }
+            report.files += 1;
+            report.file_extents += extents.len();
+            if extents.len() > 1 {
+                report.fragmented_files += 1;
+            }
+        }
+
+        let mut fs = self.borrow_mut();
+        let free = fs.free_extents()?;
+        report.free_clusters = free.iter().map(|e| e.len).sum();
+        report.used_clusters = fs.cluster_count().saturating_sub(2) - report.free_clusters;
+        report.free_extents = free.len();
+        report.largest_free_extent = free.iter().map(|e| e.len).max().unwrap_or(0);
+        Ok(report)
+    }
+
+    /// Relocates the file at `path` into a single run of contiguous clusters.
+    /// Returns `true` if the file was moved and `false` if it was already
+    /// contiguous.
+    ///
+    /// The data is first copied into free clusters which are then chained in
+    /// the FAT. Only after that is on disk is the directory entry pointed at
+    /// the new chain, and only then is the old chain freed. An interruption at
+    /// any point leaves the file intact, at worst leaking the clusters of one
+    /// of the two copies.
+    ///
+    /// Handles to the file opened before it is relocated follow it to its new
+    /// clusters.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `path` refers to a directory and
+    /// `Error::NoContiguousRun` if there is no run of free clusters large
+    /// enough to hold the file. Returns the errors of `open()` for `path`, or any I/O error that
+    /// occurs while moving the file.
+    pub fn defragment<P: AsRef<Path>>(&self, path: P) -> io::Result<bool> {
+        let file = self.open(path)?.into_file().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "only files can be defragmented")
+        })?;
+
+        let mut fs = self.borrow_mut();
+        let chain = fs.chain_of(file.cluster)?;
+        if extents_of(chain.iter().cloned()).len() <= 1 {
+            return Ok(false);
+        }
+
+        let len = chain.len() as u32;
+        let target = fs.free_extents()?
+            .into_iter()
+            .find(|extent| extent.len >= len)
+            .ok_or(Error::NoContiguousRun { clusters: len })?;
+
+        // Copy the data and chain the copy in the FAT.
+        let mut buf = vec![0u8; fs.bytes_per_cluster()];
+        for (i, &cluster) in chain.iter().enumerate() {
+            let new_cluster = Cluster::from(target.start + i as u32);
+            fs.read_cluster(cluster, 0, &mut buf)?;
+            fs.write_cluster(new_cluster, 0, &buf)?;
+        }
+        for i in 0..len {
+            let next = if i + 1 == len { END_OF_CHAIN } else { target.start + i + 1 };
+            fs.set_fat_entry(Cluster::from(target.start + i), next)?;
+        }
+        fs.sync()?;
+
+        // Point the directory entry at the copy.
+        let raw = fs.read_dir_entry(file.location.dir, file.location.index)?;
+        let mut entry = VFatDirEntry::from_bytes(&raw);
+        unsafe { entry.regular.metadata.set_first_cluster(target.start) };
+        fs.write_dir_entry(file.location.dir, file.location.index, &entry.to_bytes())?;
+        fs.sync()?;
+        if let Some(node) = fs.open_files.at(file.location) {
+            node.cluster = Cluster::from(target.start);
+        }
+
+        // Release the original chain.
+        for cluster in chain {
+            fs.set_fat_entry(cluster, 0)?;
+        }
+        fs.sync()?;
+        Ok(true)
+    }
+
+    /// Relocates every fragmented file on the volume into a contiguous run of
+    /// clusters and returns the number of files moved. Files for which no
+    /// large enough free run exists are left in place.
+    ///
+    /// # Errors
+    ///
+    /// Returns any I/O error that occurs while reading directories or moving
+    /// a file.
+    pub fn defragment_all(&self) -> io::Result<usize> {
+        let mut moved = 0;
+        for path in self.files()? {
+            match self.defragment(path) {
+                Ok(true) => moved += 1,
+                Ok(false) => {}
+                Err(ref e) if is_no_contiguous_run(e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(moved)
+    }
+}
@@ -3,28 +3,44 @@ use std::io;
 use std::mem::size_of;
 
 use std::fmt;
-use std::str;
 use traits;
 use vfat::{Attributes, Metadata};
-use vfat::{Cluster, Entry, File, Shared, VFat};
+use vfat::{Cluster, Entry, Shared, VFat};
+use vfat::dcache::Dentry;
+
+/// The size, in bytes, of an on-disk directory entry.
+pub(super) const DIR_ENTRY_SIZE: usize = 32;
+
+/// The first byte of a directory entry that has been deleted.
+pub(super) const DELETED: u8 = 0xE5;
+
+/// The position of an on-disk directory entry: the first cluster of the
+/// directory holding it and the entry's index within that directory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(super) struct EntryLocation {
+    pub(super) dir: Cluster,
+    pub(super) index: usize,
+}
 
 pub struct Dir {
     // FIXME: Fill me in.
-    cluster: Cluster,
-    fs: Shared<VFat>,
-    short_name: String,
-    long_name: String,
+    pub(super) cluster: Cluster,
+    pub(super) fs: Shared<VFat>,
+    pub(super) short_name: String,
+    pub(super) long_name: String,
     pub(super) metadata: Metadata,
+    /// The location of this directory's entry, or `None` for the root.
+    pub(super) location: Option<EntryLocation>,
 }
 
 #[repr(C, align(32))]
 #[derive(Debug, Copy, Clone)]
 pub struct VFatRegularDirEntry {
     // FIXME: Fill me in.
-    short_file_name: [u8; 8],
-    short_file_extension: [u8; 3],
-    metadata: Metadata,
-    file_size: u32,
+    pub(super) short_file_name: [u8; 8],
+    pub(super) short_file_extension: [u8; 3],
+    pub(super) metadata: Metadata,
+    pub(super) file_size: u32,
 }
 
 #[repr(C, packed)]
@@ -35,7 +51,7 @@ pub struct VFatLfnDirEntry {
     name_characters: [u16; 5],
     attributes: Attributes,
     type_: u8,
-    checksum_of_file_name: u8,
+    pub(super) checksum_of_file_name: u8,
     name_characters_2: [u16; 6],
     always_zero: u16,
     name_characters_3: [u16; 2],
@@ -45,9 +61,9 @@ pub struct VFatLfnDirEntry {
 #[derive(Copy, Clone)]
 pub struct VFatUnknownDirEntry {
     // FIXME: Fill me in.
-    status: u8,
+    pub(super) status: u8,
     __r1: [u8; 10],
-    attributes: Attributes,
+    pub(super) attributes: Attributes,
     __r2: [u8; 20],
 }
 
@@ -59,12 +75,57 @@ pub struct VFatDummyDirEntry {
 
 #[derive(Copy, Clone)]
 pub union VFatDirEntry {
-    unknown: VFatUnknownDirEntry,
-    regular: VFatRegularDirEntry,
-    long_filename: VFatLfnDirEntry,
+    pub(super) unknown: VFatUnknownDirEntry,
+    pub(super) regular: VFatRegularDirEntry,
+    pub(super) long_filename: VFatLfnDirEntry,
     dummy: VFatDummyDirEntry,
 }
 
+impl VFatDirEntry {
+    /// Reinterprets the raw on-disk bytes `raw` as a directory entry.
+    pub(super) fn from_bytes(raw: &[u8; DIR_ENTRY_SIZE]) -> VFatDirEntry {
+        let mut dummy = VFatDummyDirEntry::default();
+        dummy.__r1.copy_from_slice(raw);
+        VFatDirEntry { dummy }
+    }
+
+    /// Returns the raw on-disk bytes of this directory entry.
+    pub(super) fn to_bytes(&self) -> [u8; DIR_ENTRY_SIZE] {
+        unsafe { self.dummy.__r1 }
+    }
+}
+
+impl VFatLfnDirEntry {
+    /// Returns the 13 UCS-2 name characters stored in this entry.
+    pub(super) fn characters(&self) -> [u16; 13] {
+        let mut characters = [0u16; 13];
+        characters[0..5].copy_from_slice(&self.name_characters);
+        characters[5..11].copy_from_slice(&self.name_characters_2);
+        characters[11..13].copy_from_slice(&self.name_characters_3);
+        characters
+    }
+}
+
+impl VFatRegularDirEntry {
+    /// Returns the 8.3 name of this entry with padding removed, joining the
+    /// name and extension with a `.` if the extension is non-empty.
+    pub(super) fn short_name(&self) -> String {
+        let mut short_file_name = self.short_file_name;
+        if short_file_name[0] == 0x05 {
+            // 0x05 is used for real 0xE5 as first byte
+            short_file_name[0] = 0xE5;
+        }
+        let name = String::from_utf8_lossy(&short_file_name);
+        let ext = String::from_utf8_lossy(&self.short_file_extension);
+        let mut short_name = String::from(name.trim_end());
+        if !ext.trim_end().is_empty() {
+            short_name.push_str(".");
+            short_name.push_str(ext.trim_end());
+        }
+        short_name
+    }
+}
+
 impl Dir {
     /// Finds the entry named `name` in `self` and returns it. Comparison is
     /// case-insensitive.
@@ -77,7 +138,6 @@ impl Dir {
     /// If `name` contains invalid UTF-8 characters, an error of `InvalidInput`
     /// is returned.
     pub fn find<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Entry> {
-        use traits::{Dir, Entry};
         let lowercase_name = match name.as_ref().to_str() {
             Some(name_str) => name_str.to_lowercase(),
             None => {
@@ -87,12 +147,35 @@ impl Dir {
                 ));
             }
         };
-        for entry in self.entries()? {
-            if lowercase_name == entry.name().to_lowercase() {
-                return Ok(entry);
+        // The lookup, the scan, the caching of its result and the opening of
+        // the entry happen under one borrow, so that an entry removed in
+        // between is neither cached nor opened.
+        let mut fs = self.fs.borrow_mut();
+        let cached = fs.dentries.get(self.cluster, &lowercase_name);
+        let dentry = match cached {
+            Some(dentry) => dentry,
+            None => {
+                let dentry = self.find_dentry(&mut fs, &lowercase_name)?;
+                fs.dentries.insert(self.cluster, lowercase_name, dentry.clone());
+                dentry
+            }
+        };
+        match dentry {
+            Some(dentry) => Ok(dentry.into_entry(&self.fs, &mut fs)),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "entry not found")),
+        }
+    }
+
+    /// Scans `self` for the entry whose lower-cased name is `lowercase_name`,
+    /// reading it through `fs`, a borrow of `self.fs`.
+    fn find_dentry(&self, fs: &mut VFat, lowercase_name: &str) -> io::Result<Option<Dentry>> {
+        let mut entries = self.entries_in(fs)?;
+        while let Some(dentry) = entries.next_dentry() {
+            if dentry.name().to_lowercase() == lowercase_name {
+                return Ok(Some(dentry));
             }
         }
-        Err(io::Error::new(io::ErrorKind::NotFound, "entry not found"))
+        Ok(None)
     }
 
     pub fn name(&self) -> &str {
@@ -103,30 +186,73 @@ impl Dir {
         }
     }
 
+    /// Reads every raw directory entry of `self`, including deleted and
+    /// unused ones.
+    pub(super) fn raw_entries(&self) -> io::Result<Vec<VFatDirEntry>> {
+        self.raw_entries_in(&mut self.fs.borrow_mut())
+    }
+
+    /// Reads every raw directory entry of `self` through `fs`, a borrow of
+    /// `self.fs`.
+    fn raw_entries_in(&self, fs: &mut VFat) -> io::Result<Vec<VFatDirEntry>> {
+        let mut data = Vec::new();
+        fs.read_chain(self.cluster, &mut data)?;
+        let num_entries = data.len() / size_of::<VFatDirEntry>();
+        let mut entries = vec![
+            VFatDirEntry {
+                dummy: VFatDummyDirEntry::default(),
+            };
+            num_entries
+        ];
+        unsafe {
+            data.as_ptr().copy_to(
+                entries.as_mut_ptr() as *mut u8,
+                num_entries * size_of::<VFatDirEntry>(),
+            );
+        }
+        Ok(entries)
+    }
+
+    /// Returns an iterator over the entries of `self`, reading them through
+    /// `fs`, a borrow of `self.fs`.
+    fn entries_in(&self, fs: &mut VFat) -> io::Result<EntryIterator> {
+        Ok(EntryIterator {
+            dir: self.cluster,
+            data: self.raw_entries_in(fs)?,
+            current_index: 0,
+            fs: self.fs.clone(),
+        })
+    }
+
     pub(super) fn new_root(fs: &Shared<VFat>) -> Dir {
         let cluster = fs.borrow().root_dir_cluster;
+        Dir::from_cluster(fs, cluster)
+    }
+
+    /// Returns an unnamed directory whose entries start at `cluster`.
+    pub(super) fn from_cluster(fs: &Shared<VFat>, cluster: Cluster) -> Dir {
         Dir {
             cluster,
             fs: fs.clone(),
             short_name: String::new(),
             long_name: String::new(),
             metadata: Metadata::default(),
+            location: None,
         }
     }
 }
 
 // FIXME: Implement `trait::Dir` for `Dir`.
 pub struct EntryIterator {
+    dir: Cluster,
     data: Vec<VFatDirEntry>,
     current_index: usize,
     fs: Shared<VFat>,
-    bytes_per_cluster: u32,
 }
 
-impl Iterator for EntryIterator {
-    type Item = Entry;
-
-    fn next(&mut self) -> Option<Self::Item> {
+impl EntryIterator {
+    /// Returns a description of the next entry of the directory.
+    fn next_dentry(&mut self) -> Option<Dentry> {
         let mut long_file_name = [0u16; 260];
         while self.current_index < self.data.len() {
             let current_entry: &VFatDirEntry = &self.data[self.current_index];
@@ -134,42 +260,29 @@ impl Iterator for EntryIterator {
             if unknown_entry.status == 0x00 {
                 // End of FAT
                 return None;
-            } else if unknown_entry.status == 0xE5 {
+            } else if unknown_entry.status == DELETED {
                 // Deleted entry
                 self.current_index += 1;
                 continue;
             }
 
             // Normal entry,
+            let location = EntryLocation {
+                dir: self.dir,
+                index: self.current_index,
+            };
             self.current_index += 1;
             if unknown_entry.attributes.lfn() {
                 let lfn_entry = unsafe { current_entry.long_filename };
                 let lfn_sequence_num = (lfn_entry.sequence_number & 0x1F) as usize - 1;
 
                 if lfn_sequence_num <= 19 {
-                    long_file_name[lfn_sequence_num * 13..lfn_sequence_num * 13 + 5]
-                        .copy_from_slice(&lfn_entry.name_characters);
-                    long_file_name[lfn_sequence_num * 13 + 5..lfn_sequence_num * 13 + 11]
-                        .copy_from_slice(&lfn_entry.name_characters_2);
-                    long_file_name[lfn_sequence_num * 13 + 11..lfn_sequence_num * 13 + 13]
-                        .copy_from_slice(&lfn_entry.name_characters_3);
+                    long_file_name[lfn_sequence_num * 13..lfn_sequence_num * 13 + 13]
+                        .copy_from_slice(&lfn_entry.characters());
                 }
             } else {
                 let regular_entry = unsafe { current_entry.regular };
-                let mut short_file_name = regular_entry.short_file_name;
-                if short_file_name[0] == 0x05 {
-                    // 0x05 is used for real 0xE5 as first byte
-                    short_file_name[0] = 0xE5;
-                }
-                let name = str::from_utf8(&short_file_name).unwrap().trim_end();
-                let ext = str::from_utf8(&regular_entry.short_file_extension)
-                    .unwrap()
-                    .trim_end();
-                let mut short_name = String::from(name);
-                if !ext.is_empty() {
-                    short_name.push_str(".");
-                    short_name.push_str(ext);
-                }
+                let short_name = regular_entry.short_name();
                 let mut nul_byte_index = None;
                 for (i, byte) in long_file_name.iter().enumerate() {
                     if *byte == 0 {
@@ -183,35 +296,28 @@ impl Iterator for EntryIterator {
                     &long_file_name
                 })
                 .unwrap();
-                if regular_entry.metadata.attributes.directory() {
-                    return Some(Entry::Dir(Dir {
-                        cluster: Cluster::from(regular_entry.metadata.first_cluster()),
-                        fs: self.fs.clone(),
-                        short_name,
-                        long_name,
-                        metadata: regular_entry.metadata,
-                    }));
-                } else {
-                    return Some(Entry::File(File {
-                        cluster: Cluster::from(regular_entry.metadata.first_cluster()),
-                        fs: self.fs.clone(),
-                        short_name,
-                        long_name,
-                        metadata: regular_entry.metadata,
-                        file_size: regular_entry.file_size,
-                        current_offset: 0,
-                        current_cluster: Some(Cluster::from(
-                            regular_entry.metadata.first_cluster(),
-                        )),
-                        bytes_per_cluster: self.bytes_per_cluster,
-                    }));
-                }
+                return Some(Dentry {
+                    short_name,
+                    long_name,
+                    metadata: regular_entry.metadata,
+                    file_size: regular_entry.file_size,
+                    location,
+                });
             }
         }
         None
     }
 }
 
+impl Iterator for EntryIterator {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let dentry = self.next_dentry()?;
+        Some(dentry.into_entry(&self.fs, &mut self.fs.borrow_mut()))
+    }
+}
+
 impl traits::Dir for Dir {
     type Entry = Entry;
 
@@ -220,32 +326,7 @@ impl traits::Dir for Dir {
 
     /// Returns an interator over the entries in this directory.
     fn entries(&self) -> io::Result<Self::Iter> {
-        let mut data = Vec::new();
-        let bytes_per_cluster = {
-            let mut fs_borrow = self.fs.borrow_mut();
-            fs_borrow.read_chain(self.cluster, &mut data)?;
-            fs_borrow.bytes_per_sector as u32 * fs_borrow.sectors_per_cluster as u32
-        };
-        let num_entries = data.len() / size_of::<VFatDirEntry>();
-        let mut entries = vec![
-            VFatDirEntry {
-                dummy: VFatDummyDirEntry::default(),
-            };
-            num_entries
-        ];
-        unsafe {
-            data.as_ptr().copy_to(
-                entries.as_mut_ptr() as *mut u8,
-                num_entries * size_of::<VFatDirEntry>(),
-            );
-        }
-
-        Ok(EntryIterator {
-            data: entries,
-            current_index: 0,
-            fs: self.fs.clone(),
-            bytes_per_cluster,
-        })
+        self.entries_in(&mut self.fs.borrow_mut())
     }
 }
 
@@ -69,6 +69,14 @@ impl BiosParameterBlock {
         }
     }
 
+    pub(super) fn total_sectors(&self) -> u32 {
+        if self.total_logical_sectors > 0 {
+            self.total_logical_sectors as u32
+        } else {
+            self.total_logical_sectors_2
+        }
+    }
+
     pub(super) fn sectors_per_fat(&self) -> u32 {
         if self.sectors_per_fat > 0 {
             self.sectors_per_fat as u32
@@ -1,13 +1,122 @@
-use std::io;
+use std::{error, fmt, io};
 
 use mbr;
 
 #[derive(Debug)]
 pub enum Error {
+    /// There was an error while reading the MBR.
     Mbr(mbr::Error),
+    /// There was an I/O error while reading the EBPB.
     Io(io::Error),
+    /// The EBPB magic signature was invalid.
     BadSignature,
-    NotFound
+    /// The requested entry does not exist.
+    NotFound,
+    /// The MBR does not contain a FAT32 partition.
+    NoVfatPartition,
+    /// The logical sector size `.0` is zero or not a multiple of the device's
+    /// sector size.
+    UnsupportedSectorSize(u16),
+    /// The EBPB field `field` holds the unusable value `value`.
+    BadBpbField { field: &'static str, value: u32 },
+    /// Cluster `.0` is not a data cluster of the volume.
+    InvalidCluster(u32),
+    /// The FAT entry of `cluster`, which is part of a chain, is free, reserved
+    /// or marked bad.
+    BrokenChain { cluster: u32 },
+    /// The cluster chain starting at `start` loops back on itself.
+    ClusterChainCycle { start: u32 },
+    /// The I/O error `error` occurred while accessing sector `sector` of the
+    /// partition.
+    Sector { sector: u64, error: io::Error },
+    /// A path component that must be a directory is a file.
+    NotADirectory,
+    /// The file was removed while a handle to it was open.
+    FileRemoved,
+    /// No run of free clusters is long enough to hold the `clusters` clusters
+    /// of a file being defragmented.
+    NoContiguousRun { clusters: u32 },
+}
+
+impl Error {
+    /// Returns the `Error` wrapped by `err`, if `err` was converted from one.
+    pub fn from_io(err: &io::Error) -> Option<&Error> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<Error>())
+    }
+
+    /// The `io::ErrorKind` this error is reported as.
+    fn kind(&self) -> io::ErrorKind {
+        match *self {
+            Error::Mbr(mbr::Error::Io(ref err)) | Error::Io(ref err) => err.kind(),
+            Error::Sector { ref error, .. } => error.kind(),
+            Error::NotFound | Error::NoVfatPartition | Error::FileRemoved => {
+                io::ErrorKind::NotFound
+            }
+            Error::NotADirectory => io::ErrorKind::InvalidInput,
+            Error::NoContiguousRun { .. } => io::ErrorKind::Other,
+            Error::Mbr(_)
+            | Error::BadSignature
+            | Error::UnsupportedSectorSize(_)
+            | Error::BadBpbField { .. }
+            | Error::InvalidCluster(_)
+            | Error::BrokenChain { .. }
+            | Error::ClusterChainCycle { .. } => io::ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Mbr(ref err) => write!(f, "invalid MBR: {:?}", err),
+            Error::Io(ref err) => write!(f, "I/O error: {}", err),
+            Error::BadSignature => write!(f, "bad EBPB signature"),
+            Error::NotFound => write!(f, "entry not found"),
+            Error::NoVfatPartition => write!(f, "no FAT32 partition in the MBR"),
+            Error::UnsupportedSectorSize(size) => {
+                write!(f, "unsupported logical sector size {}", size)
+            }
+            Error::BadBpbField { field, value } => {
+                write!(f, "invalid EBPB field {}: {}", field, value)
+            }
+            Error::InvalidCluster(cluster) => write!(f, "invalid cluster {}", cluster),
+            Error::BrokenChain { cluster } => {
+                write!(f, "cluster chain broken at cluster {}", cluster)
+            }
+            Error::ClusterChainCycle { start } => {
+                write!(f, "cycle in cluster chain starting at cluster {}", start)
+            }
+            Error::Sector { sector, ref error } => {
+                write!(f, "I/O error at sector {}: {}", sector, error)
+            }
+            Error::NotADirectory => write!(f, "not a directory"),
+            Error::FileRemoved => write!(f, "file has been removed"),
+            Error::NoContiguousRun { clusters } => {
+                write!(f, "no run of {} free clusters", clusters)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Mbr(_) => "invalid MBR",
+            Error::Io(_) => "I/O error",
+            Error::BadSignature => "bad EBPB signature",
+            Error::NotFound => "entry not found",
+            Error::NoVfatPartition => "no FAT32 partition",
+            Error::UnsupportedSectorSize(_) => "unsupported logical sector size",
+            Error::BadBpbField { .. } => "invalid EBPB field",
+            Error::InvalidCluster(_) => "invalid cluster",
+            Error::BrokenChain { .. } => "broken cluster chain",
+            Error::ClusterChainCycle { .. } => "cycle in cluster chain",
+            Error::Sector { .. } => "sector I/O error",
+            Error::NotADirectory => "not a directory",
+            Error::FileRemoved => "file has been removed",
+            Error::NoContiguousRun { .. } => "no contiguous free run large enough",
+        }
+    }
 }
 
 impl From<mbr::Error> for Error {
@@ -21,3 +130,12 @@ impl From<io::Error> for Error {
         Error::Io(error)
     }
 }
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> io::Error {
+        match error {
+            Error::Io(err) => err,
+            error => io::Error::new(error.kind(), error),
+        }
+    }
+}
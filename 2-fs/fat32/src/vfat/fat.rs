@@ -48,6 +48,12 @@ impl FatEntry {
             _ => unreachable!()
         }
     }
+
+    /// Sets the value of the FAT entry `self` to `value`, preserving the
+    /// reserved upper four bits.
+    pub(super) fn set(&mut self, value: u32) {
+        self.0 = (self.0 & 0xF000_0000) | (value & 0x0FFF_FFFF);
+    }
 }
 
 impl fmt::Debug for FatEntry {
@@ -2,7 +2,8 @@ use std::cmp::min;
 use std::io::{self, SeekFrom};
 
 use traits;
-use vfat::{Cluster, Metadata, Shared, Status, VFat};
+use vfat::{Cluster, Error, Metadata, Shared, VFat};
+use vfat::dir::EntryLocation;
 use std::fmt;
 
 pub struct File {
@@ -16,6 +17,10 @@ pub struct File {
     pub(super) current_offset: u32,
     pub(super) current_cluster: Option<Cluster>,
     pub(super) bytes_per_cluster: u32,
+    /// The location of this file's directory entry.
+    pub(super) location: EntryLocation,
+    /// The id of the file's node in the volume's open file table.
+    pub(super) node: u64,
 }
 
 // FIXME: Implement `traits::File` (and its supertraits) for `File`.
@@ -25,15 +30,45 @@ impl traits::File for File {
     }
 
     fn size(&self) -> u64 {
-        self.file_size as u64
+        match self.fs.borrow().open_files.get(self.node) {
+            Some(node) => node.size as u64,
+            None => self.file_size as u64,
+        }
+    }
+
+    /// Truncates the file to `size` bytes, freeing the clusters past its new
+    /// end. The offset of the handle is left unchanged.
+    ///
+    /// # Errors
+    ///
+    /// Files cannot be extended: a `size` larger than the file's results in an
+    /// `InvalidInput` error. Truncating a file that has been removed results
+    /// in a `NotFound` error.
+    fn set_len(&mut self, size: u64) -> io::Result<()> {
+        let fs = self.fs.clone();
+        let mut fs = fs.borrow_mut();
+        self.refresh(&mut fs)?;
+        if size > self.file_size as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "files cannot be extended",
+            ));
+        }
+        if size < self.file_size as u64 {
+            fs.truncate(self.location, self.cluster, size as u32)?;
+            self.refresh(&mut fs)?;
+        }
+        Ok(())
     }
 }
 
 impl io::Read for File {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let read_size = min(buf.len(), (self.file_size - self.current_offset) as usize);
+        let fs = self.fs.clone();
+        let mut fs = fs.borrow_mut();
+        self.refresh(&mut fs)?;
+        let read_size = min(buf.len(), self.file_size.saturating_sub(self.current_offset) as usize);
         let mut current_offset_in_cluster = (self.current_offset % self.bytes_per_cluster) as usize;
-        let mut fs = self.fs.borrow_mut();
         let mut rest_size = read_size;
         let mut current_cluster = self.current_cluster;
         let mut buffer_offset = 0;
@@ -45,18 +80,7 @@ impl io::Read for File {
             )?;
             if newly_read_size == self.bytes_per_cluster as usize - current_offset_in_cluster {
                 // read all content of current cluster
-                match fs.fat_entry(current_cluster.unwrap())?.status() {
-                    Status::Eoc(_) => current_cluster = None,
-                    Status::Data(next_cluster) => {
-                        current_cluster = Some(next_cluster);
-                    }
-                    _ => {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "Invalid cluster chain",
-                        ))
-                    }
-                }
+                current_cluster = fs.next_cluster(current_cluster.unwrap())?;
             }
             buffer_offset += newly_read_size;
             rest_size -= newly_read_size;
@@ -91,8 +115,12 @@ impl io::Seek for File {
     /// # Errors
     ///
     /// Seeking before the start of a file or beyond the end of the file results
-    /// in an `InvalidInput` error.
+    /// in an `InvalidInput` error. Seeking in a file that has been removed
+    /// results in a `NotFound` error.
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let fs = self.fs.clone();
+        let mut fs = fs.borrow_mut();
+        self.refresh(&mut fs)?;
         let new_offset = match pos {
             SeekFrom::Start(start) => start as u32,
             SeekFrom::Current(offset) => self.current_offset.wrapping_add(offset as u32),
@@ -104,23 +132,62 @@ impl io::Seek for File {
                 "invalid seek position",
             ))
         } else {
-            let mut current_cluster = self.cluster;
-            let mut fs = self.fs.borrow_mut();
-            for _ in 0..(new_offset / self.bytes_per_cluster) {
-                match fs.fat_entry(current_cluster)?.status() {
-                    Status::Data(next_cluster) => {
-                        current_cluster = next_cluster;
-                    }
-                    _ => unimplemented!(),
-                }
-            }
-            self.current_cluster = Some(current_cluster);
+            self.current_cluster =
+                Some(File::cluster_at(&mut fs, self.cluster, new_offset / self.bytes_per_cluster)?);
             self.current_offset = new_offset;
             Ok(self.current_offset as u64)
         }
     }
 }
 
+impl File {
+    /// Brings this handle up to date with the file's node: fails if the file
+    /// has been removed, and follows its data if it has been moved.
+    fn refresh(&mut self, fs: &mut VFat) -> Result<(), Error> {
+        let (cluster, size) = match fs.open_files.get(self.node) {
+            Some(node) if node.removed => return Err(Error::FileRemoved),
+            Some(node) => (node.cluster, node.size),
+            None => return Err(Error::FileRemoved),
+        };
+        self.file_size = size;
+        if cluster != self.cluster && !cluster.is_valid() {
+            // The file was truncated to nothing.
+            self.cluster = cluster;
+            self.current_cluster = None;
+        } else if cluster != self.cluster {
+            self.cluster = cluster;
+            let index = self.current_offset / self.bytes_per_cluster;
+            self.current_cluster = if self.current_offset % self.bytes_per_cluster == 0 && index > 0 {
+                // The previous read ended on a cluster boundary and already
+                // moved on to the following cluster.
+                let previous = File::cluster_at(fs, cluster, index - 1)?;
+                fs.next_cluster(previous)?
+            } else {
+                Some(File::cluster_at(fs, cluster, index)?)
+            };
+        }
+        Ok(())
+    }
+
+    /// Returns the cluster at position `index` of the chain starting at
+    /// `first`.
+    fn cluster_at(fs: &mut VFat, first: Cluster, index: u32) -> Result<Cluster, Error> {
+        let mut cluster = first;
+        for _ in 0..index {
+            cluster = fs.next_cluster(cluster)?.ok_or(Error::BrokenChain {
+                cluster: cluster.cluster_num(),
+            })?;
+        }
+        Ok(cluster)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        self.fs.borrow_mut().open_files.close(self.node);
+    }
+}
+
 impl File {
     pub fn name(&self) -> &str {
         if !self.long_name.is_empty() {
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use vfat::dir::EntryLocation;
+use vfat::Cluster;
+
+/// The state shared by every open handle to one file.
+#[derive(Debug)]
+pub(super) struct Node {
+    /// The first cluster of the file's data.
+    pub(super) cluster: Cluster,
+    /// The size of the file in bytes.
+    pub(super) size: u32,
+    /// Whether the file has been removed.
+    pub(super) removed: bool,
+    location: EntryLocation,
+    handles: usize,
+}
+
+/// The files of a volume that have open handles.
+///
+/// Every `File` registers itself here when it is created and reads the
+/// current position and size of its data from its node, so that a change made
+/// through the file system is seen by every handle to the file.
+#[derive(Debug, Default)]
+pub(super) struct OpenFiles {
+    nodes: HashMap<u64, Node>,
+    by_location: HashMap<EntryLocation, u64>,
+    next_id: u64,
+}
+
+impl OpenFiles {
+    /// Registers a new handle to the file whose directory entry is at
+    /// `location` and whose entry records `cluster` and `size`. Returns the id
+    /// of the file's node.
+    pub(super) fn open(&mut self, location: EntryLocation, cluster: Cluster, size: u32) -> u64 {
+        if let Some(&id) = self.by_location.get(&location) {
+            if let Some(node) = self.nodes.get_mut(&id) {
+                node.handles += 1;
+                return id;
+            }
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.nodes.insert(id, Node { cluster, size, removed: false, location, handles: 1 });
+        self.by_location.insert(location, id);
+        id
+    }
+
+    /// Unregisters a handle to the node `id`, forgetting the node once no
+    /// handles to it remain.
+    pub(super) fn close(&mut self, id: u64) {
+        let forget = match self.nodes.get_mut(&id) {
+            Some(node) => {
+                node.handles -= 1;
+                node.handles == 0
+            }
+            None => false,
+        };
+        if forget {
+            let node = self.nodes.remove(&id).unwrap();
+            if self.by_location.get(&node.location) == Some(&id) {
+                self.by_location.remove(&node.location);
+            }
+        }
+    }
+
+    /// Returns the node `id`.
+    pub(super) fn get(&self, id: u64) -> Option<&Node> {
+        self.nodes.get(&id)
+    }
+
+    /// Returns the node of the open file whose directory entry is at
+    /// `location`, if there is one.
+    pub(super) fn at(&mut self, location: EntryLocation) -> Option<&mut Node> {
+        let id = *self.by_location.get(&location)?;
+        self.nodes.get_mut(&id)
+    }
+
+    /// Marks the open file whose directory entry is at `location` as removed.
+    /// Handles to it fail from then on, and a file later created at the same
+    /// location gets a new node.
+    pub(super) fn remove(&mut self, location: EntryLocation) {
+        if let Some(id) = self.by_location.remove(&location) {
+            if let Some(node) = self.nodes.get_mut(&id) {
+                node.removed = true;
+            }
+        }
+    }
+}
@@ -88,7 +88,7 @@ impl Attributes {
         (self.0 & 0x04) != 0
     }
 
-    fn volume_id(&self) -> bool {
+    pub(super) fn volume_id(&self) -> bool {
         (self.0 & 0x08) != 0
     }
 
@@ -185,4 +185,9 @@ impl Metadata {
     pub(super) fn first_cluster(&self) -> u32 {
         ((self.high_two_bytes_first_cluster as u32) << 16) | self.low_two_bytes_first_cluster as u32
     }
+
+    pub(super) fn set_first_cluster(&mut self, cluster: u32) {
+        self.high_two_bytes_first_cluster = (cluster >> 16) as u16;
+        self.low_two_bytes_first_cluster = cluster as u16;
+    }
 }
\ No newline at end of file
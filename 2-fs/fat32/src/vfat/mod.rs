@@ -9,6 +9,10 @@ pub(crate) mod entry;
 pub(crate) mod metadata;
 pub(crate) mod cache;
 pub(crate) mod shared;
+pub(crate) mod undelete;
+pub(crate) mod defrag;
+pub(crate) mod dcache;
+pub(crate) mod handles;
 
 pub use self::ebpb::BiosParameterBlock;
 pub use self::file::File;
@@ -18,6 +22,8 @@ pub use self::vfat::VFat;
 pub use self::entry::Entry;
 pub use self::metadata::{Metadata, Attributes, Date, Time, Timestamp};
 pub use self::shared::Shared;
+pub use self::undelete::DeletedEntry;
+pub use self::defrag::{Extent, FragmentationReport};
 
 pub(crate) use self::cache::{CachedDevice, Partition};
 pub(crate) use self::fat::{Status, FatEntry};
@@ -1,47 +1,27 @@
 use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
 
 /// A smart pointer to a shared instance of type `T`.
 ///
 /// The inner `T` can be borrowed immutably with `.borrow()` and mutably with
 /// `.borrow_mut()`. The implementation guarantees the usual reference
 /// guarantees.
+///
+/// A `Shared<T>` is `Send` and `Sync` exactly when `T` is `Send`: clones may
+/// be handed to other threads, and borrows from different threads are
+/// serialized.
+///
+/// Borrows spin until the value is free, and leave IRQs alone: on ROS, the
+/// holder of a borrow may be preempted, or block while it waits for the disk,
+/// and a borrow must not be waited for where the holder can not run in the
+/// meantime, with IRQs masked or at EL1.
 #[derive(Debug)]
-pub struct Shared<T>(imp::Inner<T>);
-
-#[cfg(target_os = "ros")]
-mod imp {
-    use std::rc::Rc;
-    use std::sync::Mutex;
-    use super::Shared;
-
-    pub type Inner<T> = Rc<Mutex<T>>;
-
-    pub fn new<T>(val: T) -> Inner<T> {
-        Rc::new(Mutex::new(val))
-    }
-
-    // Without an enabled MMU/cache, the processor faults on atomic accesses.
-    // As such, use an `Rc` instead of an `Arc` when running on ROS until
-    // multithreading, the MMU, and caches are enabled.
-    unsafe impl<T> Sync for Shared<T> {}
-    unsafe impl<T> Send for Shared<T> {}
-}
-
-#[cfg(not(target_os = "ros"))]
-mod imp {
-    use std::sync::{Arc, Mutex};
-
-    pub type Inner<T> = ::std::sync::Arc<::std::sync::Mutex<T>>;
-
-    pub fn new<T>(val: T) -> Inner<T> {
-        Arc::new(Mutex::new(val))
-    }
-}
+pub struct Shared<T>(Arc<Mutex<T>>);
 
 impl<T> Shared<T> {
     /// Wraps `val` into a `Shared<T>` and returns it.
     pub fn new(val: T) -> Shared<T> {
-        Shared(imp::new(val))
+        Shared(Arc::new(Mutex::new(val)))
     }
 
     /// Returns an immutable borrow to the inner value.
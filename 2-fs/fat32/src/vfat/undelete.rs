@@ -0,0 +1,260 @@
+use std::io;
+use std::path::Path;
+
+use traits::FileSystem;
+use vfat::dir::{VFatDirEntry, DELETED, DIR_ENTRY_SIZE};
+use vfat::{Cluster, Shared, VFat};
+
+/// The FAT value marking the last cluster of a chain.
+const END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+
+/// A deleted directory entry whose metadata is still present on disk.
+///
+/// Deleted entries are found with `Shared<VFat>::deleted_entries()` and
+/// restored with `Shared<VFat>::undelete()`.
+#[derive(Debug, Clone)]
+pub struct DeletedEntry {
+    name: String,
+    size: u32,
+    first_cluster: Cluster,
+    is_dir: bool,
+    clusters_free: bool,
+    dir: Cluster,
+    index: usize,
+}
+
+impl DeletedEntry {
+    /// The recovered name of the entry.
+    ///
+    /// If the long file name entries preceding the deleted entry survived and
+    /// match its checksum, this is the long file name. Otherwise, it is the
+    /// 8.3 name with the lost first character replaced by `_`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The size of the file in bytes. Always `0` for directories.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// The first cluster of the deleted file's data.
+    pub fn first_cluster(&self) -> u32 {
+        self.first_cluster.cluster_num()
+    }
+
+    /// Whether the entry was a directory.
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    /// Whether every cluster the entry is presumed to occupy is still free.
+    ///
+    /// FAT does not retain the cluster chain of a deleted file, so the data is
+    /// presumed to occupy contiguous clusters starting at `first_cluster()`.
+    /// An entry can only be recovered if this returns `true`.
+    pub fn clusters_free(&self) -> bool {
+        self.clusters_free
+    }
+
+    /// The number of clusters the entry is presumed to occupy.
+    fn cluster_num(&self, bytes_per_cluster: usize) -> u32 {
+        if self.is_dir {
+            1
+        } else {
+            ((self.size as usize + bytes_per_cluster - 1) / bytes_per_cluster) as u32
+        }
+    }
+}
+
+/// Computes the checksum of an 8.3 name stored in long file name entries.
+fn lfn_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0u8, |sum, &byte| {
+        ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(byte)
+    })
+}
+
+/// Converts `name` into a padded, upper-cased 8.3 name.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if `name` is not a valid 8.3 name.
+fn short_name_bytes(name: &str) -> io::Result<[u8; 11]> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "name is not a valid 8.3 name");
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) => (&name[..dot], &name[dot + 1..]),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 {
+        return Err(invalid());
+    }
+
+    let mut bytes = [b' '; 11];
+    let positions = (0..8).zip(base.bytes()).chain((8..11).zip(ext.bytes()));
+    for (i, byte) in positions {
+        let valid = byte.is_ascii_alphanumeric() || b"!#$%&'()-@^_`{}~".contains(&byte);
+        if !valid {
+            return Err(invalid());
+        }
+        bytes[i] = byte.to_ascii_uppercase();
+    }
+    Ok(bytes)
+}
+
+/// Recovers the name of the deleted regular entry at `index` of `entries`
+/// from the deleted long file name entries preceding it, if they survived.
+fn recover_long_name(entries: &[VFatDirEntry], index: usize) -> Option<String> {
+    let regular = unsafe { entries[index].regular };
+    let mut characters = Vec::new();
+    let mut checksum = None;
+    for entry in entries[..index].iter().rev().take(20) {
+        let unknown = unsafe { entry.unknown };
+        if unknown.status != DELETED || !unknown.attributes.lfn() {
+            break;
+        }
+        let lfn = unsafe { entry.long_filename };
+        checksum = Some(lfn.checksum_of_file_name);
+        characters.extend_from_slice(&lfn.characters());
+    }
+
+    let checksum = checksum?;
+    let len = characters.iter().position(|&c| c == 0 || c == 0xFFFF).unwrap_or(characters.len());
+    let name = String::from_utf16(&characters[..len]).ok()?;
+
+    // The first byte of the short name was overwritten by the deletion
+    // marker. It is usually the upper-cased first character of the long
+    // name; accept the long name only if that guess matches the checksum.
+    let first = name.chars().next()?.to_ascii_uppercase();
+    if !first.is_ascii() {
+        return None;
+    }
+    let mut short_name = [0u8; 11];
+    short_name[..8].copy_from_slice(&regular.short_file_name);
+    short_name[8..].copy_from_slice(&regular.short_file_extension);
+    short_name[0] = first as u8;
+    if lfn_checksum(&short_name) == checksum {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+/// Returns `true` if every cluster `entry` is presumed to occupy is free.
+fn clusters_free(fs: &mut VFat, entry: &DeletedEntry) -> io::Result<bool> {
+    let cluster_num = entry.cluster_num(fs.bytes_per_cluster());
+    for i in 0..cluster_num {
+        let cluster = Cluster::from(entry.first_cluster.cluster_num() + i);
+        if !fs.is_free(cluster)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+impl Shared<VFat> {
+    /// Returns the deleted entries of the directory at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `open_dir()` for `path`, or any I/O error that
+    /// occurs while reading the directory or the FAT.
+    pub fn deleted_entries<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<DeletedEntry>> {
+        let dir = self.open_dir(path)?;
+        let entries = dir.raw_entries()?;
+
+        let mut deleted = Vec::new();
+        for (index, entry) in entries.iter().enumerate() {
+            let unknown = unsafe { entry.unknown };
+            if unknown.status == 0x00 {
+                break;
+            } else if unknown.status != DELETED || unknown.attributes.lfn() {
+                continue;
+            }
+
+            let regular = unsafe { entry.regular };
+            if regular.metadata.attributes.volume_id() {
+                continue;
+            }
+            let name = recover_long_name(&entries, index).unwrap_or_else(|| {
+                let short_name = regular.short_name();
+                let mut chars = short_name.chars();
+                chars.next();
+                format!("_{}", chars.as_str())
+            });
+
+            let mut deleted_entry = DeletedEntry {
+                name,
+                size: regular.file_size,
+                first_cluster: Cluster::from(regular.metadata.first_cluster()),
+                is_dir: regular.metadata.attributes.directory(),
+                clusters_free: true,
+                dir: dir.cluster,
+                index,
+            };
+            deleted_entry.clusters_free = clusters_free(&mut self.borrow_mut(), &deleted_entry)?;
+            deleted.push(deleted_entry);
+        }
+
+        Ok(deleted)
+    }
+
+    /// Recovers the deleted entry `entry` under the 8.3 name `name`.
+    ///
+    /// The cluster chain of the entry is rebuilt assuming its data occupies
+    /// contiguous clusters starting at its first cluster. The FAT is updated
+    /// before the directory entry so that an interrupted recovery at worst
+    /// leaves unreferenced clusters allocated.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `name` is not a valid 8.3 name,
+    /// `AlreadyExists` if the directory already contains an entry named
+    /// `name`, `NotFound` if the directory entry is no longer the deleted
+    /// entry described by `entry`, and `Other` if any of the presumed clusters
+    /// have since been reused.
+    pub fn undelete(&self, entry: &DeletedEntry, name: &str) -> io::Result<()> {
+        use traits::{Dir, Entry};
+
+        let short_name = short_name_bytes(name)?;
+        let dir = ::vfat::Dir::from_cluster(self, entry.dir);
+        if dir.entries()?.any(|e| e.name().eq_ignore_ascii_case(name)) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "entry already exists"));
+        }
+
+        // The clusters are checked under the same borrow as the FAT update,
+        // so that no allocation can claim them in between.
+        let mut fs = self.borrow_mut();
+        if !clusters_free(&mut fs, entry)? {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "clusters of the deleted entry have been reused",
+            ));
+        }
+
+        let raw = fs.read_dir_entry(entry.dir, entry.index)?;
+        let mut current = VFatDirEntry::from_bytes(&raw);
+        {
+            let regular = unsafe { &mut current.regular };
+            if regular.short_file_name[0] != DELETED
+                || regular.metadata.first_cluster() != entry.first_cluster.cluster_num()
+            {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "deleted entry has changed"));
+            }
+            regular.short_file_name.copy_from_slice(&short_name[..8]);
+            regular.short_file_extension.copy_from_slice(&short_name[8..]);
+        }
+
+        let first = entry.first_cluster.cluster_num();
+        let cluster_num = entry.cluster_num(fs.bytes_per_cluster());
+        for i in 0..cluster_num {
+            let next = if i + 1 == cluster_num { END_OF_CHAIN } else { first + i + 1 };
+            fs.set_fat_entry(Cluster::from(first + i), next)?;
+        }
+        fs.sync()?;
+
+        let bytes: [u8; DIR_ENTRY_SIZE] = current.to_bytes();
+        fs.write_dir_entry(entry.dir, entry.index, &bytes)?;
+        fs.sync()?;
+        Ok(())
+    }
+}
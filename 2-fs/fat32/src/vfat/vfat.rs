@@ -8,17 +8,27 @@ use mbr::MasterBootRecord;
 use vfat::{Cluster, Dir, Entry, Error, FatEntry, File, Shared, Status};
 use vfat::{BiosParameterBlock, CachedDevice, Partition};
 use traits::{BlockDevice, FileSystem};
+use vfat::dir::{EntryLocation, VFatDirEntry, DELETED, DIR_ENTRY_SIZE};
+use vfat::dcache::DentryCache;
+use vfat::handles::OpenFiles;
 use std::path::Component;
 
+/// The FAT entry value marking the last cluster of a chain.
+const END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+
 #[derive(Debug)]
 pub struct VFat {
     device: CachedDevice,
     pub(super) bytes_per_sector: u16,
     pub(super) sectors_per_cluster: u8,
     sectors_per_fat: u32,
+    number_of_fats: u8,
+    cluster_count: u32,
     fat_start_sector: u64,
     data_start_sector: u64,
     pub(super) root_dir_cluster: Cluster,
+    pub(super) dentries: DentryCache,
+    pub(super) open_files: OpenFiles,
 }
 
 impl VFat {
@@ -30,21 +40,61 @@ impl VFat {
         let fat_start_sector_option = mbr.first_vfat_partition_lba();
         let fat_start_sector = match fat_start_sector_option {
             Some(sector) => sector as u64,
-            None => {
-                return Err(Error::Io(io::Error::new(
-                    io::ErrorKind::NotFound,
-                    "cannot find the first vfat partition",
-                )))
-            }
+            None => return Err(Error::NoVfatPartition),
         };
+        VFat::from_sector(device, fat_start_sector)
+    }
+
+    /// Returns the file system on `device`, a partition without a master
+    /// boot record whose first sector is the EBPB.
+    pub fn from_partition<T>(device: T) -> Result<Shared<VFat>, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        VFat::from_sector(device, 0)
+    }
+
+    /// Returns the file system whose EBPB is sector `fat_start_sector` of
+    /// `device`.
+    fn from_sector<T>(mut device: T, fat_start_sector: u64) -> Result<Shared<VFat>, Error>
+    where
+        T: BlockDevice + 'static,
+    {
         let ebpb = BiosParameterBlock::from(&mut device, fat_start_sector)?;
         if ebpb.bytes_per_sector == 0 || ebpb.bytes_per_sector % device.sector_size() as u16 != 0 {
-            return Err(Error::Io(io::Error::new(
-                io::ErrorKind::Other,
-                "unsupported logical sector size",
-            )));
+            return Err(Error::UnsupportedSectorSize(ebpb.bytes_per_sector));
+        }
+        if !ebpb.sectors_per_cluster.is_power_of_two() {
+            return Err(Error::BadBpbField {
+                field: "sectors_per_cluster",
+                value: ebpb.sectors_per_cluster as u32,
+            });
+        }
+        if ebpb.number_of_fats == 0 {
+            return Err(Error::BadBpbField {
+                field: "number_of_fats",
+                value: ebpb.number_of_fats as u32,
+            });
         }
         let sectors_per_fat = ebpb.sectors_per_fat();
+        if sectors_per_fat == 0 {
+            return Err(Error::BadBpbField {
+                field: "sectors_per_fat",
+                value: sectors_per_fat,
+            });
+        }
+        let data_sectors = ebpb.total_sectors()
+            .saturating_sub(ebpb.reserved_sectors as u32)
+            .saturating_sub(sectors_per_fat * ebpb.number_of_fats as u32);
+        let fat_entries = sectors_per_fat * ebpb.bytes_per_sector as u32 / size_of::<FatEntry>() as u32;
+        let cluster_count = min(data_sectors / ebpb.sectors_per_cluster as u32 + 2, fat_entries);
+        let root_dir_cluster = Cluster::from(ebpb.root_directory_cluster);
+        if !root_dir_cluster.is_valid() || root_dir_cluster.cluster_num() >= cluster_count {
+            return Err(Error::BadBpbField {
+                field: "root_directory_cluster",
+                value: ebpb.root_directory_cluster,
+            });
+        }
         let cached_device = CachedDevice::new(
             device,
             Partition {
@@ -57,10 +107,14 @@ impl VFat {
             bytes_per_sector: ebpb.bytes_per_sector,
             sectors_per_cluster: ebpb.sectors_per_cluster,
             sectors_per_fat,
+            number_of_fats: ebpb.number_of_fats,
+            cluster_count,
             fat_start_sector: fat_start_sector + ebpb.reserved_sectors as u64,
             data_start_sector: fat_start_sector + ebpb.reserved_sectors as u64
                 + sectors_per_fat as u64 * ebpb.number_of_fats as u64,
-            root_dir_cluster: Cluster::from(ebpb.root_directory_cluster),
+            root_dir_cluster,
+            dentries: DentryCache::default(),
+            open_files: OpenFiles::default(),
         }))
     }
 
@@ -89,14 +143,82 @@ impl VFat {
     //
     //    fn fat_entry(&mut self, cluster: Cluster) -> io::Result<&FatEntry>;
 
-    pub(super) fn fat_entry(&mut self, cluster: Cluster) -> io::Result<&FatEntry> {
+    /// Returns `Ok(())` if `cluster` is a data cluster of the volume and
+    /// `InvalidCluster` otherwise.
+    fn check_cluster(&self, cluster: Cluster) -> Result<(), Error> {
+        if cluster.is_valid() && cluster.cluster_num() < self.cluster_count {
+            Ok(())
+        } else {
+            Err(Error::InvalidCluster(cluster.cluster_num()))
+        }
+    }
+
+    /// Returns the cached contents of sector `sector` of the partition.
+    fn sector(&mut self, sector: u64) -> Result<&[u8], Error> {
+        self.device.get(sector).map_err(|error| Error::Sector { sector, error })
+    }
+
+    /// Returns the cached contents of sector `sector` of the partition for
+    /// writing.
+    fn sector_mut(&mut self, sector: u64) -> Result<&mut [u8], Error> {
+        self.device.get_mut(sector).map_err(|error| Error::Sector { sector, error })
+    }
+
+    /// Returns the sector (relative to the start of a FAT) and the index
+    /// within that sector of the FAT entry for `cluster`.
+    fn fat_entry_position(&self, cluster: Cluster) -> (u64, usize) {
         let cluster_num_sector: u64 = cluster.cluster_num() as u64 * size_of::<FatEntry>() as u64
             / self.bytes_per_sector as u64;
         let entry_offset: usize =
             cluster.cluster_num() as usize * size_of::<FatEntry>() % self.bytes_per_sector as usize;
-        let content = self.device.get(self.fat_start_sector + cluster_num_sector)?;
+        (cluster_num_sector, entry_offset / size_of::<FatEntry>())
+    }
+
+    pub(super) fn fat_entry(&mut self, cluster: Cluster) -> Result<&FatEntry, Error> {
+        self.check_cluster(cluster)?;
+        let (sector, index) = self.fat_entry_position(cluster);
+        let fat_start_sector = self.fat_start_sector;
+        let content = self.sector(fat_start_sector + sector)?;
         let entries: &[FatEntry] = unsafe { content.cast() };
-        Ok(&entries[entry_offset / size_of::<FatEntry>()])
+        Ok(&entries[index])
+    }
+
+    /// Sets the FAT entry for `cluster` to `value` in every copy of the FAT.
+    pub(super) fn set_fat_entry(&mut self, cluster: Cluster, value: u32) -> Result<(), Error> {
+        self.check_cluster(cluster)?;
+        let (sector, index) = self.fat_entry_position(cluster);
+        for fat in 0..self.number_of_fats as u64 {
+            let fat_sector = self.fat_start_sector + fat * self.sectors_per_fat as u64 + sector;
+            let content = self.sector_mut(fat_sector)?;
+            let entries: &mut [FatEntry] = unsafe { content.cast_mut() };
+            entries[index].set(value);
+        }
+        Ok(())
+    }
+
+    /// Returns the number of bytes in a cluster.
+    pub(super) fn bytes_per_cluster(&self) -> usize {
+        self.bytes_per_sector as usize * self.sectors_per_cluster as usize
+    }
+
+    /// Returns `true` if `cluster` is a valid, unused cluster.
+    pub(super) fn is_free(&mut self, cluster: Cluster) -> Result<bool, Error> {
+        if self.check_cluster(cluster).is_err() {
+            return Ok(false);
+        }
+        Ok(self.fat_entry(cluster)?.status() == Status::Free)
+    }
+
+    /// Writes any dirty cached sectors back to the disk.
+    pub(super) fn sync(&mut self) -> Result<(), Error> {
+        Ok(self.device.sync()?)
+    }
+
+    /// Returns the partition sector holding byte `offset` of `cluster`.
+    fn cluster_sector(&self, cluster: Cluster, offset: usize) -> u64 {
+        self.data_start_sector
+            + cluster.cluster_index() as u64 * self.sectors_per_cluster as u64
+            + offset as u64 / self.bytes_per_sector as u64
     }
 
     pub(super) fn read_cluster(
@@ -104,26 +226,16 @@ impl VFat {
         cluster: Cluster,
         offset: usize,
         buf: &mut [u8],
-    ) -> io::Result<usize> {
-        if !cluster.is_valid() {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Invalid cluster",
-            ));
-        }
+    ) -> Result<usize, Error> {
+        self.check_cluster(cluster)?;
 
         let sector_size = self.device.sector_size() as usize;
-        let size = min(
-            buf.len(),
-            self.bytes_per_sector as usize * self.sectors_per_cluster as usize - offset,
-        );
-        let mut current_sector = self.data_start_sector
-            + cluster.cluster_index() as u64 * self.sectors_per_cluster as u64
-            + offset as u64 / self.bytes_per_sector as u64;
+        let size = min(buf.len(), self.bytes_per_cluster() - offset);
+        let mut current_sector = self.cluster_sector(cluster, offset);
         let mut bytes_read = 0;
         let mut offset_once = offset % self.bytes_per_sector as usize;
         while bytes_read < size {
-            let content = self.device.get(current_sector)?;
+            let content = self.sector(current_sector)?;
             let copy_size = min(size - bytes_read, sector_size - offset_once);
             buf[bytes_read..bytes_read + copy_size]
                 .copy_from_slice(&content[offset_once..offset_once + copy_size]);
@@ -135,40 +247,55 @@ impl VFat {
         Ok(size)
     }
 
-    fn next_cluster(&mut self, cluster: Cluster) -> io::Result<Option<Cluster>> {
-        if !cluster.is_valid() {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Invalid cluster num",
-            ));
+    pub(super) fn write_cluster(
+        &mut self,
+        cluster: Cluster,
+        offset: usize,
+        buf: &[u8],
+    ) -> Result<usize, Error> {
+        self.check_cluster(cluster)?;
+
+        let sector_size = self.device.sector_size() as usize;
+        let size = min(buf.len(), self.bytes_per_cluster() - offset);
+        let mut current_sector = self.cluster_sector(cluster, offset);
+        let mut bytes_written = 0;
+        let mut offset_once = offset % self.bytes_per_sector as usize;
+        while bytes_written < size {
+            let content = self.sector_mut(current_sector)?;
+            let copy_size = min(size - bytes_written, sector_size - offset_once);
+            content[offset_once..offset_once + copy_size]
+                .copy_from_slice(&buf[bytes_written..bytes_written + copy_size]);
+            offset_once = 0;
+            bytes_written += copy_size;
+            current_sector += 1;
         }
+
+        Ok(size)
+    }
+
+    /// Returns the cluster following `cluster` in its chain, or `None` if
+    /// `cluster` is the last one.
+    pub(super) fn next_cluster(&mut self, cluster: Cluster) -> Result<Option<Cluster>, Error> {
         match self.fat_entry(cluster)?.status() {
             Status::Eoc(_) => Ok(None),
             Status::Data(next_cluster) => {
-                if next_cluster.is_valid() {
-                    Ok(Some(next_cluster))
-                } else {
-                    Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Invalid cluster num",
-                    ))
-                }
+                self.check_cluster(next_cluster)?;
+                Ok(Some(next_cluster))
             }
-            _ => Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Invalid cluster chain",
-            )),
+            _ => Err(Error::BrokenChain {
+                cluster: cluster.cluster_num(),
+            }),
         }
     }
 
-    pub(super) fn read_chain(&mut self, start: Cluster, buf: &mut Vec<u8>) -> io::Result<usize> {
+    pub(super) fn read_chain(&mut self, start: Cluster, buf: &mut Vec<u8>) -> Result<usize, Error> {
         // Floyd's Cycle Detection Algorithm
         // This is the tortoise
         let mut current_cluster = start;
         // This is the hare
         let mut hare_cluster = self.next_cluster(current_cluster)?;
         let mut current_cluster_num = 0;
-        let bytes_per_cluster = self.bytes_per_sector as usize * self.sectors_per_cluster as usize;
+        let bytes_per_cluster = self.bytes_per_cluster();
         while Some(current_cluster) != hare_cluster {
             current_cluster_num += 1;
             buf.resize(bytes_per_cluster * current_cluster_num, 0);
@@ -192,7 +319,143 @@ impl VFat {
                 hare_cluster = self.next_cluster(cluster)?;
             }
         }
-        Err(io::Error::new(io::ErrorKind::InvalidData, "Cycle detected in cluster chain"))
+        Err(Error::ClusterChainCycle {
+            start: start.cluster_num(),
+        })
+    }
+
+    /// Returns one past the highest valid cluster number of the volume.
+    pub(super) fn cluster_count(&self) -> u32 {
+        self.cluster_count
+    }
+
+    /// Returns the clusters of the chain starting at `start`, in order.
+    pub(super) fn chain(&mut self, start: Cluster) -> Result<Vec<Cluster>, Error> {
+        let mut clusters = Vec::new();
+        let mut current_cluster = Some(start);
+        while let Some(cluster) = current_cluster {
+            if clusters.len() >= self.cluster_count as usize {
+                return Err(Error::ClusterChainCycle {
+                    start: start.cluster_num(),
+                });
+            }
+            clusters.push(cluster);
+            current_cluster = self.next_cluster(cluster)?;
+        }
+        Ok(clusters)
+    }
+
+    /// Returns the cluster and byte offset within it that holds the directory
+    /// entry at `index` of the directory starting at `dir`.
+    fn dir_entry_position(&mut self, dir: Cluster, index: usize) -> Result<(Cluster, usize), Error> {
+        let offset = index * DIR_ENTRY_SIZE;
+        let bytes_per_cluster = self.bytes_per_cluster();
+        let mut cluster = dir;
+        for _ in 0..offset / bytes_per_cluster {
+            cluster = self.next_cluster(cluster)?.ok_or(Error::NotFound)?;
+        }
+        Ok((cluster, offset % bytes_per_cluster))
+    }
+
+    /// Reads the raw directory entry at `index` of the directory starting at
+    /// `dir`.
+    pub(super) fn read_dir_entry(
+        &mut self,
+        dir: Cluster,
+        index: usize,
+    ) -> Result<[u8; DIR_ENTRY_SIZE], Error> {
+        let (cluster, offset) = self.dir_entry_position(dir, index)?;
+        let mut buf = [0u8; DIR_ENTRY_SIZE];
+        self.read_cluster(cluster, offset, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Overwrites the raw directory entry at `index` of the directory starting
+    /// at `dir` with `entry`.
+    pub(super) fn write_dir_entry(
+        &mut self,
+        dir: Cluster,
+        index: usize,
+        entry: &[u8; DIR_ENTRY_SIZE],
+    ) -> Result<(), Error> {
+        self.dentries.invalidate(dir);
+        let (cluster, offset) = self.dir_entry_position(dir, index)?;
+        self.write_cluster(cluster, offset, entry)?;
+        Ok(())
+    }
+
+    /// Deletes the directory entry at `location`, along with its long file
+    /// name entries, and frees the cluster chain starting at `first`. Open
+    /// handles to a removed file fail from then on.
+    ///
+    /// The entry is deleted before the chain is freed so that an interrupted
+    /// removal at worst leaves unreferenced clusters allocated.
+    fn remove_entry(&mut self, location: EntryLocation, first: Cluster) -> Result<(), Error> {
+        let mut index = location.index;
+        loop {
+            let mut raw = self.read_dir_entry(location.dir, index)?;
+            raw[0] = DELETED;
+            self.write_dir_entry(location.dir, index, &raw)?;
+            if index == 0 {
+                break;
+            }
+            let previous = VFatDirEntry::from_bytes(&self.read_dir_entry(location.dir, index - 1)?);
+            let unknown = unsafe { previous.unknown };
+            if unknown.status == DELETED || !unknown.attributes.lfn() {
+                break;
+            }
+            index -= 1;
+        }
+        self.sync()?;
+
+        if first.is_valid() {
+            for cluster in self.chain(first)? {
+                self.set_fat_entry(cluster, 0)?;
+            }
+            self.sync()?;
+        }
+        self.open_files.remove(location);
+        self.dentries.invalidate(first);
+        Ok(())
+    }
+
+    /// Truncates the file whose directory entry is at `location` and whose
+    /// data starts at `first` to `size` bytes, which must not be more than its
+    /// size, and frees the clusters past its new end.
+    ///
+    /// The entry is updated before the clusters are freed so that an
+    /// interrupted truncation at worst leaves unreferenced clusters allocated.
+    pub(super) fn truncate(
+        &mut self,
+        location: EntryLocation,
+        first: Cluster,
+        size: u32,
+    ) -> Result<(), Error> {
+        let bytes_per_cluster = self.bytes_per_cluster() as u64;
+        let keep = ((size as u64 + bytes_per_cluster - 1) / bytes_per_cluster) as usize;
+        let chain = if first.is_valid() { self.chain(first)? } else { Vec::new() };
+        let start = if keep == 0 { Cluster::from(0) } else { first };
+
+        let mut entry = VFatDirEntry::from_bytes(&self.read_dir_entry(location.dir, location.index)?);
+        unsafe {
+            entry.regular.metadata.set_first_cluster(start.cluster_num());
+            entry.regular.file_size = size;
+        }
+        self.write_dir_entry(location.dir, location.index, &entry.to_bytes())?;
+        self.sync()?;
+        if let Some(node) = self.open_files.at(location) {
+            node.cluster = start;
+            node.size = size;
+        }
+
+        if keep > 0 && keep < chain.len() {
+            self.set_fat_entry(chain[keep - 1], END_OF_CHAIN)?;
+        }
+        for &cluster in chain.iter().skip(keep) {
+            self.set_fat_entry(cluster, 0)?;
+        }
+        self.sync()?;
+        Ok(())
     }
 }
 
@@ -219,12 +482,7 @@ impl Shared<VFat> {
                     let new_entry = match dir_entries.last() {
                         Some(current_entry) => match current_entry.as_dir() {
                             Some(dir) => dir.find(name)?,
-                            None => {
-                                return Err(io::Error::new(
-                                    io::ErrorKind::NotFound,
-                                    "file not found",
-                                ))
-                            }
+                            None => return Err(Error::NotADirectory.into()),
                         },
                         None => return Err(io::Error::from(io::ErrorKind::NotFound)),
                     };
@@ -244,6 +502,30 @@ impl Shared<VFat> {
     }
 }
 
+impl Shared<VFat> {
+    /// Removes `entry` and, if it is a directory, everything below it.
+    fn remove_recursive(&self, entry: Entry) -> io::Result<()> {
+        use traits::{Dir as DirTrait, Entry as EntryTrait};
+
+        let (location, first) = match entry {
+            Entry::File(file) => (file.location, file.cluster),
+            Entry::Dir(dir) => {
+                for child in dir.entries()? {
+                    if child.name() != "." && child.name() != ".." {
+                        self.remove_recursive(child)?;
+                    }
+                }
+                match dir.location {
+                    Some(location) => (location, dir.cluster),
+                    None => return Ok(()),
+                }
+            }
+        };
+        self.borrow_mut().remove_entry(location, first)?;
+        Ok(())
+    }
+}
+
 impl<'a> FileSystem for &'a Shared<VFat> {
     type File = File;
     type Dir = Dir;
@@ -287,7 +569,18 @@ impl<'a> FileSystem for &'a Shared<VFat> {
         unimplemented!("read only file system")
     }
 
-    fn remove<P: AsRef<Path>>(self, _path: P, _children: bool) -> io::Result<()> {
-        unimplemented!("read only file system")
+    fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()> {
+        let entry = self.open(path)?;
+        if let Entry::Dir(ref dir) = entry {
+            if dir.location.is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cannot remove the root directory",
+                ));
+            } else if !children {
+                return Err(io::Error::new(io::ErrorKind::Other, "entry is a directory"));
+            }
+        }
+        self.remove_recursive(entry)
     }
 }
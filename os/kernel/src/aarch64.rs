@@ -67,6 +67,120 @@ pub fn sp_sel() -> u8 {
     (ptr & 1) as u8
 }
 
+/// Returns the faulting virtual address of the last synchronous abort taken
+/// to EL1 (`FAR_EL1`).
+#[cfg(not(test))]
+#[inline(always)]
+pub fn far() -> usize {
+    let far: usize;
+    unsafe {
+        asm!("mrs $0, FAR_EL1" : "=r"(far));
+    }
+
+    far
+}
+
+/// Returns the faulting virtual address of the last synchronous abort. Tests
+/// take no aborts.
+#[cfg(test)]
+pub fn far() -> usize {
+    0
+}
+
+/// Returns whether IRQs are masked on the current core.
+///
+/// At EL0, `DAIF` is readable only by processes running kernel code, for
+/// which the kernel sets `SCTLR_EL1.UMA`.
+#[inline(always)]
+pub fn irqs_masked() -> bool {
+    let daif: u64;
+    unsafe {
+        asm!("mrs $0, DAIF" : "=r"(daif));
+    }
+
+    daif & (1 << 7) != 0
+}
+
+/// Returns the ID of the current process, or 0 before the first process
+/// runs: the kernel restores `TPIDR_EL0` from each process's trap frame.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn pid() -> u64 {
+    let pid: u64;
+    unsafe {
+        asm!("mrs $0, TPIDR_EL0" : "=r"(pid));
+    }
+
+    pid
+}
+
+/// Returns the ID of the current process. Tests run outside processes.
+#[cfg(test)]
+pub fn pid() -> u64 {
+    0
+}
+
+/// Returns the smallest data and instruction cache line sizes, in bytes, from
+/// `CTR_EL0`. At EL0, `CTR_EL0` is readable because the kernel sets
+/// `SCTLR_EL1.UCT`.
+#[cfg(not(test))]
+fn cache_line_sizes() -> (usize, usize) {
+    let ctr: u64;
+    unsafe {
+        asm!("mrs $0, CTR_EL0" : "=r"(ctr));
+    }
+
+    (4 << ((ctr >> 16) & 0xF), 4 << (ctr & 0xF))
+}
+
+/// Runs the cache maintenance instruction template `$op` on every line of `$size`
+/// bytes at `$start`, then waits for it to complete.
+#[cfg(not(test))]
+macro_rules! by_line {
+    ($op:tt, $line:expr, $start:expr, $size:expr) => {{
+        let mut address = $start & !($line - 1);
+        while address < $start + $size {
+            asm!($op :: "r"(address) : "memory" : "volatile");
+            address += $line;
+        }
+        asm!("dsb sy" ::: "memory" : "volatile");
+    }};
+}
+
+/// Writes the `size` bytes at `start` back from the data cache to memory, for
+/// a device to read them by DMA.
+#[cfg_attr(test, allow(unused_variables))]
+pub fn clean_dcache(start: usize, size: usize) {
+    #[cfg(not(test))]
+    unsafe {
+        by_line!("dc cvac, $0", cache_line_sizes().0, start, size);
+    }
+}
+
+/// Writes back and then discards the `size` bytes at `start` from the data
+/// cache, so that the next reads see what a device wrote there by DMA.
+#[cfg_attr(test, allow(unused_variables))]
+pub fn invalidate_dcache(start: usize, size: usize) {
+    #[cfg(not(test))]
+    unsafe {
+        by_line!("dc civac, $0", cache_line_sizes().0, start, size);
+    }
+}
+
+/// Makes the `size` bytes of code written at `start` visible to instruction
+/// fetches: the data cache is cleaned to the point of unification and the
+/// instruction cache is invalidated.
+#[cfg_attr(test, allow(unused_variables))]
+pub fn sync_icache(start: usize, size: usize) {
+    #[cfg(not(test))]
+    unsafe {
+        let (dline, iline) = cache_line_sizes();
+        by_line!("dc cvau, $0", dline, start, size);
+        by_line!("ic ivau, $0", iline, start, size);
+        asm!("isb" ::: "memory" : "volatile");
+    }
+}
+
 /// Returns the core currently executing.
 ///
 /// # Safety
@@ -88,11 +202,41 @@ pub fn nop() {
     }
 }
 
+/// Waits for an interrupt, conserving energy until one is pending.
+pub fn wfi() {
+    #[cfg(not(test))]
+    unsafe {
+        asm!("wfi" :::: "volatile");
+    }
+}
+
+/// Signals an event to every core, waking those waiting in `wfe`.
+pub fn sev() {
+    #[cfg(not(test))]
+    unsafe {
+        asm!("dsb sy
+              sev" :::: "volatile");
+    }
+}
+
 extern "C" {
     fn __text_start();
     fn __text_end();
 }
 
+/// Returns the name of the kernel function containing `pc`, or `None` if `pc`
+/// is not in the kernel's code or its function is unknown.
+#[cfg_attr(test, allow(unused_variables))]
+pub fn symbolize(pc: usize) -> Option<String> {
+    #[cfg(not(test))]
+    unsafe {
+        if pc >= __text_start as usize && pc < __text_end as usize {
+            return dwarf::get_function_from_pc(pc);
+        }
+    }
+    None
+}
+
 pub fn bt() {
     #[cfg(not(test))]
     unsafe {
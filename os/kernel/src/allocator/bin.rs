@@ -43,6 +43,11 @@ impl Allocator {
         }
     }
 
+    /// Returns the number of bytes allocated and the number of bytes managed.
+    pub fn usage(&self) -> (usize, usize) {
+        (self.allocated, self.total)
+    }
+
     /// Allocates memory. Returns a pointer meeting the size and alignment
     /// properties of `layout.size()` and `layout.align()`.
     ///
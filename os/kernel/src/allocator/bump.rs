@@ -5,6 +5,7 @@ use allocator::util::*;
 /// A "bump" allocator: allocates memory by bumping a pointer; never frees.
 #[derive(Debug)]
 pub struct Allocator {
+    start: usize,
     current: usize,
     end: usize,
 }
@@ -14,11 +15,17 @@ impl Allocator {
     /// starting at address `start` and ending at address `end`.
     pub fn new(start: usize, end: usize) -> Allocator {
         Allocator {
+            start: start,
             current: start,
             end: end,
         }
     }
 
+    /// Returns the number of bytes allocated and the number of bytes managed.
+    pub fn usage(&self) -> (usize, usize) {
+        (self.current - self.start, self.end - self.start)
+    }
+
     /// Allocates memory. Returns a pointer meeting the size and alignment
     /// properties of `layout.size()` and `layout.align()`.
     ///
@@ -0,0 +1,285 @@
+use std::{fmt, mem, slice};
+
+use allocator::util::{align_down, align_up};
+use mutex::Mutex;
+use vm::{PhysicalAddr, PAGE_SIZE};
+
+/// The largest reference count a frame can have.
+const MAX_COUNT: u16 = u16::max_value();
+
+/// An allocator of the 4KiB physical page frames of a region of memory.
+///
+/// Every frame has a reference count: zero if the frame is free, and the
+/// number of owners sharing it otherwise. The counts are kept in the first
+/// frames of the region, which are never handed out.
+pub struct Frames {
+    /// The address of the first frame that can be allocated.
+    base: usize,
+    /// The reference count of every frame from `base` on.
+    counts: &'static mut [u16],
+    /// The number of free frames.
+    free: usize,
+    /// The index at which the search for a single free frame starts.
+    next: usize,
+}
+
+impl Frames {
+    /// Returns an allocator of the frames between physical addresses `start`
+    /// and `end`, which are rounded inwards to page boundaries. Every frame is
+    /// free.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be unused, and stay reserved for the allocator, for as
+    /// long as the allocator is used.
+    pub unsafe fn new(start: usize, end: usize) -> Frames {
+        let start = align_up(start, PAGE_SIZE);
+        let end = align_down(end, PAGE_SIZE).max(start);
+        let frames = (end - start) / PAGE_SIZE;
+        let counts_size = align_up(frames * mem::size_of::<u16>(), PAGE_SIZE);
+        let base = (start + counts_size).min(end);
+
+        let counts = PhysicalAddr::from(start).as_mut_ptr() as *mut u16;
+        let counts = slice::from_raw_parts_mut(counts, (end - base) / PAGE_SIZE);
+        for count in counts.iter_mut() {
+            *count = 0;
+        }
+        Frames { base, free: counts.len(), counts, next: 0 }
+    }
+
+    /// Returns the index of the frame at `frame`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `frame` is not the address of a frame of this allocator.
+    fn index(&self, frame: PhysicalAddr) -> usize {
+        assert!(self.contains(frame), "{:?} is not a managed frame", frame);
+        (frame.as_usize() - self.base) / PAGE_SIZE
+    }
+
+    fn address(&self, index: usize) -> PhysicalAddr {
+        (self.base + index * PAGE_SIZE).into()
+    }
+
+    /// Returns whether `frame` is the address of a frame of this allocator.
+    pub fn contains(&self, frame: PhysicalAddr) -> bool {
+        let address = frame.as_usize();
+        address >= self.base
+            && address % PAGE_SIZE == 0
+            && (address - self.base) / PAGE_SIZE < self.counts.len()
+    }
+
+    /// Allocates a frame with a reference count of one. Returns `None` if
+    /// every frame is in use. The contents of the frame are unspecified.
+    pub fn alloc(&mut self) -> Option<PhysicalAddr> {
+        let len = self.counts.len();
+        let index = (self.next..len).chain(0..self.next).find(|&i| self.counts[i] == 0)?;
+        self.counts[index] = 1;
+        self.free -= 1;
+        self.next = (index + 1) % len;
+        Some(self.address(index))
+    }
+
+    /// Allocates `count` physically contiguous frames, the first of which is
+    /// aligned to `align` bytes, each with a reference count of one. Returns
+    /// the address of the first frame, or `None` if there is no such run of
+    /// free frames.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `align` is not a power of two.
+    pub fn alloc_contiguous(&mut self, count: usize, align: usize) -> Option<PhysicalAddr> {
+        if count == 0 || count > self.free {
+            return None;
+        }
+
+        let mut index = 0;
+        while index + count <= self.counts.len() {
+            let aligned = (align_up(self.base + index * PAGE_SIZE, align) - self.base) / PAGE_SIZE;
+            if aligned != index {
+                index = aligned;
+                continue;
+            }
+            match self.counts[index..index + count].iter().rposition(|&count| count != 0) {
+                Some(used) => index += used + 1,
+                None => {
+                    for frame in &mut self.counts[index..index + count] {
+                        *frame = 1;
+                    }
+                    self.free -= count;
+                    return Some(self.address(index));
+                }
+            }
+        }
+        None
+    }
+
+    /// Adds an owner to the allocated frame at `frame`, returning its new
+    /// reference count.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `frame` is not an allocated frame of this allocator, or if
+    /// its reference count would overflow.
+    pub fn retain(&mut self, frame: PhysicalAddr) -> usize {
+        let index = self.index(frame);
+        let count = &mut self.counts[index];
+        assert!(*count != 0, "retained free frame {:?}", frame);
+        assert!(*count != MAX_COUNT, "too many references to frame {:?}", frame);
+        *count += 1;
+        *count as usize
+    }
+
+    /// Removes an owner from the allocated frame at `frame`, returning its
+    /// new reference count. The frame is freed when the count reaches zero.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `frame` is not an allocated frame of this allocator.
+    pub fn release(&mut self, frame: PhysicalAddr) -> usize {
+        let index = self.index(frame);
+        assert!(self.counts[index] != 0, "released free frame {:?}", frame);
+        self.counts[index] -= 1;
+        if self.counts[index] == 0 {
+            self.free += 1;
+        }
+        self.counts[index] as usize
+    }
+
+    /// Releases each of the `count` frames starting at `frame`. See
+    /// `release()`.
+    pub fn release_contiguous(&mut self, frame: PhysicalAddr, count: usize) {
+        for i in 0..count {
+            self.release((frame.as_usize() + i * PAGE_SIZE).into());
+        }
+    }
+
+    /// Returns the reference count of the frame at `frame`: zero if it is
+    /// free.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `frame` is not a frame of this allocator.
+    pub fn count(&self, frame: PhysicalAddr) -> usize {
+        self.counts[self.index(frame)] as usize
+    }
+
+    /// Returns the number of free frames.
+    pub fn free(&self) -> usize {
+        self.free
+    }
+
+    /// Returns the number of allocated frames.
+    pub fn used(&self) -> usize {
+        self.total() - self.free
+    }
+
+    /// Returns the number of frames managed by the allocator.
+    pub fn total(&self) -> usize {
+        self.counts.len()
+    }
+}
+
+impl fmt::Debug for Frames {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Frames")
+            .field("base", &self.address(0))
+            .field("used", &self.used())
+            .field("free", &self.free())
+            .finish()
+    }
+}
+
+/// The size of the memory the frame allocator manages in tests.
+#[cfg(test)]
+const TEST_MEMORY: usize = 128 << 20;
+
+/// Thread-safe (locking) wrapper around the physical frame allocator.
+///
+/// In tests, the allocator initializes itself on first use with memory
+/// taken from the system allocator.
+#[derive(Debug)]
+pub struct FrameAllocator(Mutex<Option<Frames>>);
+
+impl FrameAllocator {
+    /// Returns an uninitialized `FrameAllocator`.
+    ///
+    /// The allocator must be initialized by calling `initialize()` before the
+    /// first frame allocation. Failure to do will result in panics.
+    pub const fn uninitialized() -> Self {
+        FrameAllocator(Mutex::new(None))
+    }
+
+    /// Initializes the frame allocator with the system's available memory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the system's memory map could not be retrieved.
+    pub fn initialize(&self) {
+        let (start, end) = super::memory_map().expect("failed to find memory map");
+        *self.0.lock() = Some(unsafe { Frames::new(start, end) });
+    }
+
+    fn with<R, F: FnOnce(&mut Frames) -> R>(&self, f: F) -> R {
+        let mut frames = self.0.lock();
+        #[cfg(test)]
+        {
+            if frames.is_none() {
+                *frames = Some(test_frames());
+            }
+        }
+        f(frames.as_mut().expect("frame allocator uninitialized"))
+    }
+
+    /// Allocates a frame. See `Frames::alloc()`.
+    pub fn alloc(&self) -> Option<PhysicalAddr> {
+        self.with(|frames| frames.alloc())
+    }
+
+    /// Allocates contiguous frames. See `Frames::alloc_contiguous()`.
+    pub fn alloc_contiguous(&self, count: usize, align: usize) -> Option<PhysicalAddr> {
+        self.with(|frames| frames.alloc_contiguous(count, align))
+    }
+
+    /// Adds an owner to a frame. See `Frames::retain()`.
+    pub fn retain(&self, frame: PhysicalAddr) -> usize {
+        self.with(|frames| frames.retain(frame))
+    }
+
+    /// Removes an owner from a frame. See `Frames::release()`.
+    pub fn release(&self, frame: PhysicalAddr) -> usize {
+        self.with(|frames| frames.release(frame))
+    }
+
+    /// Removes an owner from contiguous frames. See
+    /// `Frames::release_contiguous()`.
+    pub fn release_contiguous(&self, frame: PhysicalAddr, count: usize) {
+        self.with(|frames| frames.release_contiguous(frame, count))
+    }
+
+    /// Returns the reference count of a frame. See `Frames::count()`.
+    pub fn count(&self, frame: PhysicalAddr) -> usize {
+        self.with(|frames| frames.count(frame))
+    }
+
+    /// Returns whether `frame` is a frame of the allocator.
+    pub fn contains(&self, frame: PhysicalAddr) -> bool {
+        self.with(|frames| frames.contains(frame))
+    }
+
+    /// Returns the number of free frames and the number of frames managed by
+    /// the allocator, or `None` if it is uninitialized.
+    pub fn usage(&self) -> Option<(usize, usize)> {
+        self.0.lock().as_ref().map(|frames| (frames.free(), frames.total()))
+    }
+}
+
+#[cfg(test)]
+fn test_frames() -> Frames {
+    use std::alloc::{GlobalAlloc, Layout, System};
+
+    let layout = Layout::from_size_align(TEST_MEMORY, PAGE_SIZE).unwrap();
+    let start = unsafe { System.alloc(layout) } as usize;
+    assert!(start != 0, "failed to allocate test memory");
+    unsafe { Frames::new(start, start + TEST_MEMORY) }
+}
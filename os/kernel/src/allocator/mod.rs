@@ -1,5 +1,6 @@
+mod frame;
 mod linked_list;
-mod util;
+pub mod util;
 
 #[path = "bin.rs"]
 mod imp;
@@ -7,13 +8,17 @@ mod imp;
 #[cfg(test)]
 mod tests;
 
+pub use self::frame::{FrameAllocator, Frames};
+
+use FRAMES;
 use mutex::Mutex;
 #[cfg(test)]
 use std::alloc::{GlobalAlloc, Layout};
 #[cfg(not(test))]
 use std::{GlobalAlloc, Layout};
-use std::cmp::max;
+use std::cmp::{max, min};
 use pi::atags::Atags;
+use vm::{KERNEL_BASE, PAGE_SIZE};
 
 /// Thread-safe (locking) wrapper around a particular memory allocator.
 #[derive(Debug)]
@@ -28,14 +33,25 @@ impl Allocator {
         Allocator(Mutex::new(None))
     }
 
-    /// Initializes the memory allocator.
+    /// Initializes the memory allocator with half of the free frames of the
+    /// frame allocator, which must have been initialized.
     ///
     /// # Panics
     ///
-    /// Panics if the system's memory map could not be retrieved.
+    /// Panics if the heap could not be reserved.
     pub fn initialize(&self) {
-        let (start, end) = memory_map().expect("failed to find memory map");
-        *self.0.lock() = Some(imp::Allocator::new(start, end));
+        let (free, _) = FRAMES.usage().expect("frame allocator uninitialized");
+        let size = free / 2 * PAGE_SIZE;
+        let start = FRAMES.alloc_contiguous(free / 2, PAGE_SIZE)
+            .expect("failed to reserve the kernel heap")
+            .as_mut_ptr() as usize;
+        *self.0.lock() = Some(imp::Allocator::new(start, start + size));
+    }
+
+    /// Returns the number of bytes allocated and the number of bytes managed
+    /// by the allocator, or `None` if it is uninitialized.
+    pub fn usage(&self) -> Option<(usize, usize)> {
+        self.0.lock().as_ref().map(|allocator| allocator.usage())
     }
 }
 
@@ -95,12 +111,17 @@ extern "C" {
 }
 
 /// Returns the (start address, end address) of the available memory on this
-/// system if it can be determined. If it cannot, `None` is returned.
+/// system, as physical addresses, if it can be determined. If it cannot,
+/// `None` is returned.
+///
+/// Memory holding the kernel binary or the initial RAM disk is not
+/// available.
 ///
 /// This function is expected to return `Some` under all normal cirumstances.
 fn memory_map() -> Option<(usize, usize)> {
-    let binary_end = unsafe { (&_end as *const u8) as usize };
+    let binary_end = unsafe { (&_end as *const u8) as usize - KERNEL_BASE };
 
+    let mut map = None;
     for tag in Atags::get() {
         match tag.mem() {
             Some(mem) => {
@@ -109,11 +130,36 @@ fn memory_map() -> Option<(usize, usize)> {
                 if binary_end < end {
                     start = max(start, binary_end);
                 }
-                return Some((start, end));
+                map = Some((start, end));
+                break;
             }
             _ => {}
         }
     }
 
-    None
+    let initrd = Atags::get().filter_map(|tag| tag.initrd()).next();
+    match (map, initrd) {
+        (Some(region), Some(initrd)) => {
+            let start = initrd.start as usize;
+            Some(exclude(region, (start, start + initrd.size as usize)))
+        }
+        (map, _) => map,
+    }
+}
+
+/// Returns the larger of the parts of the memory `region` before and after
+/// `hole`, both given as (start address, end address).
+fn exclude(region: (usize, usize), hole: (usize, usize)) -> (usize, usize) {
+    let (start, end) = region;
+    if hole.1 <= start || end <= hole.0 {
+        return region;
+    }
+
+    let before = (start, max(start, hole.0));
+    let after = (min(end, hole.1), end);
+    if before.1 - before.0 >= after.1 - after.0 {
+        before
+    } else {
+        after
+    }
 }
@@ -337,3 +337,127 @@ mod linked_list {
         assert_eq!(iter.next(), None);
     }
 }
+
+mod memory_map {
+    use allocator::exclude;
+
+    #[test]
+    fn test_exclude() {
+        assert_eq!(exclude((0x1000, 0x9000), (0x9000, 0xA000)), (0x1000, 0x9000));
+        assert_eq!(exclude((0x1000, 0x9000), (0x0, 0x1000)), (0x1000, 0x9000));
+        assert_eq!(exclude((0x1000, 0x9000), (0x2000, 0x3000)), (0x3000, 0x9000));
+        assert_eq!(exclude((0x1000, 0x9000), (0x7000, 0x8000)), (0x1000, 0x7000));
+        assert_eq!(exclude((0x1000, 0x9000), (0x0, 0x4000)), (0x4000, 0x9000));
+        assert_eq!(exclude((0x1000, 0x9000), (0x6000, 0xA000)), (0x1000, 0x6000));
+        assert_eq!(exclude((0x1000, 0x9000), (0x0, 0xA000)), (0x1000, 0x1000));
+    }
+}
+
+mod frame {
+    use allocator::Frames;
+    use alloc::raw_vec::RawVec;
+    use vm::{PhysicalAddr, PAGE_SIZE};
+
+    /// Returns an allocator over `size` bytes of fresh memory, and that memory.
+    fn frames(size: usize) -> (Frames, RawVec<u8>) {
+        let mem: RawVec<u8> = RawVec::with_capacity(size);
+        let start = mem.ptr() as usize;
+        (unsafe { Frames::new(start, start + size) }, mem)
+    }
+
+    fn addr(frame: PhysicalAddr) -> usize {
+        frame.as_usize()
+    }
+
+    #[test]
+    fn test_alloc_release() {
+        let (mut frames, mem) = frames(64 * PAGE_SIZE);
+        // 62 or 63 whole frames, minus one holding the reference counts.
+        let total = frames.total();
+        assert!(total == 61 || total == 62, "{} frames", total);
+        assert_eq!((frames.free(), frames.used()), (total, 0));
+
+        let mut allocated = vec![];
+        while let Some(frame) = frames.alloc() {
+            assert_eq!(addr(frame) % PAGE_SIZE, 0);
+            assert!(addr(frame) > mem.ptr() as usize);
+            assert!(addr(frame) + PAGE_SIZE <= mem.ptr() as usize + 64 * PAGE_SIZE);
+            assert_eq!(frames.count(frame), 1);
+            allocated.push(addr(frame));
+        }
+        assert_eq!(allocated.len(), total);
+        assert_eq!((frames.free(), frames.used()), (0, total));
+        allocated.sort();
+        allocated.dedup();
+        assert_eq!(allocated.len(), total, "frames are allocated once");
+
+        assert_eq!(frames.release(allocated[3].into()), 0);
+        assert_eq!(frames.free(), 1);
+        assert_eq!(addr(frames.alloc().unwrap()), allocated[3]);
+        assert!(frames.alloc().is_none());
+    }
+
+    #[test]
+    fn test_refcounts() {
+        let (mut frames, _mem) = frames(16 * PAGE_SIZE);
+        let frame = frames.alloc().unwrap();
+        assert_eq!(frames.retain(frame), 2);
+        assert_eq!(frames.retain(frame), 3);
+        assert_eq!(frames.count(frame), 3);
+        let free = frames.free();
+
+        assert_eq!(frames.release(frame), 2);
+        assert_eq!(frames.release(frame), 1);
+        assert_eq!(frames.free(), free);
+        assert_eq!(frames.release(frame), 0);
+        assert_eq!(frames.free(), free + 1);
+        assert_eq!(frames.count(frame), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_release_free() {
+        let (mut frames, _mem) = frames(16 * PAGE_SIZE);
+        let frame = frames.alloc().unwrap();
+        frames.release(frame);
+        frames.release(frame);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_unmanaged() {
+        let (mut frames, mem) = frames(16 * PAGE_SIZE);
+        frames.retain((mem.ptr() as usize).into());
+    }
+
+    #[test]
+    fn test_contiguous() {
+        let (mut frames, mem) = frames(128 * PAGE_SIZE);
+        let start = mem.ptr() as usize;
+        assert!(!frames.contains(start.into()));
+        assert!(frames.alloc_contiguous(0, PAGE_SIZE).is_none());
+        let total = frames.total();
+        assert!(frames.alloc_contiguous(total + 1, PAGE_SIZE).is_none());
+
+        let single = frames.alloc().unwrap();
+        let run = frames.alloc_contiguous(8, PAGE_SIZE).unwrap();
+        assert!(addr(run) > addr(single), "runs skip allocated frames");
+        for i in 0..8 {
+            assert_eq!(frames.count((addr(run) + i * PAGE_SIZE).into()), 1);
+        }
+
+        let aligned = frames.alloc_contiguous(4, 16 * PAGE_SIZE).unwrap();
+        assert_eq!(addr(aligned) % (16 * PAGE_SIZE), 0);
+        assert!(addr(aligned) >= addr(run) + 8 * PAGE_SIZE);
+
+        let free = frames.free();
+        frames.release_contiguous(run, 8);
+        assert_eq!(frames.free(), free + 8);
+        assert_eq!(addr(frames.alloc_contiguous(8, PAGE_SIZE).unwrap()), addr(run));
+
+        // Free frames on both sides of allocated ones do not form a run.
+        frames.release_contiguous(run, 8);
+        let free = frames.free();
+        assert!(frames.alloc_contiguous(free, PAGE_SIZE).is_none());
+    }
+}
@@ -0,0 +1,432 @@
+mod partition;
+#[cfg(test)]
+mod tests;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::{cmp, fmt, io};
+use std::mem::replace;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use fat32::traits::BlockDevice;
+use mutex::Mutex;
+use process::WaitQueue;
+use traps::TrapFrame;
+use SCHEDULER;
+
+pub use self::partition::{Kind, Partition};
+
+/// The ID of a request queued in a `BlockLayer`.
+pub type RequestId = u64;
+
+/// An operation on a sector of a disk.
+#[derive(Debug)]
+enum Operation {
+    Read(u64),
+    Write(u64, Vec<u8>),
+}
+
+#[derive(Debug)]
+struct Request {
+    id: RequestId,
+    disk: usize,
+    operation: Operation,
+}
+
+struct Disk {
+    name: String,
+    /// The device, locked while a request is transferred to it.
+    device: Arc<Mutex<Box<BlockDevice + Send>>>,
+    /// The request being transferred to the disk, if any. Later requests to
+    /// the disk stay queued until it completes.
+    busy: Option<RequestId>,
+}
+
+impl fmt::Debug for Disk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Disk").field("name", &self.name).finish()
+    }
+}
+
+#[derive(Debug)]
+struct Layer {
+    disks: Vec<Disk>,
+    devices: Vec<Device>,
+    queue: VecDeque<Request>,
+    done: BTreeMap<RequestId, io::Result<Vec<u8>>>,
+    /// The processes blocked until each request completes.
+    waiters: BTreeMap<RequestId, WaitQueue>,
+    /// The processes blocked until a request is queued.
+    workers: WaitQueue,
+    last_id: RequestId,
+}
+
+impl Layer {
+    fn new() -> Layer {
+        Layer {
+            disks: Vec::new(),
+            devices: Vec::new(),
+            queue: VecDeque::new(),
+            done: BTreeMap::new(),
+            waiters: BTreeMap::new(),
+            workers: WaitQueue::new(),
+            last_id: 0,
+        }
+    }
+
+    /// Returns whether request `id` is queued or being transferred.
+    fn is_pending(&self, id: RequestId) -> bool {
+        self.queue.iter().any(|request| request.id == id)
+            || self.disks.iter().any(|disk| disk.busy == Some(id))
+    }
+}
+
+/// The block layer: a registry of disks and their partitions, and a queue of
+/// I/O requests to them.
+///
+/// Disks are registered with `register()`, which exposes each disk and each
+/// of its partitions as a `Device`. Reading or writing a sector of a
+/// `Device` queues a request and waits for it to complete. A process waits
+/// blocked on the request's wait queue while other processes run; the queue
+/// is serviced by a worker process, which blocks in turn until requests are
+/// queued. Outside a process, with IRQs masked or before the scheduler
+/// starts, requests are serviced by the waiter itself.
+///
+/// A file system claims the device it is on with `Device::claim()`. While a
+/// disk is claimed, only the claimed devices may write to it, which keeps the
+/// file system's caches up to date.
+#[derive(Debug)]
+pub struct BlockLayer(Mutex<Option<Layer>>);
+
+impl BlockLayer {
+    /// Returns an empty `BlockLayer`.
+    pub const fn uninitialized() -> BlockLayer {
+        BlockLayer(Mutex::new(None))
+    }
+
+    fn with<T, F: FnOnce(&mut Layer) -> T>(&self, f: F) -> T {
+        let mut guard = self.0.lock();
+        f(guard.get_or_insert_with(Layer::new))
+    }
+
+    /// Registers `device` as the disk `name`, and each partition in its
+    /// partition table as `name` followed by the partition's number. Returns
+    /// the device for the whole disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `AlreadyExists` if a disk is already named `name`,
+    /// or the error encountered while reading the partition table.
+    pub fn register<B>(&'static self, name: &str, mut device: B) -> io::Result<Device>
+    where
+        B: BlockDevice + Send + 'static,
+    {
+        if self.get(name).is_some() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "disk already exists"));
+        }
+        let partitions = partition::read(&mut device)?;
+        let sector_size = device.sector_size();
+
+        Ok(self.with(|layer| {
+            let disk = Device {
+                layer: self,
+                name: name.to_string(),
+                disk: layer.disks.len(),
+                sector_size,
+                partition: None,
+                claims: Arc::new(AtomicUsize::new(0)),
+                claim: None,
+            };
+            let device = Box::new(device) as Box<BlockDevice + Send>;
+            layer.disks.push(Disk {
+                name: name.to_string(),
+                device: Arc::new(Mutex::new(device)),
+                busy: None,
+            });
+            layer.devices.push(disk.clone());
+            for partition in partitions {
+                layer.devices.push(Device {
+                    name: format!("{}{}", name, partition.number),
+                    partition: Some(partition),
+                    ..disk.clone()
+                });
+            }
+            disk
+        }))
+    }
+
+    /// Returns every registered device: each disk, followed by its
+    /// partitions.
+    pub fn devices(&self) -> Vec<Device> {
+        self.with(|layer| layer.devices.clone())
+    }
+
+    /// Returns the device named `name`, if there is one.
+    pub fn get(&self, name: &str) -> Option<Device> {
+        self.with(|layer| layer.devices.iter().find(|device| device.name == name).cloned())
+    }
+
+    /// Returns the partitions of the disk named `disk`.
+    pub fn partitions(&self, disk: &str) -> Vec<Device> {
+        self.with(|layer| {
+            let index = match layer.disks.iter().position(|d| d.name == disk) {
+                Some(index) => index,
+                None => return Vec::new(),
+            };
+            layer.devices
+                .iter()
+                .filter(|device| device.disk == index && device.partition.is_some())
+                .cloned()
+                .collect()
+        })
+    }
+
+    /// Queues `operation` on disk `disk`, wakes the workers, and returns the
+    /// ID of the request.
+    fn submit(&self, disk: usize, operation: Operation) -> RequestId {
+        let (id, mut workers) = self.queue(disk, operation);
+        SCHEDULER.wake_all(&mut workers);
+        id
+    }
+
+    /// Queues `operation` on disk `disk` and returns the ID of the request,
+    /// and the workers waiting for requests, which must be woken.
+    fn queue(&self, disk: usize, operation: Operation) -> (RequestId, WaitQueue) {
+        self.with(|layer| {
+            layer.last_id += 1;
+            let id = layer.last_id;
+            layer.queue.push_back(Request { id, disk, operation });
+            (id, replace(&mut layer.workers, WaitQueue::new()))
+        })
+    }
+
+    /// Services the first queued request to a disk that is not busy and wakes
+    /// the processes waiting for it. Returns `false` if there was no such
+    /// request.
+    pub fn service(&self) -> bool {
+        match self.complete_next() {
+            Some(mut waiters) => {
+                SCHEDULER.wake_all(&mut waiters);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Services the first queued request to a disk that is not busy and
+    /// returns the processes waiting for it, or `None` if there was no such
+    /// request.
+    ///
+    /// The transfer holds the lock of the disk, not the layer's, so that other
+    /// disks can be serviced and requests queued meanwhile. The disk is busy
+    /// until the request completes, which keeps its requests in order, and
+    /// only the request's servicer locks it: its lock is taken before the
+    /// layer's is released and held until the completion is posted, so that
+    /// the servicer can not be preempted, by a waiter servicing requests with
+    /// IRQs masked, while the disk is busy.
+    fn complete_next(&self) -> Option<WaitQueue> {
+        let mut guard = self.0.lock();
+        let (request, device) = {
+            let layer = guard.get_or_insert_with(Layer::new);
+            let index = layer.queue
+                .iter()
+                .position(|request| layer.disks[request.disk].busy.is_none())?;
+            let request = layer.queue.remove(index).unwrap();
+            let disk = &mut layer.disks[request.disk];
+            disk.busy = Some(request.id);
+            (request, disk.device.clone())
+        };
+        let mut device = device.lock();
+        drop(guard);
+
+        let (id, disk) = (request.id, request.disk);
+        let result = match request.operation {
+            Operation::Read(sector) => {
+                let mut data = Vec::new();
+                device.read_all_sector(sector, &mut data).map(|_| data)
+            }
+            Operation::Write(sector, data) => {
+                device.write_sector(sector, &data).map(|_| Vec::new())
+            }
+        };
+        Some(self.with(|layer| {
+            layer.disks[disk].busy = None;
+            layer.done.insert(id, result);
+            layer.waiters.remove(&id).unwrap_or_else(WaitQueue::new)
+        }))
+    }
+
+    /// Blocks the current process, whose registers are `tf`, until request
+    /// `id` completes, or until a request is queued if `id` is 0, switching to
+    /// the next process as by `GlobalScheduler::block_on()`. Returns `false`,
+    /// without blocking, if the request has completed or was never queued, or
+    /// if a request is queued already.
+    pub fn block_on(&self, id: RequestId, tf: &mut TrapFrame) -> bool {
+        self.block_with(id, |queue| SCHEDULER.block_on(queue, tf).is_some())
+    }
+
+    /// Calls `block` with the wait queue of request `id`, or with the workers
+    /// if `id` is 0, unless the process need not wait. Returns what `block`
+    /// did, or `false`.
+    fn block_with<F>(&self, id: RequestId, block: F) -> bool
+    where
+        F: FnOnce(&mut WaitQueue) -> bool,
+    {
+        self.with(|layer| {
+            if id == 0 {
+                return layer.queue.is_empty() && block(&mut layer.workers);
+            }
+            if !layer.is_pending(id) {
+                return false;
+            }
+            block(layer.waiters.entry(id).or_insert_with(WaitQueue::new))
+        })
+    }
+
+    /// Waits for request `id` to complete and returns its result: the
+    /// contents of the sector for a read, nothing for a write.
+    fn wait(&'static self, id: RequestId) -> io::Result<Vec<u8>> {
+        #[cfg(not(test))]
+        {
+            use aarch64::irqs_masked;
+            use user::wait_request;
+            use {BLOCK, SCHEDULER};
+
+            // The system call blocks on `BLOCK`, so only its requests can be
+            // waited for blocked.
+            if ::std::ptr::eq(self, &BLOCK) && SCHEDULER.is_running() && !irqs_masked() {
+                wait_request(id);
+            }
+        }
+
+        loop {
+            let (result, pending) = self.with(|layer| {
+                (layer.done.remove(&id), layer.is_pending(id))
+            });
+            if let Some(result) = result {
+                return result;
+            }
+            if !pending {
+                return Err(io::Error::new(io::ErrorKind::Other, "request was not queued"));
+            }
+            // The request is queued, or being transferred by another core.
+            self.service();
+        }
+    }
+}
+
+/// A registered block device: a whole disk or one of its partitions.
+///
+/// Sectors are numbered from the start of the device and are read and
+/// written through the request queue of the block layer. Every clone of a
+/// device refers to the same sectors.
+#[derive(Clone)]
+pub struct Device {
+    layer: &'static BlockLayer,
+    name: String,
+    disk: usize,
+    sector_size: u64,
+    partition: Option<Partition>,
+    /// The number of claims on the disk, shared by all of its devices.
+    claims: Arc<AtomicUsize>,
+    /// The claim this device holds, shared by its clones.
+    claim: Option<Arc<Claim>>,
+}
+
+/// A claim on a disk, released when dropped.
+#[derive(Debug)]
+struct Claim(Arc<AtomicUsize>);
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Device {
+    /// Returns the name of the device.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the partition the device is, or `None` if it is a whole disk.
+    pub fn partition(&self) -> Option<Partition> {
+        self.partition
+    }
+
+    /// Returns a clone of this device that claims its disk, for a file system
+    /// to use: until the clone and its own clones are dropped, the devices of
+    /// the disk that do not hold a claim are read only.
+    pub fn claim(&self) -> Device {
+        self.claims.fetch_add(1, Ordering::SeqCst);
+        Device { claim: Some(Arc::new(Claim(self.claims.clone()))), ..self.clone() }
+    }
+
+    /// Returns whether the device's disk is claimed by a file system.
+    pub fn is_claimed(&self) -> bool {
+        self.claims.load(Ordering::SeqCst) > 0
+    }
+
+    /// Returns the sector of the disk that is sector `n` of the device.
+    fn disk_sector(&self, n: u64) -> io::Result<u64> {
+        match self.partition {
+            Some(partition) if n >= partition.sectors => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "sector is past the end of the partition",
+            )),
+            Some(partition) => Ok(partition.start + n),
+            None => Ok(n),
+        }
+    }
+}
+
+impl fmt::Debug for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Device")
+            .field("name", &self.name)
+            .field("partition", &self.partition)
+            .field("claimed", &self.claim.is_some())
+            .finish()
+    }
+}
+
+impl BlockDevice for Device {
+    fn sector_size(&self) -> u64 {
+        self.sector_size
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector = self.disk_sector(n)?;
+        let id = self.layer.submit(self.disk, Operation::Read(sector));
+        let data = self.layer.wait(id)?;
+        let len = cmp::min(buf.len(), data.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok(len)
+    }
+
+    /// Writes sector `n` through the request queue.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `PermissionDenied` if the disk is claimed and this
+    /// device does not hold a claim.
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        if self.claim.is_none() && self.is_claimed() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "disk is in use by a file system",
+            ));
+        }
+        let sector = self.disk_sector(n)?;
+        let len = self.sector_size as usize;
+        if buf.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "buffer is smaller than a sector",
+            ));
+        }
+        let id = self.layer.submit(self.disk, Operation::Write(sector, buf[..len].to_vec()));
+        self.layer.wait(id)?;
+        Ok(len)
+    }
+}
@@ -0,0 +1,144 @@
+use std::{fmt, io};
+
+use fat32::traits::BlockDevice;
+
+/// The MBR partition type of a GPT protective MBR.
+const GPT_PROTECTIVE: u8 = 0xEE;
+
+/// The signature at the start of a GPT header.
+const GPT_SIGNATURE: &[u8] = b"EFI PART";
+
+/// The on-disk bytes of the GPT "basic data" partition type GUID,
+/// EBD0A0A2-B9E5-4433-87C0-68B6B72699C7, used for FAT partitions.
+const GPT_BASIC_DATA: [u8; 16] = [
+    0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44,
+    0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7,
+];
+
+/// The type of a partition, as recorded in its partition table.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Kind {
+    /// The partition type byte of an MBR partition.
+    Mbr(u8),
+    /// The on-disk bytes of the partition type GUID of a GPT partition.
+    Gpt([u8; 16]),
+}
+
+impl Kind {
+    /// Returns whether partitions of this type hold a FAT file system.
+    pub fn is_fat(&self) -> bool {
+        match *self {
+            Kind::Mbr(kind) => kind == 0x0B || kind == 0x0C,
+            Kind::Gpt(guid) => guid == GPT_BASIC_DATA,
+        }
+    }
+}
+
+impl fmt::Debug for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Kind::Mbr(kind) => write!(f, "Mbr({:#04x})", kind),
+            Kind::Gpt(ref guid) => {
+                write!(f, "Gpt(")?;
+                for byte in guid.iter() {
+                    write!(f, "{:02x}", byte)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// A partition of a disk.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Partition {
+    /// The number of the partition, from 1, in partition table order.
+    pub number: usize,
+    /// The type of the partition.
+    pub kind: Kind,
+    /// The first sector of the partition.
+    pub start: u64,
+    /// The number of sectors in the partition.
+    pub sectors: u64,
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    buf[offset..offset + 4].iter().rev().fold(0, |value, &byte| value << 8 | byte as u32)
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u32_at(buf, offset) as u64 | (u32_at(buf, offset + 4) as u64) << 32
+}
+
+fn read_sector<B: BlockDevice>(device: &mut B, n: u64) -> io::Result<Vec<u8>> {
+    let mut sector = Vec::new();
+    device.read_all_sector(n, &mut sector)?;
+    if sector.len() < 512 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "sector is too small"));
+    }
+    Ok(sector)
+}
+
+/// Reads the partition table of `device` and returns its partitions.
+///
+/// The partitions of a GPT disk are read from its primary GPT, and those of
+/// other disks from their master boot record; extended MBR partitions are
+/// not followed. A disk without a master boot record has no partitions.
+///
+/// # Errors
+///
+/// Returns an error if reading from `device` fails, or of `InvalidData` if a
+/// protective MBR is not followed by a valid GPT header.
+pub fn read<B: BlockDevice>(device: &mut B) -> io::Result<Vec<Partition>> {
+    let mbr = read_sector(device, 0)?;
+    if mbr[510..512] != [0x55, 0xAA] {
+        return Ok(Vec::new());
+    }
+
+    let mut partitions = Vec::new();
+    for (i, entry) in mbr[446..510].chunks(16).enumerate() {
+        let kind = entry[4];
+        let (start, sectors) = (u32_at(entry, 8) as u64, u32_at(entry, 12) as u64);
+        if kind == GPT_PROTECTIVE {
+            return read_gpt(device);
+        } else if kind != 0 && sectors != 0 {
+            partitions.push(Partition { number: i + 1, kind: Kind::Mbr(kind), start, sectors });
+        }
+    }
+    Ok(partitions)
+}
+
+/// Reads the partitions of the GPT whose header is in sector 1 of `device`.
+fn read_gpt<B: BlockDevice>(device: &mut B) -> io::Result<Vec<Partition>> {
+    let header = read_sector(device, 1)?;
+    let sector_size = device.sector_size() as usize;
+    let entry_size = u32_at(&header, 84) as usize;
+    if &header[..8] != GPT_SIGNATURE || entry_size < 128 || sector_size % entry_size != 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid GPT header"));
+    }
+
+    let first_entry_sector = u64_at(&header, 72);
+    let entries = u32_at(&header, 80) as usize;
+    let mut partitions = Vec::new();
+    let mut sector = Vec::new();
+    for i in 0..entries {
+        let offset = i * entry_size;
+        if offset % sector_size == 0 {
+            sector = read_sector(device, first_entry_sector + (offset / sector_size) as u64)?;
+        }
+        let entry = &sector[offset % sector_size..offset % sector_size + entry_size];
+
+        let mut guid = [0; 16];
+        guid.copy_from_slice(&entry[..16]);
+        let (first, last) = (u64_at(entry, 32), u64_at(entry, 40));
+        if guid != [0; 16] && first <= last {
+            partitions.push(Partition {
+                number: i + 1,
+                kind: Kind::Gpt(guid),
+                start: first,
+                sectors: last - first + 1,
+            });
+        }
+    }
+    Ok(partitions)
+}
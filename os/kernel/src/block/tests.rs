@@ -0,0 +1,216 @@
+use std::io::{self, Cursor};
+
+use block::{BlockLayer, Kind, Operation};
+use fat32::traits::BlockDevice;
+use process::{Process, Scheduler};
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    for i in 0..4 {
+        buf[offset + i] = (value >> (8 * i)) as u8;
+    }
+}
+
+fn put_u64(buf: &mut [u8], offset: usize, value: u64) {
+    put_u32(buf, offset, value as u32);
+    put_u32(buf, offset + 4, (value >> 32) as u32);
+}
+
+/// Returns a disk of `sectors` sectors whose sector `n` is filled with `n`,
+/// with an MBR holding `partitions` of (type, start, sectors).
+fn mbr_disk(sectors: usize, partitions: &[(u8, u32, u32)]) -> Vec<u8> {
+    let mut disk = vec![0; sectors * 512];
+    for (n, sector) in disk.chunks_mut(512).enumerate() {
+        for byte in sector.iter_mut() {
+            *byte = n as u8;
+        }
+    }
+    for byte in disk[..512].iter_mut() {
+        *byte = 0;
+    }
+    for (i, &(kind, start, count)) in partitions.iter().enumerate() {
+        let entry = 446 + i * 16;
+        disk[entry + 4] = kind;
+        put_u32(&mut disk, entry + 8, start);
+        put_u32(&mut disk, entry + 12, count);
+    }
+    disk[510] = 0x55;
+    disk[511] = 0xAA;
+    disk
+}
+
+#[test]
+fn test_mbr_partitions() {
+    static LAYER: BlockLayer = BlockLayer::uninitialized();
+    let disk = mbr_disk(64, &[(0x0C, 8, 16), (0, 0, 0), (0x83, 32, 8)]);
+    let mut whole = LAYER.register("disk", Cursor::new(disk)).unwrap();
+
+    let names: Vec<String> = LAYER.devices().iter().map(|d| d.name().to_string()).collect();
+    assert_eq!(names, vec!["disk", "disk1", "disk3"]);
+    let partitions = LAYER.partitions("disk");
+    assert_eq!(partitions.len(), 2);
+    let fat = partitions[0].partition().unwrap();
+    assert_eq!((fat.number, fat.kind, fat.start, fat.sectors), (1, Kind::Mbr(0x0C), 8, 16));
+    assert!(fat.kind.is_fat());
+    assert!(!partitions[1].partition().unwrap().kind.is_fat());
+    assert!(whole.partition().is_none());
+
+    let mut first = LAYER.get("disk1").unwrap();
+    let mut sector = [0; 512];
+    assert_eq!(first.read_sector(0, &mut sector).unwrap(), 512);
+    assert!(sector.iter().all(|&byte| byte == 8));
+    assert_eq!(first.write_sector(15, &[0xAB; 512]).unwrap(), 512);
+    whole.read_sector(23, &mut sector).unwrap();
+    assert!(sector.iter().all(|&byte| byte == 0xAB));
+
+    let error = first.read_sector(16, &mut sector).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    let error = first.write_sector(0, &[0; 100]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    let error = LAYER.register("disk", Cursor::new(vec![0; 512])).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+    assert!(!LAYER.service());
+}
+
+#[test]
+fn test_claimed_disk() {
+    static LAYER: BlockLayer = BlockLayer::uninitialized();
+    let disk = mbr_disk(32, &[(0x0C, 8, 16)]);
+    let mut whole = LAYER.register("disk", Cursor::new(disk)).unwrap();
+    let mut raw = LAYER.get("disk1").unwrap();
+    assert!(!raw.is_claimed());
+
+    // Only the claimed devices write to a claimed disk.
+    let mut claimed = raw.claim();
+    let mut clone = claimed.clone();
+    assert!(whole.is_claimed() && raw.is_claimed());
+    for device in [&mut whole, &mut raw].iter_mut() {
+        let error = device.write_sector(1, &[0xCD; 512]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+    }
+    assert_eq!(claimed.write_sector(1, &[0xCD; 512]).unwrap(), 512);
+    let mut sector = [0; 512];
+    raw.read_sector(1, &mut sector).unwrap();
+    assert!(sector.iter().all(|&byte| byte == 0xCD));
+
+    // The claim lasts until every clone of the claimed device is dropped.
+    drop(claimed);
+    assert_eq!(clone.write_sector(2, &[0xEF; 512]).unwrap(), 512);
+    assert!(raw.write_sector(3, &[0; 512]).is_err());
+    drop(clone);
+    assert!(!whole.is_claimed());
+    assert_eq!(whole.write_sector(0, &[0; 512]).unwrap(), 512);
+}
+
+#[test]
+fn test_gpt_partitions() {
+    static LAYER: BlockLayer = BlockLayer::uninitialized();
+    let mut disk = mbr_disk(64, &[(0xEE, 1, 63)]);
+    {
+        let header = &mut disk[512..1024];
+        header[..8].copy_from_slice(b"EFI PART");
+        put_u64(header, 72, 2);
+        put_u32(header, 80, 8);
+        put_u32(header, 84, 128);
+    }
+    let basic_data = [
+        0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44,
+        0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7,
+    ];
+    for byte in disk[1024..2048].iter_mut() {
+        *byte = 0;
+    }
+    for &(i, guid, first, last) in [(0, basic_data, 34, 40), (5, [7; 16], 41, 63)].iter() {
+        let entry = &mut disk[1024 + i * 128..1024 + (i + 1) * 128];
+        entry[..16].copy_from_slice(&guid);
+        put_u64(entry, 32, first);
+        put_u64(entry, 40, last);
+    }
+    LAYER.register("gpt", Cursor::new(disk)).unwrap();
+
+    let partitions: Vec<_> = LAYER.partitions("gpt")
+        .iter()
+        .map(|device| device.partition().unwrap())
+        .collect();
+    assert_eq!(partitions.len(), 2);
+    assert_eq!((partitions[0].number, partitions[0].start, partitions[0].sectors), (1, 34, 7));
+    assert!(partitions[0].kind.is_fat());
+    assert_eq!((partitions[1].number, partitions[1].start, partitions[1].sectors), (6, 41, 23));
+    assert_eq!(partitions[1].kind, Kind::Gpt([7; 16]));
+
+    let mut sector = [0; 512];
+    LAYER.get("gpt6").unwrap().read_sector(0, &mut sector).unwrap();
+    assert!(sector.iter().all(|&byte| byte == 41));
+}
+
+#[test]
+fn test_unpartitioned_disk() {
+    static LAYER: BlockLayer = BlockLayer::uninitialized();
+    let disk = LAYER.register("ram", Cursor::new(vec![1; 4 * 512])).unwrap();
+    assert!(LAYER.partitions("ram").is_empty());
+    assert_eq!(LAYER.devices().len(), 1);
+    assert_eq!(disk.name(), "ram");
+    assert!(LAYER.get("ram1").is_none());
+}
+
+#[test]
+fn test_block_on_request() {
+    static LAYER: BlockLayer = BlockLayer::uninitialized();
+    LAYER.register("disk", Cursor::new(mbr_disk(4, &[]))).unwrap();
+    let scheduler = Scheduler::new();
+    let reader = scheduler.add(Process::new().unwrap()).unwrap();
+    let worker = scheduler.add(Process::new().unwrap()).unwrap();
+    let mut tf = unsafe { *scheduler.enter(0) };
+    assert_eq!(tf.tpidr, reader);
+
+    // The reader blocks until its request completes, and the worker runs.
+    let (id, _) = LAYER.queue(0, Operation::Read(2));
+    assert!(LAYER.block_with(id, |waiters| scheduler.block_on(waiters, &mut tf).is_some()));
+    assert_eq!(tf.tpidr, worker);
+    assert!(scheduler.with_process(reader, |process| process.is_blocked()).unwrap());
+
+    // The worker has a request to service, which wakes the reader.
+    assert!(!LAYER.block_with(0, |_| panic!("the worker blocked")));
+    let mut waiters = LAYER.complete_next().unwrap();
+    assert_eq!(scheduler.wake_all(&mut waiters), 1);
+    assert!(scheduler.with_process(reader, |process| process.is_ready()).unwrap());
+    assert!(!LAYER.block_with(id, |_| panic!("blocked on a completed request")));
+    let sector = LAYER.with(|layer| layer.done.remove(&id)).unwrap().unwrap();
+    assert!(sector.iter().all(|&byte| byte == 2));
+
+    // With nothing left to service, the worker blocks until a request is
+    // queued.
+    assert!(LAYER.block_with(0, |workers| scheduler.block_on(workers, &mut tf).is_some()));
+    assert_eq!(tf.tpidr, reader);
+    assert!(scheduler.with_process(worker, |process| process.is_blocked()).unwrap());
+    let (_, mut workers) = LAYER.queue(0, Operation::Read(3));
+    assert_eq!(scheduler.wake_all(&mut workers), 1);
+    assert!(scheduler.with_process(worker, |process| process.is_ready()).unwrap());
+}
+
+#[test]
+fn test_busy_disk() {
+    static LAYER: BlockLayer = BlockLayer::uninitialized();
+    LAYER.register("a", Cursor::new(mbr_disk(4, &[]))).unwrap();
+    LAYER.register("b", Cursor::new(mbr_disk(4, &[]))).unwrap();
+    let (first, _) = LAYER.queue(0, Operation::Read(1));
+    let (second, _) = LAYER.queue(0, Operation::Read(2));
+    let (other, _) = LAYER.queue(1, Operation::Read(3));
+
+    // While a request to disk `a` is in flight, its later requests wait and
+    // the other disk is serviced.
+    LAYER.with(|layer| {
+        layer.queue.pop_front();
+        layer.disks[0].busy = Some(first);
+    });
+    assert!(LAYER.service());
+    assert!(LAYER.with(|layer| layer.done.contains_key(&other)));
+    assert!(!LAYER.service());
+    assert!(LAYER.with(|layer| layer.is_pending(first) && layer.is_pending(second)));
+    assert!(LAYER.block_with(first, |_| true), "in flight requests can be waited for");
+
+    LAYER.with(|layer| layer.disks[0].busy = None);
+    assert!(LAYER.service());
+    let sector = LAYER.with(|layer| layer.done.remove(&second)).unwrap().unwrap();
+    assert!(sector.iter().all(|&byte| byte == 2));
+    assert!(LAYER.wait(first).is_err(), "the request was never completed");
+}
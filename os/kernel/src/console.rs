@@ -1,5 +1,6 @@
 use std::io;
 use std::fmt;
+use std::sync::atomic::spin_loop_hint;
 
 use pi::uart::MiniUart;
 
@@ -29,9 +30,15 @@ impl Console {
         self.inner.as_mut().unwrap()
     }
 
-    /// Reads a byte from the UART device, blocking until a byte is available.
-    pub fn read_byte(&mut self) -> u8 {
-        self.inner().read_byte()
+    /// Reads a byte from the UART device if one is available, without
+    /// blocking.
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        let inner = self.inner();
+        if inner.has_byte() {
+            Some(inner.read_byte())
+        } else {
+            None
+        }
     }
 
     /// Writes the byte `byte` to the UART device.
@@ -40,12 +47,6 @@ impl Console {
     }
 }
 
-impl io::Read for Console {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.inner().read(buf)
-    }
-}
-
 impl io::Write for Console {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
        self.inner().write(buf)
@@ -65,6 +66,19 @@ impl fmt::Write for Console {
 /// Global `Console` singleton.
 pub static CONSOLE: Mutex<Console> = Mutex::new(Console::new());
 
+/// Reads a byte from the console, blocking until a byte is available.
+///
+/// `CONSOLE` masks IRQs while it is held, so it is only locked to poll the
+/// UART: the caller can be preempted, and others can print, while it waits.
+pub fn read_byte() -> u8 {
+    loop {
+        if let Some(byte) = CONSOLE.lock().try_read_byte() {
+            return byte;
+        }
+        spin_loop_hint();
+    }
+}
+
 /// Internal function called by the `kprint[ln]!` macros.
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
@@ -1,6 +1,7 @@
 extern crate gimli;
 use std::slice;
 use console::kprintln;
+use vm::KERNEL_BASE;
 
 extern "C" {
     fn __debug_info_start();
@@ -16,11 +17,11 @@ pub unsafe fn get_function_from_pc(pc: usize) -> Option<String> {
     let debug_info_start = __debug_info_start as usize;
     let debug_info_end = __debug_info_end as usize;
     let debug_info = gimli::DebugInfo::new(slice::from_raw_parts(debug_info_start as *const u8, debug_info_end - debug_info_start), endian);
-    // the positions in debug_info are absolute
-    let debug_abbrev_end = __debug_abbrev_end as usize;
-    let debug_abbrev = gimli::DebugAbbrev::new(slice::from_raw_parts(0 as *const u8, debug_abbrev_end), endian);
-    let debug_str_end = __debug_str_end as usize;
-    let debug_str = gimli::DebugStr::new(slice::from_raw_parts(0 as *const u8, debug_str_end), endian);
+    // the positions in debug_info are physical addresses, mapped at KERNEL_BASE
+    let debug_abbrev_end = __debug_abbrev_end as usize - KERNEL_BASE;
+    let debug_abbrev = gimli::DebugAbbrev::new(slice::from_raw_parts(KERNEL_BASE as *const u8, debug_abbrev_end), endian);
+    let debug_str_end = __debug_str_end as usize - KERNEL_BASE;
+    let debug_str = gimli::DebugStr::new(slice::from_raw_parts(KERNEL_BASE as *const u8, debug_str_end), endian);
     match get_function_from_pc_gimli(debug_info, debug_abbrev, debug_str, pc as u64) {
         Ok(res) => Some(res),
         Err(err) => {
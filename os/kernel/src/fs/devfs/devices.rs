@@ -0,0 +1,334 @@
+use std::io::{self, Read, SeekFrom, Write};
+use std::{cmp, str};
+
+use fat32::traits::{self, BlockDevice};
+use pi::gpio::Gpio;
+use pi::timer::current_time;
+
+use console::{self, CONSOLE};
+
+fn not_seekable() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "device is not seekable")
+}
+
+fn not_resizable() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "device cannot be truncated or extended")
+}
+
+/// `/dev/null`: reads nothing and discards every write.
+#[derive(Debug)]
+pub struct Null;
+
+impl Read for Null {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for Null {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for Null {
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        Ok(0)
+    }
+}
+
+/// `/dev/zero`: reads an endless run of zeroes and discards every write.
+#[derive(Debug)]
+pub struct Zero;
+
+impl Read for Zero {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        for byte in buf.iter_mut() {
+            *byte = 0;
+        }
+        Ok(buf.len())
+    }
+}
+
+impl Write for Zero {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for Zero {
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        Ok(0)
+    }
+}
+
+/// `/dev/console`: the mini UART behind `CONSOLE`.
+#[derive(Debug)]
+pub struct Console;
+
+impl Read for Console {
+    /// Waits for the first byte without holding `CONSOLE`, then reads the
+    /// bytes already available.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        buf[0] = console::read_byte();
+        let mut console = CONSOLE.lock();
+        let mut read = 1;
+        while read < buf.len() {
+            match console.try_read_byte() {
+                Some(byte) => buf[read] = byte,
+                None => break,
+            }
+            read += 1;
+        }
+        Ok(read)
+    }
+}
+
+impl Write for Console {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        CONSOLE.lock().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        CONSOLE.lock().flush()
+    }
+}
+
+impl io::Seek for Console {
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        Err(not_seekable())
+    }
+}
+
+/// Copies the part of `text` at `*offset` into `buf` and advances `*offset`.
+fn read_text(text: &[u8], offset: &mut u64, buf: &mut [u8]) -> usize {
+    let start = cmp::min(*offset, text.len() as u64) as usize;
+    let read = cmp::min(buf.len(), text.len() - start);
+    buf[..read].copy_from_slice(&text[start..start + read]);
+    *offset += read as u64;
+    read
+}
+
+/// `/dev/timer`: the system timer.
+///
+/// Reading from offset 0 samples the time since boot, in microseconds, as a
+/// decimal line. Seek back to 0 to sample it again.
+#[derive(Debug, Default)]
+pub struct Timer {
+    sample: Vec<u8>,
+    offset: u64,
+}
+
+impl Read for Timer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.offset == 0 {
+            self.sample = format!("{}\n", current_time()).into_bytes();
+        }
+        Ok(read_text(&self.sample, &mut self.offset, buf))
+    }
+}
+
+impl Write for Timer {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, "timer is read only"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for Timer {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match pos {
+            SeekFrom::Start(offset) => self.offset = offset,
+            _ => return Err(not_seekable()),
+        }
+        Ok(self.offset)
+    }
+}
+
+/// The GPIO pins the kernel's own devices use, which are not exposed as
+/// `GpioPin`s: 14 and 15 carry the mini UART and 48 to 53 the SD card.
+pub const RESERVED_PINS: [u8; 8] = [14, 15, 48, 49, 50, 51, 52, 53];
+
+/// `/dev/gpio/N`: GPIO pin `N`.
+///
+/// Writing `1` or `0` makes the pin an output and sets or clears it. Reading
+/// from offset 0 samples the level of the pin as `1` or `0` on a line of its
+/// own, leaving its function alone. Seek back to 0 to sample it again.
+#[derive(Debug)]
+pub struct GpioPin {
+    pin: u8,
+    sample: Vec<u8>,
+    offset: u64,
+}
+
+impl GpioPin {
+    /// Returns a handle to pin `pin`, which must be at most 53 and not one of
+    /// `RESERVED_PINS`. The pin is not touched until the handle is written to.
+    pub fn new(pin: u8) -> GpioPin {
+        GpioPin { pin, sample: Vec::new(), offset: 0 }
+    }
+}
+
+impl Read for GpioPin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.offset == 0 {
+            let level = Gpio::new(self.pin).level();
+            self.sample = if level { b"1\n".to_vec() } else { b"0\n".to_vec() };
+        }
+        Ok(read_text(&self.sample, &mut self.offset, buf))
+    }
+}
+
+impl Write for GpioPin {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match str::from_utf8(buf).map(|value| value.trim()) {
+            Ok("1") => Gpio::new(self.pin).into_output().set(),
+            Ok("0") => Gpio::new(self.pin).into_output().clear(),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "write 1 or 0 to a GPIO pin",
+                ));
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for GpioPin {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match pos {
+            SeekFrom::Start(offset) => self.offset = offset,
+            _ => return Err(not_seekable()),
+        }
+        Ok(self.offset)
+    }
+}
+
+/// A block device as a file of bytes, such as `/dev/sd`.
+///
+/// Reads and writes may start and end anywhere; a write that covers part of
+/// a sector reads the sector first. The size of the device is not known, so
+/// the file reports a size of 0 and cannot be seeked from its end.
+#[derive(Debug)]
+pub struct Block<B: BlockDevice> {
+    device: B,
+    offset: u64,
+}
+
+impl<B: BlockDevice> Block<B> {
+    pub fn new(device: B) -> Block<B> {
+        Block { device, offset: 0 }
+    }
+
+    /// Reads the sector holding `self.offset`, returning it and the offset
+    /// into it.
+    fn current_sector(&mut self) -> io::Result<(u64, Vec<u8>, usize)> {
+        let sector_size = self.device.sector_size();
+        let sector = self.offset / sector_size;
+        let mut data = Vec::new();
+        self.device.read_all_sector(sector, &mut data)?;
+        Ok((sector, data, (self.offset % sector_size) as usize))
+    }
+}
+
+impl<B: BlockDevice> Read for Block<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let (_, data, start) = self.current_sector()?;
+        let read = cmp::min(buf.len(), data.len().saturating_sub(start));
+        buf[..read].copy_from_slice(&data[start..start + read]);
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+impl<B: BlockDevice> Write for Block<B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let (sector, mut data, start) = self.current_sector()?;
+        let written = cmp::min(buf.len(), data.len().saturating_sub(start));
+        data[start..start + written].copy_from_slice(&buf[..written]);
+        self.device.write_sector(sector, &data)?;
+        self.offset += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<B: BlockDevice> io::Seek for Block<B> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let offset = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) if delta < 0 => {
+                self.offset.checked_sub(delta.wrapping_neg() as u64)
+            }
+            SeekFrom::Current(delta) => self.offset.checked_add(delta as u64),
+            SeekFrom::End(_) => return Err(not_seekable()),
+        };
+        self.offset = offset.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of the device")
+        })?;
+        Ok(self.offset)
+    }
+}
+
+/// Implements `traits::File` for a device without a size or buffering.
+macro_rules! impl_file {
+    ($($device:ty),*) => {$(
+        impl traits::File for $device {
+            fn sync(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+
+            fn size(&self) -> u64 {
+                0
+            }
+
+            fn set_len(&mut self, _size: u64) -> io::Result<()> {
+                Err(not_resizable())
+            }
+        }
+    )*};
+}
+
+impl_file!(Null, Zero, Console, Timer, GpioPin);
+
+impl<B: BlockDevice> traits::File for Block<B> {
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        0
+    }
+
+    fn set_len(&mut self, _size: u64) -> io::Result<()> {
+        Err(not_resizable())
+    }
+}
@@ -0,0 +1,239 @@
+mod devices;
+#[cfg(test)]
+mod tests;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{fmt, io};
+
+use block;
+use fat32::vfat::Shared;
+
+use fs::vfs::{names, DirEntry, DirObject, FileObject, FileSystemObject, Metadata, Object};
+
+pub use self::devices::{Block, Console, GpioPin, Null, Timer, Zero, RESERVED_PINS};
+
+/// Opens a new handle to a device.
+type Open = Box<Fn() -> Box<FileObject> + Send>;
+
+enum Node {
+    Device(Open),
+    Dir(BTreeMap<String, Node>),
+}
+
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Node::Device(_) => write!(f, "Device"),
+            Node::Dir(ref children) => f.debug_map().entries(children.iter()).finish(),
+        }
+    }
+}
+
+fn read_only() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "device file system is read only")
+}
+
+/// Inserts a device opened by `open` into `dir` at the path made of `names`,
+/// creating missing directories.
+fn insert(dir: &mut BTreeMap<String, Node>, names: &[String], open: Open) -> io::Result<()> {
+    let (name, rest) = names.split_first().expect("path is not the root");
+    if rest.is_empty() {
+        if dir.contains_key(name) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "device already exists"));
+        }
+        dir.insert(name.clone(), Node::Device(open));
+        return Ok(());
+    }
+
+    match *dir.entry(name.clone()).or_insert_with(|| Node::Dir(BTreeMap::new())) {
+        Node::Dir(ref mut children) => insert(children, rest, open),
+        Node::Device(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "path component is a device",
+        )),
+    }
+}
+
+/// Returns the entry for `node`, named `name` and found at `path`.
+fn dir_entry(fs: &DevFs, path: Vec<String>, name: String, node: &Node) -> DirEntry {
+    match *node {
+        Node::Device(ref open) => DirEntry {
+            name,
+            metadata: Metadata { system: true, ..Metadata::default() },
+            object: Object::File(open()),
+        },
+        Node::Dir(_) => DirEntry {
+            name,
+            metadata: Metadata::default(),
+            object: Object::Dir(Box::new(DevDir { fs: fs.clone(), path })),
+        },
+    }
+}
+
+/// A file system of device nodes, usually mounted at `/dev`.
+///
+/// Opening a node returns a fresh handle to its device; handles must do
+/// nothing to the hardware until they are read from or written to, since
+/// listing a directory opens every node in it. The tree is fixed once built:
+/// files cannot be created, renamed or removed through the file system.
+#[derive(Debug, Clone)]
+pub struct DevFs {
+    root: Shared<BTreeMap<String, Node>>,
+}
+
+impl DevFs {
+    /// Returns a `DevFs` with no devices.
+    pub fn new() -> DevFs {
+        DevFs { root: Shared::new(BTreeMap::new()) }
+    }
+
+    /// Returns a `DevFs` with the devices of the Raspberry Pi:
+    ///
+    ///   * `/console`: the mini UART console.
+    ///   * `/null` and `/zero`.
+    ///   * `/timer`: the system timer. See `Timer`.
+    ///   * `/gpio/0` to `/gpio/53`: the GPIO pins, except `RESERVED_PINS`. See
+    ///     `GpioPin`.
+    ///   * `/NAME` for each device of the block layer in `disks`, such as
+    ///     `/sd` and `/sd1`, as a file of bytes. They are read only while a
+    ///     file system on their disk is in use: see `Device::claim()`.
+    pub fn pi(disks: &[block::Device]) -> DevFs {
+        let fs = DevFs::new();
+        fs.add("/console", || Console).unwrap();
+        fs.add("/null", || Null).unwrap();
+        fs.add("/zero", || Zero).unwrap();
+        fs.add("/timer", Timer::default).unwrap();
+        for pin in (0..54).filter(|pin| !RESERVED_PINS.contains(pin)) {
+            fs.add(format!("/gpio/{}", pin), move || GpioPin::new(pin)).unwrap();
+        }
+        for disk in disks {
+            let disk = disk.clone();
+            fs.add(format!("/{}", disk.name()), move || Block::new(disk.clone())).unwrap();
+        }
+        fs
+    }
+
+    /// Adds a device at the absolute path `path` whose handles are opened by
+    /// calling `open`. Missing parent directories are created.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `AlreadyExists` if there is already a node at
+    /// `path`, and of `InvalidInput` if `path` is the root or a component of
+    /// it is a device.
+    pub fn add<P, F, D>(&self, path: P, open: F) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        F: Fn() -> D + Send + 'static,
+        D: FileObject + 'static,
+    {
+        let names = names(path.as_ref())?;
+        if names.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is the root"));
+        }
+        let open: Open = Box::new(move || -> Box<FileObject> { Box::new(open()) });
+        insert(&mut self.root.borrow_mut(), &names, open)
+    }
+
+    /// Calls `f` with the node at the path made of `names`, which must not be
+    /// empty.
+    fn with_node<T, F>(&self, names: &[String], f: F) -> io::Result<T>
+    where
+        F: FnOnce(&Node) -> T,
+    {
+        let root = self.root.borrow();
+        let (name, parents) = match names.split_last() {
+            Some(split) => split,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is the root")),
+        };
+        let mut dir = &*root;
+        for parent in parents {
+            dir = match dir.get(parent) {
+                Some(&Node::Dir(ref children)) => children,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "path component is not a directory",
+                    ));
+                }
+            };
+        }
+        match dir.get(name) {
+            Some(node) => Ok(f(node)),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "no such device")),
+        }
+    }
+}
+
+/// A directory of a `DevFs`.
+struct DevDir {
+    fs: DevFs,
+    path: Vec<String>,
+}
+
+impl DirObject for DevDir {
+    fn entries(&self) -> io::Result<Box<Iterator<Item = DirEntry>>> {
+        let entries = {
+            let root = self.fs.root.borrow();
+            let mut dir = &*root;
+            for name in &self.path {
+                dir = match dir.get(name) {
+                    Some(&Node::Dir(ref children)) => children,
+                    _ => return Err(io::Error::new(io::ErrorKind::NotFound, "no such directory")),
+                };
+            }
+            dir.iter()
+                .map(|(name, node)| {
+                    let mut path = self.path.clone();
+                    path.push(name.clone());
+                    dir_entry(&self.fs, path, name.clone(), node)
+                })
+                .collect::<Vec<_>>()
+        };
+        Ok(Box::new(entries.into_iter()))
+    }
+}
+
+impl FileSystemObject for DevFs {
+    fn open(&self, path: &Path) -> io::Result<DirEntry> {
+        let names = names(path)?;
+        if names.is_empty() {
+            let root = DevDir { fs: self.clone(), path: names };
+            return Ok(DirEntry {
+                name: "/".to_string(),
+                metadata: Metadata::default(),
+                object: Object::Dir(Box::new(root)),
+            });
+        }
+
+        let name = names.last().unwrap().clone();
+        self.with_node(&names, |node| dir_entry(self, names.clone(), name, node))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let names = names(path)?;
+        if !names.is_empty() {
+            self.with_node(&names, |_| ())?;
+        }
+        let mut canonical = PathBuf::from("/");
+        canonical.extend(&names);
+        Ok(canonical)
+    }
+
+    fn create_file(&self, _path: &Path) -> io::Result<Box<FileObject>> {
+        Err(read_only())
+    }
+
+    fn create_dir(&self, _path: &Path, _parents: bool) -> io::Result<Box<DirObject>> {
+        Err(read_only())
+    }
+
+    fn rename(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    fn remove(&self, _path: &Path, _children: bool) -> io::Result<()> {
+        Err(read_only())
+    }
+}
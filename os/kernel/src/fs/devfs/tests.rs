@@ -0,0 +1,84 @@
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+use fs::devfs::{Block, DevFs, Null, Zero, RESERVED_PINS};
+use fs::ramfs::RamFs;
+use fs::traits::{Dir, Entry, File, FileSystem, Metadata};
+use fs::vfs::Vfs;
+
+fn kind<T>(result: io::Result<T>) -> io::ErrorKind {
+    result.err().expect("expected an error").kind()
+}
+
+fn dev() -> Vfs {
+    let devfs = DevFs::new();
+    devfs.add("/null", || Null).unwrap();
+    devfs.add("/zero", || Zero).unwrap();
+    devfs.add("/disk/0", || Block::new(Cursor::new(vec![7u8; 1024]))).unwrap();
+    assert_eq!(kind(devfs.add("/null", || Null)), io::ErrorKind::AlreadyExists);
+    assert_eq!(kind(devfs.add("/null/x", || Null)), io::ErrorKind::InvalidInput);
+
+    let vfs = Vfs::new();
+    vfs.mount("/", RamFs::new()).unwrap();
+    vfs.mount("/dev", devfs).unwrap();
+    vfs
+}
+
+#[test]
+fn test_devfs_tree() {
+    let vfs = dev();
+    let names: Vec<String> = vfs.open_dir("/dev").unwrap().entries().unwrap()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, vec!["disk", "null", "zero"]);
+
+    let null = vfs.open("/dev/null").unwrap();
+    assert!(null.is_file() && null.metadata().system());
+    assert!(vfs.open("/dev/disk").unwrap().is_dir());
+    assert_eq!(kind(vfs.open("/dev/missing")), io::ErrorKind::NotFound);
+    assert_eq!(kind(vfs.open("/dev/null/x")), io::ErrorKind::InvalidInput);
+    assert_eq!(kind(vfs.create_file("/dev/new")), io::ErrorKind::PermissionDenied);
+    assert_eq!(kind(vfs.remove("/dev/null", false)), io::ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn test_reserved_gpio_pins() {
+    let vfs = Vfs::new();
+    vfs.mount("/", RamFs::new()).unwrap();
+    vfs.mount("/dev", DevFs::pi(&[])).unwrap();
+    let pins: Vec<u8> = vfs.open_dir("/dev/gpio").unwrap().entries().unwrap()
+        .map(|entry| entry.name().parse().unwrap())
+        .collect();
+    assert_eq!(pins.len(), 54 - RESERVED_PINS.len());
+    assert!(pins.contains(&16) && pins.contains(&47));
+    assert!(RESERVED_PINS.iter().all(|pin| !pins.contains(pin)));
+    assert_eq!(kind(vfs.open("/dev/gpio/14")), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn test_null_zero() {
+    let vfs = dev();
+    let mut buf = [1u8; 16];
+    let mut null = vfs.open_file("/dev/null").unwrap();
+    assert_eq!(null.read(&mut buf).unwrap(), 0);
+    assert_eq!(null.write(b"gone").unwrap(), 4);
+
+    assert_eq!(kind(null.set_len(0)), io::ErrorKind::InvalidInput);
+
+    let mut zero = vfs.open_file("/dev/zero").unwrap();
+    zero.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [0u8; 16]);
+}
+
+#[test]
+fn test_block_device() {
+    let vfs = dev();
+    let mut disk = vfs.open_file("/dev/disk/0").unwrap();
+    disk.seek(SeekFrom::Start(510)).unwrap();
+    disk.write_all(b"span").unwrap();
+
+    let mut data = [0u8; 8];
+    disk.seek(SeekFrom::Start(508)).unwrap();
+    disk.read_exact(&mut data).unwrap();
+    assert_eq!(&data, b"\x07\x07span\x07\x07");
+    assert_eq!(kind(disk.seek(SeekFrom::End(0))), io::ErrorKind::InvalidInput);
+}
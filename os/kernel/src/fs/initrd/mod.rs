@@ -0,0 +1,86 @@
+#[cfg(test)]
+mod tests;
+
+use std::{cmp, io, ptr};
+
+use fat32::traits::BlockDevice;
+use pi::atags::Atags;
+use vm::PhysicalAddr;
+
+/// The size of a sector of an `Initrd`, in bytes.
+const SECTOR_SIZE: usize = 512;
+
+/// An initial RAM disk: a region of memory, usually loaded next to the
+/// kernel and described by an `INITRD2` ATAG, as a device of 512-byte
+/// sectors.
+///
+/// It is registered with the block layer like the SD card, so its image may
+/// hold a partition table or a FAT file system directly. Every clone of an
+/// `Initrd` reads and writes the same memory.
+#[derive(Debug, Clone)]
+pub struct Initrd {
+    start: usize,
+    size: usize,
+}
+
+impl Initrd {
+    /// Returns the initial RAM disk described by the ATAGs, if there is one.
+    /// The allocator never hands out its memory.
+    pub fn get() -> Option<Initrd> {
+        let initrd = Atags::get().filter_map(|tag| tag.initrd()).next()?;
+        let start = PhysicalAddr::from(initrd.start as usize).as_mut_ptr() as usize;
+        Some(unsafe { Initrd::new(start, initrd.size as usize) })
+    }
+
+    /// Returns a RAM disk over the `size` bytes of memory at address `start`.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be valid for reads and writes for as long as the RAM
+    /// disk or any clone of it is used, and must not be used for anything
+    /// else meanwhile.
+    pub unsafe fn new(start: usize, size: usize) -> Initrd {
+        Initrd { start, size }
+    }
+
+    /// Returns the size of the RAM disk in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the address of the part of sector `n` inside the RAM disk and
+    /// its length, which is at most `SECTOR_SIZE` and `max`.
+    fn sector(&self, n: u64, max: usize) -> io::Result<(*mut u8, usize)> {
+        let offset = match n.checked_mul(SECTOR_SIZE as u64) {
+            Some(offset) if offset < self.size as u64 => offset as usize,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "sector is past the end of the RAM disk",
+                ));
+            }
+        };
+        let len = cmp::min(cmp::min(SECTOR_SIZE, max), self.size - offset);
+        Ok(((self.start + offset) as *mut u8, len))
+    }
+}
+
+impl BlockDevice for Initrd {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let (sector, len) = self.sector(n, buf.len())?;
+        unsafe { ptr::copy_nonoverlapping(sector, buf.as_mut_ptr(), len) };
+        Ok(len)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() < SECTOR_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "buffer is smaller than a sector",
+            ));
+        }
+        let (sector, len) = self.sector(n, buf.len())?;
+        unsafe { ptr::copy_nonoverlapping(buf.as_ptr(), sector, len) };
+        Ok(len)
+    }
+}
@@ -0,0 +1,29 @@
+use std::io;
+
+use fat32::traits::BlockDevice;
+use fs::initrd::Initrd;
+
+#[test]
+fn test_initrd_sectors() {
+    let mut memory: Vec<u8> = (0..1200).map(|i| i as u8).collect();
+    let mut initrd = unsafe { Initrd::new(memory.as_mut_ptr() as usize, memory.len()) };
+    assert_eq!(initrd.size(), 1200);
+
+    let mut sector = [0; 512];
+    assert_eq!(initrd.read_sector(1, &mut sector).unwrap(), 512);
+    assert_eq!(sector[0], (512 % 256) as u8);
+    assert_eq!(initrd.read_sector(2, &mut sector).unwrap(), 176);
+    assert_eq!(sector[175], (1199 % 256) as u8);
+    let error = initrd.read_sector(3, &mut sector).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+    assert_eq!(initrd.clone().write_sector(0, &[0xAB; 512]).unwrap(), 512);
+    assert_eq!(initrd.write_sector(2, &[0xCD; 512]).unwrap(), 176);
+    let error = initrd.write_sector(0, &[0; 100]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    drop(initrd);
+
+    assert!(memory[..512].iter().all(|&byte| byte == 0xAB));
+    assert_eq!(memory[512], 0);
+    assert!(memory[1024..].iter().all(|&byte| byte == 0xCD));
+}
@@ -1,16 +1,35 @@
+pub mod devfs;
+pub mod initrd;
+pub mod procfs;
+pub mod ramfs;
 pub mod sd;
+pub mod vfs;
 
 use std::io;
 use std::path::{Path, PathBuf};
 
+use fat32::traits::BlockDevice;
 use fat32::vfat::{Shared, VFat};
+use pi::atags::Atags;
 pub use fat32::traits;
 
+use console::kprintln;
 use mutex::Mutex;
+use BLOCK;
+use self::devfs::DevFs;
+use self::initrd::Initrd;
+use self::procfs::ProcFs;
+use self::ramfs::RamFs;
 use self::sd::Sd;
+use self::vfs::{FileSystemObject, Vfs};
 
+/// The kernel's file system: a `Vfs` holding every mounted file system.
+///
+/// The lock is only held to get the `Vfs`, never while a file system is used:
+/// file systems may wait for the disk, blocking the process using them, which
+/// must not hold a `Mutex` in the meantime.
 #[derive(Debug)]
-pub struct FileSystem(Mutex<Option<Shared<VFat>>>);
+pub struct FileSystem(Mutex<Option<Vfs>>);
 
 impl FileSystem {
     /// Returns an uninitialized `FileSystem`.
@@ -21,39 +40,107 @@ impl FileSystem {
         FileSystem(Mutex::new(None))
     }
 
-    /// Initializes the file system.
+    /// Initializes the file system, registering the SD card with the block
+    /// layer and mounting its first FAT partition at `/`, a `RamFs` at `/tmp`,
+    /// the block and other devices at `/dev` and the kernel status files at
+    /// `/proc`.
+    ///
+    /// An initial RAM disk holding a FAT file system is mounted at `/initrd`,
+    /// or at `/` if the kernel command line has `root=initrd` or the SD card
+    /// is unusable. Without either, a `RamFs` is mounted at `/`.
     ///
     /// # Panics
     ///
-    /// Panics if the underlying disk or file sytem failed to initialize.
+    /// Panics if the file systems could not be mounted.
     pub fn initialize(&self) {
-        *self.0.lock() = Some(VFat::from(Sd::new().unwrap()).unwrap());
+        let vfs = Vfs::new();
+        let mut root = Sd::new()
+            .map_err(|e| format!("failed to initialize SD card: {:?}", e))
+            .and_then(|sd| register("sd", sd))
+            .and_then(|()| fat_file_system("sd"));
+        let mut initrd = Initrd::get().map(|initrd| {
+            register("initrd", initrd).and_then(|()| fat_file_system("initrd"))
+        });
+
+        let initrd_usable = match initrd {
+            Some(Ok(_)) => true,
+            _ => false,
+        };
+        if initrd_usable && (root.is_err() || boot_option("root=initrd")) {
+            if let Err(ref err) = root {
+                kprintln!("{}; using the initial RAM disk for /", err);
+            }
+            root = initrd.take().unwrap();
+        }
+
+        match root {
+            Ok(vfat) => vfs.mount("/", vfat),
+            Err(err) => {
+                kprintln!("{}; using a RAM file system for /", err);
+                vfs.mount("/", RamFs::new())
+            }
+        }.unwrap_or_else(|e| panic!("failed to mount /: {}", e));
+        match initrd {
+            Some(Ok(vfat)) => vfs.mount("/initrd", vfat)
+                .unwrap_or_else(|e| panic!("failed to mount /initrd: {}", e)),
+            Some(Err(err)) => kprintln!("{}", err),
+            None => {}
+        }
+        vfs.mount("/tmp", RamFs::new()).unwrap_or_else(|e| panic!("failed to mount /tmp: {}", e));
+        vfs.mount("/dev", DevFs::pi(&BLOCK.devices()))
+            .unwrap_or_else(|e| panic!("failed to mount /dev: {}", e));
+        vfs.mount("/proc", ProcFs::kernel())
+            .unwrap_or_else(|e| panic!("failed to mount /proc: {}", e));
+        *self.0.lock() = Some(vfs);
+    }
+
+    /// Returns the `Vfs`, which shares its mount table with every clone.
+    fn vfs(&self) -> Vfs {
+        self.0.lock().as_ref().expect("file system uninitialized").clone()
+    }
+
+    /// Mounts `fs` at `path`. See `Vfs::mount()`.
+    pub fn mount<P, F>(&self, path: P, fs: F) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        F: FileSystemObject + 'static,
+    {
+        self.vfs().mount(path, fs)
+    }
+
+    /// Unmounts the file system at `path`. See `Vfs::umount()`.
+    pub fn umount<P: AsRef<Path>>(&self, path: P) -> io::Result<Box<FileSystemObject>> {
+        self.vfs().umount(path)
+    }
+
+    /// Returns the paths file systems are mounted at.
+    pub fn mount_points(&self) -> Vec<PathBuf> {
+        self.vfs().mount_points()
     }
 }
 
-// FIXME: Implement `fat32::traits::FileSystem` for a useful type.
 impl<'a> traits::FileSystem for &'a FileSystem {
-    type File = <&'a Shared<VFat> as traits::FileSystem>::File;
-    type Dir = <&'a Shared<VFat> as traits::FileSystem>::Dir;
-    type Entry = <&'a Shared<VFat> as traits::FileSystem>::Entry;
+    type File = vfs::File;
+    type Dir = vfs::Dir;
+    type Entry = vfs::Entry;
 
     fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
-        self.0.lock().as_ref().unwrap().open(path)
+        self.vfs().open(path)
     }
 
     fn canonicalize<P: AsRef<Path>>(self, path: P) -> io::Result<PathBuf> {
-        self.0.lock().as_ref().unwrap().canonicalize(path)
+        self.vfs().canonicalize(path)
     }
 
     fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
-        self.0.lock().as_ref().unwrap().create_file(path)
+        self.vfs().create_file(path)
     }
 
     fn create_dir<P>(self, path: P, parents: bool) -> io::Result<Self::Dir>
     where
         P: AsRef<Path>,
     {
-        self.0.lock().as_ref().unwrap().create_dir(path, parents)
+        self.vfs().create_dir(path, parents)
     }
 
     fn rename<P, Q>(self, from: P, to: Q) -> io::Result<()>
@@ -61,10 +148,38 @@ impl<'a> traits::FileSystem for &'a FileSystem {
         P: AsRef<Path>,
         Q: AsRef<Path>,
     {
-        self.0.lock().as_ref().unwrap().rename(from, to)
+        self.vfs().rename(from, to)
     }
 
     fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()> {
-        self.0.lock().as_ref().unwrap().remove(path, children)
+        self.vfs().remove(path, children)
     }
 }
+
+/// Registers `device` with the block layer as the disk `name`.
+fn register<B: BlockDevice + Send + 'static>(name: &str, device: B) -> Result<(), String> {
+    BLOCK.register(name, device)
+        .map(|_| ())
+        .map_err(|e| format!("failed to register {}: {}", name, e))
+}
+
+/// Returns the file system on the first FAT partition of the disk `name`, or
+/// on the whole disk if it has no partitions. The file system claims the
+/// disk, so its raw devices are read only while it is in use.
+fn fat_file_system(name: &str) -> Result<Shared<VFat>, String> {
+    let partitions = BLOCK.partitions(name);
+    let device = if partitions.is_empty() {
+        BLOCK.get(name)
+    } else {
+        partitions.into_iter().find(|p| p.partition().map_or(false, |p| p.kind.is_fat()))
+    };
+    let device = device.ok_or_else(|| format!("no FAT partition on {}", name))?;
+    VFat::from_partition(device.claim()).map_err(|e| format!("bad file system on {}: {}", name, e))
+}
+
+/// Returns whether `option` is one of the words of the kernel command line.
+fn boot_option(option: &str) -> bool {
+    Atags::get()
+        .filter_map(|tag| tag.cmd())
+        .any(|cmd| cmd.split_whitespace().any(|word| word == option))
+}
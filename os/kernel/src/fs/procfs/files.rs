@@ -0,0 +1,130 @@
+use std::fmt::Write;
+
+use pi::atags::Atags;
+use pi::timer::current_time;
+
+use process::{Process, State};
+use traps::{interrupt_counts, local_interrupt_counts};
+#[cfg(not(test))]
+use ALLOCATOR;
+use FILE_SYSTEM;
+use FRAMES;
+use vm::PAGE_SIZE;
+
+/// `/meminfo`: the bytes allocated, managed and free in the kernel heap, and
+/// the physical frames used, managed and free.
+pub fn meminfo() -> String {
+    #[cfg(not(test))]
+    let usage = ALLOCATOR.usage();
+    #[cfg(test)]
+    let usage: Option<(usize, usize)> = None;
+
+    let mut contents = match usage {
+        Some((allocated, total)) => format!(
+            "allocated: {}\ntotal: {}\nfree: {}\n",
+            allocated,
+            total,
+            total - allocated
+        ),
+        None => String::new(),
+    };
+    if let Some((free, total)) = FRAMES.usage() {
+        writeln!(contents, "frames used: {}\nframes total: {}\nframes free: {}\nframe size: {}",
+                 total - free, total, free, PAGE_SIZE).unwrap();
+    }
+    contents
+}
+
+/// `/uptime`: the time since boot in seconds.
+pub fn uptime() -> String {
+    let time = current_time();
+    format!("uptime: {}.{:06}\n", time / 1000 / 1000, time % (1000 * 1000))
+}
+
+/// `/atags`: the ATAGs passed by the firmware, one per line.
+pub fn atags() -> String {
+    let mut contents = String::new();
+    for atag in Atags::get() {
+        writeln!(contents, "{:?}", atag).unwrap();
+    }
+    contents
+}
+
+/// `/interrupts`: the number of times each interrupt has been handled, and
+/// each local interrupt by each core.
+pub fn interrupts() -> String {
+    let mut contents = String::new();
+    for (interrupt, count) in interrupt_counts() {
+        writeln!(contents, "{:?}: {}", interrupt, count).unwrap();
+    }
+    for (core, interrupt, count) in local_interrupt_counts() {
+        writeln!(contents, "{:?} (core {}): {}", interrupt, core, count).unwrap();
+    }
+    contents
+}
+
+/// `/mounts`: the paths file systems are mounted at, one per line.
+pub fn mounts() -> String {
+    let mut contents = String::new();
+    for path in FILE_SYSTEM.mount_points() {
+        writeln!(contents, "{}", path.display()).unwrap();
+    }
+    contents
+}
+
+/// `/<pid>/status`: the ID, parent ID (0 if none), scheduling state, nice
+/// value, CPU time used, core and core affinity of the process, and why it
+/// exited if it is a zombie.
+pub fn status(process: &Process) -> String {
+    let state = match process.state {
+        State::Ready => "ready",
+        State::Running => "running",
+        State::Blocked => "blocked",
+        State::Sleeping { .. } => "sleeping",
+        State::WaitingForChild(_) => "waiting",
+        State::Zombie(_) => "zombie",
+    };
+    let parent = process.parent.unwrap_or(0);
+    let mut contents = format!("pid: {}\nppid: {}\nstate: {}\n", process.trap_frame.tpidr, parent, state);
+    let sched = &process.sched;
+    writeln!(contents, "nice: {}\nruntime: {} us\nslices: {}", sched.nice, sched.runtime, sched.slices).unwrap();
+    match sched.affinity {
+        Some(affinity) => writeln!(contents, "core: {}\naffinity: {}", sched.core, affinity).unwrap(),
+        None => writeln!(contents, "core: {}\naffinity: any", sched.core).unwrap(),
+    }
+    if let State::Zombie(exit) = process.state {
+        writeln!(contents, "exit: {:?}", exit).unwrap();
+    }
+    contents
+}
+
+/// `/<pid>/stack`: the bounds of the process's stack and its saved stack
+/// pointer.
+pub fn stack(process: &Process) -> String {
+    let (bottom, top) = process.stack_bounds();
+    format!(
+        "top: {:#x}\nbottom: {:#x}\nsize: {}\nsp: {:#x}\n",
+        top,
+        bottom,
+        ::process::Stack::SIZE,
+        process.trap_frame.sp
+    )
+}
+
+/// `/<pid>/trapframe`: the registers saved when the process was last
+/// switched out.
+pub fn trap_frame(process: &Process) -> String {
+    let tf = &process.trap_frame;
+    let mut contents = format!(
+        "elr: {:#018x}\nspsr: {:#018x}\nsp: {:#018x}\ntpidr: {:#018x}\nx0: {:#018x}\n",
+        tf.elr, tf.spsr, tf.sp, tf.tpidr, tf.x0
+    );
+    for (i, x) in tf.x1to29.iter().enumerate() {
+        writeln!(contents, "x{}: {:#018x}", i + 1, x).unwrap();
+    }
+    writeln!(contents, "x30: {:#018x}", tf.x30).unwrap();
+    for (i, q) in tf.q0to31.iter().enumerate() {
+        writeln!(contents, "q{}: {:#034x}", i, q).unwrap();
+    }
+    contents
+}
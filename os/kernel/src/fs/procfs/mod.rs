@@ -0,0 +1,269 @@
+mod files;
+#[cfg(test)]
+mod tests;
+
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::{cmp, fmt};
+
+use fs::traits;
+use fs::vfs::{names, DirEntry, DirObject, FileObject, FileSystemObject, Metadata, Object};
+use process::{Id, Process};
+use SCHEDULER;
+
+/// Where the contents of a file come from.
+#[derive(Copy, Clone)]
+enum Source {
+    Kernel(fn() -> String),
+    Process(Id, fn(&Process) -> String),
+}
+
+impl Source {
+    fn generate(&self) -> io::Result<String> {
+        match *self {
+            Source::Kernel(generate) => Ok(generate()),
+            Source::Process(id, generate) => SCHEDULER
+                .with_process(id, generate)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "process has exited")),
+        }
+    }
+}
+
+/// The files in each `/<pid>` directory.
+const PROCESS_FILES: [(&str, fn(&Process) -> String); 3] = [
+    ("status", files::status),
+    ("stack", files::stack),
+    ("trapframe", files::trap_frame),
+];
+
+fn read_only() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "process file system is read only")
+}
+
+fn file_entry(name: &str, source: Source) -> DirEntry {
+    DirEntry {
+        name: name.to_string(),
+        metadata: Metadata { read_only: true, ..Metadata::default() },
+        object: Object::File(Box::new(File { source, contents: None, offset: 0 })),
+    }
+}
+
+fn dir_entry(name: String, dir: Box<DirObject>) -> DirEntry {
+    DirEntry {
+        name,
+        metadata: Metadata { read_only: true, ..Metadata::default() },
+        object: Object::Dir(dir),
+    }
+}
+
+/// A file system of kernel and process status files, usually mounted at
+/// `/proc`.
+///
+/// Every file is generated when it is first read through a handle and is
+/// regenerated when the handle seeks back to the start. Each line of a file
+/// is a `key: value` pair, except in `atags` and `mounts`, which list one item
+/// per line. Each process has a directory named after its ID with the files
+/// `status`, `stack` and `trapframe`.
+#[derive(Clone)]
+pub struct ProcFs {
+    files: Vec<(&'static str, fn() -> String)>,
+}
+
+impl ProcFs {
+    /// Returns a `ProcFs` with only the process directories.
+    pub fn new() -> ProcFs {
+        ProcFs { files: Vec::new() }
+    }
+
+    /// Returns a `ProcFs` with the process directories and the files
+    /// `meminfo`, `uptime`, `atags`, `interrupts` and `mounts`.
+    pub fn kernel() -> ProcFs {
+        let mut fs = ProcFs::new();
+        fs.add("meminfo", files::meminfo);
+        fs.add("uptime", files::uptime);
+        fs.add("atags", files::atags);
+        fs.add("interrupts", files::interrupts);
+        fs.add("mounts", files::mounts);
+        fs
+    }
+
+    /// Adds the file `/name`, whose contents are generated by `generate`.
+    pub fn add(&mut self, name: &'static str, generate: fn() -> String) {
+        self.files.push((name, generate));
+    }
+
+    /// Returns the ID of the process whose directory is named `name`, if it
+    /// exists.
+    fn process(name: &str) -> Option<Id> {
+        let id = name.parse().ok()?;
+        SCHEDULER.with_process(id, |_| id)
+    }
+}
+
+impl fmt::Debug for ProcFs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.files.iter().map(|&(name, _)| name)).finish()
+    }
+}
+
+struct RootDir(ProcFs);
+
+impl DirObject for RootDir {
+    fn entries(&self) -> io::Result<Box<Iterator<Item = DirEntry>>> {
+        let mut entries: Vec<DirEntry> = self.0.files
+            .iter()
+            .map(|&(name, generate)| file_entry(name, Source::Kernel(generate)))
+            .collect();
+        for id in SCHEDULER.ids() {
+            entries.push(dir_entry(id.to_string(), Box::new(ProcessDir(id))));
+        }
+        Ok(Box::new(entries.into_iter()))
+    }
+}
+
+struct ProcessDir(Id);
+
+impl DirObject for ProcessDir {
+    fn entries(&self) -> io::Result<Box<Iterator<Item = DirEntry>>> {
+        let id = self.0;
+        let entries: Vec<DirEntry> = PROCESS_FILES
+            .iter()
+            .map(|&(name, generate)| file_entry(name, Source::Process(id, generate)))
+            .collect();
+        Ok(Box::new(entries.into_iter()))
+    }
+}
+
+impl FileSystemObject for ProcFs {
+    fn open(&self, path: &Path) -> io::Result<DirEntry> {
+        let names = names(path)?;
+        let not_found = || io::Error::new(io::ErrorKind::NotFound, "entry not found");
+        match names.len() {
+            0 => Ok(dir_entry("/".to_string(), Box::new(RootDir(self.clone())))),
+            1 => {
+                let name = &names[0];
+                if let Some(&(name, generate)) = self.files.iter().find(|f| f.0 == name) {
+                    return Ok(file_entry(name, Source::Kernel(generate)));
+                }
+                let id = ProcFs::process(name).ok_or_else(not_found)?;
+                Ok(dir_entry(name.clone(), Box::new(ProcessDir(id))))
+            }
+            2 => {
+                let id = ProcFs::process(&names[0]).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "path component does not exist")
+                })?;
+                let &(name, generate) = PROCESS_FILES
+                    .iter()
+                    .find(|f| f.0 == names[1])
+                    .ok_or_else(not_found)?;
+                Ok(file_entry(name, Source::Process(id, generate)))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "path component is not a directory",
+            )),
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.open(path)?;
+        Ok(path.to_path_buf())
+    }
+
+    fn create_file(&self, _path: &Path) -> io::Result<Box<FileObject>> {
+        Err(read_only())
+    }
+
+    fn create_dir(&self, _path: &Path, _parents: bool) -> io::Result<Box<DirObject>> {
+        Err(read_only())
+    }
+
+    fn rename(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    fn remove(&self, _path: &Path, _children: bool) -> io::Result<()> {
+        Err(read_only())
+    }
+}
+
+/// An open file of a `ProcFs`.
+struct File {
+    source: Source,
+    contents: Option<Vec<u8>>,
+    offset: u64,
+}
+
+impl File {
+    /// Returns the contents of the file, generating them if needed.
+    fn contents(&mut self) -> io::Result<&[u8]> {
+        if self.contents.is_none() {
+            self.contents = Some(self.source.generate()?.into_bytes());
+        }
+        Ok(self.contents.as_ref().unwrap())
+    }
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let offset = self.offset;
+        let read = {
+            let contents = self.contents()?;
+            let start = cmp::min(offset, contents.len() as u64) as usize;
+            let read = cmp::min(buf.len(), contents.len() - start);
+            buf[..read].copy_from_slice(&contents[start..start + read]);
+            read
+        };
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+impl io::Write for File {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(read_only())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(0) => {
+                self.contents = None;
+                (0, 0)
+            }
+            SeekFrom::Start(offset) => (offset, 0),
+            SeekFrom::Current(delta) => (self.offset, delta),
+            SeekFrom::End(delta) => (self.contents()?.len() as u64, delta),
+        };
+        let offset = if delta < 0 {
+            base.checked_sub(delta.wrapping_neg() as u64)
+        } else {
+            base.checked_add(delta as u64)
+        };
+        self.offset = offset.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of the file")
+        })?;
+        Ok(self.offset)
+    }
+}
+
+impl traits::File for File {
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Returns the size of the contents generated so far, or 0 if the file
+    /// has not been read yet.
+    fn size(&self) -> u64 {
+        self.contents.as_ref().map_or(0, |contents| contents.len() as u64)
+    }
+
+    fn set_len(&mut self, _size: u64) -> io::Result<()> {
+        Err(read_only())
+    }
+}
@@ -0,0 +1,54 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use fs::procfs::ProcFs;
+use fs::ramfs::RamFs;
+use fs::traits::{Dir, Entry, File, FileSystem, Metadata};
+use fs::vfs::Vfs;
+
+fn kind<T>(result: io::Result<T>) -> io::ErrorKind {
+    result.err().expect("expected an error").kind()
+}
+
+fn hello() -> String {
+    "greeting: hello\n".to_string()
+}
+
+fn proc() -> Vfs {
+    let mut procfs = ProcFs::new();
+    procfs.add("hello", hello);
+    let vfs = Vfs::new();
+    vfs.mount("/", RamFs::new()).unwrap();
+    vfs.mount("/proc", procfs).unwrap();
+    vfs
+}
+
+#[test]
+fn test_procfs() {
+    let vfs = proc();
+    let names: Vec<String> = vfs.open_dir("/proc").unwrap().entries().unwrap()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, vec!["hello"]);
+    assert!(vfs.open("/proc/hello").unwrap().metadata().read_only());
+
+    let mut file = vfs.open_file("/proc/hello").unwrap();
+    assert_eq!(kind(file.set_len(0)), io::ErrorKind::PermissionDenied);
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "greeting: hello\n");
+    assert_eq!(file.seek(SeekFrom::End(-6)).unwrap(), 10);
+    contents.clear();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "hello\n");
+    assert_eq!(kind(file.write(b"x")), io::ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn test_procfs_errors() {
+    let vfs = proc();
+    assert_eq!(kind(vfs.open("/proc/missing")), io::ErrorKind::NotFound);
+    assert_eq!(kind(vfs.open("/proc/1")), io::ErrorKind::NotFound);
+    assert_eq!(kind(vfs.open("/proc/1/status")), io::ErrorKind::InvalidInput);
+    assert_eq!(kind(vfs.create_file("/proc/new")), io::ErrorKind::PermissionDenied);
+    assert_eq!(kind(vfs.remove("/proc/hello", false)), io::ErrorKind::PermissionDenied);
+}
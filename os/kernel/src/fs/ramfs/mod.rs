@@ -0,0 +1,456 @@
+#[cfg(test)]
+mod tests;
+
+use std::collections::BTreeMap;
+use std::io::{self, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::{cmp, fmt, vec};
+
+use fat32::vfat::Shared;
+
+use fs::traits;
+use fs::vfs::Metadata;
+
+/// The contents of a node.
+enum Kind {
+    File(Vec<u8>),
+    Dir(BTreeMap<String, NodeRef>),
+}
+
+/// A file or directory.
+///
+/// A node lives for as long as it is linked into a directory or referenced by
+/// an open handle, so a removed file stays readable through handles that were
+/// open when it was removed.
+struct Node {
+    metadata: Metadata,
+    kind: Kind,
+}
+
+type NodeRef = Shared<Node>;
+
+impl Node {
+    fn new(kind: Kind) -> NodeRef {
+        Shared::new(Node { metadata: Metadata::default(), kind })
+    }
+}
+
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            Kind::File(ref data) => f.debug_struct("File").field("size", &data.len()).finish(),
+            Kind::Dir(ref children) => f.debug_set().entries(children.keys()).finish(),
+        }
+    }
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "entry not found")
+}
+
+fn exists() -> io::Error {
+    io::Error::new(io::ErrorKind::AlreadyExists, "entry already exists")
+}
+
+/// Returns the names along the absolute path `path`, with `.` and `..`
+/// resolved.
+fn components(path: &Path) -> io::Result<Vec<String>> {
+    if !path.is_absolute() {
+        return Err(invalid("path must be absolute"));
+    }
+
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => {
+                let name = name.to_str().ok_or_else(|| invalid("path is not valid UTF-8"))?;
+                names.push(name.to_string());
+            }
+            Component::ParentDir => {
+                names.pop();
+            }
+            _ => {}
+        }
+    }
+    Ok(names)
+}
+
+/// A file system kept entirely in the kernel heap.
+///
+/// Names are case-sensitive. There is no clock, so every timestamp is zero.
+/// A `RamFs` is cheap to clone; every clone refers to the same files.
+#[derive(Debug, Clone)]
+pub struct RamFs {
+    root: NodeRef,
+}
+
+impl RamFs {
+    /// Returns an empty file system.
+    pub fn new() -> RamFs {
+        RamFs { root: Node::new(Kind::Dir(BTreeMap::new())) }
+    }
+
+    /// Returns the node at the path made of `names`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if a component but the last is not
+    /// an existing directory and of `NotFound` if the last does not exist.
+    fn lookup(&self, names: &[String]) -> io::Result<NodeRef> {
+        let mut node = self.root.clone();
+        for (i, name) in names.iter().enumerate() {
+            let next = match node.borrow().kind {
+                Kind::Dir(ref children) => children.get(name).cloned(),
+                Kind::File(_) => return Err(invalid("path component is not a directory")),
+            };
+            node = match next {
+                Some(next) => next,
+                None if i + 1 < names.len() => {
+                    return Err(invalid("path component does not exist"));
+                }
+                None => return Err(not_found()),
+            };
+        }
+        Ok(node)
+    }
+
+    /// Returns the directory that contains the entry at the path made of
+    /// `names`, and the entry's name.
+    fn lookup_parent<'n>(&self, names: &'n [String]) -> io::Result<(NodeRef, &'n str)> {
+        let (name, parents) = names.split_last().ok_or_else(|| invalid("path is the root"))?;
+        let parent = match self.lookup(parents) {
+            Ok(parent) => parent,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(invalid("path component does not exist"));
+            }
+            Err(err) => return Err(err),
+        };
+        if let Kind::File(_) = parent.borrow().kind {
+            return Err(invalid("path component is not a directory"));
+        }
+        Ok((parent, name))
+    }
+
+    /// Links a new node of `kind` into `dir` as `name`.
+    fn create(dir: &NodeRef, name: &str, kind: Kind) -> io::Result<NodeRef> {
+        let mut dir = dir.borrow_mut();
+        match dir.kind {
+            Kind::Dir(ref mut children) => {
+                if children.contains_key(name) {
+                    return Err(exists());
+                }
+                let node = Node::new(kind);
+                children.insert(name.to_string(), node.clone());
+                Ok(node)
+            }
+            Kind::File(_) => Err(invalid("path component is not a directory")),
+        }
+    }
+
+    /// Unlinks `name` from `dir` and returns its node.
+    fn unlink(dir: &NodeRef, name: &str) -> io::Result<NodeRef> {
+        match dir.borrow_mut().kind {
+            Kind::Dir(ref mut children) => children.remove(name).ok_or_else(not_found),
+            Kind::File(_) => Err(invalid("path component is not a directory")),
+        }
+    }
+}
+
+/// Returns an open handle to `node`, which is named `name`.
+fn entry(name: String, node: NodeRef) -> Entry {
+    let (metadata, is_dir) = {
+        let node = node.borrow();
+        let is_dir = match node.kind {
+            Kind::Dir(_) => true,
+            Kind::File(_) => false,
+        };
+        (node.metadata, is_dir)
+    };
+    if is_dir {
+        Entry::Dir(Dir { name, metadata, node })
+    } else {
+        Entry::File(File { name, metadata, node, offset: 0 })
+    }
+}
+
+impl<'a> traits::FileSystem for &'a RamFs {
+    type File = File;
+    type Dir = Dir;
+    type Entry = Entry;
+
+    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Entry> {
+        let names = components(path.as_ref())?;
+        let node = self.lookup(&names)?;
+        Ok(entry(names.last().cloned().unwrap_or_else(|| "/".to_string()), node))
+    }
+
+    fn canonicalize<P: AsRef<Path>>(self, path: P) -> io::Result<PathBuf> {
+        let names = components(path.as_ref())?;
+        self.lookup(&names)?;
+        let mut canonical = PathBuf::from("/");
+        canonical.extend(&names);
+        Ok(canonical)
+    }
+
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<File> {
+        let names = components(path.as_ref())?;
+        let (dir, name) = self.lookup_parent(&names)?;
+        let node = RamFs::create(&dir, name, Kind::File(Vec::new()))?;
+        Ok(File { name: name.to_string(), metadata: Metadata::default(), node, offset: 0 })
+    }
+
+    fn create_dir<P: AsRef<Path>>(self, path: P, parents: bool) -> io::Result<Dir> {
+        let names = components(path.as_ref())?;
+        if names.is_empty() {
+            return Err(exists());
+        }
+        if parents {
+            for end in 1..names.len() {
+                match self.lookup(&names[..end]) {
+                    Ok(_) => {}
+                    Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                        let (dir, name) = self.lookup_parent(&names[..end])?;
+                        RamFs::create(&dir, name, Kind::Dir(BTreeMap::new()))?;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        let (dir, name) = self.lookup_parent(&names)?;
+        let node = RamFs::create(&dir, name, Kind::Dir(BTreeMap::new()))?;
+        Ok(Dir { name: name.to_string(), metadata: Metadata::default(), node })
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()> {
+        let (from, to) = (components(from.as_ref())?, components(to.as_ref())?);
+        if from.is_empty() {
+            return Err(invalid("cannot rename the root"));
+        }
+        if to.starts_with(&from) {
+            return Err(invalid("cannot move a directory into itself"));
+        }
+
+        let (from_dir, from_name) = self.lookup_parent(&from)?;
+        let (to_dir, to_name) = self.lookup_parent(&to)?;
+        self.lookup(&from)?;
+        match self.lookup(&to) {
+            Ok(_) => return Err(exists()),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        let node = RamFs::unlink(&from_dir, from_name)?;
+        match to_dir.borrow_mut().kind {
+            Kind::Dir(ref mut children) => children.insert(to_name.to_string(), node),
+            Kind::File(_) => unreachable!("parent is a directory"),
+        };
+        Ok(())
+    }
+
+    fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()> {
+        let names = components(path.as_ref())?;
+        if names.is_empty() {
+            return Err(invalid("cannot remove the root"));
+        }
+        let (dir, name) = self.lookup_parent(&names)?;
+        let node = self.lookup(&names)?;
+        if let Kind::Dir(_) = node.borrow().kind {
+            if !children {
+                return Err(io::Error::new(io::ErrorKind::Other, "entry is a directory"));
+            }
+        }
+        RamFs::unlink(&dir, name).map(|_| ())
+    }
+}
+
+/// An open file of a `RamFs`.
+pub struct File {
+    name: String,
+    metadata: Metadata,
+    node: NodeRef,
+    offset: u64,
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = match self.node.borrow().kind {
+            Kind::File(ref data) => {
+                let start = cmp::min(self.offset, data.len() as u64) as usize;
+                let read = cmp::min(buf.len(), data.len() - start);
+                buf[..read].copy_from_slice(&data[start..start + read]);
+                read
+            }
+            Kind::Dir(_) => unreachable!("file node is a directory"),
+        };
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+impl io::Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.node.borrow_mut().kind {
+            Kind::File(ref mut data) => {
+                let start = self.offset as usize;
+                let end = start + buf.len();
+                if data.len() < end {
+                    data.resize(end, 0);
+                }
+                data[start..end].copy_from_slice(buf);
+            }
+            Kind::Dir(_) => unreachable!("file node is a directory"),
+        }
+        self.offset += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for File {
+    /// Seeks to the offset `pos` in the file. Seeking past the end is allowed;
+    /// a write there fills the gap with zeroes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if the seek would go before the
+    /// start of the file.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let offset = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => add(traits::File::size(self), delta),
+            SeekFrom::Current(delta) => add(self.offset, delta),
+        };
+        self.offset = offset.ok_or_else(|| invalid("seek before the start of the file"))?;
+        Ok(self.offset)
+    }
+}
+
+fn add(offset: u64, delta: i64) -> Option<u64> {
+    if delta < 0 {
+        offset.checked_sub(delta.wrapping_neg() as u64)
+    } else {
+        offset.checked_add(delta as u64)
+    }
+}
+
+impl traits::File for File {
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        match self.node.borrow().kind {
+            Kind::File(ref data) => data.len() as u64,
+            Kind::Dir(_) => unreachable!("file node is a directory"),
+        }
+    }
+
+    /// Truncates or zero-extends the file to `size` bytes. The offset of the
+    /// handle is left unchanged.
+    fn set_len(&mut self, size: u64) -> io::Result<()> {
+        match self.node.borrow_mut().kind {
+            Kind::File(ref mut data) => data.resize(size as usize, 0),
+            Kind::Dir(_) => unreachable!("file node is a directory"),
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("File")
+            .field("name", &self.name)
+            .field("offset", &self.offset)
+            .finish()
+    }
+}
+
+/// An open directory of a `RamFs`.
+#[derive(Debug)]
+pub struct Dir {
+    name: String,
+    metadata: Metadata,
+    node: NodeRef,
+}
+
+impl traits::Dir for Dir {
+    type Entry = Entry;
+    type Iter = vec::IntoIter<Entry>;
+
+    /// Returns the entries of the directory, in order of name, as they are
+    /// when this is called.
+    fn entries(&self) -> io::Result<vec::IntoIter<Entry>> {
+        let children: Vec<(String, NodeRef)> = match self.node.borrow().kind {
+            Kind::Dir(ref children) => {
+                children.iter().map(|(name, node)| (name.clone(), node.clone())).collect()
+            }
+            Kind::File(_) => unreachable!("directory node is a file"),
+        };
+        let entries: Vec<Entry> =
+            children.into_iter().map(|(name, node)| entry(name, node)).collect();
+        Ok(entries.into_iter())
+    }
+}
+
+/// An entry of a `RamFs`.
+#[derive(Debug)]
+pub enum Entry {
+    File(File),
+    Dir(Dir),
+}
+
+impl traits::Entry for Entry {
+    type File = File;
+    type Dir = Dir;
+    type Metadata = Metadata;
+
+    fn name(&self) -> &str {
+        match *self {
+            Entry::File(ref file) => &file.name,
+            Entry::Dir(ref dir) => &dir.name,
+        }
+    }
+
+    fn metadata(&self) -> &Metadata {
+        match *self {
+            Entry::File(ref file) => &file.metadata,
+            Entry::Dir(ref dir) => &dir.metadata,
+        }
+    }
+
+    fn as_file(&self) -> Option<&File> {
+        match *self {
+            Entry::File(ref file) => Some(file),
+            Entry::Dir(_) => None,
+        }
+    }
+
+    fn as_dir(&self) -> Option<&Dir> {
+        match *self {
+            Entry::Dir(ref dir) => Some(dir),
+            Entry::File(_) => None,
+        }
+    }
+
+    fn into_file(self) -> Option<File> {
+        match self {
+            Entry::File(file) => Some(file),
+            Entry::Dir(_) => None,
+        }
+    }
+
+    fn into_dir(self) -> Option<Dir> {
+        match self {
+            Entry::Dir(dir) => Some(dir),
+            Entry::File(_) => None,
+        }
+    }
+}
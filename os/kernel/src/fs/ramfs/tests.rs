@@ -0,0 +1,163 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use fs::ramfs::RamFs;
+use fs::traits::{Dir, Entry, File, FileSystem};
+use fs::vfs::Vfs;
+
+fn names<D: Dir>(dir: &D) -> Vec<String> {
+    dir.entries().unwrap().map(|entry| entry.name().to_string()).collect()
+}
+
+fn read_all<F: FileSystem>(fs: F, path: &str) -> String {
+    let mut contents = String::new();
+    fs.open_file(path).unwrap().read_to_string(&mut contents).unwrap();
+    contents
+}
+
+fn kind<T>(result: io::Result<T>) -> io::ErrorKind {
+    result.err().expect("expected an error").kind()
+}
+
+#[test]
+fn test_read_write_seek() {
+    let fs = RamFs::new();
+    let mut file = fs.create_file("/hello").unwrap();
+    file.write_all(b"hello, world").unwrap();
+    assert_eq!(file.size(), 12);
+    assert_eq!(read_all(&fs, "/hello"), "hello, world");
+
+    file.seek(SeekFrom::Start(7)).unwrap();
+    file.write_all(b"ramfs!").unwrap();
+    assert_eq!(read_all(&fs, "/hello"), "hello, ramfs!");
+
+    file.seek(SeekFrom::End(2)).unwrap();
+    file.write_all(b"x").unwrap();
+    assert_eq!(read_all(&fs, "/hello"), "hello, ramfs!\0\0x");
+    assert_eq!(kind(file.seek(SeekFrom::Current(-100))), io::ErrorKind::InvalidInput);
+
+    file.set_len(5).unwrap();
+    assert_eq!(read_all(&fs, "/hello"), "hello");
+    let mut rest = Vec::new();
+    file.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn test_dirs() {
+    let fs = RamFs::new();
+    fs.create_dir("/a/b/c", true).unwrap();
+    fs.create_file("/a/z").unwrap();
+    fs.create_file("/a/b/file").unwrap();
+
+    assert_eq!(names(&fs.open_dir("/a").unwrap()), vec!["b", "z"]);
+    assert_eq!(names(&fs.open_dir("/a/b/c/..").unwrap()), vec!["c", "file"]);
+    assert!(fs.open("/a/b").unwrap().is_dir());
+    assert!(fs.open("/a/z").unwrap().is_file());
+    assert_eq!(fs.canonicalize("/a/./b/../z").unwrap(), PathBuf::from("/a/z"));
+
+    assert_eq!(kind(fs.open("/a/missing")), io::ErrorKind::NotFound);
+    assert_eq!(kind(fs.open("/a/missing/x")), io::ErrorKind::InvalidInput);
+    assert_eq!(kind(fs.open("/a/z/x")), io::ErrorKind::InvalidInput);
+    assert_eq!(kind(fs.open("a")), io::ErrorKind::InvalidInput);
+    assert_eq!(kind(fs.create_file("/a/z")), io::ErrorKind::AlreadyExists);
+    assert_eq!(kind(fs.create_dir("/x/y", false)), io::ErrorKind::InvalidInput);
+    assert_eq!(kind(fs.create_dir("/a/b", true)), io::ErrorKind::AlreadyExists);
+}
+
+#[test]
+fn test_rename_remove() {
+    let fs = RamFs::new();
+    fs.create_dir("/a/b", true).unwrap();
+    fs.create_file("/a/b/file").unwrap().write_all(b"data").unwrap();
+
+    fs.rename("/a/b", "/c").unwrap();
+    assert_eq!(read_all(&fs, "/c/file"), "data");
+    assert_eq!(kind(fs.open("/a/b")), io::ErrorKind::NotFound);
+    assert_eq!(kind(fs.rename("/c", "/c/d")), io::ErrorKind::InvalidInput);
+    assert_eq!(kind(fs.rename("/c", "/a")), io::ErrorKind::AlreadyExists);
+    assert_eq!(kind(fs.rename("/missing", "/b")), io::ErrorKind::NotFound);
+
+    let mut open = fs.open_file("/c/file").unwrap();
+    assert_eq!(kind(fs.remove("/c", false)), io::ErrorKind::Other);
+    fs.remove("/c", true).unwrap();
+    assert_eq!(kind(fs.open("/c")), io::ErrorKind::NotFound);
+    let mut contents = String::new();
+    open.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "data");
+
+    fs.remove("/a", true).unwrap();
+    assert!(names(&fs.open_dir("/").unwrap()).is_empty());
+    assert_eq!(kind(fs.remove("/", true)), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_vfs_mounts() {
+    let (root, tmp) = (RamFs::new(), RamFs::new());
+    root.create_dir("/bin", false).unwrap();
+    let vfs = Vfs::new();
+    vfs.mount("/", root.clone()).unwrap();
+    vfs.mount("/tmp", tmp.clone()).unwrap();
+    assert_eq!(kind(vfs.mount("/tmp", RamFs::new())), io::ErrorKind::AlreadyExists);
+    assert_eq!(kind(vfs.mount("/missing/dir", RamFs::new())), io::ErrorKind::InvalidInput);
+
+    vfs.create_file("/tmp/scratch").unwrap().write_all(b"temp").unwrap();
+    assert_eq!(read_all(&tmp, "/scratch"), "temp");
+    assert_eq!(kind(root.open("/tmp")), io::ErrorKind::NotFound);
+
+    assert_eq!(names(&vfs.open_dir("/").unwrap()), vec!["bin", "tmp"]);
+    assert_eq!(names(&vfs.open_dir("/tmp/../tmp").unwrap()), vec!["scratch"]);
+    assert_eq!(vfs.open("/tmp").unwrap().name(), "tmp");
+    assert_eq!(vfs.canonicalize("/tmp/../bin").unwrap(), PathBuf::from("/bin"));
+
+    assert_eq!(kind(vfs.rename("/tmp/scratch", "/bin/scratch")), io::ErrorKind::Other);
+    assert_eq!(kind(vfs.remove("/tmp", true)), io::ErrorKind::Other);
+    assert_eq!(kind(vfs.umount("/")), io::ErrorKind::Other);
+
+    let mut open = vfs.open_file("/tmp/scratch").unwrap();
+    vfs.umount("/tmp").unwrap();
+    assert_eq!(vfs.mount_points(), vec![PathBuf::from("/")]);
+    assert_eq!(kind(vfs.open("/tmp")), io::ErrorKind::NotFound);
+    let mut contents = String::new();
+    open.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "temp");
+}
+
+#[test]
+fn test_vfs_concurrent_mounts() {
+    use std::thread;
+
+    for _ in 0..20 {
+        let vfs = Vfs::new();
+        vfs.mount("/", RamFs::new()).unwrap();
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let vfs = vfs.clone();
+                thread::spawn(move || vfs.mount("/mnt", RamFs::new()).is_ok())
+            })
+            .collect();
+        let mounted = threads.into_iter().map(|thread| thread.join().unwrap());
+        assert_eq!(mounted.filter(|&ok| ok).count(), 1);
+        assert_eq!(vfs.mount_points().len(), 2);
+    }
+}
+
+#[test]
+fn test_vfs_set_len() {
+    let tmp = RamFs::new();
+    let vfs = Vfs::new();
+    vfs.mount("/", RamFs::new()).unwrap();
+    vfs.mount("/tmp", tmp.clone()).unwrap();
+
+    let mut file = vfs.create_file("/tmp/log").unwrap();
+    file.write_all(b"first line\n").unwrap();
+    file.set_len(5).unwrap();
+    assert_eq!(file.size(), 5);
+    assert_eq!(read_all(&tmp, "/log"), "first");
+
+    // The offset is kept: writing past the new end fills the gap with zeroes.
+    file.write_all(b"!").unwrap();
+    assert_eq!(read_all(&vfs, "/tmp/log"), "first\0\0\0\0\0\0!");
+    vfs.open_file("/tmp/log").unwrap().set_len(7).unwrap();
+    assert_eq!(read_all(&tmp, "/log"), "first\0\0");
+}
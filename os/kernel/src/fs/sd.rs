@@ -1,82 +1,95 @@
-use pi::timer::spin_sleep_us;
-use std::io;
-use fat32::traits::BlockDevice;
-
-extern "C" {
-    /// A global representing the last SD controller error that occured.
-    static sd_err: i64;
-
-    /// Initializes the SD card controller.
-    ///
-    /// Returns 0 if initialization is successful. If initialization fails,
-    /// returns -1 if a timeout occured, or -2 if an error sending commands to
-    /// the SD controller occured.
-    fn sd_init() -> i32;
+use std::{cmp, io};
 
-    /// Reads sector `n` (512 bytes) from the SD card and writes it to `buffer`.
-    /// It is undefined behavior if `buffer` does not point to at least 512
-    /// bytes of memory.
-    ///
-    /// On success, returns the number of bytes read: a positive number.
-    ///
-    /// On error, returns 0. The true error code is stored in the `sd_err`
-    /// global. `sd_err` will be set to -1 if a timeout occured or -2 if an
-    /// error sending commands to the SD controller occured. Other error codes
-    /// are also possible but defined only as being less than zero.
-    fn sd_readsector(n: i32, buffer: *mut u8) -> i32;
-}
+use fat32::traits::BlockDevice;
+use pi::emmc::{Card, Emmc, BLOCK_SIZE};
 
-// FIXME: Define a `#[no_mangle]` `wait_micros` function for use by `libsd`.
-// The `wait_micros` C signature is: `void wait_micros(unsigned int);`
+pub use pi::emmc::Error;
 
-#[no_mangle]
-pub extern "C" fn wait_micros(micros: u32) {
-    spin_sleep_us((micros * 100).into());
+/// Converts an SD card error into an I/O error.
+fn io_error(err: Error) -> io::Error {
+    match err {
+        Error::TimedOut => {
+            io::Error::new(io::ErrorKind::TimedOut, "SD card operation timed out")
+        }
+        Error::Command(flags) => io::Error::new(
+            io::ErrorKind::Other,
+            format!("Sending command to SD card controller failed: {:#x}", flags),
+        ),
+        Error::Data(flags) => io::Error::new(
+            io::ErrorKind::Other,
+            format!("SD card data transfer failed: {:#x}", flags),
+        ),
+        Error::Card(status) => io::Error::new(
+            io::ErrorKind::Other,
+            format!("Got card error: {:#x}", status),
+        ),
+        Error::Unsupported => {
+            io::Error::new(io::ErrorKind::Other, "SD card is not supported")
+        }
+    }
 }
 
-#[derive(Debug)]
-pub enum Error {
-    // FIXME: Fill me in.
-    TimedOut,
-    SendingCommandFailed,
-    UnknownError(i64),
+/// A handle to the SD card, driven by the EMMC controller through
+/// `pi::emmc`.
+///
+/// Every clone of a handle drives the same controller.
+#[derive(Debug, Clone)]
+pub struct Sd {
+    card: Card,
 }
 
-/// A handle to an SD card controller.
-#[derive(Debug)]
-pub struct Sd;
+impl Sd {
+    /// Initializes the SD card controller and the card in it, and returns a
+    /// handle to the card.
+    pub fn new() -> Result<Sd, Error> {
+        let card = Emmc::new().initialize()?;
+        Ok(Sd { card })
+    }
 
-impl From<Error> for io::Error {
-    fn from(err: Error) -> io::Error {
-        match err {
-            Error::TimedOut => {
-                io::Error::new(io::ErrorKind::TimedOut, "SD card operation timed out")
-            }
-            Error::SendingCommandFailed => io::Error::new(
-                io::ErrorKind::Other,
-                "Sending command to SD card controller failed",
-            ),
-            Error::UnknownError(err) => io::Error::new(
-                io::ErrorKind::Other,
-                format!("Got unknown card error: {}", err),
-            ),
+    /// Checks that the whole sectors of a `len` byte buffer can be
+    /// transferred starting at sector `n`, and returns their length in bytes.
+    fn transfer_len(n: u64, len: usize) -> io::Result<usize> {
+        let len = len - len % BLOCK_SIZE;
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "buffer is smaller than a sector",
+            ));
+        } else if n + (len / BLOCK_SIZE) as u64 > 0x7FFFFFFF {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "sector is out of range",
+            ));
         }
+        Ok(len)
     }
-}
 
-impl Sd {
-    /// Initializes the SD card controller and returns a handle to it.
-    pub fn new() -> Result<Sd, Error> {
-        let result = unsafe { sd_init() };
-        if result == 0 {
-            Ok(Sd {})
-        } else if result == -1 {
-            Err(Error::TimedOut)
-        } else if result == -2 {
-            Err(Error::SendingCommandFailed)
-        } else {
-            Err(Error::UnknownError(unsafe { sd_err }))
-        }
+    /// Reads consecutive sectors starting at sector `n` into `buf`, in a
+    /// single multi-block transfer if `buf` spans several sectors. On
+    /// success, the number of bytes read is returned: `buf.len()` rounded
+    /// down to a whole number of sectors.
+    ///
+    /// # Errors
+    ///
+    /// Errors are reported as in `read_sector`.
+    pub fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let len = Sd::transfer_len(n, buf.len())?;
+        Emmc::new().read_blocks(&self.card, n as u32, &mut buf[..len]).map_err(io_error)?;
+        Ok(len)
+    }
+
+    /// Writes `buf` to consecutive sectors starting at sector `n`, in a
+    /// single multi-block transfer if `buf` spans several sectors. On
+    /// success, the number of bytes written is returned: `buf.len()` rounded
+    /// down to a whole number of sectors.
+    ///
+    /// # Errors
+    ///
+    /// Errors are reported as in `write_sector`.
+    pub fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let len = Sd::transfer_len(n, buf.len())?;
+        Emmc::new().write_blocks(&self.card, n as u32, &buf[..len]).map_err(io_error)?;
+        Ok(len)
     }
 }
 
@@ -86,36 +99,33 @@ impl BlockDevice for Sd {
     ///
     /// # Errors
     ///
-    /// An I/O error of kind `InvalidInput` is returned if `buf.len() < 512` or
-    /// `n > 2^31 - 1` (the maximum value for an `i32`).
+    /// An I/O error of kind `UnexpectedEof` is returned if `buf.len() < 512`,
+    /// and of kind `InvalidInput` if `n > 2^31 - 1`.
     ///
     /// An error of kind `TimedOut` is returned if a timeout occurs while
     /// reading from the SD card.
     ///
     /// An error of kind `Other` is returned for all other errors.
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
-        if buf.len() < 512 || n > 0x7FFFFFFF {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "read_sector received wrong parameter",
-            ));
-        }
-        let result = unsafe { sd_readsector(n as i32, buf.as_mut_ptr()) };
-        if result > 0 {
-            Ok(result as usize)
-        } else {
-            let errno = unsafe { sd_err };
-            if errno == -1 {
-                Err(Error::TimedOut.into())
-            } else if errno == -2 {
-                Err(Error::SendingCommandFailed.into())
-            } else {
-                Err(Error::UnknownError(errno).into())
-            }
-        }
+        let len = cmp::min(buf.len(), BLOCK_SIZE);
+        self.read_sectors(n, &mut buf[..len])
     }
 
-    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("SD card and file system are read only")
+    /// Overwrites sector `n` of the SD card with the first 512 bytes of `buf`
+    /// and waits for the card to finish programming it. On success, the
+    /// number of bytes written is returned.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error of kind `UnexpectedEof` is returned if `buf.len() < 512`,
+    /// and of kind `InvalidInput` if `n > 2^31 - 1`.
+    ///
+    /// An error of kind `TimedOut` is returned if a timeout occurs while
+    /// writing to the SD card.
+    ///
+    /// An error of kind `Other` is returned for all other errors.
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let len = cmp::min(buf.len(), BLOCK_SIZE);
+        self.write_sectors(n, &buf[..len])
     }
 }
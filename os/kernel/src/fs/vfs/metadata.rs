@@ -0,0 +1,129 @@
+use std::fmt;
+
+use fs::traits;
+
+/// A point in time, copied out of the timestamp of any mounted file system.
+#[derive(Default, Copy, Clone, PartialEq, Eq)]
+pub struct Timestamp {
+    pub year: usize,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl Timestamp {
+    /// Returns a copy of `timestamp`.
+    pub fn of<T: traits::Timestamp>(timestamp: &T) -> Timestamp {
+        Timestamp {
+            year: timestamp.year(),
+            month: timestamp.month(),
+            day: timestamp.day(),
+            hour: timestamp.hour(),
+            minute: timestamp.minute(),
+            second: timestamp.second(),
+        }
+    }
+}
+
+impl traits::Timestamp for Timestamp {
+    fn year(&self) -> usize {
+        self.year
+    }
+
+    fn month(&self) -> u8 {
+        self.month
+    }
+
+    fn day(&self) -> u8 {
+        self.day
+    }
+
+    fn hour(&self) -> u8 {
+        self.hour
+    }
+
+    fn minute(&self) -> u8 {
+        self.minute
+    }
+
+    fn second(&self) -> u8 {
+        self.second
+    }
+}
+
+impl fmt::Debug for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// The metadata of an entry of any mounted file system.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    pub read_only: bool,
+    pub hidden: bool,
+    pub system: bool,
+    pub volume_id: bool,
+    pub archive: bool,
+    pub created: Timestamp,
+    pub accessed: Timestamp,
+    pub modified: Timestamp,
+}
+
+impl Metadata {
+    /// Returns a copy of `metadata`.
+    pub fn of<M: traits::Metadata>(metadata: &M) -> Metadata {
+        Metadata {
+            read_only: metadata.read_only(),
+            hidden: metadata.hidden(),
+            system: metadata.system(),
+            volume_id: metadata.volume_id(),
+            archive: metadata.archive(),
+            created: Timestamp::of(&metadata.created()),
+            accessed: Timestamp::of(&metadata.accessed()),
+            modified: Timestamp::of(&metadata.modified()),
+        }
+    }
+}
+
+impl traits::Metadata for Metadata {
+    type Timestamp = Timestamp;
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn hidden(&self) -> bool {
+        self.hidden
+    }
+
+    fn system(&self) -> bool {
+        self.system
+    }
+
+    fn volume_id(&self) -> bool {
+        self.volume_id
+    }
+
+    fn archive(&self) -> bool {
+        self.archive
+    }
+
+    fn created(&self) -> Timestamp {
+        self.created
+    }
+
+    fn accessed(&self) -> Timestamp {
+        self.accessed
+    }
+
+    fn modified(&self) -> Timestamp {
+        self.modified
+    }
+}
@@ -0,0 +1,469 @@
+mod metadata;
+mod object;
+
+use std::{fmt, io, vec};
+use std::path::{Component, Path, PathBuf};
+
+use fat32::vfat::Shared;
+
+use fs::traits;
+
+pub use self::metadata::{Metadata, Timestamp};
+pub use self::object::{DirEntry, DirObject, FileObject, FileSystemObject, Object};
+
+/// A file system mounted at `path`.
+struct Mount {
+    path: PathBuf,
+    fs: Box<FileSystemObject>,
+}
+
+/// The file systems mounted into a `Vfs`, deepest mount point first.
+#[derive(Default)]
+struct MountTable {
+    mounts: Vec<Mount>,
+}
+
+impl MountTable {
+    /// Returns the mount that `path`, which must be normal, lies on, and the
+    /// path of the entry within that mount's file system.
+    fn resolve(&self, path: &Path) -> io::Result<(&Mount, PathBuf)> {
+        for mount in &self.mounts {
+            if let Ok(rest) = path.strip_prefix(&mount.path) {
+                return Ok((mount, Path::new("/").join(rest)));
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::NotFound, "no file system mounted"))
+    }
+
+    /// Returns `true` if a file system is mounted at or below `path`.
+    fn busy(&self, path: &Path) -> bool {
+        self.mounts.iter().any(|mount| mount.path.starts_with(path))
+    }
+
+    /// Returns the root directories of the file systems mounted directly
+    /// below the directory `dir`, named after their mount points.
+    fn mounted_in(&self, dir: &Path) -> Vec<DirEntry> {
+        self.mounts
+            .iter()
+            .filter(|mount| mount.path.parent() == Some(dir))
+            .filter_map(|mount| {
+                let mut root = mount.fs.open(Path::new("/")).ok()?;
+                root.name = mount_name(&mount.path);
+                Some(root)
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for MountTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.mounts.iter().map(|mount| &mount.path)).finish()
+    }
+}
+
+/// Returns `path` made absolute against nothing: `.` components are dropped
+/// and `..` components remove the component before them.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if `path` is not absolute.
+fn normalize(path: &Path) -> io::Result<PathBuf> {
+    if !path.is_absolute() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "path must be absolute"));
+    }
+
+    let mut normal = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normal.push(name),
+            Component::ParentDir => {
+                normal.pop();
+            }
+            _ => {}
+        }
+    }
+    Ok(normal)
+}
+
+/// Returns the names of the components of `path`, a path within a mounted
+/// file system as passed to a `FileSystemObject`.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if `path` is not absolute and normal or
+/// is not valid UTF-8.
+pub fn names(path: &Path) -> io::Result<Vec<String>> {
+    if !path.is_absolute() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "path must be absolute"));
+    }
+
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => match name.to_str() {
+                Some(name) => names.push(name.to_string()),
+                None => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid name"));
+                }
+            },
+            Component::RootDir => {}
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "path must be normal")),
+        }
+    }
+    Ok(names)
+}
+
+/// The name of the root directory of the file system mounted at `path`.
+fn mount_name(path: &Path) -> String {
+    path.file_name().and_then(|name| name.to_str()).unwrap_or("/").to_string()
+}
+
+fn busy() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "a file system is mounted there")
+}
+
+/// A virtual file system: a single namespace over every mounted file system.
+///
+/// Each path is served by the file system mounted at the deepest mount point
+/// the path lies below. A mount point need not exist in the file system it is
+/// on; listing its parent directory shows the root directory of the mounted
+/// file system in its place. `..` is resolved against the path, so it leaves a
+/// mounted file system through its mount point.
+///
+/// A `Vfs` is cheap to clone; every clone shares the same mount table.
+#[derive(Debug, Clone)]
+pub struct Vfs {
+    table: Shared<MountTable>,
+}
+
+impl Vfs {
+    /// Returns a `Vfs` with nothing mounted.
+    pub fn new() -> Vfs {
+        Vfs { table: Shared::new(MountTable::default()) }
+    }
+
+    /// Mounts `fs` at the absolute path `path`.
+    ///
+    /// Unless `path` is the root, its parent must be an existing directory
+    /// and `path` itself must be a directory or not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `AlreadyExists` if a file system is already mounted
+    /// at `path`, and of `InvalidInput` if `path` is not absolute or is a file.
+    /// Returns the errors of opening the parent of `path`.
+    pub fn mount<P, F>(&self, path: P, fs: F) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        F: FileSystemObject + 'static,
+    {
+        let path = normalize(path.as_ref())?;
+        if let Some(parent) = path.parent() {
+            match traits::FileSystem::open(self, &path) {
+                Ok(ref entry) if !traits::Entry::is_dir(entry) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "mount point is not a directory",
+                    ));
+                }
+                Ok(_) => {}
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                    traits::FileSystem::open_dir(self, parent)?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        // Checked under the guard the mount is added with, so that concurrent
+        // mounts at `path` can not both succeed.
+        let mut table = self.table.borrow_mut();
+        if table.mounts.iter().any(|mount| mount.path == path) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "already mounted"));
+        }
+        table.mounts.push(Mount { path, fs: Box::new(fs) });
+        table.mounts.sort_by(|a, b| {
+            b.path.components().count().cmp(&a.path.components().count())
+        });
+        Ok(())
+    }
+
+    /// Unmounts the file system mounted at `path` and returns it. Files and
+    /// directories of it that are still open remain usable.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if no file system is mounted at
+    /// `path`, and of `Other` if another file system is mounted below it.
+    pub fn umount<P: AsRef<Path>>(&self, path: P) -> io::Result<Box<FileSystemObject>> {
+        let path = normalize(path.as_ref())?;
+        let mut table = self.table.borrow_mut();
+        let index = table.mounts.iter().position(|mount| mount.path == path).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "not a mount point")
+        })?;
+        if table.mounts.iter().any(|mount| mount.path != path && mount.path.starts_with(&path)) {
+            return Err(busy());
+        }
+        Ok(table.mounts.remove(index).fs)
+    }
+
+    /// Returns the paths file systems are mounted at, in order.
+    pub fn mount_points(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> =
+            self.table.borrow().mounts.iter().map(|mount| mount.path.clone()).collect();
+        paths.sort();
+        paths
+    }
+
+    /// Places `entry`, found at the normal path `path`, in the namespace.
+    fn place(&self, path: PathBuf, entry: DirEntry) -> Entry {
+        let node = match entry.object {
+            Object::File(file) => Node::File(File(file)),
+            Object::Dir(dir) => Node::Dir(Dir { path, dir, vfs: self.clone() }),
+        };
+        Entry { name: entry.name, metadata: entry.metadata, node }
+    }
+}
+
+/// A file of a `Vfs`.
+pub struct File(Box<FileObject>);
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl io::Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl io::Seek for File {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl traits::File for File {
+    fn sync(&mut self) -> io::Result<()> {
+        self.0.sync()
+    }
+
+    fn size(&self) -> u64 {
+        self.0.size()
+    }
+
+    fn set_len(&mut self, size: u64) -> io::Result<()> {
+        self.0.set_len(size)
+    }
+}
+
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("File").field("size", &self.0.size()).finish()
+    }
+}
+
+/// A directory of a `Vfs`.
+pub struct Dir {
+    path: PathBuf,
+    dir: Box<DirObject>,
+    vfs: Vfs,
+}
+
+impl traits::Dir for Dir {
+    type Entry = Entry;
+    type Iter = EntryIter;
+
+    fn entries(&self) -> io::Result<EntryIter> {
+        let mounted = self.vfs.table.borrow().mounted_in(&self.path);
+        Ok(EntryIter {
+            covered: mounted.iter().map(|entry| entry.name.clone()).collect(),
+            entries: self.dir.entries()?,
+            mounted: mounted.into_iter(),
+            path: self.path.clone(),
+            vfs: self.vfs.clone(),
+        })
+    }
+}
+
+impl fmt::Debug for Dir {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Dir").field("path", &self.path).finish()
+    }
+}
+
+/// An iterator over the entries of a `Dir`: those of the directory itself,
+/// but with mounted file systems in place of the entries they cover.
+pub struct EntryIter {
+    path: PathBuf,
+    entries: Box<Iterator<Item = DirEntry>>,
+    covered: Vec<String>,
+    mounted: vec::IntoIter<DirEntry>,
+    vfs: Vfs,
+}
+
+impl Iterator for EntryIter {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        let entry = loop {
+            match self.entries.next() {
+                Some(ref entry) if self.covered.contains(&entry.name) => continue,
+                Some(entry) => break entry,
+                None => break self.mounted.next()?,
+            }
+        };
+        let path = normalize(&self.path.join(&entry.name)).unwrap_or_else(|_| self.path.clone());
+        Some(self.vfs.place(path, entry))
+    }
+}
+
+enum Node {
+    File(File),
+    Dir(Dir),
+}
+
+/// An entry of a `Vfs`.
+pub struct Entry {
+    name: String,
+    metadata: Metadata,
+    node: Node,
+}
+
+impl traits::Entry for Entry {
+    type File = File;
+    type Dir = Dir;
+    type Metadata = Metadata;
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    fn as_file(&self) -> Option<&File> {
+        match self.node {
+            Node::File(ref file) => Some(file),
+            Node::Dir(_) => None,
+        }
+    }
+
+    fn as_dir(&self) -> Option<&Dir> {
+        match self.node {
+            Node::Dir(ref dir) => Some(dir),
+            Node::File(_) => None,
+        }
+    }
+
+    fn into_file(self) -> Option<File> {
+        match self.node {
+            Node::File(file) => Some(file),
+            Node::Dir(_) => None,
+        }
+    }
+
+    fn into_dir(self) -> Option<Dir> {
+        match self.node {
+            Node::Dir(dir) => Some(dir),
+            Node::File(_) => None,
+        }
+    }
+}
+
+impl fmt::Debug for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = f.debug_struct("Entry");
+        debug.field("name", &self.name).field("metadata", &self.metadata);
+        match self.node {
+            Node::File(ref file) => debug.field("file", file),
+            Node::Dir(ref dir) => debug.field("dir", dir),
+        };
+        debug.finish()
+    }
+}
+
+impl<'a> traits::FileSystem for &'a Vfs {
+    type File = File;
+    type Dir = Dir;
+    type Entry = Entry;
+
+    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Entry> {
+        let path = normalize(path.as_ref())?;
+        let entry = {
+            let table = self.table.borrow();
+            let (mount, local) = table.resolve(&path)?;
+            let mut entry = mount.fs.open(&local)?;
+            if mount.path == path && path.parent().is_some() {
+                entry.name = mount_name(&path);
+            }
+            entry
+        };
+        Ok(self.place(path, entry))
+    }
+
+    fn canonicalize<P: AsRef<Path>>(self, path: P) -> io::Result<PathBuf> {
+        let path = normalize(path.as_ref())?;
+        let table = self.table.borrow();
+        let (mount, local) = table.resolve(&path)?;
+        let canonical = mount.fs.canonicalize(&local)?;
+        Ok(mount.path.join(canonical.strip_prefix("/").unwrap_or(&canonical)))
+    }
+
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<File> {
+        let path = normalize(path.as_ref())?;
+        let table = self.table.borrow();
+        if table.busy(&path) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "path is a mount point"));
+        }
+        let (mount, local) = table.resolve(&path)?;
+        mount.fs.create_file(&local).map(File)
+    }
+
+    fn create_dir<P: AsRef<Path>>(self, path: P, parents: bool) -> io::Result<Dir> {
+        let path = normalize(path.as_ref())?;
+        let dir = {
+            let table = self.table.borrow();
+            if table.busy(&path) {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "path is a mount point"));
+            }
+            let (mount, local) = table.resolve(&path)?;
+            mount.fs.create_dir(&local, parents)?
+        };
+        Ok(Dir { path, dir, vfs: self.clone() })
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()> {
+        let (from, to) = (normalize(from.as_ref())?, normalize(to.as_ref())?);
+        let table = self.table.borrow();
+        if table.busy(&from) || table.busy(&to) {
+            return Err(busy());
+        }
+        let (mount, from) = table.resolve(&from)?;
+        let (to_mount, to) = table.resolve(&to)?;
+        if mount.path != to_mount.path {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "cannot rename across file systems",
+            ));
+        }
+        mount.fs.rename(&from, &to)
+    }
+
+    fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()> {
+        let path = normalize(path.as_ref())?;
+        let table = self.table.borrow();
+        if table.busy(&path) {
+            return Err(busy());
+        }
+        let (mount, local) = table.resolve(&path)?;
+        mount.fs.remove(&local, children)
+    }
+}
@@ -0,0 +1,155 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use fat32::vfat::{Shared, VFat};
+
+use fs::ramfs::RamFs;
+use fs::traits;
+use fs::vfs::Metadata;
+
+/// An open file of a mounted file system.
+///
+/// This is the object-safe counterpart of `traits::File`, which every
+/// `traits::File` implements.
+pub trait FileObject: io::Read + io::Write + io::Seek + Send {
+    /// Writes any buffered data to the file system.
+    fn sync(&mut self) -> io::Result<()>;
+
+    /// Returns the size of the file in bytes.
+    fn size(&self) -> u64;
+
+    /// Truncates or extends the file to `size` bytes. The offset of the
+    /// handle is left unchanged.
+    fn set_len(&mut self, size: u64) -> io::Result<()>;
+}
+
+impl<T: traits::File + Send> FileObject for T {
+    fn sync(&mut self) -> io::Result<()> {
+        traits::File::sync(self)
+    }
+
+    fn size(&self) -> u64 {
+        traits::File::size(self)
+    }
+
+    fn set_len(&mut self, size: u64) -> io::Result<()> {
+        traits::File::set_len(self, size)
+    }
+}
+
+/// A directory of a mounted file system.
+///
+/// This is the object-safe counterpart of `traits::Dir`, which every
+/// `traits::Dir` whose entries can be boxed implements.
+pub trait DirObject: Send {
+    /// Returns an iterator over the entries in this directory.
+    fn entries(&self) -> io::Result<Box<Iterator<Item = DirEntry>>>;
+}
+
+impl<D> DirObject for D
+where
+    D: traits::Dir + Send + 'static,
+    D::Entry: traits::Entry<Dir = D> + 'static,
+    D::Iter: 'static,
+    <D::Entry as traits::Entry>::File: Send + 'static,
+{
+    fn entries(&self) -> io::Result<Box<Iterator<Item = DirEntry>>> {
+        Ok(Box::new(traits::Dir::entries(self)?.map(DirEntry::from_entry)))
+    }
+}
+
+/// A file or directory of a mounted file system.
+pub enum Object {
+    File(Box<FileObject>),
+    Dir(Box<DirObject>),
+}
+
+/// An entry of a mounted file system, as returned before the VFS places it
+/// in its namespace.
+pub struct DirEntry {
+    pub name: String,
+    pub metadata: Metadata,
+    pub object: Object,
+}
+
+impl DirEntry {
+    /// Boxes the file or directory of `entry`.
+    pub fn from_entry<E>(entry: E) -> DirEntry
+    where
+        E: traits::Entry,
+        E::File: Send + 'static,
+        E::Dir: DirObject + 'static,
+    {
+        let name = entry.name().to_string();
+        let metadata = Metadata::of(entry.metadata());
+        let object = if entry.is_dir() {
+            Object::Dir(Box::new(entry.into_dir().unwrap()))
+        } else {
+            Object::File(Box::new(entry.into_file().unwrap()))
+        };
+        DirEntry { name, metadata, object }
+    }
+}
+
+/// A file system that can be mounted into a `Vfs`.
+///
+/// This is the object-safe counterpart of `traits::FileSystem`. Every path
+/// passed in is absolute and relative to the root of this file system, not of
+/// the VFS. The documentation of `traits::FileSystem` describes the expected
+/// errors.
+pub trait FileSystemObject: Send {
+    /// Opens the entry at `path`.
+    fn open(&self, path: &Path) -> io::Result<DirEntry>;
+
+    /// Returns the canonical form of `path`, which must exist.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Creates a new file at `path` and opens it.
+    fn create_file(&self, path: &Path) -> io::Result<Box<FileObject>>;
+
+    /// Creates a new directory at `path`, and its missing parents if
+    /// `parents` is `true`, and opens it.
+    fn create_dir(&self, path: &Path, parents: bool) -> io::Result<Box<DirObject>>;
+
+    /// Renames the entry at `from` to `to`.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Removes the entry at `path`, and everything below it if `children` is
+    /// `true`.
+    fn remove(&self, path: &Path, children: bool) -> io::Result<()>;
+}
+
+/// Implements `FileSystemObject` for `$fs`, a type whose shared references
+/// implement `traits::FileSystem`, by forwarding every call.
+macro_rules! forward_file_system {
+    ($fs:ty) => {
+        impl FileSystemObject for $fs {
+            fn open(&self, path: &Path) -> io::Result<DirEntry> {
+                traits::FileSystem::open(self, path).map(DirEntry::from_entry)
+            }
+
+            fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+                traits::FileSystem::canonicalize(self, path)
+            }
+
+            fn create_file(&self, path: &Path) -> io::Result<Box<FileObject>> {
+                Ok(Box::new(traits::FileSystem::create_file(self, path)?))
+            }
+
+            fn create_dir(&self, path: &Path, parents: bool) -> io::Result<Box<DirObject>> {
+                Ok(Box::new(traits::FileSystem::create_dir(self, path, parents)?))
+            }
+
+            fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+                traits::FileSystem::rename(self, from, to)
+            }
+
+            fn remove(&self, path: &Path, children: bool) -> io::Result<()> {
+                traits::FileSystem::remove(self, path, children)
+            }
+        }
+    };
+}
+
+forward_file_system!(Shared<VFat>);
+forward_file_system!(RamFs);
@@ -21,6 +21,7 @@ extern crate pi;
 extern crate stack_vec;
 
 pub mod allocator;
+pub mod block;
 #[cfg(not(test))]
 use pi::timer::spin_sleep_ms;
 
@@ -32,15 +33,16 @@ pub mod fs;
 pub mod traps;
 pub mod aarch64;
 pub mod process;
+pub mod smp;
 pub mod vm;
 mod dwarf;
 
 #[cfg(not(test))]
 use allocator::Allocator;
+use allocator::FrameAllocator;
+use block::BlockLayer;
 use fs::FileSystem;
 use process::GlobalScheduler;
-#[cfg(feature = "qemu")]
-use pi::timer::Timer;
 
 #[cfg(not(test))]
 #[global_allocator]
@@ -52,6 +54,10 @@ use std::alloc::System;
 #[global_allocator]
 pub static ALLOCATOR: System = System;
 
+pub static FRAMES: FrameAllocator = FrameAllocator::uninitialized();
+
+pub static BLOCK: BlockLayer = BlockLayer::uninitialized();
+
 pub static FILE_SYSTEM: FileSystem = FileSystem::uninitialized();
 
 pub static SCHEDULER: GlobalScheduler = GlobalScheduler::uninitialized();
@@ -59,14 +65,24 @@ pub static SCHEDULER: GlobalScheduler = GlobalScheduler::uninitialized();
 #[no_mangle]
 #[cfg(not(test))]
 pub extern "C" fn kmain() {
+    unsafe { vm::mmu::initialize() };
+    FRAMES.initialize();
     ALLOCATOR.initialize();
-    #[cfg(feature = "qemu")]
-    Timer::initialize();
     FILE_SYSTEM.initialize();
     spin_sleep_ms(200);
     SCHEDULER.start();
 }
 
+/// The entry point of the secondary cores, once released by
+/// `smp::start_secondary_cores()`.
+#[no_mangle]
+#[cfg(not(test))]
+pub extern "C" fn kmain_secondary() {
+    unsafe { vm::mmu::enable() };
+    smp::online();
+    SCHEDULER.start_core();
+}
+
 pub extern "C" fn shell_thread() {
     loop {
         user::shell("$ ");
@@ -78,3 +94,20 @@ pub extern "C" fn shell_thread_2() {
         user::timer();
     }
 }
+
+/// The block worker, which services the requests queued in `BLOCK` and
+/// otherwise waits blocked for more.
+pub extern "C" fn block_thread() {
+    loop {
+        while BLOCK.service() {}
+        user::wait_request(0);
+    }
+}
+
+/// The idle process, which runs when no other process is ready: it waits for
+/// the next interrupt.
+pub extern "C" fn idle_thread() {
+    loop {
+        aarch64::wfi();
+    }
+}
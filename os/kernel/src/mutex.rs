@@ -1,93 +0,0 @@
-use std::sync::atomic::{AtomicBool, AtomicUsize};
-use std::sync::atomic::Ordering::Relaxed;
-use std::cell::UnsafeCell;
-use std::ops::{Deref, DerefMut, Drop};
-use std::fmt;
-
-#[repr(align(32))]
-pub struct Mutex<T> {
-    data: UnsafeCell<T>,
-    lock: AtomicBool,
-    owner: AtomicUsize
-}
-
-unsafe impl<T: Send> Send for Mutex<T> {}
-unsafe impl<T: Send> Sync for Mutex<T> {}
-
-pub struct MutexGuard<'a, T: 'a> {
-    lock: &'a Mutex<T>,
-}
-
-impl<'a, T> !Send for MutexGuard<'a, T> {}
-unsafe impl<'a, T: Sync> Sync for MutexGuard<'a, T> {}
-
-impl<T> Mutex<T> {
-    pub const fn new(val: T) -> Mutex<T> {
-        Mutex {
-            lock: AtomicBool::new(false),
-            owner: AtomicUsize::new(usize::max_value()),
-            data: UnsafeCell::new(val),
-        }
-    }
-}
-
-impl<T> Mutex<T> {
-    // Once MMU/cache is enabled, do the right thing here. For now, we don't
-    // need any real synchronization.
-    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
-        let this = 0;
-        if !self.lock.load(Relaxed) || self.owner.load(Relaxed) == this {
-            self.lock.store(true, Relaxed);
-            self.owner.store(this, Relaxed);
-            Some(MutexGuard { lock: &self })
-        } else {
-            None
-        }
-    }
-
-    // Once MMU/cache is enabled, do the right thing here. For now, we don't
-    // need any real synchronization.
-    #[inline(never)]
-    pub fn lock(&self) -> MutexGuard<T> {
-        // Wait until we can "aquire" the lock, then "acquire" it.
-        loop {
-            match self.try_lock() {
-                Some(guard) => return guard,
-                None => continue,
-            }
-        }
-    }
-
-    fn unlock(&self) {
-        self.lock.store(false, Relaxed);
-    }
-}
-
-impl<'a, T: 'a> Deref for MutexGuard<'a, T> {
-    type Target = T;
-
-    fn deref(&self) -> &T {
-        unsafe { &*self.lock.data.get() }
-    }
-}
-
-impl<'a, T: 'a> DerefMut for MutexGuard<'a, T> {
-    fn deref_mut(&mut self) -> &mut T {
-        unsafe { &mut *self.lock.data.get() }
-    }
-}
-
-impl<'a, T: 'a> Drop for MutexGuard<'a, T> {
-    fn drop(&mut self) {
-        self.lock.unlock()
-    }
-}
-
-impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.try_lock() {
-            Some(guard) => f.debug_struct("Mutex").field("data", &&*guard).finish(),
-            None => f.debug_struct("Mutex").field("data", &"<locked>").finish(),
-        }
-    }
-}
@@ -0,0 +1,324 @@
+use std::sync::atomic::AtomicUsize;
+#[cfg(not(test))]
+use std::sync::atomic::spin_loop_hint;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut, Drop};
+use std::fmt;
+
+use smp;
+#[cfg(not(test))]
+use smp::CORES;
+
+#[cfg(test)]
+mod tests;
+
+/// The `owner` of a lock that is not held.
+const NO_OWNER: usize = usize::max_value();
+
+/// A ticket spinlock: cores acquire the lock in the order they asked for it.
+///
+/// Each core waiting for the lock takes a ticket and spins until the lock
+/// serves it, so no core can lose the race for the lock indefinitely. The lock
+/// leaves IRQs alone: data also used by code that runs on an IRQ must be
+/// protected by a `Mutex` instead.
+///
+/// The lock is not reentrant: a process locking it again while holding it
+/// would wait for itself forever.
+#[repr(align(32))]
+pub struct SpinLock<T> {
+    data: UnsafeCell<T>,
+    /// The next ticket to hand out.
+    next: AtomicUsize,
+    /// The ticket of the holder, or the next ticket if the lock is free.
+    serving: AtomicUsize,
+    /// The core holding the lock, or `NO_OWNER`.
+    owner: AtomicUsize,
+    /// The ID of the process that acquired the lock.
+    pid: AtomicUsize,
+}
+
+unsafe impl<T: Send> Send for SpinLock<T> {}
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+
+pub struct SpinLockGuard<'a, T: 'a> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<'a, T> !Send for SpinLockGuard<'a, T> {}
+unsafe impl<'a, T: Sync> Sync for SpinLockGuard<'a, T> {}
+
+/// A ticket spinlock that masks IRQs on the core holding it: the IRQ-saving
+/// variant of `SpinLock`.
+///
+/// While a core holds any mutex, IRQs are masked on it: the holder can not be
+/// preempted, so the code that runs on an IRQ, which takes the scheduler and
+/// allocator locks among others, never waits for a lock held by the process
+/// it interrupted. Processes running kernel code at EL0 may mask IRQs because
+/// the kernel sets `SCTLR_EL1.UMA` while they run.
+///
+/// Since the holder can not be preempted, a core locking a mutex it already
+/// holds is locking it recursively: debug builds panic instead of waiting for
+/// themselves forever, naming the core and the process holding the lock.
+pub struct Mutex<T> {
+    lock: SpinLock<T>,
+}
+
+pub struct MutexGuard<'a, T: 'a> {
+    lock: &'a Mutex<T>,
+}
+
+impl<'a, T> !Send for MutexGuard<'a, T> {}
+unsafe impl<'a, T: Sync> Sync for MutexGuard<'a, T> {}
+
+/// The number of mutexes each core holds.
+#[cfg(not(test))]
+static mut HELD: [usize; CORES] = [0; CORES];
+/// The `DAIF` value to restore when each core releases its last mutex.
+#[cfg(not(test))]
+static mut SAVED_DAIF: [u64; CORES] = [0; CORES];
+
+/// Masks IRQs on the current core for one more mutex.
+fn mask_irqs() {
+    #[cfg(not(test))]
+    unsafe {
+        let daif: u64;
+        asm!("mrs $0, DAIF
+              msr DAIFSet, #0b0010"
+             : "=r"(daif)
+             :
+             : "memory"
+             : "volatile");
+        let core = smp::core();
+        if HELD[core] == 0 {
+            SAVED_DAIF[core] = daif;
+        }
+        HELD[core] += 1;
+    }
+}
+
+/// Undoes one `mask_irqs()`, restoring IRQs once no mutex is held.
+fn restore_irqs() {
+    #[cfg(not(test))]
+    unsafe {
+        let core = smp::core();
+        HELD[core] -= 1;
+        if HELD[core] == 0 {
+            asm!("msr DAIF, $0" :: "r"(SAVED_DAIF[core]) : "memory" : "volatile");
+        }
+    }
+}
+
+/// Returns the number of mutexes the current core holds.
+pub fn held() -> usize {
+    #[cfg(not(test))]
+    unsafe {
+        HELD[smp::core()]
+    }
+
+    #[cfg(test)]
+    { 0 }
+}
+
+/// Returns an identifier of the current core.
+#[cfg(not(test))]
+fn core() -> usize {
+    smp::core()
+}
+
+/// Returns an identifier of the current core. Tests run on several threads,
+/// which stand in for cores.
+#[cfg(test)]
+fn core() -> usize {
+    thread_local!(static CORE: u8 = 0);
+    CORE.with(|core| core as *const u8 as usize)
+}
+
+/// Waits a little while spinning for a lock.
+#[cfg(not(test))]
+fn relax() {
+    spin_loop_hint();
+}
+
+/// Waits a little while spinning for a lock. The thread standing in for the
+/// holder may need the CPU to release it.
+#[cfg(test)]
+fn relax() {
+    ::std::thread::yield_now();
+}
+
+impl<T> SpinLock<T> {
+    pub const fn new(val: T) -> SpinLock<T> {
+        SpinLock {
+            data: UnsafeCell::new(val),
+            next: AtomicUsize::new(0),
+            serving: AtomicUsize::new(0),
+            owner: AtomicUsize::new(NO_OWNER),
+            pid: AtomicUsize::new(0),
+        }
+    }
+
+    /// Acquires the lock if it is free, and returns `None` otherwise.
+    pub fn try_lock(&self) -> Option<SpinLockGuard<T>> {
+        if self.acquire_now() {
+            Some(SpinLockGuard { lock: &self })
+        } else {
+            None
+        }
+    }
+
+    /// Spins until the lock is acquired.
+    #[inline(never)]
+    pub fn lock(&self) -> SpinLockGuard<T> {
+        self.acquire();
+        SpinLockGuard { lock: &self }
+    }
+
+    /// Returns the core and the ID of the process holding the lock, or `None`
+    /// if it is free.
+    pub fn owner(&self) -> Option<(usize, u64)> {
+        match self.owner.load(Relaxed) {
+            NO_OWNER => None,
+            core => Some((core, self.pid.load(Relaxed) as u64)),
+        }
+    }
+
+    /// Takes the next ticket if it would be served right away.
+    fn acquire_now(&self) -> bool {
+        let serving = self.serving.load(Acquire);
+        let taken = self.next
+            .compare_exchange(serving, serving.wrapping_add(1), Acquire, Relaxed)
+            .is_ok();
+        if taken {
+            self.set_owner();
+        }
+        taken
+    }
+
+    /// Takes a ticket and spins until it is served.
+    fn acquire(&self) {
+        let ticket = self.next.fetch_add(1, Relaxed);
+        while self.serving.load(Acquire) != ticket {
+            relax();
+        }
+        self.set_owner();
+    }
+
+    fn set_owner(&self) {
+        self.owner.store(core(), Relaxed);
+        self.pid.store(smp::current().unwrap_or(0) as usize, Relaxed);
+    }
+
+    /// Serves the next ticket.
+    fn release(&self) {
+        self.owner.store(NO_OWNER, Relaxed);
+        self.serving.fetch_add(1, Release);
+    }
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(val: T) -> Mutex<T> {
+        Mutex {
+            lock: SpinLock::new(val),
+        }
+    }
+
+    /// Acquires the lock if it is free, masking IRQs, and returns `None`
+    /// otherwise.
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        mask_irqs();
+        if self.lock.acquire_now() {
+            Some(MutexGuard { lock: &self })
+        } else {
+            restore_irqs();
+            None
+        }
+    }
+
+    /// Masks IRQs and spins until the lock is acquired.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics if the current core already holds the lock.
+    #[inline(never)]
+    pub fn lock(&self) -> MutexGuard<T> {
+        mask_irqs();
+        #[cfg(debug_assertions)]
+        {
+            // With IRQs masked, the holder can not be preempted: if it runs
+            // on this core, it is the caller.
+            if let Some((core, pid)) = self.owner().filter(|&(owner, _)| owner == core()) {
+                panic!("recursive locking: the lock is held by core {} (process {})", core, pid);
+            }
+        }
+        self.lock.acquire();
+        MutexGuard { lock: &self }
+    }
+
+    /// Returns the core and the ID of the process holding the lock, or `None`
+    /// if it is free.
+    pub fn owner(&self) -> Option<(usize, u64)> {
+        self.lock.owner()
+    }
+}
+
+impl<'a, T: 'a> Deref for SpinLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: 'a> DerefMut for SpinLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: 'a> Drop for SpinLockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.release()
+    }
+}
+
+impl<'a, T: 'a> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.lock.data.get() }
+    }
+}
+
+impl<'a, T: 'a> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.lock.data.get() }
+    }
+}
+
+impl<'a, T: 'a> Drop for MutexGuard<'a, T> {
+    /// Releases the lock, then restores IRQs: an IRQ taken in between would
+    /// find the lock held by the code it interrupted.
+    fn drop(&mut self) {
+        self.lock.lock.release();
+        restore_irqs();
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SpinLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("SpinLock").field("data", &&*guard).finish(),
+            None => f.debug_struct("SpinLock").field("data", &"<locked>").finish(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("Mutex").field("data", &&*guard).finish(),
+            None => f.debug_struct("Mutex").field("data", &"<locked>").finish(),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::Arc;
+use std::thread;
+
+use mutex::{Mutex, SpinLock};
+
+#[test]
+fn test_try_lock() {
+    let lock = SpinLock::new(1);
+    {
+        let guard = lock.try_lock().expect("free lock");
+        assert_eq!(*guard, 1);
+        assert!(lock.try_lock().is_none());
+        assert!(lock.owner().is_some());
+    }
+    assert!(lock.owner().is_none());
+    *lock.lock() += 1;
+    assert_eq!(*lock.try_lock().expect("released lock"), 2);
+
+    let mutex = Mutex::new(());
+    let guard = mutex.lock();
+    assert!(mutex.try_lock().is_none());
+    assert!(mutex.owner().is_some());
+    drop(guard);
+    assert!(mutex.try_lock().is_some());
+}
+
+#[test]
+fn test_exclusion() {
+    const THREADS: usize = 4;
+    const ROUNDS: usize = 200;
+
+    let spinlock = Arc::new(SpinLock::new(0usize));
+    let mutex = Arc::new(Mutex::new(0usize));
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let (spinlock, mutex) = (spinlock.clone(), mutex.clone());
+            thread::spawn(move || {
+                for _ in 0..ROUNDS {
+                    // Updates would be lost if the lock let two threads in.
+                    let mut count = spinlock.lock();
+                    let value = *count;
+                    thread::yield_now();
+                    *count = value + 1;
+                    drop(count);
+
+                    let mut count = mutex.lock();
+                    let value = *count;
+                    thread::yield_now();
+                    *count = value + 1;
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(*spinlock.lock(), THREADS * ROUNDS);
+    assert_eq!(*mutex.lock(), THREADS * ROUNDS);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "recursive locking: the lock is held by core")]
+fn test_recursive_lock() {
+    let mutex = Mutex::new(0);
+    let _guard = mutex.lock();
+    let _again = mutex.lock();
+}
@@ -0,0 +1,282 @@
+use std::io;
+
+/// The four bytes every ELF file starts with.
+const MAGIC: &[u8] = b"\x7fELF";
+
+/// `EI_CLASS` of a 64-bit object.
+const CLASS_64: u8 = 2;
+
+/// `EI_DATA` of a little endian object.
+const DATA_LSB: u8 = 1;
+
+/// `e_machine` of an AArch64 object.
+const MACHINE_AARCH64: u16 = 183;
+
+/// The size of the ELF64 file header.
+const HEADER_SIZE: usize = 64;
+
+/// The size of an ELF64 program header.
+pub const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// The size of an ELF64 dynamic section entry.
+const DYN_SIZE: usize = 16;
+
+/// The size of an ELF64 relocation entry with an addend.
+const RELA_SIZE: usize = 24;
+
+/// The type of an ELF file, from `e_type`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Kind {
+    /// An executable linked to run at a fixed address (`ET_EXEC`).
+    Executable,
+    /// A position independent executable or shared object (`ET_DYN`).
+    Dynamic,
+    /// Any other type, such as a relocatable object or a core file.
+    Other(u16),
+}
+
+/// The type of a segment, from `p_type`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SegmentKind {
+    /// A segment to load into memory (`PT_LOAD`).
+    Load,
+    /// The dynamic linking information (`PT_DYNAMIC`).
+    Dynamic,
+    /// The path of a program interpreter (`PT_INTERP`).
+    Interp,
+    /// The program header table itself (`PT_PHDR`).
+    Phdr,
+    /// Any other type, which the loader ignores.
+    Other(u32),
+}
+
+impl From<u32> for SegmentKind {
+    fn from(kind: u32) -> SegmentKind {
+        match kind {
+            1 => SegmentKind::Load,
+            2 => SegmentKind::Dynamic,
+            3 => SegmentKind::Interp,
+            6 => SegmentKind::Phdr,
+            kind => SegmentKind::Other(kind),
+        }
+    }
+}
+
+/// A program header, describing one segment of an ELF file.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ProgramHeader {
+    pub kind: SegmentKind,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+/// A relocation of a loaded image.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Relocation {
+    /// Stores the load bias plus `addend` at `offset` (`R_AARCH64_RELATIVE`).
+    Relative { offset: u64, addend: u64 },
+}
+
+/// `R_AARCH64_RELATIVE`.
+const R_AARCH64_RELATIVE: u32 = 1027;
+
+/// Dynamic section tags used by the loader.
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    buf[offset] as u16 | (buf[offset + 1] as u16) << 8
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    buf[offset..offset + 4].iter().rev().fold(0, |value, &byte| value << 8 | byte as u32)
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u32_at(buf, offset) as u64 | (u32_at(buf, offset + 4) as u64) << 32
+}
+
+/// Returns the `len` bytes of `data` at `offset`, or an error if they are not
+/// all in `data`.
+fn slice(data: &[u8], offset: u64, len: u64) -> io::Result<&[u8]> {
+    let end = offset.checked_add(len).ok_or_else(|| invalid("offset overflows"))?;
+    if end > data.len() as u64 {
+        return Err(invalid("file is truncated"));
+    }
+    Ok(&data[offset as usize..end as usize])
+}
+
+/// A validated AArch64 ELF64 file.
+#[derive(Debug)]
+pub struct Elf<'a> {
+    data: &'a [u8],
+    /// The type of the file.
+    pub kind: Kind,
+    /// The virtual address of the entry point.
+    pub entry: u64,
+    /// The file offset of the program header table.
+    pub phoff: u64,
+    /// The program headers, in file order.
+    pub program_headers: Vec<ProgramHeader>,
+}
+
+impl<'a> Elf<'a> {
+    /// Parses and validates the ELF file in `data`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidData` if `data` is not a little endian
+    /// ELF64 file for AArch64, if its program header table is malformed or
+    /// truncated, or if a segment lies outside of `data` or has a file size
+    /// larger than its memory size.
+    pub fn parse(data: &'a [u8]) -> io::Result<Elf<'a>> {
+        if data.len() < HEADER_SIZE || &data[..4] != MAGIC {
+            return Err(invalid("not an ELF file"));
+        }
+        if data[4] != CLASS_64 || data[5] != DATA_LSB {
+            return Err(invalid("not a little endian ELF64 file"));
+        }
+        if u16_at(data, 18) != MACHINE_AARCH64 {
+            return Err(invalid("not an AArch64 ELF file"));
+        }
+
+        let kind = match u16_at(data, 16) {
+            2 => Kind::Executable,
+            3 => Kind::Dynamic,
+            kind => Kind::Other(kind),
+        };
+        let phoff = u64_at(data, 32);
+        let phentsize = u16_at(data, 54) as u64;
+        let phnum = u16_at(data, 56) as u64;
+        if phnum != 0 && phentsize != PROGRAM_HEADER_SIZE as u64 {
+            return Err(invalid("unexpected program header size"));
+        }
+
+        let table = slice(data, phoff, phentsize * phnum)?;
+        let mut program_headers = Vec::with_capacity(phnum as usize);
+        for entry in table.chunks(PROGRAM_HEADER_SIZE) {
+            let header = ProgramHeader {
+                kind: SegmentKind::from(u32_at(entry, 0)),
+                flags: u32_at(entry, 4),
+                offset: u64_at(entry, 8),
+                vaddr: u64_at(entry, 16),
+                filesz: u64_at(entry, 32),
+                memsz: u64_at(entry, 40),
+                align: u64_at(entry, 48),
+            };
+            if header.kind == SegmentKind::Load {
+                slice(data, header.offset, header.filesz)?;
+                if header.filesz > header.memsz {
+                    return Err(invalid("segment is larger in the file than in memory"));
+                }
+                if header.vaddr.checked_add(header.memsz).is_none() {
+                    return Err(invalid("segment overflows the address space"));
+                }
+                if header.align > 1 && !header.align.is_power_of_two() {
+                    return Err(invalid("segment alignment is not a power of two"));
+                }
+            }
+            program_headers.push(header);
+        }
+
+        Ok(Elf {
+            data,
+            kind,
+            entry: u64_at(data, 24),
+            phoff,
+            program_headers,
+        })
+    }
+
+    /// Returns an iterator over the `PT_LOAD` program headers.
+    pub fn segments<'b>(&'b self) -> impl Iterator<Item = &'b ProgramHeader> {
+        self.program_headers.iter().filter(|header| header.kind == SegmentKind::Load)
+    }
+
+    /// Returns the bytes of the segment described by `header` that are stored
+    /// in the file.
+    pub fn data(&self, header: &ProgramHeader) -> io::Result<&'a [u8]> {
+        slice(self.data, header.offset, header.filesz)
+    }
+
+    /// Returns whether the file names a program interpreter.
+    pub fn has_interpreter(&self) -> bool {
+        self.program_headers.iter().any(|header| header.kind == SegmentKind::Interp)
+    }
+
+    /// Returns the relocations listed in the dynamic section, if any.
+    ///
+    /// The dynamic section and the relocation table are located by file
+    /// offset, through the `PT_LOAD` segment that contains them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidData` if the dynamic section or the
+    /// relocation table is malformed, or if a relocation is not of a type the
+    /// loader supports.
+    pub fn relocations(&self) -> io::Result<Vec<Relocation>> {
+        let dynamic = match self.program_headers.iter().find(|h| h.kind == SegmentKind::Dynamic) {
+            Some(header) => slice(self.data, header.offset, header.filesz)?,
+            None => return Ok(Vec::new()),
+        };
+
+        let (mut rela, mut relasz, mut relaent) = (None, 0, RELA_SIZE as u64);
+        for entry in dynamic.chunks(DYN_SIZE).filter(|entry| entry.len() == DYN_SIZE) {
+            match u64_at(entry, 0) {
+                DT_NULL => break,
+                DT_RELA => rela = Some(u64_at(entry, 8)),
+                DT_RELASZ => relasz = u64_at(entry, 8),
+                DT_RELAENT => relaent = u64_at(entry, 8),
+                _ => {}
+            }
+        }
+
+        let rela = match rela {
+            Some(rela) => rela,
+            None => return Ok(Vec::new()),
+        };
+        if relaent != RELA_SIZE as u64 {
+            return Err(invalid("unexpected relocation entry size"));
+        }
+        let offset = self.file_offset(rela).ok_or_else(|| invalid("relocations are not loaded"))?;
+        let table = slice(self.data, offset, relasz)?;
+
+        let mut relocations = Vec::with_capacity(table.len() / RELA_SIZE);
+        for entry in table.chunks(RELA_SIZE).filter(|entry| entry.len() == RELA_SIZE) {
+            match u64_at(entry, 8) as u32 {
+                R_AARCH64_RELATIVE => relocations.push(Relocation::Relative {
+                    offset: u64_at(entry, 0),
+                    addend: u64_at(entry, 16),
+                }),
+                _ => return Err(invalid("unsupported relocation type")),
+            }
+        }
+        Ok(relocations)
+    }
+
+    /// Returns the file offset holding the virtual address `vaddr`, if a
+    /// `PT_LOAD` segment maps it from the file.
+    fn file_offset(&self, vaddr: u64) -> Option<u64> {
+        self.segments()
+            .find(|h| vaddr >= h.vaddr && vaddr - h.vaddr < h.filesz)
+            .map(|h| h.offset + (vaddr - h.vaddr))
+    }
+
+    /// Returns the virtual address the program header table is loaded at, if
+    /// a `PT_LOAD` segment maps it from the file.
+    pub fn phdr(&self) -> Option<u64> {
+        self.segments()
+            .find(|h| self.phoff >= h.offset && self.phoff - h.offset < h.filesz)
+            .map(|h| h.vaddr + (self.phoff - h.offset))
+    }
+}
@@ -0,0 +1,229 @@
+use std::{fmt, io, mem};
+use std::ptr::Unique;
+
+use FRAMES;
+use aarch64;
+use allocator::util::{align_down, align_up};
+use process::Stack;
+use process::elf::{Elf, Kind, Relocation};
+use vm::{AddressSpace, Permission, PhysicalAddr, PAGE_SIZE, USER_IMAGE_BASE, USER_STACK_TOP};
+
+/// `p_flags` bits of executable and writable segments.
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+/// The end of the addresses images may be mapped at: the user stack is below.
+const USER_IMAGE_END: usize = USER_STACK_TOP - Stack::SIZE;
+
+/// The memory holding the loaded segments of an ELF file.
+///
+/// The image is placed in contiguous frames wherever the frame allocator finds
+/// room, and mapped into its process's address space at its base address.
+/// Position independent executables are mapped at `USER_IMAGE_BASE` and
+/// relocated to run there. Executables linked to run at a fixed address are
+/// mapped at that address, which must be between `USER_IMAGE_BASE` and the
+/// user stack.
+pub struct Image {
+    ptr: Unique<u8>,
+    size: usize,
+    /// The virtual address the image is mapped at.
+    base: u64,
+    /// The difference between the address a byte is loaded at and its virtual
+    /// address in the file.
+    bias: u64,
+    entry: u64,
+    phdr: Option<u64>,
+}
+
+fn unsupported(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl Image {
+    /// Copies the `PT_LOAD` segments of `elf` into newly allocated memory,
+    /// zeroing the rest of each segment (its `.bss`), applies the relocations
+    /// of its dynamic section and synchronizes the instruction cache with it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidData` if `elf` is not an executable,
+    /// is linked to run outside of the addresses images are mapped at, needs
+    /// a program interpreter, has no loadable segments or has relocations the
+    /// loader does not support. Returns an error of kind `Other` if the image
+    /// could not be allocated.
+    pub fn load(elf: &Elf) -> io::Result<Image> {
+        if let Kind::Other(_) = elf.kind {
+            return Err(unsupported("file is not an executable"));
+        }
+        if elf.has_interpreter() {
+            return Err(unsupported("dynamically linked executables are not supported"));
+        }
+
+        let start = elf.segments().map(|h| h.vaddr).min()
+            .ok_or_else(|| unsupported("executable has no loadable segments"))?;
+        let end = elf.segments().map(|h| h.vaddr + h.memsz).max().unwrap();
+        let start = align_down(start as usize, PAGE_SIZE);
+        let size = align_up(end as usize, PAGE_SIZE) - start;
+        let base = match elf.kind {
+            Kind::Dynamic => USER_IMAGE_BASE,
+            _ if start < USER_IMAGE_BASE => {
+                return Err(unsupported("executable is linked to run below the user image base"));
+            }
+            _ => start,
+        };
+        if base.checked_add(size).map_or(true, |end| end > USER_IMAGE_END) {
+            return Err(unsupported("executable does not fit below the user stack"));
+        }
+        let raw_ptr = FRAMES.alloc_contiguous(size / PAGE_SIZE, PAGE_SIZE)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "out of memory"))?
+            .as_mut_ptr();
+
+        let mut image = Image {
+            ptr: Unique::new(raw_ptr).unwrap(),
+            size,
+            base: base as u64,
+            bias: (base as u64).wrapping_sub(start as u64),
+            entry: 0,
+            phdr: None,
+        };
+        image.entry = image.address(elf.entry)
+            .ok_or_else(|| unsupported("entry point is not loaded"))?;
+        image.phdr = elf.phdr().and_then(|phdr| image.address(phdr));
+
+        {
+            let memory = image.as_mut_slice();
+            for byte in memory.iter_mut() {
+                *byte = 0;
+            }
+            for header in elf.segments() {
+                let offset = header.vaddr as usize - start;
+                let data = elf.data(header)?;
+                memory[offset..offset + data.len()].copy_from_slice(data);
+            }
+        }
+
+        for relocation in elf.relocations()? {
+            match relocation {
+                Relocation::Relative { offset, addend } => {
+                    let value = image.bias.wrapping_add(addend);
+                    let slot = image.address(offset)
+                        .filter(|&slot| slot + 8 <= image.end())
+                        .ok_or_else(|| unsupported("relocation is outside of the image"))?;
+                    let slot = unsafe { image.ptr.as_ptr().add((slot - image.base) as usize) };
+                    unsafe { (slot as *mut u64).write_unaligned(value) };
+                }
+            }
+        }
+
+        aarch64::sync_icache(image.ptr.as_ptr() as usize, image.size());
+        Ok(image)
+    }
+
+    /// Maps the pages of the image holding the segments of `elf`, which must be
+    /// the file the image was loaded from, into `space` from the image's base
+    /// address on. A page is writable or executable at EL0 if any
+    /// segment on it is.
+    ///
+    /// The address space takes over the frames of the mapped pages; the frames
+    /// of the pages between segments are freed, as are all the frames if an
+    /// error is returned.
+    pub fn map(self, elf: &Elf, space: &mut AddressSpace) -> io::Result<()> {
+        let mut flags = vec![None; self.size() / PAGE_SIZE];
+        for header in elf.segments().filter(|header| header.memsz > 0) {
+            let offset = |vaddr| (self.address(vaddr).unwrap() - self.base) as usize;
+            let (first, last) = (offset(header.vaddr), offset(header.vaddr + header.memsz - 1));
+            for page in &mut flags[first / PAGE_SIZE..last / PAGE_SIZE + 1] {
+                *page = Some(page.unwrap_or(0) | header.flags);
+            }
+        }
+
+        let (start, base) = (self.start().as_usize(), self.base as usize);
+        mem::forget(self);
+        let mut result = Ok(());
+        for (i, page) in flags.iter().enumerate() {
+            let frame = (start + i * PAGE_SIZE).into();
+            match *page {
+                Some(flags) if result.is_ok() => {
+                    let perm = Permission::user(flags & PF_W != 0, flags & PF_X != 0);
+                    result = space.map_frame((base + i * PAGE_SIZE).into(), frame, perm);
+                    if result.is_err() {
+                        FRAMES.release(frame);
+                    }
+                }
+                _ => {
+                    FRAMES.release(frame);
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns the virtual address the byte at virtual address `vaddr` of the
+    /// file is mapped at, or `None` if it is outside of the image.
+    fn address(&self, vaddr: u64) -> Option<u64> {
+        let address = vaddr.wrapping_add(self.bias);
+        if address >= self.base && address < self.end() {
+            Some(address)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the virtual address of the end of the image.
+    fn end(&self) -> u64 {
+        self.base + self.size as u64
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { ::std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.size) }
+    }
+
+    /// Returns the physical address of the start of the image.
+    pub fn start(&self) -> PhysicalAddr {
+        self.ptr.as_ptr().into()
+    }
+
+    /// Returns the size of the image in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the virtual address the image is mapped at.
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    /// Returns the load bias: the value added to every virtual address of the
+    /// file to find where it is mapped.
+    pub fn bias(&self) -> u64 {
+        self.bias
+    }
+
+    /// Returns the virtual address of the entry point.
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    /// Returns the virtual address of the loaded program header table, if it is
+    /// part of a loaded segment.
+    pub fn phdr(&self) -> Option<u64> {
+        self.phdr
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        FRAMES.release_contiguous(self.start(), self.size / PAGE_SIZE)
+    }
+}
+
+impl fmt::Debug for Image {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Image")
+            .field("start", &self.start())
+            .field("size", &self.size())
+            .field("base", &format_args!("{:#x}", self.base))
+            .field("entry", &format_args!("{:#x}", self.entry))
+            .finish()
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::mem::replace;
+
+use fat32::traits::FileSystem;
+use mutex::Mutex;
+use process::{Id, Process, WaitQueue};
+use traps::TrapFrame;
+use user;
+use {FILE_SYSTEM, SCHEDULER};
+
+/// A program being read for a process that called `exec`.
+#[derive(Debug)]
+struct Load {
+    path: String,
+    /// The contents of the program, once read.
+    data: Option<io::Result<Vec<u8>>>,
+    /// The process that called `exec`, until the program is read.
+    waiters: WaitQueue,
+}
+
+/// The programs being read, by the ID of the process that called `exec`.
+static LOADS: Mutex<Option<BTreeMap<Id, Load>>> = Mutex::new(None);
+
+fn with<T, F: FnOnce(&mut BTreeMap<Id, Load>) -> T>(f: F) -> T {
+    let mut guard = LOADS.lock();
+    f(guard.get_or_insert_with(BTreeMap::new))
+}
+
+/// Returns the contents of the program at `path`, read for the process whose
+/// registers are `tf`.
+///
+/// Reading a file may wait for the disk, which a system call must not do, so
+/// programs are read by a loader process. If the program has not been read
+/// yet, this starts a loader, blocks the process until the loader is done,
+/// switching to the next process as by `GlobalScheduler::block_on()`, and
+/// returns `None`: the system call must then be restarted once the process
+/// runs again.
+pub fn read_program(path: &str, tf: &mut TrapFrame) -> Option<io::Result<Vec<u8>>> {
+    let id = tf.tpidr;
+    with(|loads| {
+        let read = loads.get(&id).map(|load| load.data.is_some());
+        match read {
+            Some(true) => loads.remove(&id).and_then(|load| load.data),
+            Some(false) => None,
+            None => {
+                if let Err(err) = start_loader(id) {
+                    return Some(Err(err));
+                }
+                let load = Load { path: path.to_string(), data: None, waiters: WaitQueue::new() };
+                let blocked = SCHEDULER.block_on(&mut loads.entry(id).or_insert(load).waiters, tf);
+                if blocked.is_none() {
+                    loads.remove(&id);
+                    return Some(Err(io::Error::new(io::ErrorKind::Other, "no current process")));
+                }
+                None
+            }
+        }
+    })
+}
+
+/// Starts a loader process reading the program for the process `caller`.
+fn start_loader(caller: Id) -> io::Result<()> {
+    let error = || io::Error::new(io::ErrorKind::Other, "could not start a loader");
+    let mut loader = Process::new().ok_or_else(error)?;
+    loader.trap_frame.sp = loader.stack.as_ref().unwrap().top().as_ptr() as u64;
+    loader.trap_frame.elr = loader_thread as *mut u8 as u64;
+    loader.trap_frame.spsr = 0b1101_00_0000; // To EL 0, currently only unmasking IRQ
+    loader.trap_frame.x0 = caller;
+    SCHEDULER.add(loader).map(|_| ()).ok_or_else(error)
+}
+
+/// The loader process: reads the program the process `caller` asked for,
+/// wakes it, and exits.
+extern "C" fn loader_thread(caller: Id) {
+    let path = with(|loads| loads.get(&caller).map(|load| load.path.clone()));
+    if let Some(path) = path {
+        let mut data = Vec::new();
+        let result = FILE_SYSTEM
+            .open_file(&path)
+            .and_then(|mut file| file.read_to_end(&mut data))
+            .map(|_| data);
+        let mut waiters = with(|loads| {
+            let load = loads.get_mut(&caller)?;
+            load.data = Some(result);
+            Some(replace(&mut load.waiters, WaitQueue::new()))
+        });
+        if let Some(ref mut waiters) = waiters {
+            SCHEDULER.wake_all(waiters);
+        }
+    }
+    user::exit(0);
+}
@@ -1,9 +1,23 @@
+pub mod elf;
+mod image;
+mod loader;
+mod policy;
 mod process;
 mod state;
 mod scheduler;
 mod stack;
+mod wait;
+
+#[cfg(test)]
+mod tests;
 
 pub use self::process::{Process, Id};
-pub use self::state::State;
-pub use self::scheduler::{GlobalScheduler, TICK};
+pub use self::state::{Exit, State};
+pub use self::scheduler::{GlobalScheduler, INIT, TICK};
+#[cfg(test)]
+pub(crate) use self::scheduler::Scheduler;
 pub use self::stack::Stack;
+pub use self::image::Image;
+pub use self::loader::read_program;
+pub use self::policy::{clamp_nice, Fair, Policy, Priority, RoundRobin, SchedInfo, MAX_NICE, MIN_NICE};
+pub use self::wait::{TimerQueue, WaitQueue};
@@ -0,0 +1,230 @@
+use std::cmp::max;
+use std::collections::VecDeque;
+use std::fmt;
+
+use process::{Id, Process};
+
+/// The lowest (most favored) nice value.
+pub const MIN_NICE: i64 = -20;
+
+/// The highest (least favored) nice value.
+pub const MAX_NICE: i64 = 19;
+
+/// The scheduling parameters of a process and the CPU time it has used.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct SchedInfo {
+    /// The nice value, from `MIN_NICE` to `MAX_NICE`: the lower it is, the
+    /// more CPU time the process gets.
+    pub nice: i64,
+    /// The total time the process has run, in microseconds.
+    pub runtime: u64,
+    /// The number of time slices the process has run for.
+    pub slices: u64,
+    /// The number of times the process was passed over while ready, since it
+    /// last ran. Used by `Priority`.
+    pub age: u64,
+    /// The time the process has run, in microseconds, weighted by its nice
+    /// value. Used by `Fair`.
+    pub vruntime: u64,
+    /// The core the process must run on, or `None` if it may run on any.
+    pub affinity: Option<usize>,
+    /// The core whose run queue holds the process.
+    pub core: usize,
+}
+
+impl SchedInfo {
+    /// Returns the scheduling information of a process forked from one with
+    /// this information: the nice value and the affinity are inherited,
+    /// nothing else is.
+    pub fn fork(&self) -> SchedInfo {
+        SchedInfo { nice: self.nice, affinity: self.affinity, ..SchedInfo::default() }
+    }
+}
+
+/// Returns `nice` clamped to the range of nice values.
+pub fn clamp_nice(nice: i64) -> i64 {
+    max(MIN_NICE, nice.min(MAX_NICE))
+}
+
+/// A scheduling policy: decides which ready process runs next.
+///
+/// The scheduler keeps the processes of each core in a queue, in the order
+/// they last ran, the least recent first, and the running process at the
+/// front. Each core has its own policy, which keeps its per-process state in
+/// the processes' `SchedInfo`.
+pub trait Policy: fmt::Debug + Send {
+    /// Returns the name of the policy.
+    fn name(&self) -> &'static str;
+
+    /// Prepares `process` to be scheduled, when it is added to the scheduler
+    /// or when the policy starts being used.
+    fn admit(&mut self, _process: &mut Process) {}
+
+    /// Accounts for `process` having run for `ran` microseconds.
+    fn charge(&mut self, _process: &mut Process, _ran: u64) {}
+
+    /// Returns the index in `processes` of the ready process to run next,
+    /// never choosing the process whose ID is `idle`, or `None` if no other
+    /// process is ready.
+    fn pick(&mut self, processes: &mut VecDeque<Process>, idle: Option<Id>) -> Option<usize>;
+
+    /// Returns whether `woken`, which just became ready, should run in place
+    /// of `running` right away instead of when the time slice ends.
+    fn preempts(&self, _woken: &Process, _running: &Process) -> bool {
+        false
+    }
+}
+
+/// Returns whether `process` may be picked to run.
+fn is_runnable(process: &Process, idle: Option<Id>) -> bool {
+    process.is_ready() && Some(process.trap_frame.tpidr) != idle
+}
+
+/// Runs the ready processes in turn, regardless of their nice values.
+#[derive(Debug, Default)]
+pub struct RoundRobin;
+
+impl Policy for RoundRobin {
+    fn name(&self) -> &'static str {
+        "round-robin"
+    }
+
+    fn pick(&mut self, processes: &mut VecDeque<Process>, idle: Option<Id>) -> Option<usize> {
+        processes.iter().position(|process| is_runnable(process, idle))
+    }
+}
+
+/// Runs the ready process with the highest priority, the one that ran the
+/// least recently among equals.
+///
+/// A process's priority is set by its nice value and rises by one each time
+/// it is passed over while ready, until it runs. A process niced `n` below
+/// another thus runs `n` times in a row, at most, while the other waits.
+#[derive(Debug, Default)]
+pub struct Priority;
+
+impl Priority {
+    /// Returns the current priority of `process`: the higher, the sooner it
+    /// runs.
+    pub fn priority(process: &Process) -> i64 {
+        let sched = &process.sched;
+        MAX_NICE - sched.nice + sched.age as i64
+    }
+}
+
+impl Policy for Priority {
+    fn name(&self) -> &'static str {
+        "priority"
+    }
+
+    fn pick(&mut self, processes: &mut VecDeque<Process>, idle: Option<Id>) -> Option<usize> {
+        let mut next: Option<usize> = None;
+        for (index, process) in processes.iter().enumerate() {
+            if is_runnable(process, idle)
+                && next.map_or(true, |next| Priority::priority(process) > Priority::priority(&processes[next]))
+            {
+                next = Some(index);
+            }
+        }
+
+        let next = next?;
+        for (index, process) in processes.iter_mut().enumerate() {
+            if index == next {
+                process.sched.age = 0;
+            } else if is_runnable(process, idle) {
+                process.sched.age += 1;
+            }
+        }
+        Some(next)
+    }
+
+    fn preempts(&self, woken: &Process, running: &Process) -> bool {
+        Priority::priority(woken) > Priority::priority(running)
+    }
+}
+
+/// The weight of a process with a nice value of 0 under `Fair`.
+const NICE_0_WEIGHT: u64 = 1024;
+
+/// The weights of nice values under `Fair`, from `MIN_NICE` to `MAX_NICE`:
+/// each nice level gets about 1.25 times the CPU time of the next.
+const WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291,
+    29154, 23254, 18705, 14949, 11916,
+    9548, 7620, 6100, 4904, 3906,
+    3121, 2501, 1991, 1586, 1277,
+    1024, 820, 655, 526, 423,
+    335, 272, 215, 172, 137,
+    110, 87, 70, 56, 45,
+    36, 29, 23, 18, 15,
+];
+
+/// The most virtual runtime, in microseconds, a process that was not ready
+/// for a while is behind the others when it becomes ready again, so that it
+/// can not monopolize the CPU to catch up.
+const SLEEPER_CREDIT: u64 = 20 * 1000;
+
+/// How much more virtual runtime, in microseconds, a running process must
+/// have than a woken one to be preempted by it, so that wakeups do not switch
+/// processes back and forth.
+const WAKEUP_GRANULARITY: u64 = 1000;
+
+/// Shares the CPU between the ready processes in proportion to the weights of
+/// their nice values, like Linux's CFS.
+///
+/// Every process accumulates virtual runtime: the time it runs, scaled down
+/// the more it is favored. The ready process with the least virtual runtime
+/// runs next.
+#[derive(Debug, Default)]
+pub struct Fair {
+    /// A lower bound of the virtual runtime of the ready processes, which
+    /// only increases.
+    min_vruntime: u64,
+}
+
+impl Fair {
+    /// Returns the weight of a process with the nice value `nice`.
+    pub fn weight(nice: i64) -> u64 {
+        WEIGHTS[(clamp_nice(nice) - MIN_NICE) as usize]
+    }
+}
+
+impl Policy for Fair {
+    fn name(&self) -> &'static str {
+        "fair"
+    }
+
+    fn admit(&mut self, process: &mut Process) {
+        process.sched.vruntime = self.min_vruntime;
+    }
+
+    fn charge(&mut self, process: &mut Process, ran: u64) {
+        process.sched.vruntime += ran * NICE_0_WEIGHT / Fair::weight(process.sched.nice);
+    }
+
+    fn pick(&mut self, processes: &mut VecDeque<Process>, idle: Option<Id>) -> Option<usize> {
+        let floor = self.min_vruntime.saturating_sub(SLEEPER_CREDIT);
+        let mut next: Option<(usize, u64)> = None;
+        for (index, process) in processes.iter_mut().enumerate() {
+            if !is_runnable(process, idle) {
+                continue;
+            }
+            let vruntime = max(process.sched.vruntime, floor);
+            process.sched.vruntime = vruntime;
+            if next.map_or(true, |(_, least)| vruntime < least) {
+                next = Some((index, vruntime));
+            }
+        }
+
+        let (next, vruntime) = next?;
+        self.min_vruntime = max(self.min_vruntime, vruntime);
+        Some(next)
+    }
+
+    /// The running process's virtual runtime is the one charged when it last
+    /// stopped running.
+    fn preempts(&self, woken: &Process, running: &Process) -> bool {
+        let floor = self.min_vruntime.saturating_sub(SLEEPER_CREDIT);
+        max(woken.sched.vruntime, floor) + WAKEUP_GRANULARITY < running.sched.vruntime
+    }
+}
@@ -1,6 +1,15 @@
+use std::cmp::min;
+use std::io::{self, Read};
+use std::mem::size_of;
+use std::path::Path;
+use std::slice;
+
+use fat32::traits::FileSystem;
 use traps::TrapFrame;
-use process::{Stack, State};
-use std::mem::replace;
+use process::{Image, SchedInfo, Stack, State};
+use process::elf::{self, Elf};
+use vm::{AddressSpace, MemoryKind, Permission, PAGE_SIZE, USER_STACK_TOP};
+use FILE_SYSTEM;
 
 /// Type alias for the type of a process ID.
 pub type Id = u64;
@@ -10,60 +19,283 @@ pub type Id = u64;
 pub struct Process {
     /// The saved trap frame of a process.
     pub trap_frame: Box<TrapFrame>,
-    /// The memory allocation used for the process's stack.
-    pub stack: Stack,
+    /// The memory allocation used for the stack of a process running kernel
+    /// code. The stack of a user program belongs to its address space.
+    pub stack: Option<Stack>,
     /// The scheduling state of the process.
     pub state: State,
+    /// The translation tables used while the process runs.
+    pub address_space: AddressSpace,
+    /// The ID of the process this one was forked from, if any.
+    pub parent: Option<Id>,
+    /// The IDs of the processes forked from this one.
+    pub children: Vec<Id>,
+    /// The scheduling parameters and accounting of the process.
+    pub sched: SchedInfo,
+}
+
+fn out_of_memory() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "out of memory")
 }
 
+/// Auxiliary vector entry types passed to a program on its stack.
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_BASE: u64 = 7;
+const AT_ENTRY: u64 = 9;
+
+/// The most frames `backtrace()` returns.
+const MAX_BACKTRACE: usize = 16;
+
+/// The most bytes of arguments that can be passed to a program.
+const MAX_ARGS_SIZE: usize = Stack::SIZE / 4;
+
 impl Process {
     /// Creates a new process with a zeroed `TrapFrame` (the default), a zeroed
-    /// stack of the default size, and a state of `Ready`.
+    /// stack of the default size, and a state of `Ready`. The process runs
+    /// kernel code, so its address space is `AddressSpace::kernel()`.
     ///
     /// If enough memory could not be allocated to start the process, returns
     /// `None`. Otherwise returns `Some` of the new `Process`.
     pub fn new() -> Option<Process> {
-        match Stack::new() {
-            Some(stack) => Some(Process {
-                trap_frame: Box::new(TrapFrame::default()),
-                stack,
-                state: State::Ready,
-            }),
-            None => None,
+        let address_space = AddressSpace::kernel().ok()?;
+        let stack = Stack::new()?;
+        Some(Process::with(address_space, Some(stack)))
+    }
+
+    fn with(address_space: AddressSpace, stack: Option<Stack>) -> Process {
+        Process {
+            trap_frame: Box::new(TrapFrame::default()),
+            stack,
+            state: State::Ready,
+            address_space,
+            parent: None,
+            children: Vec::new(),
+            sched: SchedInfo::default(),
         }
     }
 
-    /// Returns `true` if this process is ready to be scheduled.
+    /// Creates a new process that runs the ELF executable in `data` at EL0
+    /// with the arguments `args`.
     ///
-    /// This functions returns `true` only if one of the following holds:
+    /// The program's segments are loaded with `Image::load()` and mapped into
+    /// an `AddressSpace::user()` at the image's base address with the
+    /// permissions of their segments, and the stack is mapped below `USER_STACK_TOP`. Its
+    /// stack starts with `argc`, then the `argv` and `envp` pointer arrays, each
+    /// terminated by a null pointer, then the auxiliary vector. The environment
+    /// is empty. `x0`, `x1` and `x2` also hold `argc`, `argv` and `envp`.
     ///
-    ///   * The state is currently `Ready`.
+    /// # Errors
     ///
-    ///   * An event being waited for has arrived.
+    /// Returns an error of kind `InvalidData` if `data` is not an executable
+    /// that can be loaded, of kind `InvalidInput` if the arguments do not fit
+    /// on the stack, or of kind `Other` if memory could not be allocated.
+    pub fn from_elf(data: &[u8], args: &[&str]) -> io::Result<Process> {
+        let elf = Elf::parse(data)?;
+        let image = Image::load(&elf)?;
+        let stack = Stack::new().ok_or_else(out_of_memory)?;
+        let mut process = Process::with(AddressSpace::user()?, None);
+
+        let auxv = [
+            (AT_PHDR, image.phdr().unwrap_or(0)),
+            (AT_PHENT, elf::PROGRAM_HEADER_SIZE as u64),
+            (AT_PHNUM, elf.program_headers.len() as u64),
+            (AT_PAGESZ, PAGE_SIZE as u64),
+            (AT_BASE, 0),
+            (AT_ENTRY, image.entry()),
+            (AT_NULL, 0),
+        ];
+        process.push_args(&stack, args, &auxv)?;
+        process.trap_frame.elr = image.entry();
+        process.trap_frame.spsr = 0b1101_00_0000; // To EL 0, currently only unmasking IRQ
+        image.map(&elf, &mut process.address_space)?;
+        stack.map(&mut process.address_space, USER_STACK_TOP.into())?;
+        Ok(process)
+    }
+
+    /// Creates a new process that runs the ELF executable at `path` with the
+    /// arguments `args`. See `from_elf()`.
+    pub fn load<P: AsRef<Path>>(path: P, args: &[&str]) -> io::Result<Process> {
+        let mut data = Vec::new();
+        FILE_SYSTEM.open_file(path)?.read_to_end(&mut data)?;
+        Process::from_elf(&data, args)
+    }
+
+    /// Copies `args` to the top of `stack` followed by `argc`, `argv`, an empty
+    /// `envp` and `auxv`, and points `sp`, `x0`, `x1` and `x2` at them as
+    /// mapped below `USER_STACK_TOP`.
+    fn push_args(&mut self, stack: &Stack, args: &[&str], auxv: &[(u64, u64)]) -> io::Result<()> {
+        let strings_size: usize = args.iter().map(|arg| arg.len() + 1).sum();
+        if strings_size > MAX_ARGS_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "arguments are too long"));
+        }
+
+        let top = stack.top().as_ptr() as u64;
+        let user = |address: u64| USER_STACK_TOP as u64 - (top - address);
+        let mut pointers = Vec::with_capacity(args.len());
+        let mut string = top;
+        for arg in args {
+            string -= arg.len() as u64 + 1;
+            unsafe {
+                let ptr = string as *mut u8;
+                ptr.copy_from_nonoverlapping(arg.as_ptr(), arg.len());
+                ptr.add(arg.len()).write(0);
+            }
+            pointers.push(user(string));
+        }
+
+        let mut words = vec![args.len() as u64];
+        words.extend(pointers);
+        words.push(0);
+        let envp = words.len();
+        words.push(0);
+        for &(kind, value) in auxv {
+            words.push(kind);
+            words.push(value);
+        }
+
+        let sp = (string - (words.len() * size_of::<u64>()) as u64) & !(Stack::ALIGN as u64 - 1);
+        unsafe {
+            (sp as *mut u64).copy_from_nonoverlapping(words.as_ptr(), words.len());
+        }
+
+        let sp = user(sp);
+        self.trap_frame.sp = sp;
+        self.trap_frame.x0 = args.len() as u64;
+        self.trap_frame.x1to29[0] = sp + size_of::<u64>() as u64;
+        self.trap_frame.x1to29[1] = sp + (envp * size_of::<u64>()) as u64;
+        Ok(())
+    }
+
+    /// Returns a copy of this process for `fork`, with the registers `tf` and
+    /// a state of `Ready`. The copy returns 0 from the system call; its parent
+    /// is the process whose ID is in `tf`.
     ///
-    ///     If the process is currently waiting, the corresponding event
-    ///     function is polled to determine if the event being waiting for has
-    ///     occured. If it has, the state is switched to `Ready` and this
-    ///     function returns `true`.
+    /// The address space is copied with `AddressSpace::fork()`, so the memory
+    /// of a user program is shared copy-on-write. A process running kernel
+    /// code shares all memory but its stack, which is copied and mapped in
+    /// place of the original in the copy's address space.
     ///
-    /// Returns `false` in all other cases.
-    pub fn is_ready(&mut self) -> bool {
-        if let State::Ready = self.state {
-            true
-        } else if let State::Running = self.state {
-            false
-        } else {
-            let state = replace(&mut self.state, State::Ready);
-            if let State::Waiting(mut event_poll_fn) = state {
-                if event_poll_fn(self) {
-                    true
-                } else {
-                    self.state = State::Waiting(event_poll_fn);
-                    false
-                }
-            } else {
-                unreachable!();
+    /// # Errors
+    ///
+    /// Returns an error of kind `Other` if memory could not be allocated.
+    pub fn fork(&mut self, tf: &TrapFrame) -> io::Result<Process> {
+        let mut address_space = self.address_space.fork()?;
+        let stack = match self.stack {
+            Some(ref stack) => {
+                let copy = stack.try_clone().ok_or_else(out_of_memory)?;
+                address_space.map_range(
+                    (stack.bottom().as_ptr() as usize).into(),
+                    copy.bottom(),
+                    Stack::SIZE,
+                    Permission::UserReadWrite,
+                    MemoryKind::Normal,
+                )?;
+                Some(copy)
+            }
+            None => None,
+        };
+
+        let mut process = Process::with(address_space, stack);
+        *process.trap_frame = *tf;
+        process.trap_frame.x0 = 0;
+        process.trap_frame.x1to29[6] = 0; // x7 = 0; succeed
+        process.parent = Some(tf.tpidr);
+        process.sched = self.sched.fork();
+        Ok(process)
+    }
+
+    /// Returns the return addresses of the process, whose registers are `tf`,
+    /// starting with the address of the current instruction: the frame
+    /// pointer chain is followed through the process's memory for at most
+    /// `MAX_BACKTRACE` frames, stopping at the first misaligned frame record
+    /// or one the process itself could not read.
+    pub fn backtrace(&self, tf: &TrapFrame) -> Vec<u64> {
+        let mut addresses = vec![tf.elr];
+        let mut fp = tf.x1to29[28];
+        while fp != 0 && fp % 16 == 0 && addresses.len() < MAX_BACKTRACE {
+            // A frame record is the caller's frame pointer, then the return
+            // address; both are on the same page since records are 16-byte
+            // aligned.
+            let record = match self.translate_readable(fp) {
+                Some(record) => record as *const u64,
+                None => break,
+            };
+            let (next, lr) = unsafe { (record.read(), record.add(1).read()) };
+            if lr == 0 {
+                break;
             }
+            addresses.push(lr);
+            fp = next;
+        }
+        addresses
+    }
+
+    /// Copies the `len` bytes at `va` in the process's address space, or
+    /// returns `None` if any of them is not normal memory the process itself
+    /// could read.
+    pub fn read_user(&self, va: u64, len: u64) -> Option<Vec<u8>> {
+        let end = va.checked_add(len)?;
+        let mut bytes = Vec::new();
+        let mut va = va;
+        while va < end {
+            let page_end = min((va / PAGE_SIZE as u64 + 1) * PAGE_SIZE as u64, end);
+            let ptr = self.translate_readable(va)?;
+            bytes.extend_from_slice(unsafe {
+                slice::from_raw_parts(ptr, (page_end - va) as usize)
+            });
+            va = page_end;
+        }
+        Some(bytes)
+    }
+
+    /// Returns a pointer the kernel reads `va` through if the process can read
+    /// it and it is normal memory.
+    fn translate_readable(&self, va: u64) -> Option<*const u8> {
+        let va = (va as usize).into();
+        let readable = self.address_space.permission(va).map_or(false, |perm| perm.is_user())
+            && self.address_space.kind(va) == Some(MemoryKind::Normal);
+        match self.address_space.translate(va) {
+            Some(pa) if readable => Some(pa.as_ptr()),
+            _ => None,
+        }
+    }
+
+    /// Returns the addresses of the bottom and the top of the process's stack
+    /// in its address space.
+    pub fn stack_bounds(&self) -> (u64, u64) {
+        match self.stack {
+            Some(ref stack) => (stack.bottom().as_ptr() as u64, stack.top().as_ptr() as u64),
+            None => ((USER_STACK_TOP - Stack::SIZE) as u64, USER_STACK_TOP as u64),
+        }
+    }
+
+    /// Returns `true` if this process is ready to be scheduled: blocked and
+    /// sleeping processes are made ready by the scheduler when they are woken,
+    /// and processes waiting for a child when the child exits.
+    pub fn is_ready(&self) -> bool {
+        match self.state {
+            State::Ready => true,
+            _ => false,
+        }
+    }
+
+    /// Returns whether this process is blocked on a wait queue.
+    pub fn is_blocked(&self) -> bool {
+        match self.state {
+            State::Blocked => true,
+            _ => false,
+        }
+    }
+
+    /// Returns whether this process has exited and waits to be reaped.
+    pub fn is_zombie(&self) -> bool {
+        match self.state {
+            State::Zombie(_) => true,
+            _ => false,
         }
     }
 }
@@ -1,36 +1,69 @@
-use std::collections::VecDeque;
+use std::cmp::min;
+use std::collections::{BTreeSet, VecDeque};
+use std::mem::replace;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
 
-use mutex::Mutex;
-use process::{Id, Process, State};
+use mutex::{Mutex, MutexGuard};
+use process::{clamp_nice, Exit, Id, Policy, Priority, Process, State, TimerQueue, WaitQueue, MIN_NICE};
+use smp::{self, CORES};
 use traps::TrapFrame;
+use vm::VirtualAddr;
+use block_thread;
+use idle_thread;
 use shell_thread;
 use shell_thread_2;
-use pi::interrupt::{Controller, Interrupt};
-use pi::timer::tick_in;
+#[cfg(not(test))]
+use pi::timer::current_time;
 
 /// The `tick` time.
 // FIXME: When you're ready, change this to something more reasonable.
 // pub const TICK: u32 = 2 * 1000 * 1000;
 pub const TICK: u32 = 10 * 1000; // 10ms
 
+/// The ID of the init process: the first process started, which adopts the
+/// children of exiting processes. The scheduler reaps the orphans init
+/// adopted, and processes without a parent, as soon as they exit, unless init
+/// is waiting for them.
+pub const INIT: Id = 1;
+
 /// Process scheduler for the entire machine.
+///
+/// The scheduler is created by `start()` and lives for as long as the machine
+/// runs. It is not behind a lock of its own: see `Scheduler` for its locks.
 #[derive(Debug)]
-pub struct GlobalScheduler(Mutex<Option<Scheduler>>);
+pub struct GlobalScheduler(AtomicPtr<Scheduler>);
 
 impl GlobalScheduler {
     /// Returns an uninitialized wrapper around a local scheduler.
     pub const fn uninitialized() -> GlobalScheduler {
-        GlobalScheduler(Mutex::new(None))
+        GlobalScheduler(AtomicPtr::new(ptr::null_mut()))
+    }
+
+    /// Returns the scheduler, or `None` if it has not been started.
+    fn get(&self) -> Option<&Scheduler> {
+        let scheduler = self.0.load(Ordering::Acquire);
+        if scheduler.is_null() {
+            None
+        } else {
+            Some(unsafe { &*scheduler })
+        }
+    }
+
+    /// Returns the scheduler.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the scheduler has not been started.
+    fn scheduler(&self) -> &Scheduler {
+        self.get().expect("scheduler uninitialized")
     }
 
     /// Adds a process to the scheduler's queue and returns that process's ID.
     /// For more details, see the documentation on `Scheduler::add()`.
     pub fn add(&self, process: Process) -> Option<Id> {
-        self.0
-            .lock()
-            .as_mut()
-            .expect("scheduler uninitialized")
-            .add(process)
+        self.scheduler().add(process)
     }
 
     /// Performs a context switch using `tf` by setting the state of the current
@@ -39,120 +72,1054 @@ impl GlobalScheduler {
     /// the documentation on `Scheduler::switch()`.
     #[must_use]
     pub fn switch(&self, new_state: State, tf: &mut TrapFrame) -> Option<Id> {
-        self.0
-            .lock()
-            .as_mut()
-            .expect("scheduler uninitialized")
-            .switch(new_state, tf)
+        self.scheduler().switch(new_state, tf)
+    }
+
+    /// Handles a timer interrupt or a reschedule request of the current core:
+    /// wakes the processes of the core whose sleep has ended, switches to the
+    /// next process as by `switch()` with the current process staying ready,
+    /// and programs the core's timer for the end of the next time slice or
+    /// the core's next deadline, whichever comes first.
+    #[must_use]
+    pub fn tick(&self, tf: &mut TrapFrame) -> Option<Id> {
+        let scheduler = self.scheduler();
+        let core = smp::core();
+        let now = clock();
+        scheduler.queue(core).expire(now);
+        let next = scheduler.switch(State::Ready, tf);
+        scheduler.queue(core).program_timer(now);
+        next
+    }
+
+    /// Puts the current process to sleep for `ms` milliseconds and performs a
+    /// context switch to the next process on `tf`, programming the timer as
+    /// by `tick()`. For more details, see the documentation on
+    /// `Scheduler::sleep()`.
+    #[must_use]
+    pub fn sleep(&self, ms: u32, tf: &mut TrapFrame) -> Option<Id> {
+        let scheduler = self.scheduler();
+        let now = clock();
+        let next = scheduler.sleep(now, now + ms as u64 * 1000, tf);
+        scheduler.queue(smp::core()).program_timer(now);
+        next
+    }
+
+    /// Blocks the current process on `queue` and performs a context switch to
+    /// the next process on `tf`. For more details, see the documentation on
+    /// `Scheduler::block_on()`.
+    #[must_use]
+    pub fn block_on(&self, queue: &mut WaitQueue, tf: &mut TrapFrame) -> Option<Id> {
+        self.scheduler().block_on(queue, tf)
+    }
+
+    /// Wakes the process that has waited the longest on `queue`, returning its
+    /// ID, or `None` if no process was woken. The queue may be used before
+    /// the scheduler starts, in which case nothing is woken.
+    pub fn wake_one(&self, queue: &mut WaitQueue) -> Option<Id> {
+        self.get()?.wake_one(queue)
+    }
+
+    /// Wakes every process waiting on `queue`, returning how many were
+    /// woken. See `wake_one()`.
+    pub fn wake_all(&self, queue: &mut WaitQueue) -> usize {
+        match self.get() {
+            Some(scheduler) => scheduler.wake_all(queue),
+            None => 0,
+        }
+    }
+
+    /// Replaces the current process with `process`, which keeps the current
+    /// process's ID, and restores its trap frame into `tf`. For more details,
+    /// see the documentation on `Scheduler::replace()`.
+    #[must_use]
+    pub fn replace(&self, process: Process, tf: &mut TrapFrame) -> Option<Id> {
+        self.scheduler().replace(process, tf)
+    }
+
+    /// Adds a copy of the current process, whose registers are `tf`, to the
+    /// scheduler's queue and returns the copy's ID. For more details, see the
+    /// documentation on `Scheduler::fork()`.
+    #[must_use]
+    pub fn fork(&self, tf: &mut TrapFrame) -> Option<Id> {
+        self.scheduler().fork(tf)
+    }
+
+    /// Terminates the current process for the reason `exit` and performs a
+    /// context switch to the next process on `tf`. For more details, see the
+    /// documentation on `Scheduler::exit()`.
+    #[must_use]
+    pub fn exit(&self, exit: Exit, tf: &mut TrapFrame) -> Option<Id> {
+        self.scheduler().exit(exit, tf)
     }
 
-    /// Initializes the scheduler and starts executing processes in user space
-    /// using timer interrupt based preemptive scheduling. This method should
-    /// not return under normal conditions.
+    /// Returns the backtrace of the current process, whose registers are
+    /// `tf`, or an empty list if there is no current process. For more
+    /// details, see the documentation on `Process::backtrace()`.
+    pub fn backtrace(&self, tf: &TrapFrame) -> Vec<u64> {
+        let scheduler = match self.get() {
+            Some(scheduler) => scheduler,
+            None => return Vec::new(),
+        };
+        let queue = scheduler.queue(smp::core());
+        match queue.current {
+            Some(_) => queue.processes[0].backtrace(tf),
+            None => Vec::new(),
+        }
+    }
+
+    /// Collects the exit status of a child of the current process, waiting
+    /// for one to exit if needed. For more details, see the documentation on
+    /// `Scheduler::wait()`.
+    #[must_use]
+    pub fn wait(&self, child: Option<Id>, tf: &mut TrapFrame) -> bool {
+        self.scheduler().wait(child, tf)
+    }
+
+    /// Resolves a write to the copy-on-write page at `va` in the current
+    /// process's address space. Returns `false` if there is no current
+    /// process, `va` is not a copy-on-write page or memory is exhausted.
+    pub fn copy_on_write(&self, va: VirtualAddr) -> bool {
+        match self.get() {
+            Some(scheduler) => scheduler.copy_on_write(va),
+            None => false,
+        }
+    }
+
+    /// Makes a policy returned by `policy` choose which process runs next on
+    /// each core from now on. Does nothing if the scheduler is uninitialized.
+    pub fn set_policy(&self, policy: fn() -> Box<Policy>) {
+        if let Some(scheduler) = self.get() {
+            scheduler.set_policy(policy);
+        }
+    }
+
+    /// Returns the name of the scheduling policy, or `None` if the scheduler
+    /// is uninitialized.
+    pub fn policy(&self) -> Option<&'static str> {
+        self.get().map(|scheduler| scheduler.queue(0).policy.name())
+    }
+
+    /// Sets the nice value of the process whose ID is `id` on behalf of the
+    /// process whose ID is `caller`. For more details, see the documentation
+    /// on `Scheduler::set_nice()`.
+    pub fn set_nice(&self, caller: Id, id: Id, nice: i64) -> Option<i64> {
+        self.get()?.set_nice(caller, id, nice)
+    }
+
+    /// Restricts the process whose ID is `id` to run on `core` only, or lets
+    /// it run on any core if `core` is `None`. For more details, see the
+    /// documentation on `Scheduler::set_affinity()`.
+    pub fn set_affinity(&self, id: Id, core: Option<usize>) -> bool {
+        match self.get() {
+            Some(scheduler) => scheduler.set_affinity(id, core),
+            None => false,
+        }
+    }
+
+    /// Returns whether the scheduler has been started.
+    pub fn is_running(&self) -> bool {
+        self.get().is_some()
+    }
+
+    /// Returns the IDs of every process, in increasing order. Returns an
+    /// empty list if the scheduler is uninitialized.
+    pub fn ids(&self) -> Vec<Id> {
+        match self.get() {
+            Some(scheduler) => scheduler.family.lock().ids.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Calls `f` with the process whose ID is `id` and returns its result, or
+    /// returns `None` if there is no such process.
+    ///
+    /// The trap frame of a running process is the one saved when it was last
+    /// switched out.
+    pub fn with_process<T, F>(&self, id: Id, f: F) -> Option<T>
+    where
+        F: FnOnce(&Process) -> T,
+    {
+        self.get()?.with_process(id, |process| f(process))
+    }
+
+    /// Initializes the scheduler, starts the secondary cores and starts
+    /// executing processes in user space on every core using timer interrupt
+    /// based preemptive scheduling. This method should not return under normal
+    /// conditions.
+    ///
+    /// The shells and the block worker start on core 0, and move to other
+    /// cores as they start. The worker is the most favored process, so that
+    /// requests are serviced soon after they are queued. Each core also gets
+    /// an idle process, which runs at EL1 with IRQs unmasked whenever no other
+    /// process is ready on the core.
     pub fn start(&self) {
-        let mut scheduler = Scheduler::new();
-        Controller::new().enable(Interrupt::Timer1);
-        tick_in(TICK);
+        let scheduler = Scheduler::new();
 
         let mut process = Process::new().unwrap();
-        process.trap_frame.sp = process.stack.top().as_u64();
+        process.trap_frame.sp = process.stack.as_ref().unwrap().top().as_ptr() as u64;
         process.trap_frame.elr = shell_thread as *mut u8 as u64;
         process.trap_frame.spsr = 0b1101_00_0000; // To EL 0, currently only unmasking IRQ
         scheduler.add(process).unwrap();
 
         let mut process2 = Process::new().unwrap();
-        process2.trap_frame.sp = process2.stack.top().as_u64();
+        process2.trap_frame.sp = process2.stack.as_ref().unwrap().top().as_ptr() as u64;
         process2.trap_frame.elr = shell_thread_2 as *mut u8 as u64;
         process2.trap_frame.spsr = 0b1101_00_0000; // To EL 0, currently only unmasking IRQ
         scheduler.add(process2).unwrap();
 
-        *self.0.lock() = Some(scheduler);
+        let mut worker = Process::new().unwrap();
+        worker.trap_frame.sp = worker.stack.as_ref().unwrap().top().as_ptr() as u64;
+        worker.trap_frame.elr = block_thread as *mut u8 as u64;
+        worker.trap_frame.spsr = 0b1101_00_0000; // To EL 0, currently only unmasking IRQ
+        worker.sched.nice = MIN_NICE;
+        scheduler.add(worker).unwrap();
+
+        // The scheduler is never dropped.
+        self.0.store(Box::into_raw(Box::new(scheduler)), Ordering::Release);
+        smp::start_secondary_cores();
+        self.run();
+    }
+
+    /// Starts executing processes on the current secondary core, once core 0
+    /// has initialized the scheduler. This method should not return under
+    /// normal conditions.
+    pub fn start_core(&self) {
+        self.run();
+    }
+
+    /// Adds the idle process of the current core, lets the core's run queue
+    /// take processes, and runs the first process on the core.
+    fn run(&self) {
+        let scheduler = self.scheduler();
+        let core = smp::core();
+        let mut idle = Process::new().unwrap();
+        idle.trap_frame.sp = idle.stack.as_ref().unwrap().top().as_ptr() as u64;
+        idle.trap_frame.elr = idle_thread as *mut u8 as u64;
+        idle.trap_frame.spsr = 0b1101_00_0100; // To EL 1 on SP_EL0, only unmasking IRQ
+        idle.sched.affinity = Some(core);
+
+        scheduler.cores[core].online.store(true, Ordering::SeqCst);
+        let idle = scheduler.add(idle);
+        scheduler.queue(core).idle = idle;
+        // The trap frame is boxed, so it stays put while the process runs. No
+        // lock may be held across the `eret`, which never returns.
+        #[cfg_attr(test, allow(unused_variables))]
+        let trap_frame = scheduler.enter(core);
+        smp::enable_interrupts();
+        scheduler.queue(core).program_timer(clock());
+        // `context_restore` leaves x0 alone, so it carries the stack top.
         #[cfg(not(test))]
         unsafe {
-            asm!("mov sp, $0
+            asm!("mov x0, $1
+              mov sp, $0
               bl context_restore
-              adr lr, _start
-              mov sp, lr
+              mov sp, x0
+              mov x0, xzr
               mov lr, xzr
-              eret" :: "r"(&*(self.0.lock().as_mut().unwrap()).processes[0].trap_frame) :: "volatile");
+              eret" :: "r"(trap_frame), "r"(smp::stack_top(core)) : "x0" : "volatile");
         };
     }
 }
 
+/// The processes of one core.
 #[derive(Debug)]
-struct Scheduler {
+struct RunQueue {
+    /// The core the run queue belongs to.
+    core: usize,
+    /// The processes, in the order they last ran, the least recent first. The
+    /// running process is at the front.
     processes: VecDeque<Process>,
+    /// The ID of the running process, or `None` until the core starts.
     current: Option<Id>,
-    last_id: Option<Id>,
+    /// The process run when no other is ready.
+    idle: Option<Id>,
+    /// Chooses the process to run next.
+    policy: Box<Policy>,
+    /// When the current process started running, in microseconds.
+    started: u64,
+    /// The sleeping processes of the core.
+    timers: TimerQueue,
 }
 
-impl Scheduler {
-    /// Returns a new `Scheduler` with an empty queue.
-    fn new() -> Scheduler {
-        Scheduler {
+impl RunQueue {
+    fn new(core: usize, policy: Box<Policy>) -> RunQueue {
+        RunQueue {
+            core,
             processes: VecDeque::new(),
             current: None,
-            last_id: None,
+            idle: None,
+            policy,
+            started: 0,
+            timers: TimerQueue::new(),
         }
     }
 
-    /// Adds a process to the scheduler's queue and returns that process's ID if
-    /// a new process can be scheduled. The process ID is newly allocated for
-    /// the process and saved in its `trap_frame`. If no further processes can
-    /// be scheduled, returns `None`.
-    ///
-    /// If this is the first process added, it is marked as the current process.
-    /// It is the caller's responsibility to ensure that the first time `switch`
-    /// is called, that process is executing on the CPU.
-    fn add(&mut self, mut process: Process) -> Option<Id> {
-        let id = self.last_id.get_or_insert(0);
+    /// Returns the number of processes waiting to run, besides the idle
+    /// process.
+    fn ready(&self) -> usize {
+        let idle = self.idle;
+        self.processes.iter()
+            .filter(|process| process.is_ready() && Some(process.trap_frame.tpidr) != idle)
+            .count()
+    }
+
+    /// Returns whether the core runs its idle process.
+    fn is_idle(&self) -> bool {
+        self.current.is_some() && self.current == self.idle
+    }
+
+    /// Returns the index of the process whose ID is `id`, if it is queued
+    /// here.
+    fn position(&self, id: Id) -> Option<usize> {
+        self.processes.iter().position(|process| process.trap_frame.tpidr == id)
+    }
 
-        // FIXME: handle overflow
-        *id += 1;
-        process.trap_frame.tpidr = *id;
+    /// Appends `process`, which the policy admits.
+    fn push(&mut self, mut process: Process) {
+        process.sched.core = self.core;
+        self.policy.admit(&mut process);
         self.processes.push_back(process);
+    }
 
-        if let None = self.current {
-            self.current = Some(*id);
+    /// Asks the core to reschedule, with an inter-processor interrupt, if the
+    /// process whose ID is `id` should preempt the process running there.
+    fn kick(&self, id: Id) {
+        if self.preempts(id) {
+            smp::reschedule(self.core);
         }
-
-        Some(*id)
     }
 
-    /// Sets the current process's state to `new_state`, finds the next process
-    /// to switch to, and performs the context switch on `tf` by saving `tf`
-    /// into the current process and restoring the next process's trap frame
-    /// into `tf`. If there is no current process, returns `None`. Otherwise,
-    /// returns `Some` of the process ID that was context switched into `tf`.
-    ///
-    /// This method blocks until there is a process to switch to, conserving
-    /// energy as much as possible in the interim.
-    fn switch(&mut self, new_state: State, tf: &mut TrapFrame) -> Option<Id> {
-        if self.current != Some(tf.tpidr) {
-            return None;
+    /// Returns whether the process whose ID is `id` is ready and should run
+    /// in place of the current process right away: if the core idles, or if
+    /// its policy says so.
+    fn preempts(&self, id: Id) -> bool {
+        let find = |id: Id| self.processes.iter().find(|process| process.trap_frame.tpidr == id);
+        let running = match self.current.and_then(find) {
+            Some(running) if running.trap_frame.tpidr != id => running,
+            _ => return false,
+        };
+        match find(id) {
+            Some(woken) if woken.is_ready() => {
+                self.is_idle() || self.policy.preempts(woken, running)
+            }
+            _ => false,
         }
+    }
+
+    /// Returns the index of a ready process that may move to `core`: any but
+    /// the idle process and those with an affinity for another core.
+    fn stealable(&self, core: usize) -> Option<usize> {
+        let idle = self.idle;
+        self.processes.iter().position(|process| {
+            process.is_ready()
+                && Some(process.trap_frame.tpidr) != idle
+                && process.sched.affinity.map_or(true, |affinity| affinity == core)
+        })
+    }
 
+    /// Takes the current process off the core, setting its state to
+    /// `new_state`, saving `tf` into it and charging it for the time it ran
+    /// until `now`. Returns the process, which is no longer queued.
+    fn take_current(&mut self, new_state: State, tf: &TrapFrame, now: u64) -> Process {
+        let current = self.current.expect("no current process");
+        let ran = now.saturating_sub(self.started);
         let mut process = self.processes.pop_front().unwrap();
         process.state = new_state;
         *process.trap_frame = *tf;
-        self.processes.push_back(process);
+        process.trap_frame.tpidr = current;
+        process.sched.runtime += ran;
+        process.sched.slices += 1;
+        self.policy.charge(&mut process, ran);
+        process
+    }
+
+    /// Moves the process the policy picks, or else the idle process, to the
+    /// front of the queue, activates its address space and runs it from
+    /// `now`. Returns its ID.
+    ///
+    /// The pick considers every process made ready so far, so the pending
+    /// reschedule requests of the core are acknowledged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no process, not even the idle process, is ready.
+    fn resume(&mut self, now: u64) -> Id {
+        let index = match self.policy.pick(&mut self.processes, self.idle) {
+            Some(index) => index,
+            None => self.processes.iter()
+                .position(|process| process.is_ready())
+                .expect("no process is ready to run"),
+        };
+        let mut process = self.processes.remove(index).unwrap();
+        process.address_space.activate();
+        process.state = State::Running;
+        let id = process.trap_frame.tpidr;
+        self.processes.push_front(process);
+        self.current = Some(id);
+        self.started = now;
+        smp::set_current(id);
+        smp::acknowledge();
+        id
+    }
+
+    /// Wakes the sleeping processes whose deadline is at or before `now`,
+    /// storing how long they slept, in milliseconds, in their `x0`. Returns
+    /// how many were woken.
+    fn expire(&mut self, now: u64) -> usize {
+        let mut woken = 0;
+        for id in self.timers.expire(now) {
+            let index = match self.position(id) {
+                Some(index) => index,
+                None => continue,
+            };
+            let process = &mut self.processes[index];
+            if let State::Sleeping { since, .. } = process.state {
+                process.trap_frame.x0 = (now - since) / 1000;
+                process.state = State::Ready;
+                woken += 1;
+            }
+        }
+        woken
+    }
+
+    /// Programs the timer of the core, which must be the current core, to
+    /// interrupt at the end of a time slice started at `now`, or at the next
+    /// deadline of a sleeping process if it is sooner.
+    fn program_timer(&self, now: u64) {
+        let slice = match self.timers.next_deadline() {
+            Some(deadline) => min(deadline.saturating_sub(now), TICK as u64),
+            None => TICK as u64,
+        };
+        smp::tick_in(slice.max(1) as u32);
+    }
+}
+
+/// A core: its run queue and what other cores know of it without locking it.
+#[derive(Debug)]
+struct Core {
+    queue: Mutex<RunQueue>,
+    /// Whether the core has started and takes processes.
+    online: AtomicBool,
+    /// The number of processes waiting to run on the core when its run queue
+    /// was last unlocked: a hint for balancing.
+    ready: AtomicUsize,
+}
+
+/// A locked run queue, which publishes its number of ready processes when it
+/// is unlocked.
+struct QueueGuard<'a> {
+    core: &'a Core,
+    queue: MutexGuard<'a, RunQueue>,
+}
+
+impl<'a> Deref for QueueGuard<'a> {
+    type Target = RunQueue;
 
+    fn deref(&self) -> &RunQueue {
+        &self.queue
+    }
+}
+
+impl<'a> DerefMut for QueueGuard<'a> {
+    fn deref_mut(&mut self) -> &mut RunQueue {
+        &mut self.queue
+    }
+}
+
+impl<'a> Drop for QueueGuard<'a> {
+    fn drop(&mut self) {
+        self.core.ready.store(self.queue.ready(), Ordering::Relaxed);
+    }
+}
+
+/// The process tree, and the IDs in use.
+#[derive(Debug)]
+struct Family {
+    last_id: Option<Id>,
+    /// The ID of every process.
+    ids: BTreeSet<Id>,
+    /// The processes `INIT` adopted.
+    orphans: BTreeSet<Id>,
+}
+
+/// The run queues of every core, and their locks.
+///
+/// Each core locks its own run queue to switch processes, so that cores do
+/// not wait for each other to schedule. Processes are moved between run
+/// queues, to follow their affinity or for a core to steal work, under the
+/// migration lock only: it is taken before any run queue's, and only its
+/// holder locks two run queues at once. Adding processes and changing the
+/// process tree (fork, exit, wait) take the family lock, before any other.
+///
+/// A process is found by its ID by searching the run queues one at a time;
+/// the search starts over if a process moved meanwhile, since it may have
+/// moved from a run queue not yet searched to one already searched.
+#[derive(Debug)]
+pub(crate) struct Scheduler {
+    cores: Vec<Core>,
+    migration: Mutex<()>,
+    family: Mutex<Family>,
+    /// The number of processes moved between run queues so far.
+    moves: AtomicUsize,
+}
+
+impl Scheduler {
+    /// Returns a new `Scheduler` with empty run queues using the `Priority`
+    /// policy, where only core 0 takes processes.
+    pub(crate) fn new() -> Scheduler {
+        let cores: Vec<Core> = (0..CORES)
+            .map(|core| Core {
+                queue: Mutex::new(RunQueue::new(core, Box::new(Priority))),
+                online: AtomicBool::new(core == 0),
+                ready: AtomicUsize::new(0),
+            })
+            .collect();
+        Scheduler {
+            cores,
+            migration: Mutex::new(()),
+            family: Mutex::new(Family {
+                last_id: None,
+                ids: BTreeSet::new(),
+                orphans: BTreeSet::new(),
+            }),
+            moves: AtomicUsize::new(0),
+        }
+    }
+
+    /// Locks the run queue of `core`.
+    fn queue(&self, core: usize) -> QueueGuard {
+        let core = &self.cores[core];
+        QueueGuard { core, queue: core.queue.lock() }
+    }
+
+    /// Returns whether `core` has started and takes processes.
+    fn is_online(&self, core: usize) -> bool {
+        core < CORES && self.cores[core].online.load(Ordering::SeqCst)
+    }
+
+    /// Calls `f` with the run queue holding the process whose ID is `id` and
+    /// the process's index in it, and returns its result, or `None` if there
+    /// is no such process.
+    fn locate<T, F>(&self, id: Id, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut RunQueue, usize) -> T,
+    {
         loop {
-            let num_processes = self.processes.len();
-            for _ in 0..num_processes {
-                let mut new_process = self.processes.pop_front().unwrap();
-                if new_process.is_ready() {
-                    *tf = *new_process.trap_frame;
-                    new_process.state = State::Running;
-                    self.current = Some(tf.tpidr);
-                    self.processes.push_front(new_process);
-                    return self.current;
-                } else {
-                    self.processes.push_back(new_process);
+            let moves = self.moves.load(Ordering::SeqCst);
+            for core in 0..CORES {
+                let mut queue = self.queue(core);
+                if let Some(index) = queue.position(id) {
+                    return Some(f(&mut queue, index));
+                }
+            }
+            if self.moves.load(Ordering::SeqCst) == moves {
+                return None;
+            }
+        }
+    }
+
+    /// Calls `f` with the process whose ID is `id` and returns its result, or
+    /// returns `None` if there is no such process.
+    pub(crate) fn with_process<T, F>(&self, id: Id, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut Process) -> T,
+    {
+        self.locate(id, |queue, index| f(&mut queue.processes[index]))
+    }
+
+    /// Moves `process`, taken off the run queue `from`, to the run queue `to`,
+    /// whose policy admits it, along with its timer if it sleeps. The caller
+    /// holds the migration lock.
+    fn migrate(&self, from: &mut RunQueue, to: &mut RunQueue, process: Process) {
+        let id = process.trap_frame.tpidr;
+        if let State::Sleeping { until, .. } = process.state {
+            from.timers.remove(id);
+            to.timers.insert(until, id);
+            // The core programs its timer for the new deadline as it
+            // reschedules.
+            smp::reschedule(to.core);
+        }
+        to.push(process);
+        to.kick(id);
+        self.moves.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Adds a process to the run queue of the core it has an affinity for, or
+    /// of the online core with the fewest processes, and returns that
+    /// process's ID if a new process can be scheduled. The process ID is newly
+    /// allocated for the process and saved in its `trap_frame`. If no further
+    /// processes can be scheduled, returns `None`.
+    ///
+    /// The process's core is asked to reschedule if the process should
+    /// preempt the one running there.
+    pub(crate) fn add(&self, process: Process) -> Option<Id> {
+        self.spawn(&mut self.family.lock(), process)
+    }
+
+    /// Adds `process` as by `add()`, with the family lock held.
+    fn spawn(&self, family: &mut Family, mut process: Process) -> Option<Id> {
+        let id = alloc_id(family);
+        process.trap_frame.tpidr = id;
+        let core = match process.sched.affinity {
+            Some(core) if self.is_online(core) => core,
+            _ => (0..CORES)
+                .filter(|&core| self.is_online(core))
+                .min_by_key(|&core| self.queue(core).processes.len())
+                .unwrap_or(0),
+        };
+        family.ids.insert(id);
+        let mut queue = self.queue(core);
+        queue.push(process);
+        queue.kick(id);
+        Some(id)
+    }
+
+    /// Returns whether the process whose ID is `id`, on the run queue of
+    /// `core`, is ready and should run in place of the core's current process
+    /// right away: if the core idles, or if its policy says so.
+    #[cfg(test)]
+    pub(crate) fn preempts(&self, core: usize, id: Id) -> bool {
+        self.queue(core).preempts(id)
+    }
+
+    /// Replaces the scheduling policy of every core with one returned by
+    /// `policy`, which admits every process of the core.
+    pub(crate) fn set_policy(&self, policy: fn() -> Box<Policy>) {
+        for core in 0..CORES {
+            let mut queue = self.queue(core);
+            let mut policy = policy();
+            for process in queue.processes.iter_mut() {
+                policy.admit(process);
+            }
+            queue.policy = policy;
+        }
+    }
+
+    /// Sets the nice value of the process whose ID is `id` to `nice`, clamped
+    /// to the range of nice values, and returns the value set.
+    ///
+    /// A process may only change its own nice value and the nice values of
+    /// its children. Returns `None` if there is no such process or it is
+    /// neither `caller` nor a child of `caller`.
+    pub(crate) fn set_nice(&self, caller: Id, id: Id, nice: i64) -> Option<i64> {
+        self.with_process(id, |process| {
+            if id != caller && process.parent != Some(caller) {
+                return None;
+            }
+            process.sched.nice = clamp_nice(nice);
+            Some(process.sched.nice)
+        })?
+    }
+
+    /// Restricts the process whose ID is `id` to `core`, or lets it run
+    /// anywhere if `core` is `None`. A process that is not running moves to
+    /// the run queue of `core` right away; a running one when it stops
+    /// running.
+    ///
+    /// Returns `false` if there is no such process or `core` is not online.
+    fn set_affinity(&self, id: Id, core: Option<usize>) -> bool {
+        if core.map_or(false, |core| !self.is_online(core)) {
+            return false;
+        }
+        let _migration = self.migration.lock();
+        for from in 0..CORES {
+            let mut queue = self.queue(from);
+            let index = match queue.position(id) {
+                Some(index) => index,
+                None => continue,
+            };
+            queue.processes[index].sched.affinity = core;
+
+            match core {
+                Some(core) if core != from && queue.current != Some(id) => {
+                    let process = queue.processes.remove(index).unwrap();
+                    self.migrate(&mut queue, &mut self.queue(core), process);
                 }
+                _ => {}
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Adds a copy of the current process, made with `Process::fork()` from the
+    /// current registers `tf`, to the queue and links it to the current
+    /// process as its child. The child's ID is returned and is also what the
+    /// current process gets back from the system call, in `x0` of `tf`.
+    ///
+    /// If there is no current process or it could not be copied, returns
+    /// `None`.
+    pub(crate) fn fork(&self, tf: &mut TrapFrame) -> Option<Id> {
+        let mut family = self.family.lock();
+        let core = smp::core();
+        let (parent, mut child) = {
+            let mut queue = self.queue(core);
+            let parent = queue.current?;
+            (parent, queue.processes[0].fork(tf).ok()?)
+        };
+        child.parent = Some(parent);
+        let id = self.spawn(&mut family, child)?;
+        self.queue(core).processes[0].children.push(id);
+        tf.x0 = id;
+        Some(id)
+    }
+
+    /// Removes the zombie process whose ID is `id` from its run queue, and
+    /// from its parent's children, releasing its memory. Returns why it
+    /// exited, or `None` if there is no such zombie.
+    fn reap(&self, family: &mut Family, id: Id) -> Option<Exit> {
+        let process = self.locate(id, |queue, index| {
+            if queue.processes[index].is_zombie() {
+                queue.processes.remove(index)
+            } else {
+                None
             }
-            #[cfg(not(test))]
-            unsafe { asm!("wfi") }
+        })??;
+        family.ids.remove(&id);
+        family.orphans.remove(&id);
+        if let Some(parent) = process.parent {
+            self.with_process(parent, |parent| parent.children.retain(|&child| child != id));
+        }
+        match process.state {
+            State::Zombie(exit) => Some(exit),
+            _ => unreachable!(),
         }
     }
+
+    /// Terminates the current process for the reason `exit`: it becomes a
+    /// zombie that keeps it until its parent collects it with `wait()`, and the
+    /// next process is switched into `tf` as by `switch()`. The process's
+    /// children are adopted by `INIT`.
+    ///
+    /// If the parent is already waiting for the process, it is made ready with
+    /// the process's ID and exit status. The zombie is then reaped right away
+    /// and its memory released, as it is if init adopted the process or if it
+    /// has no parent. If there is no current process, returns `None`.
+    /// Otherwise, returns `Some` of the process ID that was context switched
+    /// into `tf`.
+    pub(crate) fn exit(&self, exit: Exit, tf: &mut TrapFrame) -> Option<Id> {
+        let mut family = self.family.lock();
+        let (id, parent, children) = {
+            let mut queue = self.queue(smp::core());
+            let id = queue.current?;
+            let process = &mut queue.processes[0];
+            (id, process.parent, replace(&mut process.children, Vec::new()))
+        };
+
+        let adoptive = if id == INIT { None } else { Some(INIT) };
+        for &child in &children {
+            self.with_process(child, |process| process.parent = adoptive);
+        }
+        if let Some(init) = adoptive {
+            self.with_process(init, |init| init.children.extend(&children));
+            family.orphans.extend(&children);
+        }
+        for child in children {
+            self.reap(&mut family, child);
+        }
+
+        let orphan = family.orphans.contains(&id);
+        let notified = parent.and_then(|parent| self.locate(parent, |queue, index| {
+            let waiting = {
+                let parent = &mut queue.processes[index];
+                match parent.state {
+                    State::WaitingForChild(target) if target.map_or(true, |t| t == id) => {
+                        parent.trap_frame.x0 = id;
+                        parent.trap_frame.x1to29[0] = exit.status();
+                        parent.trap_frame.x1to29[6] = 0; // x7 = 0; succeed
+                        parent.state = State::Ready;
+                        true
+                    }
+                    _ => false,
+                }
+            };
+            if waiting {
+                queue.kick(queue.processes[index].trap_frame.tpidr);
+            }
+            waiting
+        }));
+        let reaped = match notified {
+            Some(true) | None => true,
+            Some(false) => orphan,
+        };
+
+        // The zombie's address space is active until the switch.
+        let next = self.switch(State::Zombie(exit), tf);
+        if reaped {
+            self.reap(&mut family, id);
+        }
+        next
+    }
+
+    /// Collects the exit status of the child of the current process whose ID
+    /// is `child`, or of any of its children if `None`. If such a child is a
+    /// zombie, it is reaped, and its ID and status are returned in `x0` and
+    /// `x1` of `tf`. Otherwise the current process waits, and the next process
+    /// is switched into `tf`; the results are stored when the child exits.
+    ///
+    /// Returns `false` if there is no current process or it has no such child.
+    pub(crate) fn wait(&self, child: Option<Id>, tf: &mut TrapFrame) -> bool {
+        let mut family = self.family.lock();
+        let children: Vec<Id> = {
+            let queue = self.queue(smp::core());
+            if queue.current.is_none() {
+                return false;
+            }
+            queue.processes[0].children.iter()
+                .cloned()
+                .filter(|&id| child.map_or(true, |child| child == id))
+                .collect()
+        };
+        if children.is_empty() {
+            return false;
+        }
+
+        for id in children {
+            if let Some(exit) = self.reap(&mut family, id) {
+                tf.x0 = id;
+                tf.x1to29[0] = exit.status();
+                tf.x1to29[6] = 0; // x7 = 0; succeed
+                return true;
+            }
+        }
+        self.switch(State::WaitingForChild(child), tf).is_some()
+    }
+
+    /// Adds the current process to `queue` and blocks it until it is woken by
+    /// `wake_one()` or `wake_all()`. The next process is switched into `tf` as
+    /// by `switch()`. The process resumes with the registers it blocked with,
+    /// so the result of a system call must be stored in `tf` beforehand.
+    ///
+    /// If there is no current process, returns `None`. Otherwise, returns
+    /// `Some` of the process ID that was context switched into `tf`.
+    pub(crate) fn block_on(&self, queue: &mut WaitQueue, tf: &mut TrapFrame) -> Option<Id> {
+        let id = self.queue(smp::core()).current?;
+        queue.push(id);
+        self.switch(State::Blocked, tf)
+    }
+
+    /// Makes the blocked process whose ID is `id` ready, asking its core to
+    /// reschedule if the process should preempt the one running there.
+    /// Returns `false` if there is no such blocked process.
+    fn wake(&self, id: Id) -> bool {
+        self.locate(id, |queue, index| {
+            if !queue.processes[index].is_blocked() {
+                return false;
+            }
+            queue.processes[index].state = State::Ready;
+            queue.kick(id);
+            true
+        }).unwrap_or(false)
+    }
+
+    /// Removes processes from the front of `queue` until one of them is
+    /// woken, and returns its ID. Returns `None` if the queue ran empty.
+    fn wake_one(&self, queue: &mut WaitQueue) -> Option<Id> {
+        while let Some(id) = queue.pop() {
+            if self.wake(id) {
+                return Some(id);
+            }
+        }
+        None
+    }
+
+    /// Wakes every process of `queue`, emptying it, and returns how many were
+    /// woken.
+    pub(crate) fn wake_all(&self, queue: &mut WaitQueue) -> usize {
+        let mut woken = 0;
+        while self.wake_one(queue).is_some() {
+            woken += 1;
+        }
+        woken
+    }
+
+    /// Puts the current process to sleep from `now` until `deadline`, both in
+    /// microseconds, and switches the next process into `tf` as by
+    /// `switch()`. The process is woken by its core's `tick()` once the
+    /// deadline has passed, with the elapsed time in milliseconds in `x0`. If
+    /// the deadline has already passed, the process only yields, and gets 0 in
+    /// `x0`.
+    ///
+    /// If there is no current process, returns `None`. Otherwise, returns
+    /// `Some` of the process ID that was context switched into `tf`.
+    fn sleep(&self, now: u64, deadline: u64, tf: &mut TrapFrame) -> Option<Id> {
+        if deadline <= now {
+            self.queue(smp::core()).current?;
+            tf.x0 = 0;
+            return self.switch(State::Ready, tf);
+        }
+        {
+            let mut queue = self.queue(smp::core());
+            let id = queue.current?;
+            queue.timers.insert(deadline, id);
+        }
+        self.switch(State::Sleeping { since: now, until: deadline }, tf)
+    }
+
+    /// Resolves a write to the copy-on-write page at `va` in the current
+    /// process's address space. See `AddressSpace::copy_on_write()`.
+    fn copy_on_write(&self, va: VirtualAddr) -> bool {
+        let mut queue = self.queue(smp::core());
+        let current = queue.current;
+        match queue.processes.front_mut() {
+            Some(ref mut process) if current == Some(process.trap_frame.tpidr) => {
+                process.address_space.copy_on_write(va).unwrap_or(false)
+            }
+            _ => false,
+        }
+    }
+
+    /// Replaces the current process with `process`, dropping the current
+    /// process's stack and address space. The new process takes over the
+    /// current process's ID, parent, children and scheduling parameters and
+    /// starts running by restoring its trap frame into `tf`.
+    /// If there is no current process, returns `None`. Otherwise, returns
+    /// `Some` of the process ID.
+    fn replace(&self, mut process: Process, tf: &mut TrapFrame) -> Option<Id> {
+        let mut queue = self.queue(smp::core());
+        let id = queue.current?;
+        let current = &mut queue.processes[0];
+        process.trap_frame.tpidr = id;
+        process.parent = current.parent;
+        process.children = replace(&mut current.children, Vec::new());
+        process.sched = current.sched;
+        process.state = State::Running;
+        *tf = *process.trap_frame;
+        process.address_space.activate();
+        *current = process;
+        Some(id)
+    }
+
+    /// Moves a ready process to `queue` from the core with the most ready
+    /// processes, if that core has at least two more than `queue`, or any
+    /// while `queue` has none. Processes with an affinity for another core
+    /// stay where they are. The caller holds the migration lock.
+    fn balance(&self, queue: &mut RunQueue) {
+        let core = queue.core;
+        let ours = queue.ready();
+        let busiest = (0..CORES)
+            .filter(|&other| other != core)
+            .max_by_key(|&other| self.queue(other).ready());
+        let mut busiest = match busiest {
+            Some(busiest) => self.queue(busiest),
+            None => return,
+        };
+        let theirs = busiest.ready();
+        if theirs == 0 || (ours != 0 && theirs < ours + 2) {
+            return;
+        }
+
+        if let Some(index) = busiest.stealable(core) {
+            let process = busiest.processes.remove(index).unwrap();
+            self.migrate(&mut busiest, queue, process);
+        }
+    }
+
+    /// Returns whether switching the current process of `queue` out, into
+    /// the state `new_state`, moves processes between run queues: if it has
+    /// an affinity for another core, or if the core would balance its run
+    /// queue, judging by the other cores' hints.
+    fn moves_processes(&self, queue: &RunQueue, new_state: &State) -> bool {
+        let process = &queue.processes[0];
+        if process.sched.affinity.map_or(false, |core| core != queue.core && self.is_online(core)) {
+            return true;
+        }
+        let stays_ready = match *new_state {
+            State::Ready => queue.current != queue.idle,
+            _ => false,
+        };
+        let ours = queue.ready() + stays_ready as usize;
+        let theirs = (0..CORES)
+            .filter(|&other| other != queue.core)
+            .map(|other| self.cores[other].ready.load(Ordering::Relaxed))
+            .max()
+            .unwrap_or(0);
+        theirs != 0 && (ours == 0 || theirs >= ours + 2)
+    }
+
+    /// Runs the first process on `core`, which has not run any yet: the one the
+    /// policy picks, or the idle process. Returns the trap frame to restore,
+    /// which is boxed, and stays put while the process runs.
+    pub(crate) fn enter(&self, core: usize) -> *const TrapFrame {
+        let _migration = self.migration.lock();
+        let mut queue = self.queue(core);
+        self.balance(&mut queue);
+        queue.resume(clock());
+        let trap_frame: *const TrapFrame = &*queue.processes[0].trap_frame;
+        trap_frame
+    }
+
+    /// Sets the current process's state to `new_state`, charges it for the
+    /// time it ran, asks the policy for the next process to switch to, and
+    /// performs the context switch on `tf` by saving `tf` into the current
+    /// process and restoring the next process's trap frame into `tf`. If there
+    /// is no current process, returns `None`. Otherwise, returns `Some` of the
+    /// process ID that was context switched into `tf`.
+    ///
+    /// A process with an affinity for another core moves to that core's run
+    /// queue, and the queues are balanced, before the next process is picked.
+    /// The idle process runs only when the policy finds no other process
+    /// ready.
+    ///
+    /// Only the core's run queue is locked, unless processes move between run
+    /// queues: the switch then starts over under the migration lock.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no process, not even the idle process, is ready.
+    pub(crate) fn switch(&self, new_state: State, tf: &mut TrapFrame) -> Option<Id> {
+        let core = smp::core();
+        let now = clock();
+        {
+            let mut queue = self.queue(core);
+            queue.current?;
+            if !self.moves_processes(&queue, &new_state) {
+                let process = queue.take_current(new_state, tf, now);
+                queue.processes.push_back(process);
+                let id = queue.resume(now);
+                *tf = *queue.processes[0].trap_frame;
+                return Some(id);
+            }
+        }
+
+        let _migration = self.migration.lock();
+        let mut queue = self.queue(core);
+        queue.current?;
+        let process = queue.take_current(new_state, tf, now);
+        match process.sched.affinity {
+            Some(target) if target != core && self.is_online(target) => {
+                self.migrate(&mut queue, &mut self.queue(target), process);
+            }
+            _ => queue.processes.push_back(process),
+        }
+        self.balance(&mut queue);
+        let id = queue.resume(now);
+        *tf = *queue.processes[0].trap_frame;
+        Some(id)
+    }
+}
+
+/// Returns an unused process ID. IDs are handed out in increasing order,
+/// wrapping around to 1 after the largest one and skipping IDs in use.
+fn alloc_id(family: &mut Family) -> Id {
+    let mut id = family.last_id.unwrap_or(0);
+    loop {
+        id = if id == Id::max_value() { 1 } else { id + 1 };
+        if !family.ids.contains(&id) {
+            family.last_id = Some(id);
+            return id;
+        }
+    }
+}
+
+/// Returns the current time in microseconds.
+#[cfg(not(test))]
+fn clock() -> u64 {
+    current_time()
+}
+
+/// Returns the current time in microseconds. Tests run without the timer.
+#[cfg(test)]
+fn clock() -> u64 {
+    0
 }
@@ -1,15 +1,11 @@
-use std::fmt;
+use std::{fmt, io, mem};
 use std::ptr::Unique;
-#[cfg(not(test))]
-use std::GlobalAlloc;
-#[cfg(test)]
-use std::alloc::GlobalAlloc;
 
-use ALLOCATOR;
-use alloc::alloc::Layout;
-use vm::PhysicalAddr;
+use FRAMES;
+use vm::{AddressSpace, Permission, PhysicalAddr, VirtualAddr, PAGE_SIZE};
 
-/// A process stack. The default size is 1M1B with an alignment of 16 bytes.
+/// A process stack. The default size is 1M1B with an alignment of a page. The
+/// stack is made of contiguous frames from the frame allocator.
 pub struct Stack {
     ptr: Unique<[u8; Stack::SIZE]>
 }
@@ -18,28 +14,53 @@ impl Stack {
     /// The default stack size is 1MiB.
     pub const SIZE: usize = 1 << 20;
 
-    /// The default stack alignment is 16 bytes.
-    pub const ALIGN: usize = 16;
+    /// The default stack alignment is a page, so that the stack can be mapped
+    /// into an address space without the memory around it.
+    pub const ALIGN: usize = PAGE_SIZE;
 
-    /// The default layout for a stack.
-    fn layout() -> Layout {
-        unsafe { Layout::from_size_align_unchecked(Self::SIZE, Self::ALIGN) }
-    }
+    /// The number of frames of a stack.
+    const FRAMES: usize = Self::SIZE / PAGE_SIZE;
 
     /// Returns a newly allocated process stack, zeroed out, if one could be
     /// successfully allocated. If there is no memory, or memory allocation
     /// fails for some other reason, returns `None`.
     pub fn new() -> Option<Stack> {
-        let raw_ptr = unsafe {
-            let raw_ptr: *mut u8 = (&ALLOCATOR).alloc(Stack::layout());
-            raw_ptr.write_bytes(0, Self::SIZE);
-            raw_ptr
-        };
+        let frame = FRAMES.alloc_contiguous(Self::FRAMES, Self::ALIGN)?;
+        let raw_ptr = frame.as_mut_ptr();
+        unsafe { raw_ptr.write_bytes(0, Self::SIZE) };
 
         let ptr = Unique::new(raw_ptr as *mut _).expect("non-null");
         Some(Stack { ptr })
     }
 
+    /// Returns a newly allocated process stack with the same contents as this
+    /// one, or `None` if one could not be allocated.
+    pub fn try_clone(&self) -> Option<Stack> {
+        let stack = Stack::new()?;
+        unsafe { stack.as_mut_ptr().copy_from_nonoverlapping(self.as_mut_ptr(), Self::SIZE) };
+        Some(stack)
+    }
+
+    /// Maps the stack into `space` as user read-write memory ending at `top`.
+    ///
+    /// The address space takes over the frames of the stack, which are freed
+    /// if an error is returned.
+    pub fn map(self, space: &mut AddressSpace, top: VirtualAddr) -> io::Result<()> {
+        let (bottom, start) = (self.bottom().as_usize(), top.as_usize() - Self::SIZE);
+        mem::forget(self);
+        let mut result = Ok(());
+        for i in 0..Self::FRAMES {
+            let frame = (bottom + i * PAGE_SIZE).into();
+            if result.is_ok() {
+                result = space.map_frame((start + i * PAGE_SIZE).into(), frame, Permission::UserReadWrite);
+            }
+            if result.is_err() {
+                FRAMES.release(frame);
+            }
+        }
+        result
+    }
+
     /// Internal method to cast to a `*mut u8`.
     unsafe fn as_mut_ptr(&self) -> *mut u8 {
         self.ptr.as_ptr() as _
@@ -58,9 +79,7 @@ impl Stack {
 
 impl Drop for Stack {
     fn drop(&mut self) {
-        unsafe {
-            (&ALLOCATOR).dealloc(self.as_mut_ptr(), Self::layout())
-        }
+        FRAMES.release_contiguous(self.bottom(), Self::FRAMES)
     }
 }
 
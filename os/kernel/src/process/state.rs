@@ -1,22 +1,49 @@
 use std::fmt;
 
-use process::Process;
-
-/// Type of a function used to determine if a process is ready to be scheduled
-/// again. The scheduler calls this function when it is the process's turn to
-/// execute. If the function returns `true`, the process is scheduled. If it
-/// returns `false`, the process is not scheduled, and this function will be
-/// called on the next time slice.
-pub type EventPollFn = Box<FnMut(&mut Process) -> bool + Send>;
+use process::Id;
+use traps::Syndrome;
 
 /// The scheduling state of a process.
 pub enum State {
     /// The process is ready to be scheduled.
     Ready,
-    /// The process is waiting on an event to occur before it can be scheduled.
-    Waiting(EventPollFn),
+    /// The process is blocked on a `WaitQueue` until it is woken.
+    Blocked,
+    /// The process has been sleeping since `since` and sleeps until `until`,
+    /// both in microseconds since boot.
+    Sleeping { since: u64, until: u64 },
     /// The process is currently running.
     Running,
+    /// The process is waiting for its child with the given ID, or for any of
+    /// its children if `None`, to exit.
+    WaitingForChild(Option<Id>),
+    /// The process has exited for the given reason, which it keeps until its
+    /// parent collects it with `wait`.
+    Zombie(Exit),
+}
+
+/// The reason a process exited.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Exit {
+    /// The process called `exit` with the given status.
+    Status(u64),
+    /// The process was killed by an exception it took at EL0 that the kernel
+    /// could not handle: the exception's syndrome, the faulting address
+    /// (`FAR_EL1`) and the address of the faulting instruction (`ELR_EL1`).
+    Fault { syndrome: Syndrome, far: u64, elr: u64 },
+}
+
+impl Exit {
+    /// The exit status of processes killed by a fault.
+    pub const FAULT_STATUS: u64 = !0;
+
+    /// Returns the exit status collected by `wait`.
+    pub fn status(&self) -> u64 {
+        match *self {
+            Exit::Status(status) => status,
+            Exit::Fault { .. } => Exit::FAULT_STATUS,
+        }
+    }
 }
 
 impl fmt::Debug for State {
@@ -24,7 +51,12 @@ impl fmt::Debug for State {
         match *self {
             State::Ready => write!(f, "State::Ready"),
             State::Running => write!(f, "State::Running"),
-            State::Waiting(_) => write!(f, "State::Waiting"),
+            State::Blocked => write!(f, "State::Blocked"),
+            State::Sleeping { since, until } => {
+                write!(f, "State::Sleeping {{ since: {}, until: {} }}", since, until)
+            }
+            State::WaitingForChild(id) => write!(f, "State::WaitingForChild({:?})", id),
+            State::Zombie(exit) => write!(f, "State::Zombie({:?})", exit),
         }
     }
 }
@@ -0,0 +1,726 @@
+use std::io;
+use std::ffi::CStr;
+use std::os::raw::c_char;
+
+use process::elf::{Elf, Kind, ProgramHeader, Relocation, SegmentKind};
+use std::collections::VecDeque;
+
+use process::{Exit, Fair, Id, Image, Policy, Priority, Process, RoundRobin, SchedInfo, Scheduler, Stack, State};
+use process::{TimerQueue, WaitQueue, INIT};
+use traps::TrapFrame;
+use vm::{Permission, KERNEL_BASE, USER_IMAGE_BASE, USER_STACK_TOP};
+
+fn put(buf: &mut [u8], offset: usize, bytes: &[u8]) {
+    buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+fn u16(value: u16) -> [u8; 2] {
+    [value as u8, (value >> 8) as u8]
+}
+
+fn u32(value: u32) -> [u8; 4] {
+    let mut bytes = [0; 4];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (value >> (8 * i)) as u8;
+    }
+    bytes
+}
+
+fn u64(value: u64) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (value >> (8 * i)) as u8;
+    }
+    bytes
+}
+
+fn put_program_header(buf: &mut [u8], index: usize, header: &ProgramHeader, kind: u32) {
+    let offset = 64 + 56 * index;
+    put(buf, offset, &u32(kind));
+    put(buf, offset + 4, &u32(header.flags));
+    put(buf, offset + 8, &u64(header.offset));
+    put(buf, offset + 16, &u64(header.vaddr));
+    put(buf, offset + 24, &u64(header.vaddr));
+    put(buf, offset + 32, &u64(header.filesz));
+    put(buf, offset + 40, &u64(header.memsz));
+    put(buf, offset + 48, &u64(header.align));
+}
+
+/// Returns a position independent executable with a text segment holding the
+/// headers and the entry point at 0xF0, a data segment at 0x1100 holding the
+/// dynamic section and one relative relocation of the first word of `.bss`
+/// (0x1160) to the entry point, and a `PT_DYNAMIC` program header.
+fn executable() -> Vec<u8> {
+    let mut elf = vec![0; 0x160];
+    put(&mut elf, 0, b"\x7fELF\x02\x01\x01");
+    put(&mut elf, 16, &u16(3)); // ET_DYN
+    put(&mut elf, 18, &u16(183)); // EM_AARCH64
+    put(&mut elf, 20, &u32(1));
+    put(&mut elf, 24, &u64(0xF0));
+    put(&mut elf, 32, &u64(64));
+    put(&mut elf, 52, &u16(64));
+    put(&mut elf, 54, &u16(56));
+    put(&mut elf, 56, &u16(3));
+
+    let text = ProgramHeader {
+        kind: SegmentKind::Load,
+        flags: 5,
+        offset: 0,
+        vaddr: 0,
+        filesz: 0x100,
+        memsz: 0x100,
+        align: 0x1000,
+    };
+    let data = ProgramHeader {
+        kind: SegmentKind::Load,
+        flags: 6,
+        offset: 0x100,
+        vaddr: 0x1100,
+        filesz: 0x60,
+        memsz: 0x200,
+        align: 0x1000,
+    };
+    let dynamic = ProgramHeader {
+        kind: SegmentKind::Dynamic,
+        flags: 6,
+        offset: 0x100,
+        vaddr: 0x1100,
+        filesz: 0x40,
+        memsz: 0x40,
+        align: 8,
+    };
+    put_program_header(&mut elf, 0, &text, 1);
+    put_program_header(&mut elf, 1, &data, 1);
+    put_program_header(&mut elf, 2, &dynamic, 2);
+
+    put(&mut elf, 0xF0, &u32(0xd65f03c0)); // ret
+
+    let entries = [(7, 0x1140), (8, 24), (9, 24), (0, 0)];
+    for (i, &(tag, value)) in entries.iter().enumerate() {
+        put(&mut elf, 0x100 + 16 * i, &u64(tag));
+        put(&mut elf, 0x108 + 16 * i, &u64(value));
+    }
+    put(&mut elf, 0x140, &u64(0x1160));
+    put(&mut elf, 0x148, &u64(1027)); // R_AARCH64_RELATIVE
+    put(&mut elf, 0x150, &u64(0xF0));
+    put(&mut elf, 0x158, &[0xAA; 8]);
+    elf
+}
+
+/// Returns `executable()` linked to run at `base` (`ET_EXEC`).
+fn fixed_executable(base: u64) -> Vec<u8> {
+    let mut elf = executable();
+    put(&mut elf, 16, &u16(2)); // ET_EXEC
+    put(&mut elf, 24, &u64(base + 0xF0));
+    for &(index, vaddr) in [(0, 0), (1, 0x1100), (2, 0x1100)].iter() {
+        put(&mut elf, 64 + 56 * index + 16, &u64(base + vaddr));
+        put(&mut elf, 64 + 56 * index + 24, &u64(base + vaddr));
+    }
+    put(&mut elf, 0x108, &u64(base + 0x1140));
+    put(&mut elf, 0x140, &u64(base + 0x1160));
+    put(&mut elf, 0x150, &u64(base + 0xF0));
+    elf
+}
+
+/// Returns the kernel address of the byte at `va` in `process`'s memory.
+fn physical(process: &Process, va: u64) -> usize {
+    process.address_space.translate((va as usize).into()).expect("mapped").as_usize()
+}
+
+fn error_kind(data: &[u8]) -> io::ErrorKind {
+    Process::from_elf(data, &[]).unwrap_err().kind()
+}
+
+#[test]
+fn test_parse() {
+    let data = executable();
+    let elf = Elf::parse(&data).expect("valid ELF");
+    assert_eq!(elf.kind, Kind::Dynamic);
+    assert_eq!(elf.entry, 0xF0);
+    assert_eq!(elf.program_headers.len(), 3);
+    assert_eq!(elf.segments().count(), 2);
+    assert_eq!(elf.program_headers[2].kind, SegmentKind::Dynamic);
+    assert_eq!(elf.phdr(), Some(64));
+    assert!(!elf.has_interpreter());
+    assert_eq!(elf.relocations().unwrap(), vec![Relocation::Relative {
+        offset: 0x1160,
+        addend: 0xF0,
+    }]);
+}
+
+#[test]
+fn test_load_image() {
+    let data = executable();
+    let image = Image::load(&Elf::parse(&data).unwrap()).expect("loaded image");
+    let start = image.start().as_u64();
+    let base = USER_IMAGE_BASE as u64;
+    assert_eq!(start % 0x1000, 0);
+    assert_eq!(image.size(), 0x2000);
+    assert_eq!(image.bias(), base);
+    assert_eq!(image.entry(), base + 0xF0);
+    assert_eq!(image.phdr(), Some(base + 64));
+
+    let memory = unsafe { ::std::slice::from_raw_parts(start as *const u8, image.size()) };
+    assert_eq!(&memory[..0x100], &data[..0x100]);
+    assert!(memory[0x100..0x1100].iter().all(|&byte| byte == 0));
+    assert_eq!(&memory[0x1100..0x1158], &data[0x100..0x158]);
+    assert_eq!(&memory[0x1158..0x1160], &[0xAA; 8]);
+    assert_eq!(&memory[0x1160..0x1168], &u64(base + 0xF0));
+    assert!(memory[0x1168..].iter().all(|&byte| byte == 0));
+}
+
+#[test]
+fn test_load_fixed_image() {
+    let base = USER_IMAGE_BASE as u64 + 0x10_0000;
+    let data = fixed_executable(base);
+    let image = Image::load(&Elf::parse(&data).unwrap()).expect("loaded image");
+    assert_eq!(image.base(), base);
+    assert_eq!(image.bias(), 0);
+    assert_eq!(image.entry(), base + 0xF0);
+    assert_eq!(image.phdr(), Some(base + 64));
+    let slot = (image.start().as_u64() + 0x1160) as *const u8;
+    assert_eq!(unsafe { ::std::slice::from_raw_parts(slot, 8) }, &u64(base + 0xF0));
+
+    let process = Process::from_elf(&data, &[]).expect("process");
+    let space = &process.address_space;
+    assert_eq!(process.trap_frame.elr, base + 0xF0);
+    assert_eq!(space.permission((base as usize).into()), Some(Permission::UserReadExecute));
+    assert_eq!(space.permission((base as usize + 0x1300).into()), Some(Permission::UserReadWrite));
+    assert!(space.translate(USER_IMAGE_BASE.into()).is_none());
+
+    // The image must be above `USER_IMAGE_BASE` and below the stack.
+    assert_eq!(error_kind(&fixed_executable(0x40_0000)), io::ErrorKind::InvalidData);
+    let stack = (USER_STACK_TOP - Stack::SIZE) as u64;
+    assert_eq!(error_kind(&fixed_executable(stack - 0x1000)), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_from_elf_stack() {
+    let data = executable();
+    let process = Process::from_elf(&data, &["prog", "hello world"]).expect("process");
+    let entry = USER_IMAGE_BASE as u64 + 0xF0;
+    let tf = &process.trap_frame;
+    assert_eq!(tf.elr, entry);
+    assert_eq!(tf.spsr, 0b1101_00_0000);
+    assert_eq!(tf.sp % 16, 0);
+    assert!(process.stack.is_none());
+    let (bottom, top) = process.stack_bounds();
+    assert_eq!(top, USER_STACK_TOP as u64);
+    assert!(tf.sp > bottom && tf.sp < top);
+
+    let words = unsafe { ::std::slice::from_raw_parts(physical(&process, tf.sp) as *const u64, 19) };
+    assert_eq!(words[0], 2);
+    assert_eq!(tf.x0, 2);
+    assert_eq!(tf.x1to29[0], tf.sp + 8);
+    assert_eq!(tf.x1to29[1], tf.sp + 32);
+    let arg = |i: usize| unsafe { CStr::from_ptr(physical(&process, words[1 + i]) as *const c_char) };
+    assert_eq!(arg(0).to_str().unwrap(), "prog");
+    assert_eq!(arg(1).to_str().unwrap(), "hello world");
+    assert_eq!(words[3], 0);
+    assert_eq!(words[4], 0);
+
+    let auxv: Vec<_> = words[5..].chunks(2).map(|pair| (pair[0], pair[1])).collect();
+    assert_eq!(&auxv[..], &[
+        (3, USER_IMAGE_BASE as u64 + 64),
+        (4, 56),
+        (5, 3),
+        (6, 4096),
+        (7, 0),
+        (9, entry),
+        (0, 0),
+    ][..]);
+}
+
+#[test]
+fn test_from_elf_address_space() {
+    let data = executable();
+    let process = Process::from_elf(&data, &[]).expect("process");
+    let space = &process.address_space;
+    let start = USER_IMAGE_BASE;
+    let text = space.translate(start.into()).unwrap().as_usize();
+    assert_eq!(text % 4096, 0);
+    assert_eq!(space.translate((start + 0x1008).into()).unwrap().as_usize(), text + 0x1008);
+    assert_eq!(space.permission(start.into()), Some(Permission::UserReadExecute));
+    assert_eq!(space.permission((start + 0x1300).into()), Some(Permission::UserReadWrite));
+    assert!(space.translate((start + 0x2000).into()).is_none());
+
+    let bottom = USER_STACK_TOP - Stack::SIZE;
+    assert_eq!(space.permission(bottom.into()), Some(Permission::UserReadWrite));
+    assert_eq!(space.permission((bottom + Stack::SIZE - 1).into()), Some(Permission::UserReadWrite));
+    assert!(space.translate((bottom - 1).into()).is_none());
+    assert!(space.translate(USER_STACK_TOP.into()).is_none());
+    assert!(space.translate(0x8_0000.into()).is_none());
+
+    let kernel = Process::new().unwrap();
+    let va = (KERNEL_BASE + 0x8_0000).into();
+    assert_eq!(kernel.address_space.permission(va), Some(Permission::UserReadWriteExecute));
+}
+
+#[test]
+fn test_from_elf_no_args() {
+    let data = executable();
+    let process = Process::from_elf(&data, &[]).expect("process");
+    let sp = physical(&process, process.trap_frame.sp);
+    let words = unsafe { ::std::slice::from_raw_parts(sp as *const u64, 3) };
+    assert_eq!(words, &[0, 0, 0]);
+}
+
+#[test]
+fn test_from_elf_errors() {
+    let data = executable();
+    assert_eq!(error_kind(&data[..40]), io::ErrorKind::InvalidData);
+
+    let mut bad = data.clone();
+    bad[0] = 0;
+    assert_eq!(error_kind(&bad), io::ErrorKind::InvalidData);
+
+    let mut bad = data.clone();
+    bad[4] = 1; // ELFCLASS32
+    assert_eq!(error_kind(&bad), io::ErrorKind::InvalidData);
+
+    let mut bad = data.clone();
+    put(&mut bad, 18, &u16(62)); // EM_X86_64
+    assert_eq!(error_kind(&bad), io::ErrorKind::InvalidData);
+
+    let mut bad = data.clone();
+    put(&mut bad, 16, &u16(2)); // ET_EXEC linked to run at 0
+    assert_eq!(error_kind(&bad), io::ErrorKind::InvalidData);
+
+    let mut bad = data.clone();
+    put(&mut bad, 64 + 56 + 32, &u64(0x1000)); // data filesz past the end
+    assert_eq!(error_kind(&bad), io::ErrorKind::InvalidData);
+
+    let mut bad = data.clone();
+    put(&mut bad, 64 + 56 + 40, &u64(0x10)); // data memsz below filesz
+    assert_eq!(error_kind(&bad), io::ErrorKind::InvalidData);
+
+    let mut bad = data.clone();
+    put(&mut bad, 64 + 56 * 2, &u32(3)); // PT_INTERP
+    assert_eq!(error_kind(&bad), io::ErrorKind::InvalidData);
+
+    let mut bad = data.clone();
+    put(&mut bad, 0x148, &u64(257)); // R_AARCH64_ABS64
+    assert_eq!(error_kind(&bad), io::ErrorKind::InvalidData);
+
+    let mut bad = data.clone();
+    put(&mut bad, 0x140, &u64(0x4000)); // relocation outside of the image
+    assert_eq!(error_kind(&bad), io::ErrorKind::InvalidData);
+
+    let long = "a".repeat(1 << 20);
+    let error = Process::from_elf(&data, &[&long]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_fork_user() {
+    let data = executable();
+    let mut parent = Process::from_elf(&data, &["prog"]).expect("process");
+    parent.trap_frame.tpidr = 7;
+    let mut tf = *parent.trap_frame;
+    tf.x0 = 42;
+    tf.x1to29[6] = 1;
+    let mut child = parent.fork(&tf).expect("child");
+    assert_eq!(child.parent, Some(7));
+    assert!(child.children.is_empty());
+    assert_eq!(child.trap_frame.x0, 0);
+    assert_eq!(child.trap_frame.x1to29[6], 0);
+    assert_eq!(child.trap_frame.sp, tf.sp);
+    assert_eq!(child.trap_frame.elr, tf.elr);
+    assert_ne!(child.address_space.asid(), parent.address_space.asid());
+
+    // Text stays read only and shared.
+    let text = USER_IMAGE_BASE as u64;
+    assert_eq!(physical(&child, text), physical(&parent, text));
+    assert_eq!(child.address_space.permission((text as usize).into()), Some(Permission::UserReadExecute));
+
+    // Writable pages are shared read only until written to.
+    let data_page = (USER_IMAGE_BASE + 0x1000).into();
+    let frame = physical(&parent, USER_IMAGE_BASE as u64 + 0x1000);
+    assert_eq!(physical(&child, USER_IMAGE_BASE as u64 + 0x1000), frame);
+    for process in &[&parent, &child] {
+        assert_eq!(process.address_space.permission(data_page), Some(Permission::UserReadOnly));
+    }
+    assert!(!child.address_space.copy_on_write(USER_IMAGE_BASE.into()).unwrap(), "text is not copy-on-write");
+
+    assert!(child.address_space.copy_on_write(data_page).unwrap());
+    let copy = physical(&child, USER_IMAGE_BASE as u64 + 0x1000);
+    assert_ne!(copy, frame);
+    assert_eq!(child.address_space.permission(data_page), Some(Permission::UserReadWrite));
+    let (original, copied) = unsafe {
+        (::std::slice::from_raw_parts(frame as *const u8, 4096), ::std::slice::from_raw_parts(copy as *const u8, 4096))
+    };
+    assert_eq!(original, copied);
+
+    // The last sharer of a frame takes it over without copying.
+    assert!(parent.address_space.copy_on_write(data_page).unwrap());
+    assert_eq!(physical(&parent, USER_IMAGE_BASE as u64 + 0x1000), frame);
+    assert_eq!(parent.address_space.permission(data_page), Some(Permission::UserReadWrite));
+    assert!(!parent.address_space.copy_on_write(data_page).unwrap());
+
+    // The stack is shared the same way.
+    let sp = child.trap_frame.sp;
+    assert_eq!(physical(&child, sp), physical(&parent, sp));
+    drop(parent);
+    let words = unsafe { ::std::slice::from_raw_parts(physical(&child, sp) as *const u64, 1) };
+    assert_eq!(words[0], 1, "argc survives the parent");
+}
+
+#[test]
+fn test_fork_kernel() {
+    let mut parent = Process::new().unwrap();
+    let (bottom, top) = parent.stack_bounds();
+    unsafe { ((top - 8) as *mut u64).write(0xDEAD_BEEF) };
+    let mut tf = TrapFrame::default();
+    tf.tpidr = 3;
+    tf.sp = top - 16;
+    let child = parent.fork(&tf).expect("child");
+    assert_eq!(child.parent, Some(3));
+    assert_eq!(child.trap_frame.sp, top - 16);
+
+    // The child sees a copy of the stack at the same addresses.
+    let copy = child.stack.as_ref().unwrap();
+    assert_ne!(copy.bottom().as_ptr() as u64, bottom);
+    assert_eq!(physical(&child, bottom), copy.bottom().as_usize());
+    assert_eq!(physical(&child, top - 8), copy.top().as_usize() - 8);
+    assert_eq!(unsafe { ((copy.top().as_ptr() as u64 - 8) as *const u64).read() }, 0xDEAD_BEEF);
+
+    // Everything else is shared.
+    let va = KERNEL_BASE + 0x8_0000;
+    assert_eq!(physical(&child, va as u64), 0x8_0000);
+    assert_eq!(child.address_space.permission(va.into()), Some(Permission::UserReadWriteExecute));
+}
+
+#[test]
+fn test_backtrace() {
+    let data = executable();
+    let process = Process::from_elf(&data, &[]).expect("process");
+    let top = USER_STACK_TOP as u64;
+    let write = |va: u64, value: u64| unsafe { (physical(&process, va) as *mut u64).write(value) };
+    // Two frame records, the outermost ending the chain.
+    write(top - 64, top - 32);
+    write(top - 56, 0x1111);
+    write(top - 32, 0);
+    write(top - 24, 0x2222);
+
+    let mut tf = *process.trap_frame;
+    tf.elr = 0x3333;
+    tf.x1to29[28] = top - 64;
+    assert_eq!(process.backtrace(&tf), vec![0x3333, 0x1111, 0x2222]);
+
+    // The walk stops at frame records the process cannot read, or misaligned
+    // ones.
+    write(top - 32, 0x1234_0000);
+    assert_eq!(process.backtrace(&tf), vec![0x3333, 0x1111, 0x2222]);
+    write(top - 32, top - 8);
+    assert_eq!(process.backtrace(&tf), vec![0x3333, 0x1111, 0x2222]);
+
+    // Loops end after a bounded number of frames.
+    write(top - 32, top - 64);
+    assert_eq!(process.backtrace(&tf).len(), 16);
+}
+
+#[test]
+fn test_wait_queue() {
+    let mut queue = WaitQueue::new();
+    assert!(queue.is_empty());
+    queue.push(3);
+    queue.push(1);
+    queue.push(2);
+    assert_eq!(queue.len(), 3);
+    assert!(queue.remove(1));
+    assert!(!queue.remove(1));
+    assert_eq!(queue.pop(), Some(3));
+    assert_eq!(queue.pop(), Some(2));
+    assert_eq!(queue.pop(), None);
+}
+
+#[test]
+fn test_timer_queue() {
+    let mut timers = TimerQueue::new();
+    assert_eq!(timers.next_deadline(), None);
+    timers.insert(300, 1);
+    timers.insert(100, 2);
+    timers.insert(200, 3);
+    timers.insert(100, 4);
+    assert_eq!(timers.len(), 4);
+    assert_eq!(timers.next_deadline(), Some(100));
+
+    assert_eq!(timers.expire(99), Vec::<u64>::new());
+    assert_eq!(timers.expire(100), vec![2, 4]);
+    assert!(timers.remove(1));
+    assert!(!timers.remove(1));
+    assert_eq!(timers.next_deadline(), Some(200));
+    assert_eq!(timers.expire(1000), vec![3]);
+    assert!(timers.is_empty());
+}
+
+/// Returns processes with the IDs 1 to `nice.len()` and the given nice values,
+/// all ready.
+fn processes(nice: &[i64]) -> VecDeque<Process> {
+    nice.iter()
+        .enumerate()
+        .map(|(i, &nice)| {
+            let mut process = Process::new().expect("process");
+            process.trap_frame.tpidr = i as u64 + 1;
+            process.sched.nice = nice;
+            process
+        })
+        .collect()
+}
+
+/// Runs `rounds` time slices of `ran` microseconds each under `policy`, moving
+/// the picked process to the back of the queue like the scheduler does, and
+/// returns the IDs of the processes run.
+fn run<P: Policy>(policy: &mut P, processes: &mut VecDeque<Process>, rounds: usize, ran: u64) -> Vec<u64> {
+    let mut picked = Vec::new();
+    for _ in 0..rounds {
+        let index = policy.pick(processes, None).expect("a ready process");
+        let mut process = processes.remove(index).unwrap();
+        policy.charge(&mut process, ran);
+        picked.push(process.trap_frame.tpidr);
+        processes.push_back(process);
+    }
+    picked
+}
+
+#[test]
+fn test_round_robin() {
+    let mut processes = processes(&[0, -10, 5]);
+    processes[1].state = State::Blocked;
+    assert_eq!(run(&mut RoundRobin, &mut processes, 4, 10), vec![1, 3, 1, 3]);
+    // The idle process only runs when no other process is ready.
+    let index = RoundRobin.pick(&mut processes, Some(1)).unwrap();
+    assert_eq!(processes[index].trap_frame.tpidr, 3);
+}
+
+#[test]
+fn test_priority() {
+    let mut processes = processes(&[0, -2, 0]);
+    let picked = run(&mut Priority, &mut processes, 8, 10);
+    // The favored process runs until the others have aged to its priority,
+    // then equals run the least recently run first.
+    assert_eq!(picked, vec![2, 2, 1, 3, 2, 1, 2, 3]);
+
+    // Neither the idle process nor processes that are not ready run.
+    for process in processes.iter_mut() {
+        if process.trap_frame.tpidr != 3 {
+            process.state = State::Blocked;
+        }
+    }
+    assert_eq!(Priority.pick(&mut processes, Some(3)), None);
+
+    // A woken process preempts a running one only if it has a higher priority.
+    let woken = self::processes(&[-5, 0, 0]);
+    assert!(Priority.preempts(&woken[0], &woken[1]));
+    assert!(!Priority.preempts(&woken[1], &woken[0]));
+    assert!(!Priority.preempts(&woken[1], &woken[2]));
+    assert!(!RoundRobin.preempts(&woken[0], &woken[1]));
+}
+
+#[test]
+fn test_fair() {
+    let mut fair = Fair::default();
+    let mut processes = processes(&[0, 0, 5]);
+    for process in processes.iter_mut() {
+        fair.admit(process);
+    }
+    let picked = run(&mut fair, &mut processes, 1000, 1000);
+    let count = |id| picked.iter().filter(|&&picked| picked == id).count();
+    // Equal nice values share equally; nice 5 gets about a third of nice 0.
+    assert!((count(1) as i64 - count(2) as i64).abs() <= 1);
+    let ratio = count(1) as f64 / count(3) as f64;
+    let expected = Fair::weight(0) as f64 / Fair::weight(5) as f64;
+    assert!((ratio - expected).abs() < 0.1, "ratio {} != {}", ratio, expected);
+
+    // A new process starts level with the others rather than catching up.
+    let mut newcomer = Process::new().expect("process");
+    newcomer.trap_frame.tpidr = 4;
+    fair.admit(&mut newcomer);
+    processes.push_back(newcomer);
+    let picked = run(&mut fair, &mut processes, 30, 1000);
+    assert!(picked.iter().filter(|&&id| id == 4).count() <= 12);
+
+    // A woken process preempts a running one well ahead of it.
+    let mut woken = self::processes(&[0, 0]);
+    woken[1].sched.vruntime = 5000;
+    let fair = Fair::default();
+    assert!(fair.preempts(&woken[0], &woken[1]));
+    woken[1].sched.vruntime = 500;
+    assert!(!fair.preempts(&woken[0], &woken[1]));
+}
+
+#[test]
+fn test_sched_info_fork() {
+    let parent = SchedInfo { nice: 3, runtime: 100, slices: 2, affinity: Some(2), core: 2, ..SchedInfo::default() };
+    let child = parent.fork();
+    assert_eq!(child.nice, 3);
+    assert_eq!(child.affinity, Some(2));
+    assert_eq!((child.runtime, child.slices, child.core), (0, 0, 0));
+}
+
+/// Returns a scheduler running `count` processes, `INIT` first, and the trap
+/// frame of the one running.
+fn scheduler(count: usize) -> (Scheduler, TrapFrame) {
+    let scheduler = Scheduler::new();
+    for _ in 0..count {
+        let mut process = Process::new().expect("process");
+        process.trap_frame.sp = process.stack.as_ref().unwrap().top().as_ptr() as u64;
+        scheduler.add(process).expect("scheduled");
+    }
+    let tf = unsafe { *scheduler.enter(0) };
+    (scheduler, tf)
+}
+
+/// Returns the children of the process `id`.
+fn children(scheduler: &Scheduler, id: Id) -> Vec<Id> {
+    scheduler.with_process(id, |process| process.children.clone()).expect("process")
+}
+
+/// Switches to the next process until the process `id` runs.
+fn run_until(scheduler: &Scheduler, tf: &mut TrapFrame, id: Id) {
+    for _ in 0..16 {
+        if tf.tpidr == id {
+            return;
+        }
+        scheduler.switch(State::Ready, tf).unwrap();
+    }
+    panic!("process {} does not run", id);
+}
+
+#[test]
+fn test_exit_to_waiting_parent() {
+    let (scheduler, mut tf) = scheduler(2);
+    let parent = INIT + 1;
+    run_until(&scheduler, &mut tf, parent);
+    let child = scheduler.fork(&mut tf).expect("child");
+    assert_eq!(scheduler.with_process(child, |process| process.parent).unwrap(), Some(parent));
+
+    assert!(scheduler.wait(Some(child), &mut tf));
+    assert_ne!(tf.tpidr, parent);
+    assert!(!scheduler.with_process(parent, |process| process.is_ready()).unwrap());
+    run_until(&scheduler, &mut tf, child);
+    scheduler.exit(Exit::Status(7), &mut tf).unwrap();
+
+    // The parent gets the child's ID and status, and the child is reaped.
+    assert!(scheduler.with_process(child, |_| ()).is_none());
+    assert!(children(&scheduler, parent).is_empty());
+    run_until(&scheduler, &mut tf, parent);
+    assert_eq!((tf.x0, tf.x1to29[0], tf.x1to29[6]), (child, 7, 0));
+}
+
+#[test]
+fn test_wait_for_zombie() {
+    let (scheduler, mut tf) = scheduler(2);
+    let parent = INIT + 1;
+    run_until(&scheduler, &mut tf, parent);
+    let child = scheduler.fork(&mut tf).expect("child");
+    run_until(&scheduler, &mut tf, child);
+    scheduler.exit(Exit::Status(3), &mut tf).unwrap();
+    assert!(scheduler.with_process(child, |process| process.is_zombie()).unwrap());
+
+    // Waiting collects the zombie right away.
+    run_until(&scheduler, &mut tf, parent);
+    assert!(scheduler.wait(None, &mut tf));
+    assert_eq!(tf.tpidr, parent);
+    assert_eq!((tf.x0, tf.x1to29[0], tf.x1to29[6]), (child, 3, 0));
+    assert!(scheduler.with_process(child, |_| ()).is_none());
+    assert!(!scheduler.wait(None, &mut tf), "the child was reaped");
+}
+
+#[test]
+fn test_orphans() {
+    let (scheduler, mut tf) = scheduler(2);
+    let parent = INIT + 1;
+    run_until(&scheduler, &mut tf, INIT);
+    let own = scheduler.fork(&mut tf).expect("child of init");
+    run_until(&scheduler, &mut tf, parent);
+    let first = scheduler.fork(&mut tf).expect("first orphan");
+    let second = scheduler.fork(&mut tf).expect("second orphan");
+    scheduler.exit(Exit::Status(0), &mut tf).unwrap();
+
+    // Init adopts the children of exiting processes.
+    assert!(scheduler.with_process(parent, |_| ()).is_none());
+    assert_eq!(scheduler.with_process(first, |process| process.parent).unwrap(), Some(INIT));
+    assert_eq!(children(&scheduler, INIT), vec![own, first, second]);
+
+    // Orphans are reaped as they exit, unless init waits for them.
+    run_until(&scheduler, &mut tf, first);
+    scheduler.exit(Exit::Status(1), &mut tf).unwrap();
+    assert!(scheduler.with_process(first, |_| ()).is_none());
+    run_until(&scheduler, &mut tf, INIT);
+    assert!(scheduler.wait(None, &mut tf));
+    run_until(&scheduler, &mut tf, second);
+    scheduler.exit(Exit::Status(2), &mut tf).unwrap();
+    assert!(scheduler.with_process(second, |_| ()).is_none());
+    assert_eq!(children(&scheduler, INIT), vec![own]);
+    run_until(&scheduler, &mut tf, INIT);
+    assert_eq!((tf.x0, tf.x1to29[0]), (second, 2));
+
+    // Init's own children are kept until it waits for them.
+    run_until(&scheduler, &mut tf, own);
+    scheduler.exit(Exit::Status(4), &mut tf).unwrap();
+    assert!(scheduler.with_process(own, |process| process.is_zombie()).unwrap());
+    run_until(&scheduler, &mut tf, INIT);
+    assert!(scheduler.wait(Some(own), &mut tf));
+    assert_eq!((tf.x0, tf.x1to29[0]), (own, 4));
+}
+
+#[test]
+fn test_wakeup_preemption() {
+    let (scheduler, tf) = scheduler(2);
+    let running = tf.tpidr;
+    let mut favored = Process::new().expect("process");
+    favored.sched.nice = -5;
+    let favored = scheduler.add(favored).expect("scheduled");
+    let mut niced = Process::new().expect("process");
+    niced.sched.nice = 5;
+    let niced = scheduler.add(niced).expect("scheduled");
+
+    // Under the default priority policy, a favored process preempts.
+    assert!(scheduler.preempts(0, favored));
+    assert!(!scheduler.preempts(0, niced));
+    assert!(!scheduler.preempts(0, running));
+    scheduler.with_process(favored, |process| process.state = State::Blocked).unwrap();
+    assert!(!scheduler.preempts(0, favored), "the process is not ready");
+
+    scheduler.with_process(favored, |process| process.state = State::Ready).unwrap();
+    scheduler.set_policy(|| -> Box<Policy> { Box::new(RoundRobin) });
+    assert!(!scheduler.preempts(0, favored));
+}
+
+#[test]
+fn test_spoofed_id() {
+    let (scheduler, mut tf) = scheduler(2);
+    let (running, other) = (tf.tpidr, tf.tpidr % 2 + 1);
+
+    // An ID the process wrote into `TPIDR_EL0` is neither saved nor trusted.
+    tf.tpidr = other;
+    scheduler.switch(State::Ready, &mut tf).unwrap();
+    assert_eq!(tf.tpidr, other);
+    tf.tpidr = running;
+    let child = scheduler.fork(&mut tf).expect("child");
+    assert_eq!(scheduler.with_process(child, |process| process.parent).unwrap(), Some(other));
+    assert_eq!(children(&scheduler, other), vec![child]);
+
+    tf.tpidr = 12345;
+    scheduler.exit(Exit::Status(0), &mut tf).unwrap();
+    let reaped = scheduler.with_process(other, |_| ()).is_none();
+    assert!(reaped, "the process without a parent was reaped");
+    assert!(!scheduler.with_process(running, |process| process.is_zombie()).unwrap());
+}
+
+#[test]
+fn test_set_nice() {
+    let (scheduler, mut tf) = scheduler(2);
+    let (parent, other) = (INIT + 1, INIT);
+    run_until(&scheduler, &mut tf, parent);
+    let child = scheduler.fork(&mut tf).expect("child");
+
+    // A process may renice itself and its children, but no other process.
+    assert_eq!(scheduler.set_nice(parent, parent, 5), Some(5));
+    assert_eq!(scheduler.set_nice(parent, child, -100), Some(-20));
+    assert_eq!(scheduler.set_nice(parent, other, -20), None);
+    assert_eq!(scheduler.set_nice(child, parent, -20), None);
+    assert_eq!(scheduler.with_process(parent, |process| process.sched.nice).unwrap(), 5);
+    assert_eq!(scheduler.set_nice(parent, 12345, 0), None);
+}
@@ -0,0 +1,107 @@
+use std::collections::VecDeque;
+
+use process::Id;
+
+/// A queue of processes blocked until an event occurs, in the order they
+/// blocked.
+///
+/// Processes are added with `GlobalScheduler::block_on()` and made ready again
+/// with `GlobalScheduler::wake_one()` or `GlobalScheduler::wake_all()`, by
+/// whoever causes the event. The queue must be kept in the same place, behind
+/// the same lock, as the state of the event, so that no process blocks after
+/// the event it waits for has occurred.
+#[derive(Debug, Default)]
+pub struct WaitQueue {
+    waiters: VecDeque<Id>,
+}
+
+impl WaitQueue {
+    /// Returns an empty wait queue.
+    pub fn new() -> WaitQueue {
+        WaitQueue { waiters: VecDeque::new() }
+    }
+
+    /// Adds the process whose ID is `id` to the back of the queue.
+    pub fn push(&mut self, id: Id) {
+        self.waiters.push_back(id);
+    }
+
+    /// Removes the process that has waited the longest from the queue and
+    /// returns its ID, or `None` if the queue is empty.
+    pub fn pop(&mut self) -> Option<Id> {
+        self.waiters.pop_front()
+    }
+
+    /// Removes the process whose ID is `id` from the queue. Returns whether it
+    /// was waiting.
+    pub fn remove(&mut self, id: Id) -> bool {
+        match self.waiters.iter().position(|&waiter| waiter == id) {
+            Some(index) => self.waiters.remove(index).is_some(),
+            None => false,
+        }
+    }
+
+    /// Returns the number of waiting processes.
+    pub fn len(&self) -> usize {
+        self.waiters.len()
+    }
+
+    /// Returns whether no process is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.waiters.is_empty()
+    }
+}
+
+/// The processes sleeping until a deadline, in microseconds since boot,
+/// ordered by deadline. Processes with the same deadline wake in the order
+/// they went to sleep.
+#[derive(Debug, Default)]
+pub struct TimerQueue {
+    timers: VecDeque<(u64, Id)>,
+}
+
+impl TimerQueue {
+    /// Returns an empty timer queue.
+    pub fn new() -> TimerQueue {
+        TimerQueue { timers: VecDeque::new() }
+    }
+
+    /// Adds the process whose ID is `id`, to wake at `deadline`.
+    pub fn insert(&mut self, deadline: u64, id: Id) {
+        let index = self.timers.iter()
+            .position(|&(other, _)| other > deadline)
+            .unwrap_or(self.timers.len());
+        self.timers.insert(index, (deadline, id));
+    }
+
+    /// Removes the timer of the process whose ID is `id`. Returns whether it
+    /// had one.
+    pub fn remove(&mut self, id: Id) -> bool {
+        match self.timers.iter().position(|&(_, timer)| timer == id) {
+            Some(index) => self.timers.remove(index).is_some(),
+            None => false,
+        }
+    }
+
+    /// Returns the earliest deadline, or `None` if the queue is empty.
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.timers.front().map(|&(deadline, _)| deadline)
+    }
+
+    /// Removes every timer whose deadline is at or before `now` and returns
+    /// their process IDs, earliest deadline first.
+    pub fn expire(&mut self, now: u64) -> Vec<Id> {
+        let count = self.timers.iter().take_while(|&&(deadline, _)| deadline <= now).count();
+        self.timers.drain(..count).map(|(_, id)| id).collect()
+    }
+
+    /// Returns the number of sleeping processes.
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Returns whether no process is sleeping.
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+}
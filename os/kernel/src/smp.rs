@@ -0,0 +1,195 @@
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(not(test))]
+use pi::local::LocalController;
+#[cfg(not(test))]
+use pi::timer::spin_sleep_ms;
+
+use aarch64;
+use process::{Id, Stack};
+use vm::{PhysicalAddr, KERNEL_BASE};
+
+pub use pi::local::CORES;
+
+/// The physical address of the spin table: a secondary core `n` waits for the
+/// physical address to jump to in the 64-bit slot at `SPIN_TABLE + 8 * n`.
+const SPIN_TABLE: usize = 0xd8;
+
+/// How long core 0 waits for the secondary cores to come online, in
+/// milliseconds.
+#[cfg(not(test))]
+const START_TIMEOUT: u64 = 100;
+
+/// The physical address of the top of the EL1 stack of each secondary core,
+/// read by `_start_secondary` with the MMU off.
+#[no_mangle]
+pub static mut CORE_STACKS: [u64; CORES] = [0; CORES];
+
+/// A bit per core that has come online.
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+/// The ID of the process each core runs, or 0 until the core runs one.
+static CURRENT: [AtomicUsize; CORES] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// Returns the core currently executing.
+///
+/// Unlike `aarch64::affinity()`, this can be called at EL0: the core is read
+/// from `TPIDRRO_EL0`, which `_start` sets on each core.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn core() -> usize {
+    let core: usize;
+    unsafe {
+        asm!("mrs $0, TPIDRRO_EL0" : "=r"(core));
+    }
+    core
+}
+
+/// Returns the core currently executing. Tests run on core 0.
+#[cfg(test)]
+pub fn core() -> usize {
+    0
+}
+
+/// Records that the process whose ID is `id` runs on the current core. Called
+/// by the scheduler on every context switch.
+pub fn set_current(id: Id) {
+    CURRENT[core()].store(id as usize, Ordering::Relaxed);
+}
+
+/// Returns the ID of the process running on the current core, as recorded by
+/// the scheduler, or `None` until the core runs one.
+///
+/// Unlike the ID in `TPIDR_EL0`, which the process can overwrite, this can be
+/// trusted.
+pub fn current() -> Option<Id> {
+    match CURRENT[core()].load(Ordering::Relaxed) {
+        0 => None,
+        id => Some(id as Id),
+    }
+}
+
+/// Returns the top of the EL1 stack of `core`: core 0 uses the memory below
+/// the kernel, the others the stacks allocated in `start_secondary_cores()`.
+pub fn stack_top(core: usize) -> u64 {
+    extern "C" {
+        fn _start();
+    }
+
+    match core {
+        0 => _start as usize as u64,
+        _ => unsafe { CORE_STACKS[core] + KERNEL_BASE as u64 },
+    }
+}
+
+/// Marks the current core as online. Called by each secondary core once its
+/// MMU is enabled.
+pub fn online() {
+    let core = unsafe { aarch64::affinity() };
+    ONLINE.fetch_or(1 << core, Ordering::SeqCst);
+}
+
+/// Returns a bit per core that has come online.
+pub fn online_cores() -> usize {
+    ONLINE.load(Ordering::SeqCst)
+}
+
+/// Releases the secondary cores from the spin table, with a new stack each,
+/// and waits for them to come online. Returns how many did before the
+/// timeout.
+///
+/// The secondary cores start with their MMU and caches off, so everything
+/// they read before enabling them is written back to memory first.
+pub fn start_secondary_cores() -> usize {
+    extern "C" {
+        fn _start_secondary();
+    }
+
+    ONLINE.fetch_or(1, Ordering::SeqCst);
+    for core in 1..CORES {
+        let stack = Stack::new().expect("out of memory for core stacks");
+        unsafe {
+            CORE_STACKS[core] = stack.top().as_u64();
+            aarch64::invalidate_dcache(stack.bottom().as_ptr() as usize, Stack::SIZE);
+        }
+        // The stacks are used for as long as the cores run.
+        mem::forget(stack);
+    }
+    unsafe {
+        aarch64::clean_dcache(CORE_STACKS.as_ptr() as usize, mem::size_of_val(&CORE_STACKS));
+    }
+
+    for core in 1..CORES {
+        let slot = PhysicalAddr::from(SPIN_TABLE + 8 * core).as_mut_ptr() as *mut u64;
+        let entry = _start_secondary as usize - KERNEL_BASE;
+        unsafe { slot.write_volatile(entry as u64) };
+        aarch64::clean_dcache(slot as usize, 8);
+    }
+    aarch64::sev();
+
+    wait_online();
+    online_cores().count_ones() as usize - 1
+}
+
+/// Waits up to `START_TIMEOUT` for every core to come online.
+#[cfg(not(test))]
+fn wait_online() {
+    for _ in 0..START_TIMEOUT {
+        if online_cores() == (1 << CORES) - 1 {
+            return;
+        }
+        spin_sleep_ms(1);
+    }
+}
+
+/// Tests start no cores.
+#[cfg(test)]
+fn wait_online() {}
+
+/// Enables the interrupts of the current core's generic timer and mailbox 0,
+/// and lets EL0 read the counter.
+pub fn enable_interrupts() {
+    #[cfg(not(test))]
+    unsafe {
+        asm!("msr cntp_ctl_el0, $0
+              msr cntkctl_el1, $1"
+             :: "r"(1u64), "r"(0b11u64)
+             :: "volatile");
+        let mut controller = LocalController::new();
+        controller.enable_timer(core());
+        controller.enable_mailbox(core());
+    }
+}
+
+/// Programs the current core's generic timer to interrupt in `us`
+/// microseconds.
+#[cfg_attr(test, allow(unused_variables))]
+pub fn tick_in(us: u32) {
+    #[cfg(not(test))]
+    unsafe {
+        let frequency: u64;
+        asm!("mrs $0, cntfrq_el0" : "=r"(frequency));
+        let ticks = frequency * us as u64 / 1000 / 1000;
+        asm!("msr cntp_tval_el0, $0" :: "r"(ticks) :: "volatile");
+    }
+}
+
+/// Asks `core` to reschedule, with an inter-processor interrupt through its
+/// mailbox 0.
+#[cfg_attr(test, allow(unused_variables))]
+pub fn reschedule(core: usize) {
+    #[cfg(not(test))]
+    LocalController::new().send(core, 1);
+}
+
+/// Acknowledges the inter-processor interrupts of the current core.
+pub fn acknowledge() {
+    #[cfg(not(test))]
+    LocalController::new().take(core());
+}
@@ -1,17 +1,98 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use pi::interrupt::Interrupt;
+use pi::local::{LocalInterrupt, CORES};
 
+use console::kprintln;
+use smp;
 use traps::TrapFrame;
-use process::State;
-use pi::timer::tick_in;
-use process::TICK;
 use SCHEDULER;
 
-pub fn handle_irq(interrupt: Interrupt, tf: &mut TrapFrame) {
+/// The interrupts of the GPU's interrupt controller that are checked for and
+/// counted, in order.
+pub const INTERRUPTS: [Interrupt; 7] = [
+    Interrupt::Timer3,
+    Interrupt::Usb,
+    Interrupt::Gpio0,
+    Interrupt::Gpio1,
+    Interrupt::Gpio2,
+    Interrupt::Gpio3,
+    Interrupt::Uart,
+];
+
+/// The number of times each interrupt in `INTERRUPTS` has been handled.
+static COUNTS: [AtomicUsize; 7] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// The local interrupts of each core that are checked for and counted, in
+/// order.
+pub const LOCAL_INTERRUPTS: [LocalInterrupt; 2] = [LocalInterrupt::Timer, LocalInterrupt::Mailbox0];
+
+/// The number of times each core has handled each interrupt in
+/// `LOCAL_INTERRUPTS`.
+static LOCAL_COUNTS: [[AtomicUsize; 2]; CORES] = [
+    [AtomicUsize::new(0), AtomicUsize::new(0)],
+    [AtomicUsize::new(0), AtomicUsize::new(0)],
+    [AtomicUsize::new(0), AtomicUsize::new(0)],
+    [AtomicUsize::new(0), AtomicUsize::new(0)],
+];
+
+/// Returns the number of times each interrupt has been handled.
+pub fn interrupt_counts() -> Vec<(Interrupt, usize)> {
+    INTERRUPTS
+        .iter()
+        .zip(COUNTS.iter())
+        .map(|(&interrupt, count)| (interrupt, count.load(Ordering::Relaxed)))
+        .collect()
+}
+
+/// Returns the number of times each core has handled each local interrupt,
+/// core by core.
+pub fn local_interrupt_counts() -> Vec<(usize, LocalInterrupt, usize)> {
+    let mut counts = Vec::new();
+    for (core, core_counts) in LOCAL_COUNTS.iter().enumerate() {
+        for (&interrupt, count) in LOCAL_INTERRUPTS.iter().zip(core_counts.iter()) {
+            counts.push((core, interrupt, count.load(Ordering::Relaxed)));
+        }
+    }
+    counts
+}
+
+/// Handles the local interrupt `interrupt` of the current core: the core's
+/// timer ends the time slice and a reschedule request lets the core switch
+/// to a process made ready for it. `tick()` acknowledges the requests.
+///
+/// An interrupt taken before the scheduler runs a process on the core leaves
+/// `tf` alone.
+pub fn handle_local_irq(interrupt: LocalInterrupt, tf: &mut TrapFrame) {
+    let core = smp::core();
+    if let Some(index) = LOCAL_INTERRUPTS.iter().position(|&i| i == interrupt) {
+        LOCAL_COUNTS[core][index].fetch_add(1, Ordering::Relaxed);
+    }
+
     match interrupt {
-        Interrupt::Timer1 => {
-            tick_in(TICK);
-            SCHEDULER.switch(State::Ready, tf).unwrap();
+        LocalInterrupt::Timer | LocalInterrupt::Mailbox0 => {
+            if SCHEDULER.tick(tf).is_none() {
+                kprintln!("core {}: {:?} interrupt without a process", core, interrupt);
+            }
         }
-        _ => unimplemented!("handle_irq()"),
+        _ => unimplemented!("handle_local_irq()"),
     }
 }
+
+/// Handles the interrupt `interrupt` of the GPU's interrupt controller. None
+/// is enabled yet.
+pub fn handle_irq(interrupt: Interrupt, _tf: &mut TrapFrame) {
+    if let Some(index) = INTERRUPTS.iter().position(|&i| i == interrupt) {
+        COUNTS[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    unimplemented!("handle_irq({:?})", interrupt)
+}
@@ -3,19 +3,26 @@ mod trap_frame;
 mod syndrome;
 mod syscall;
 
-use pi::interrupt::{Controller, Interrupt};
+use pi::interrupt::Controller;
+use pi::local::LocalController;
 
 pub use self::trap_frame::TrapFrame;
+pub use self::irq::{interrupt_counts, local_interrupt_counts};
+
+pub use self::syndrome::{Fault, Syndrome};
 
 use console::kprintln;
-use self::syndrome::Syndrome;
-use self::irq::handle_irq;
+use process::Exit;
+use self::irq::{handle_irq, handle_local_irq, INTERRUPTS, LOCAL_INTERRUPTS};
 use self::syscall::handle_syscall;
 use user::shell as shell;
 use aarch64;
+use mutex;
+use smp;
+use SCHEDULER;
 
-#[cfg(feature = "qemu")]
-use pi::timer::Timer;
+/// The ESR bit of data aborts that were caused by a write (ISS.WnR).
+const WRITE_NOT_READ: u32 = 1 << 6;
 
 #[repr(u16)]
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -42,17 +49,66 @@ pub struct Info {
     kind: Kind,
 }
 
+/// Terminates the current process, which took the unhandled exception
+/// `syndrome` at EL0 with the registers `tf`, after printing a report with its
+/// backtrace, and switches to the next process.
+///
+/// # Panics
+///
+/// Panics if the process holds a mutex, which only processes running kernel
+/// code can: killing it would leave the mutex locked and IRQs masked on its
+/// core for good.
+fn kill(syndrome: Syndrome, tf: &mut TrapFrame) {
+    let held = mutex::held();
+    if held > 0 {
+        panic!(
+            "Process {} took {:?} at {:#x} while holding {} mutexes",
+            tf.tpidr,
+            syndrome,
+            tf.elr,
+            held
+        );
+    }
+    let far = aarch64::far() as u64;
+    let backtrace: Vec<String> = SCHEDULER.backtrace(tf).iter()
+        .enumerate()
+        .map(|(i, &pc)| match aarch64::symbolize(pc as usize) {
+            Some(name) => format!("#{} {:#x} {}", i, pc, name),
+            None => format!("#{} {:#x}", i, pc),
+        })
+        .collect();
+    kprintln!(
+        "Process {} killed by {:?} at {:#x} (far {:#x}): {}",
+        tf.tpidr,
+        syndrome,
+        tf.elr,
+        far,
+        backtrace.join(" <- ")
+    );
+    SCHEDULER.exit(Exit::Fault { syndrome, far, elr: tf.elr }, tf).unwrap();
+}
+
 /// This function is called when an exception occurs. The `info` parameter
 /// specifies the source and kind of exception that has occurred. The `esr` is
 /// the value of the exception syndrome register. Finally, `tf` is a pointer to
 /// the trap frame for the exception.
+///
+/// Synchronous exceptions from EL0 that are not handled, breakpoints
+/// included, kill the current process; any other unhandled exception is a
+/// kernel bug and panics. A breakpoint at EL1 starts a debug shell.
+///
+/// The process may have overwritten `TPIDR_EL0`, so the ID in `tf` is first
+/// restored from the scheduler's record of the process running on the core.
 #[no_mangle]
 pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
+    if let Some(id) = smp::current() {
+        tf.tpidr = id;
+    }
     let syndrome = Syndrome::from(esr);
     if info.kind == Kind::Synchronous {
         // syndrome is only valid with sync
         match syndrome {
-            Syndrome::Brk(_) => {
+            Syndrome::Brk(_) if info.source != Source::LowerAArch64 => {
                 kprintln!("Got {:?} from {:?}", syndrome, info.source);
                 aarch64::bt();
                 shell(" [brk]$ ");
@@ -64,34 +120,41 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
                 handle_syscall(syscall, tf);
                 return;
             }
+            Syndrome::DataAbort { kind: Fault::Permission, .. } if esr & WRITE_NOT_READ != 0 => {
+                // Writes to copy-on-write pages are retried once resolved.
+                if SCHEDULER.copy_on_write(aarch64::far().into()) {
+                    return;
+                }
+            }
             _ => {}
         }
+        if info.source == Source::LowerAArch64 {
+            kill(syndrome, tf);
+            return;
+        }
     } else if info.kind == Kind::Irq {
-        let controller = Controller::new();
-        use self::Interrupt::*;
-        for interrupt in [Timer1, Timer3, Usb, Gpio0, Gpio1, Gpio2, Gpio3, Uart].iter() {
-            if controller.is_pending(*interrupt) {
-                handle_irq(*interrupt, tf);
+        let local = LocalController::new();
+        let core = smp::core();
+        for interrupt in LOCAL_INTERRUPTS.iter() {
+            if local.is_pending(core, *interrupt) {
+                handle_local_irq(*interrupt, tf);
                 return;
             }
         }
 
-        #[cfg(feature = "qemu")]
-        {
-            if Timer::new().is_pending() {
-                handle_irq(Timer1, tf);
+        let controller = Controller::new();
+        for interrupt in INTERRUPTS.iter() {
+            if controller.is_pending(*interrupt) {
+                handle_irq(*interrupt, tf);
                 return;
             }
         }
     }
-    kprintln!(
-        "Unhandled exception with info: {:?}, syndrome: {:?}, tf: {:?}",
+    panic!(
+        "Unhandled exception with info: {:?}, syndrome: {:?}, far: {:#x}, tf: {:?}",
         info,
         syndrome,
+        aarch64::far(),
         tf
     );
-    loop {
-        #[cfg(not(test))]
-        unsafe { asm!("wfe") }
-    }
 }
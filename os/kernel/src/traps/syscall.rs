@@ -1,30 +1,214 @@
 use traps::TrapFrame;
-use pi::timer::current_time;
 use SCHEDULER;
-use process::State;
-use process::Process;
+use process::{clamp_nice, Exit, State};
+use smp;
+use process::{read_program, Process};
+
+use block::RequestId;
+use BLOCK;
 
 /// Sleep for `ms` milliseconds.
 ///
 /// This system call takes one parameter: the number of milliseconds to sleep.
+/// Sleeping for 0 milliseconds yields to the other ready processes.
 ///
 /// In addition to the usual status value, this system call returns one
 /// parameter: the approximate true elapsed time from when `sleep` was called to
 /// when `sleep` returned.
 pub fn sleep(ms: u32, tf: &mut TrapFrame) {
-    let begin = current_time();
-    let time = begin + ms as u64 * 1000;
-    let polling_fn = Box::new(move |process: &mut Process| {
-        let current = current_time();
-        if current > time {
-            process.trap_frame.x1to29[6] = 0; // x7 = 0; succeed
-            process.trap_frame.x0 = (current - begin) / 1000; // x0 = elapsed time in ms
-            true
-        } else {
-            false
+    tf.x1to29[6] = 0; // x7 = 0; succeed
+    if SCHEDULER.sleep(ms, tf).is_none() {
+        tf.x1to29[6] = 2; // x7 = 2; failed
+    }
+}
+
+/// Wait for a request queued in the block layer to complete.
+///
+/// This system call takes one parameter: the ID of the request in `BLOCK`, or
+/// 0 to wait for a request to be queued. The process is blocked until the
+/// request is serviced by the block worker. It returns right away if the
+/// request has completed or was never queued, or if 0 is passed while a
+/// request is queued.
+pub fn wait_request(id: RequestId, tf: &mut TrapFrame) {
+    tf.x1to29[6] = 0; // x7 = 0; succeed
+    BLOCK.block_on(id, tf);
+}
+
+/// Replace the current process with the ELF executable at a path.
+///
+/// This system call takes four parameters: a pointer to and the length of the
+/// UTF-8 path of the executable, and a pointer to and the length of an array
+/// of `&str` arguments to pass to it.
+///
+/// On success, this system call does not return: the process keeps its ID but
+/// starts running the new program. If the path or an argument is not memory
+/// the process can read or not valid UTF-8, or if the program cannot be
+/// loaded, it returns with a status of 2.
+///
+/// The program is read by a loader process while the calling process is
+/// blocked, after which the system call is restarted to load it.
+pub fn exec(path: &str, args: &[&str], tf: &mut TrapFrame) {
+    let data = match read_program(path, tf) {
+        Some(data) => data,
+        None => {
+            // `svc` is 4 bytes long: the process runs it again once woken.
+            tf.elr -= 4;
+            return;
+        }
+    };
+    let replaced = data
+        .and_then(|data| Process::from_elf(&data, args))
+        .ok()
+        .and_then(|process| SCHEDULER.replace(process, tf));
+    if replaced.is_none() {
+        tf.x1to29[6] = 2; // x7 = 2; failed
+    }
+}
+
+/// Copies the path and the arguments passed to `exec` out of the memory of the
+/// calling process, whose registers are `tf`. Returns `None` if any of them is
+/// not memory the process can read or not valid UTF-8.
+fn exec_arguments(tf: &TrapFrame) -> Option<(String, Vec<String>)> {
+    /// The size of a `&str`: a pointer and a length.
+    const STR_SIZE: u64 = 16;
+
+    let string = |process: &Process, va: u64, len: u64| {
+        process.read_user(va, len).and_then(|bytes| String::from_utf8(bytes).ok())
+    };
+    SCHEDULER
+        .with_process(tf.tpidr, |process| {
+            let path = string(process, tf.x0, tf.x1to29[0])?;
+            let array = process.read_user(tf.x1to29[1], tf.x1to29[2].checked_mul(STR_SIZE)?)?;
+            let args = array
+                .chunks(STR_SIZE as usize)
+                .map(|arg| {
+                    let word = |bytes: &[u8]| {
+                        bytes.iter().rev().fold(0, |word, &byte| word << 8 | byte as u64)
+                    };
+                    string(process, word(&arg[..8]), word(&arg[8..]))
+                })
+                .collect::<Option<Vec<_>>>()?;
+            Some((path, args))
+        })
+        .and_then(|arguments| arguments)
+}
+
+/// Create a copy of the current process.
+///
+/// This system call takes no parameters.
+///
+/// The copy runs the same code with the same registers, a copy of the stack
+/// and, for user programs, copy-on-write access to the same memory. In
+/// addition to the usual status value, this system call returns one parameter:
+/// the ID of the copy in the calling process, and 0 in the copy. If the
+/// process could not be copied, it returns with a status of 2.
+pub fn fork(tf: &mut TrapFrame) {
+    match SCHEDULER.fork(tf) {
+        Some(_) => {
+            tf.x1to29[6] = 0; // x7 = 0; succeed
+        }
+        None => {
+            tf.x1to29[6] = 2; // x7 = 2; failed
+        }
+    }
+}
+
+/// Terminate the current process.
+///
+/// This system call takes one parameter: the exit status, which the process's
+/// parent collects with `wait`. It does not return.
+pub fn exit(status: u64, tf: &mut TrapFrame) {
+    if SCHEDULER.exit(Exit::Status(status), tf).is_none() {
+        tf.x1to29[6] = 2; // x7 = 2; failed
+    }
+}
+
+/// Wait for a child of the current process to exit.
+///
+/// This system call takes one parameter: the ID of the child to wait for, or 0
+/// to wait for any child.
+///
+/// In addition to the usual status value, this system call returns two
+/// parameters: the ID of the child that exited and its exit status. The child
+/// is then reaped. If the process has no such child, it returns with a status
+/// of 2.
+pub fn wait(id: u64, tf: &mut TrapFrame) {
+    let child = if id == 0 { None } else { Some(id) };
+    if !SCHEDULER.wait(child, tf) {
+        tf.x1to29[6] = 2; // x7 = 2; failed
+    }
+}
+
+/// Change the nice value of the current process.
+///
+/// This system call takes one parameter: the amount to add to the nice value,
+/// which is clamped to the range of nice values.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the new nice value.
+pub fn nice(increment: i64, tf: &mut TrapFrame) {
+    let id = tf.tpidr;
+    let nice = SCHEDULER
+        .with_process(id, |process| process.sched.nice)
+        .and_then(|nice| SCHEDULER.set_nice(id, id, clamp_nice(nice.saturating_add(increment))));
+    match nice {
+        Some(nice) => {
+            tf.x0 = nice as u64;
+            tf.x1to29[6] = 0; // x7 = 0; succeed
+        }
+        None => {
+            tf.x1to29[6] = 2; // x7 = 2; failed
+        }
+    }
+}
+
+/// Set the nice value of a process.
+///
+/// This system call takes two parameters: the ID of the process, or 0 for the
+/// current process, and its new nice value, which is clamped to the range of
+/// nice values. A process may only set its own nice value and the nice values
+/// of its children.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the nice value set. If there is no such process, or it is
+/// neither the current process nor one of its children, it returns with a
+/// status of 2.
+pub fn setpriority(id: u64, nice: i64, tf: &mut TrapFrame) {
+    let id = if id == 0 { tf.tpidr } else { id };
+    match SCHEDULER.set_nice(tf.tpidr, id, nice) {
+        Some(nice) => {
+            tf.x0 = nice as u64;
+            tf.x1to29[6] = 0; // x7 = 0; succeed
+        }
+        None => {
+            tf.x1to29[6] = 2; // x7 = 2; failed
         }
-    });
-    SCHEDULER.switch(State::Waiting(polling_fn), tf).unwrap();
+    }
+}
+
+/// Restrict a process to one core.
+///
+/// This system call takes two parameters: the ID of the process, or 0 for the
+/// current process, and the core it must run on, or `u64::max_value()` to let
+/// it run on any core. The current process moves to its core right away. If
+/// there is no such process or the core is not running, it returns with a
+/// status of 2.
+pub fn set_affinity(id: u64, core: u64, tf: &mut TrapFrame) {
+    let id = if id == 0 { tf.tpidr } else { id };
+    let core = if core == u64::max_value() { None } else { Some(core as usize) };
+    if !SCHEDULER.set_affinity(id, core) {
+        tf.x1to29[6] = 2; // x7 = 2; failed
+        return;
+    }
+    tf.x1to29[6] = 0; // x7 = 0; succeed
+    match core {
+        Some(core) if id == tf.tpidr && core != smp::core() => {
+            if SCHEDULER.switch(State::Ready, tf).is_none() {
+                tf.x1to29[6] = 2; // x7 = 2; failed
+            }
+        }
+        _ => {}
+    }
 }
 
 pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
@@ -32,6 +216,38 @@ pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
         1 => {
             sleep(tf.x0 as u32, tf);
         }
+        2 => {
+            wait_request(tf.x0, tf);
+        }
+        3 => {
+            match exec_arguments(tf) {
+                Some((path, args)) => {
+                    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+                    exec(&path, &args, tf);
+                }
+                None => {
+                    tf.x1to29[6] = 2; // x7 = 2; failed
+                }
+            }
+        }
+        4 => {
+            fork(tf);
+        }
+        5 => {
+            exit(tf.x0, tf);
+        }
+        6 => {
+            wait(tf.x0, tf);
+        }
+        7 => {
+            nice(tf.x0 as i64, tf);
+        }
+        8 => {
+            setpriority(tf.x0, tf.x1to29[0] as i64, tf);
+        }
+        9 => {
+            set_affinity(tf.x0, tf.x1to29[0], tf);
+        }
         _ => {
             tf.x1to29[6] = 1; // x7 = 1, do not exist
         }
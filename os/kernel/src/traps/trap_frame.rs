@@ -5,6 +5,8 @@ pub struct TrapFrame {
     pub elr: u64,
     pub spsr: u64,
     pub sp: u64,
+    /// The ID of the process. Restored from the scheduler on every exception,
+    /// as the process can write `TPIDR_EL0`.
     pub tpidr: u64,
     pub q0to31: [u128; 32],
     pub x1to29: [u64; 29],
@@ -2,4 +2,5 @@ mod shell;
 mod syscall;
 
 pub use self::shell::shell;
-pub use self::shell::timer;
\ No newline at end of file
+pub use self::shell::timer;
+pub use self::syscall::{exec, exit, fork, nice, set_affinity, setpriority, sleep, wait, waitpid, wait_request};
\ No newline at end of file
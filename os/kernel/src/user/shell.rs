@@ -1,4 +1,4 @@
-use console::{kprint, kprintln, CONSOLE};
+use console::{self, kprint, kprintln, CONSOLE};
 use std::path::{Path, PathBuf};
 use pi::timer::{current_time, spin_sleep_ms};
 use pi::gpio::Gpio;
@@ -6,7 +6,10 @@ use pi::atags::Atags;
 #[cfg(not(test))]
 use ALLOCATOR;
 use FILE_SYSTEM;
-use fat32::traits::{Dir, Entry, File, FileSystem, Metadata};
+use FRAMES;
+use SCHEDULER;
+use process::{Fair, Policy, Priority, Process, RoundRobin};
+use fat32::traits::{glob, Dir, Entry, File, FileSystem, Metadata, Pattern};
 use std::io::Read;
 use std::str;
 use user::syscall;
@@ -87,6 +90,7 @@ pub fn shell(prefix: &str) {
             Ok(command) => {
                 let path = command.path();
                 match path {
+                    "affinity" => affinity(&command),
                     "atags" => atags(&command),
                     "brk" => brk(),
                     "bt" => aarch64::bt(),
@@ -95,12 +99,17 @@ pub fn shell(prefix: &str) {
                     "current_el" => current_el(),
                     "echo" => echo(&command),
                     "echohex" => echohex(&command),
+                    "exec" => exec(&command, cwd.as_path()),
                     "exit" => return,
+                    "find" => find(&command, cwd.as_path()),
                     "gpio" => gpio(&command),
                     "ls" => ls(&command, cwd.as_path()),
                     "memstat" => memstat(&command),
+                    "mount" => mount(&command),
                     "panic" => panic(&command),
                     "pwd" => pwd(&command, cwd.as_path()),
+                    "renice" => renice(&command),
+                    "sched" => sched(&command),
                     "sleep" => sleep(&command),
                     "uptime" => uptime(&command),
                     _ => kprintln!("unknown command: {}", path),
@@ -114,12 +123,15 @@ pub fn shell(prefix: &str) {
 }
 
 fn read_line(history: &mut Vec<Vec<u8>>) -> String {
-    let mut console = CONSOLE.lock();
     let mut cursor = 0;
     let mut line_vec = Vec::with_capacity(512);
     let mut history_index = history.len();
     loop {
-        match console.read_byte() {
+        // The console masks IRQs while it is locked: it is locked to echo a
+        // key, not while waiting for one.
+        let byte = console::read_byte();
+        let mut console = CONSOLE.lock();
+        match byte {
             BS | DEL => {
                 // Backspace
                 if cursor > 0 {
@@ -146,63 +158,62 @@ fn read_line(history: &mut Vec<Vec<u8>>) -> String {
                 break;
             }
             ESC => {
-                match console.read_byte() {
-                    b'[' => {
-                        match console.read_byte() {
-                            b'D' => {
-                                // Left arrow
-                                if cursor > 0 {
-                                    cursor -= 1;
-                                    console.write_byte(ESC);
-                                    console.write_byte(b'[');
-                                    console.write_byte(b'D');
-                                } else {
-                                    console.write_byte(BEL);
-                                }
-                            }
-                            b'C' => {
-                                // Right arrow
-                                if cursor < line_vec.len() {
-                                    cursor += 1;
-                                    console.write_byte(ESC);
-                                    console.write_byte(b'[');
-                                    console.write_byte(b'C');
-                                } else {
-                                    console.write_byte(BEL);
-                                }
-                            }
-                            direction @ b'A' | direction @ b'B' => {
-                                if direction == b'A' && history_index > 0 {
-                                    // Up arrow
-                                    history_index -= 1;
-                                } else if direction == b'B' && history.len() > 0 // usize underflow
-                                    && history_index < history.len() - 1
-                                {
-                                    // Down arrow
-                                    history_index += 1;
-                                } else {
-                                    console.write_byte(BEL);
-                                    continue;
-                                }
-
-                                for _ in 0..line_vec.len() {
-                                    console.write_byte(BS);
-                                }
-                                for _ in 0..line_vec.len() {
-                                    console.write_byte(b' ');
-                                }
-                                for _ in 0..line_vec.len() {
-                                    console.write_byte(BS);
-                                }
-                                line_vec = history[history_index].clone();
-                                cursor = line_vec.len();
-                                for byte in &line_vec {
-                                    console.write_byte(*byte);
-                                }
-                            }
-                            _ => {
-                                console.write_byte(BEL);
-                            }
+                drop(console);
+                let sequence = match console::read_byte() {
+                    b'[' => Some(console::read_byte()),
+                    _ => None,
+                };
+                let mut console = CONSOLE.lock();
+                match sequence {
+                    Some(b'D') => {
+                        // Left arrow
+                        if cursor > 0 {
+                            cursor -= 1;
+                            console.write_byte(ESC);
+                            console.write_byte(b'[');
+                            console.write_byte(b'D');
+                        } else {
+                            console.write_byte(BEL);
+                        }
+                    }
+                    Some(b'C') => {
+                        // Right arrow
+                        if cursor < line_vec.len() {
+                            cursor += 1;
+                            console.write_byte(ESC);
+                            console.write_byte(b'[');
+                            console.write_byte(b'C');
+                        } else {
+                            console.write_byte(BEL);
+                        }
+                    }
+                    Some(direction @ b'A') | Some(direction @ b'B') => {
+                        if direction == b'A' && history_index > 0 {
+                            // Up arrow
+                            history_index -= 1;
+                        } else if direction == b'B' && history.len() > 0 // usize underflow
+                            && history_index < history.len() - 1
+                        {
+                            // Down arrow
+                            history_index += 1;
+                        } else {
+                            console.write_byte(BEL);
+                            continue;
+                        }
+
+                        for _ in 0..line_vec.len() {
+                            console.write_byte(BS);
+                        }
+                        for _ in 0..line_vec.len() {
+                            console.write_byte(b' ');
+                        }
+                        for _ in 0..line_vec.len() {
+                            console.write_byte(BS);
+                        }
+                        line_vec = history[history_index].clone();
+                        cursor = line_vec.len();
+                        for byte in &line_vec {
+                            console.write_byte(*byte);
                         }
                     }
                     _ => {
@@ -308,6 +319,7 @@ fn atags(_command: &Command) {
 fn memstat(_command: &Command) {
     #[cfg(not(test))]
     kprintln!("Allocator: {:?}", ALLOCATOR);
+    kprintln!("Frames: {:?}", FRAMES);
 }
 
 fn ls(command: &Command, cwd: &Path) {
@@ -363,6 +375,12 @@ fn ls(command: &Command, cwd: &Path) {
     }
 }
 
+fn mount(_command: &Command) {
+    for path in FILE_SYSTEM.mount_points() {
+        kprintln!("{}", path.display());
+    }
+}
+
 fn cd(command: &Command, cwd: PathBuf) -> PathBuf {
     if command.args.len() != 2 {
         kprintln!("Wrong number of args for cd");
@@ -401,6 +419,48 @@ fn cat(command: &Command, cwd: &Path) {
     }
 }
 
+fn exec(command: &Command, cwd: &Path) {
+    if command.args.len() < 2 {
+        kprintln!("Usage: exec <program> [args]");
+        return;
+    }
+    let path = cwd.join(command.args[1]);
+    match Process::load(&path, &command.args[1..]) {
+        Ok(process) => match SCHEDULER.add(process) {
+            Some(id) => kprintln!("Started process {}", id),
+            None => kprintln!("Error: too many processes"),
+        },
+        Err(err) => kprintln!("Error: {}", err),
+    }
+}
+
+fn find(command: &Command, cwd: &Path) {
+    if command.args.len() != 2 {
+        kprintln!("Wrong number of args! Usage: find <pattern>");
+        return;
+    }
+    let pattern = match Pattern::new(command.args[1]) {
+        Ok(pattern) => pattern.case_insensitive(),
+        Err(err) => {
+            kprintln!("Error: {}", err);
+            return;
+        }
+    };
+    let matches = match glob(&FILE_SYSTEM, cwd, &pattern) {
+        Ok(matches) => matches,
+        Err(err) => {
+            kprintln!("Error: {}", err);
+            return;
+        }
+    };
+    for entry in matches {
+        match entry {
+            Ok(entry) => kprintln!("{}", entry.path.display()),
+            Err(err) => kprintln!("Error: {}", err),
+        }
+    }
+}
+
 fn gpio(command: &Command) {
     if command.args.len() != 3 {
         kprintln!("Usage: gpio [pin] [set|clear|level]");
@@ -464,6 +524,86 @@ fn sleep(command: &Command) {
     }
 }
 
+fn renice(command: &Command) {
+    if command.args.len() < 2 || command.args.len() > 3 {
+        kprintln!("Usage: renice <nice> [pid]");
+        return;
+    }
+    let nice = match command.args[1].parse() {
+        Ok(nice) => nice,
+        Err(_) => {
+            kprintln!("Invalid nice value: {}", command.args[1]);
+            return;
+        }
+    };
+    let id = match command.args.get(2).map(|id| id.parse()) {
+        Some(Ok(id)) => id,
+        Some(Err(_)) => {
+            kprintln!("Invalid process ID: {}", command.args[2]);
+            return;
+        }
+        None => 0,
+    };
+    let (error, nice) = syscall::setpriority(id, nice);
+    if error != 0 {
+        kprintln!("Failed with {}", error);
+    } else {
+        kprintln!("Nice value set to {}", nice);
+    }
+}
+
+fn affinity(command: &Command) {
+    if command.args.len() < 2 || command.args.len() > 3 {
+        kprintln!("Usage: affinity <core|any> [pid]");
+        return;
+    }
+    let core = match command.args[1] {
+        "any" => None,
+        core => match core.parse() {
+            Ok(core) => Some(core),
+            Err(_) => {
+                kprintln!("Invalid core: {}", core);
+                return;
+            }
+        },
+    };
+    let id = match command.args.get(2).map(|id| id.parse()) {
+        Some(Ok(id)) => id,
+        Some(Err(_)) => {
+            kprintln!("Invalid process ID: {}", command.args[2]);
+            return;
+        }
+        None => 0,
+    };
+    let error = syscall::set_affinity(id, core);
+    if error != 0 {
+        kprintln!("Failed with {}", error);
+    }
+}
+
+fn sched(command: &Command) {
+    if command.args.len() > 2 {
+        kprintln!("Usage: sched [round-robin|priority|fair]");
+        return;
+    }
+    if command.args.len() == 2 {
+        let policy: fn() -> Box<Policy> = match command.args[1] {
+            "round-robin" => || -> Box<Policy> { Box::new(RoundRobin) },
+            "priority" => || -> Box<Policy> { Box::new(Priority) },
+            "fair" => || -> Box<Policy> { Box::new(Fair::default()) },
+            policy => {
+                kprintln!("Unknown policy: {}", policy);
+                return;
+            }
+        };
+        SCHEDULER.set_policy(policy);
+    }
+    match SCHEDULER.policy() {
+        Some(policy) => kprintln!("Policy: {}", policy),
+        None => kprintln!("The scheduler is not running"),
+    }
+}
+
 pub fn timer() {
     let (error, time_elapsed) = syscall::sleep(10 * 1000);
     if error != 0 {
@@ -17,4 +17,184 @@ pub fn sleep(time: u64) -> (u64, u64) {
 #[cfg(test)]
 pub fn sleep(_: u64) -> (u64, u64) {
     (0, 0)
-}
\ No newline at end of file
+}
+
+#[cfg(not(test))]
+pub fn wait_request(id: u64) -> u64 {
+    let error: u64;
+    unsafe {
+        asm!("mov x0, $1
+              svc 2
+              mov $0, x7"
+              : "=r"(error)
+              : "r"(id)
+              : "x0", "x7")
+    };
+    error
+}
+
+#[cfg(test)]
+pub fn wait_request(_: u64) -> u64 {
+    0
+}
+#[cfg(not(test))]
+pub fn exec(path: &str, args: &[&str]) -> u64 {
+    let error: u64;
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              mov x2, $3
+              mov x3, $4
+              svc 3
+              mov $0, x7"
+              : "=r"(error)
+              : "r"(path.as_ptr()), "r"(path.len()), "r"(args.as_ptr()), "r"(args.len())
+              : "x0", "x1", "x2", "x3", "x7")
+    };
+    error
+}
+
+#[cfg(test)]
+pub fn exec(_: &str, _: &[&str]) -> u64 {
+    0
+}
+
+#[cfg(not(test))]
+pub fn fork() -> (u64, u64) {
+    let error: u64;
+    let id: u64;
+    unsafe {
+        asm!("svc 4
+              mov $0, x0
+              mov $1, x7"
+              : "=r"(id), "=r"(error)
+              :
+              : "x0", "x7")
+    };
+    (error, id)
+}
+
+#[cfg(test)]
+pub fn fork() -> (u64, u64) {
+    (0, 0)
+}
+
+#[cfg(not(test))]
+pub fn exit(status: u64) -> ! {
+    unsafe {
+        asm!("mov x0, $0
+              svc 5"
+              :
+              : "r"(status)
+              : "x0")
+    };
+    unreachable!("exit returned")
+}
+
+#[cfg(test)]
+pub fn exit(_: u64) -> ! {
+    unreachable!("exit in a test")
+}
+
+/// Waits for the child whose ID is `id` to exit, or for any child if `id` is
+/// 0. Returns the status value, the ID of the child and its exit status.
+#[cfg(not(test))]
+pub fn waitpid(id: u64) -> (u64, u64, u64) {
+    let error: u64;
+    let child: u64;
+    let status: u64;
+    unsafe {
+        asm!("mov x0, $3
+              svc 6
+              mov $0, x0
+              mov $1, x1
+              mov $2, x7"
+              : "=r"(child), "=r"(status), "=r"(error)
+              : "r"(id)
+              : "x0", "x1", "x7")
+    };
+    (error, child, status)
+}
+
+#[cfg(test)]
+pub fn waitpid(_: u64) -> (u64, u64, u64) {
+    (0, 0, 0)
+}
+
+/// Waits for any child to exit. See `waitpid()`.
+pub fn wait() -> (u64, u64, u64) {
+    waitpid(0)
+}
+
+/// Adds `increment` to the nice value of the current process. Returns the
+/// status value and the new nice value.
+#[cfg(not(test))]
+pub fn nice(increment: i64) -> (u64, i64) {
+    let error: u64;
+    let nice: i64;
+    unsafe {
+        asm!("mov x0, $2
+              svc 7
+              mov $0, x0
+              mov $1, x7"
+              : "=r"(nice), "=r"(error)
+              : "r"(increment)
+              : "x0", "x7")
+    };
+    (error, nice)
+}
+
+#[cfg(test)]
+pub fn nice(increment: i64) -> (u64, i64) {
+    (0, increment)
+}
+
+/// Sets the nice value of the process whose ID is `id`, or of the current
+/// process if `id` is 0, to `nice`. Returns the status value and the nice
+/// value set, which is clamped to the range of nice values. Only the current
+/// process and its children can be reniced.
+#[cfg(not(test))]
+pub fn setpriority(id: u64, nice: i64) -> (u64, i64) {
+    let error: u64;
+    let set: i64;
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              svc 8
+              mov $0, x0
+              mov $1, x7"
+              : "=r"(set), "=r"(error)
+              : "r"(id), "r"(nice)
+              : "x0", "x1", "x7")
+    };
+    (error, set)
+}
+
+#[cfg(test)]
+pub fn setpriority(_: u64, nice: i64) -> (u64, i64) {
+    (0, nice)
+}
+
+/// Restricts the process whose ID is `id`, or the current process if `id` is
+/// 0, to run on `core` only, or on any core if `core` is `None`. Returns the
+/// status value.
+#[cfg(not(test))]
+pub fn set_affinity(id: u64, core: Option<usize>) -> u64 {
+    let core = core.map_or(u64::max_value(), |core| core as u64);
+    let error: u64;
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              svc 9
+              mov $0, x7"
+              : "=r"(error)
+              : "r"(id), "r"(core)
+              : "x0", "x1", "x7")
+    };
+    error
+}
+
+#[cfg(test)]
+pub fn set_affinity(_: u64, _: Option<usize>) -> u64 {
+    0
+}
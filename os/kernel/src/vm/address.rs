@@ -1,36 +1,32 @@
 use std::fmt;
 
+#[cfg(not(test))]
+use vm::KERNEL_BASE;
+
 /// A virtual address.
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub struct VirtualAddr(usize);
 
 /// A physical address.
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub struct PhysicalAddr(usize);
 
+/// The virtual address physical memory is mapped at for the kernel. Tests run
+/// in the host's address space, where memory is used at its own address.
+#[cfg(not(test))]
+const DIRECT_MAP: usize = KERNEL_BASE;
+#[cfg(test)]
+const DIRECT_MAP: usize = 0;
+
 macro_rules! impl_for {
     ($T:tt) => {
-        impl<T: Sized> From<*mut T> for $T {
-            fn from(raw_ptr: *mut T) -> $T {
-                $T(raw_ptr as usize)
+        impl From<usize> for $T {
+            fn from(addr: usize) -> $T {
+                $T(addr)
             }
         }
 
         impl $T {
-            /// Returns the inner address of `self`.
-            pub fn as_ptr(&self) -> *const u8 {
-                self.0 as *const u8
-            }
-
-            /// Returns the inner address of `self`.
-            ///
-            /// # Safety
-            ///
-            /// This method is marked `unsafe` because it can be used to create
-            /// multiple mutable aliases to the address represented by `self`. The
-            /// caller must ensure that they do not alias.
-            pub fn as_mut_ptr(&mut self) -> *mut u8 {
-                self.0 as *mut u8
-            }
-
             /// Returns the inner address of `self` as a `usize`.
             pub fn as_usize(&self) -> usize {
                 self.0
@@ -53,3 +49,49 @@ macro_rules! impl_for {
 
 impl_for!(VirtualAddr);
 impl_for!(PhysicalAddr);
+
+impl<T: Sized> From<*mut T> for VirtualAddr {
+    fn from(raw_ptr: *mut T) -> VirtualAddr {
+        VirtualAddr(raw_ptr as usize)
+    }
+}
+
+impl VirtualAddr {
+    /// Returns the inner address of `self`.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.0 as *const u8
+    }
+
+    /// Returns the inner address of `self`.
+    ///
+    /// # Safety
+    ///
+    /// This method is marked `unsafe` because it can be used to create
+    /// multiple mutable aliases to the address represented by `self`. The
+    /// caller must ensure that they do not alias.
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.0 as *mut u8
+    }
+}
+
+/// The physical address of the memory a kernel pointer points to: the kernel
+/// only points into its map of physical memory.
+impl<T: Sized> From<*mut T> for PhysicalAddr {
+    fn from(raw_ptr: *mut T) -> PhysicalAddr {
+        PhysicalAddr(raw_ptr as usize - DIRECT_MAP)
+    }
+}
+
+impl PhysicalAddr {
+    /// Returns a pointer the kernel reads the memory at `self` through.
+    pub fn as_ptr(&self) -> *const u8 {
+        (DIRECT_MAP + self.0) as *const u8
+    }
+
+    /// Returns a pointer the kernel reads and writes the memory at `self`
+    /// through. The caller must ensure that the pointers to the memory do
+    /// not alias.
+    pub fn as_mut_ptr(&self) -> *mut u8 {
+        (DIRECT_MAP + self.0) as *mut u8
+    }
+}
@@ -0,0 +1,47 @@
+use std::io;
+
+use mutex::Mutex;
+
+/// An address space identifier, tagging the TLB entries of non-global
+/// mappings so that switching address spaces does not need a TLB flush.
+pub type Asid = u8;
+
+/// Allocator for the 255 ASIDs available with 8-bit ASIDs. ASID 0 is never
+/// handed out.
+pub struct AsidAllocator(Mutex<[u64; 4]>);
+
+impl AsidAllocator {
+    /// Returns an allocator with every ASID except 0 free.
+    pub const fn new() -> AsidAllocator {
+        AsidAllocator(Mutex::new([1, 0, 0, 0]))
+    }
+
+    /// Allocates an unused ASID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Other` if every ASID is in use.
+    pub fn alloc(&self) -> io::Result<Asid> {
+        let mut used = self.0.lock();
+        for (i, word) in used.iter_mut().enumerate() {
+            if *word != !0 {
+                let bit = (!*word).trailing_zeros() as usize;
+                *word |= 1 << bit;
+                return Ok((i * 64 + bit) as Asid);
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::Other, "out of address space identifiers"))
+    }
+
+    /// Frees `asid` so that it can be allocated again. The caller must have
+    /// invalidated the TLB entries tagged with it.
+    pub fn free(&self, asid: Asid) {
+        let asid = asid as usize;
+        self.0.lock()[asid / 64] &= !(1 << (asid % 64));
+    }
+
+    /// Returns the number of ASIDs in use, excluding ASID 0.
+    pub fn used(&self) -> usize {
+        self.0.lock().iter().map(|word| word.count_ones() as usize).sum::<usize>() - 1
+    }
+}
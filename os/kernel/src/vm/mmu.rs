@@ -0,0 +1,115 @@
+use pi::common::IO_BASE;
+use vm::{Entry, MemoryKind, Permission, PhysicalAddr, Table, BLOCK_SIZE};
+use vm::table::ENTRIES;
+
+/// The physical base address of the ARM local peripherals (the generic timer and the
+/// local interrupt controller).
+pub const LOCAL_BASE: usize = 0x4000_0000;
+
+/// `SCTLR_EL1.UMA`: lets EL0 access `DAIF`, set while processes running
+/// kernel code run.
+#[cfg(not(test))]
+pub const SCTLR_UMA: u64 = 1 << 9;
+
+/// The translation tables of the upper half of the address space for the
+/// kernel and for processes running user programs: RAM and the peripherals
+/// mapped at `KERNEL_BASE`, for EL1 only.
+#[repr(C)]
+struct KernelTables {
+    l0: Table,
+    l1: Table,
+    /// The 2MiB blocks of the first 1GiB: RAM, then the `IO_BASE` window.
+    low: Table,
+    /// The 2MiB blocks of the second 1GiB, holding the local peripherals.
+    local: Table,
+}
+
+static mut TABLES: KernelTables = KernelTables {
+    l0: Table { entries: [Entry::INVALID; ENTRIES] },
+    l1: Table { entries: [Entry::INVALID; ENTRIES] },
+    low: Table { entries: [Entry::INVALID; ENTRIES] },
+    local: Table { entries: [Entry::INVALID; ENTRIES] },
+};
+
+/// A table with no mappings, installed in `TTBR0_EL1` while no process's
+/// memory is.
+static EMPTY: Table = Table { entries: [Entry::INVALID; ENTRIES] };
+
+/// Returns the physical address of the level 0 table of the kernel's upper
+/// half, built by `initialize()`.
+pub fn kernel_root() -> PhysicalAddr {
+    unsafe { (&mut TABLES.l0 as *mut Table).into() }
+}
+
+/// Returns the physical address of a level 0 table with no mappings.
+pub fn empty_root() -> PhysicalAddr {
+    (&EMPTY as *const Table as *mut Table).into()
+}
+
+/// Returns the start and end virtual addresses of the kernel's code.
+#[cfg(not(test))]
+pub fn kernel_text() -> (usize, usize) {
+    extern "C" {
+        fn __text_start();
+        fn __text_end();
+    }
+    (__text_start as usize, __text_end as usize)
+}
+
+/// Returns the start and end addresses of the kernel's code. Tests run no
+/// kernel code, so the range is empty.
+#[cfg(test)]
+pub fn kernel_text() -> (usize, usize) {
+    (0, 0)
+}
+
+/// Returns the non-global, EL1 only descriptor for the 2MiB block at physical
+/// address `address`.
+fn block(address: usize) -> Entry {
+    let kind = if address < IO_BASE { MemoryKind::Normal } else { MemoryKind::Device };
+    // Mappings are tagged with the ASID of the address space rather than
+    // global so that they never shadow the mappings of the same addresses
+    // that processes running kernel code use at EL0.
+    Entry::mapping(address as u64, true, Permission::KernelReadWrite, kind, false)
+}
+
+/// Builds the kernel's map of physical memory at `KERNEL_BASE` and switches
+/// core 0 to it from the boot tables `init.S` enabled the MMU with.
+///
+/// # Safety
+///
+/// Must be called once, at EL1, before anything else runs.
+pub unsafe fn initialize() {
+    let tables = &mut TABLES;
+    for (i, entry) in tables.low.entries.iter_mut().enumerate() {
+        *entry = block(i * BLOCK_SIZE);
+    }
+    tables.local.entries[0] = block(LOCAL_BASE);
+    // `KERNEL_BASE` is the first address of the upper half: its level 0 and
+    // level 1 indices are 0.
+    tables.l1.entries[0] = Entry::table((&mut tables.low as *mut Table).into());
+    tables.l1.entries[1] = Entry::table((&mut tables.local as *mut Table).into());
+    tables.l0.entries[0] = Entry::table((&mut tables.l1 as *mut Table).into());
+
+    enable();
+}
+
+/// Switches the current core from the boot tables to the kernel's tables
+/// built by `initialize()`, with ASID 0, and unmaps the lower half.
+///
+/// # Safety
+///
+/// Must be called at EL1, once the tables are built, before the core runs
+/// anything else.
+pub unsafe fn enable() {
+    #[cfg(not(test))]
+    asm!("msr ttbr1_el1, $0
+          msr ttbr0_el1, $1
+          isb
+          tlbi vmalle1
+          dsb ish
+          isb"
+         :: "r"(kernel_root().as_u64()), "r"(empty_root().as_u64())
+         : "memory"
+         : "volatile");
+}
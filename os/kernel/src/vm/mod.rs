@@ -1,3 +1,43 @@
 mod address;
+mod asid;
+pub mod mmu;
+mod space;
+mod table;
+
+#[cfg(test)]
+mod tests;
 
 pub use self::address::{PhysicalAddr, VirtualAddr};
+pub use self::asid::{Asid, AsidAllocator};
+pub use self::space::{AddressSpace, USER_IMAGE_BASE, USER_STACK_TOP};
+pub use self::table::{Entry, MemoryKind, Permission, Table, BLOCK_SIZE, PAGE_SIZE};
+
+/// The virtual address of physical address 0 in the upper half of every
+/// address space, translated through `TTBR1_EL1`, where the kernel runs. The
+/// kernel is linked to run there (see `ext/layout.ld`) and reaches the
+/// peripherals there too, through the `higher_half` feature of `pi`.
+pub const KERNEL_BASE: usize = 0xFFFF_0000_0000_0000;
+
+/// Invalidates the TLB entries for the page at `va` tagged with `asid`.
+#[cfg_attr(test, allow(unused_variables))]
+pub fn invalidate_page(asid: Asid, va: usize) {
+    #[cfg(not(test))]
+    unsafe {
+        asm!("dsb ishst
+              tlbi vae1is, $0
+              dsb ish
+              isb" :: "r"((asid as u64) << 48 | (va as u64 >> 12)) :: "volatile");
+    }
+}
+
+/// Invalidates every TLB entry tagged with `asid`.
+#[cfg_attr(test, allow(unused_variables))]
+pub fn invalidate_asid(asid: Asid) {
+    #[cfg(not(test))]
+    unsafe {
+        asm!("dsb ishst
+              tlbi aside1is, $0
+              dsb ish
+              isb" :: "r"((asid as u64) << 48) :: "volatile");
+    }
+}
@@ -0,0 +1,526 @@
+use std::{fmt, io};
+use std::ptr::Unique;
+
+use FRAMES;
+use pi::common::IO_BASE;
+use vm::{self, Asid, AsidAllocator, Entry, MemoryKind, Permission, PhysicalAddr, Table, VirtualAddr};
+use vm::KERNEL_BASE;
+use vm::mmu::{kernel_root, kernel_text, LOCAL_BASE};
+#[cfg(not(test))]
+use vm::mmu::{empty_root, SCTLR_UMA};
+use vm::table::{BLOCK_SIZE, ENTRIES, PAGE_SIZE};
+
+/// The ASIDs of every address space.
+static ASIDS: AsidAllocator = AsidAllocator::new();
+
+/// The number of bits of virtual address translated by the tables of each
+/// half of the address space.
+pub const VA_BITS: usize = 48;
+
+/// The virtual address user programs are loaded at. The addresses below it are
+/// never mapped, so that null pointers and small offsets from them fault.
+pub const USER_IMAGE_BASE: usize = 0x1_0000_0000;
+
+/// The virtual address of the top of the stack of user programs.
+pub const USER_STACK_TOP: usize = 0x80_0000_0000;
+
+/// The number of translation table levels for `VA_BITS` with a 4KiB granule.
+const LEVELS: usize = 4;
+
+/// Returns the index of the descriptor for `va` in a table at `level`.
+fn index(va: usize, level: usize) -> usize {
+    (va >> (12 + 9 * (LEVELS - 1 - level))) % ENTRIES
+}
+
+/// Returns a pointer to the table at physical address `address`.
+fn table_at(address: u64) -> *mut Table {
+    PhysicalAddr::from(address as usize).as_mut_ptr() as *mut Table
+}
+
+/// Allocates a frame for a zeroed translation table: every entry is invalid.
+fn alloc_table() -> io::Result<*mut Table> {
+    let frame = FRAMES.alloc()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "out of memory"))?;
+    let table = frame.as_mut_ptr() as *mut Table;
+    unsafe { table.write_bytes(0, 1) };
+    Ok(table)
+}
+
+/// Frees the table at `table`, which is at `level`, and every table below it,
+/// releasing the frames owned by its pages.
+unsafe fn free_table(table: *mut Table, level: usize) {
+    if level < LEVELS - 1 {
+        for entry in (*table).entries.iter().filter(|entry| entry.is_table()) {
+            free_table(table_at(entry.address()), level + 1);
+        }
+    } else {
+        for entry in (*table).entries.iter().filter(|entry| entry.is_owned()) {
+            FRAMES.release((entry.address() as usize).into());
+        }
+    }
+    FRAMES.release(table.into());
+}
+
+/// Copies the mappings of the table at `src`, which is at `level`, and of every
+/// table below it into the empty table at `dst`. Frames owned by pages are
+/// shared: writable pages become copy-on-write in both tables.
+unsafe fn fork_table(src: *mut Table, dst: *mut Table, level: usize) -> io::Result<()> {
+    for (from, to) in (*src).entries.iter_mut().zip((*dst).entries.iter_mut()) {
+        if level < LEVELS - 1 && from.is_table() {
+            let table = alloc_table()?;
+            *to = Entry::table(table.into());
+            fork_table(table_at(from.address()), table, level + 1)?;
+        } else if from.is_owned() {
+            FRAMES.retain((from.address() as usize).into());
+            if from.permission().is_writable() {
+                *from = from.copy_on_write();
+            }
+            *to = *from;
+        } else {
+            *to = *from;
+        }
+    }
+    Ok(())
+}
+
+/// Releases the frame of the page descriptor `entry` if it owns it.
+fn release(entry: Entry) {
+    if entry.is_owned() {
+        FRAMES.release((entry.address() as usize).into());
+    }
+}
+
+fn invalid_input(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// The translation tables of a process: those of the lower half of the
+/// address space, installed in `TTBR0_EL1` while the process runs, hold the
+/// process's memory, and those of the upper half, installed in `TTBR1_EL1`,
+/// the kernel's.
+///
+/// The kernel runs from `KERNEL_BASE`, where RAM and the peripherals are
+/// mapped. Processes running user programs share the kernel's tables for the
+/// upper half, which only EL1 can access. Processes running kernel code have
+/// their own, accessible from EL0, so that the stack of a forked process can
+/// be mapped in place of its parent's. Mappings are tagged with the address
+/// space's ASID.
+///
+/// The memory of user programs is made of frames owned by the address space:
+/// each of their pages holds a reference to its frame, released when the page
+/// is unmapped or the address space is dropped.
+pub struct AddressSpace {
+    root: Unique<Table>,
+    /// The level 0 table of the upper half: the kernel's, or one owned by the
+    /// address space if it runs kernel code.
+    upper: Unique<Table>,
+    asid: Asid,
+    /// Whether the address space runs kernel code at EL0, which may then
+    /// mask IRQs, and owns its upper half.
+    kernel: bool,
+}
+
+impl AddressSpace {
+    /// Returns an address space with no mappings in the lower half and the
+    /// kernel's upper half.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Other` if memory or an ASID could not be
+    /// allocated.
+    pub fn new() -> io::Result<AddressSpace> {
+        let asid = ASIDS.alloc()?;
+        match alloc_table() {
+            Ok(root) => Ok(AddressSpace {
+                root: Unique::new(root).unwrap(),
+                upper: Unique::new(kernel_root().as_mut_ptr() as *mut Table).unwrap(),
+                asid,
+                kernel: false,
+            }),
+            Err(err) => {
+                ASIDS.free(asid);
+                Err(err)
+            }
+        }
+    }
+
+    /// Returns an address space for processes running kernel code at EL0: its
+    /// upper half maps RAM and the peripherals at `KERNEL_BASE` like the
+    /// kernel's but accessible from EL0, and the processes may mask IRQs to
+    /// take the kernel's locks.
+    ///
+    /// The kernel's code is mapped read only so that EL1 may still execute it:
+    /// memory writable at EL0 is never executable at EL1.
+    pub fn kernel() -> io::Result<AddressSpace> {
+        let mut space = AddressSpace::new()?;
+        space.upper = Unique::new(alloc_table()?).unwrap();
+        space.kernel = true;
+        space.map_physical()?;
+        let (start, end) = kernel_text();
+        space.protect_range(start.into(), end - start, Permission::ReadExecute)?;
+        Ok(space)
+    }
+
+    /// Returns an address space for processes running user programs: the
+    /// upper half is the kernel's, for EL1 only. The program's memory must be
+    /// mapped into the lower half with `map_range()`.
+    pub fn user() -> io::Result<AddressSpace> {
+        AddressSpace::new()
+    }
+
+    fn map_physical(&mut self) -> io::Result<()> {
+        let (memory, devices) = (Permission::UserReadWriteExecute, Permission::UserReadWrite);
+        let va = |pa: usize| VirtualAddr::from(KERNEL_BASE + pa);
+        self.map_range(va(0), 0.into(), IO_BASE, memory, MemoryKind::Normal)?;
+        self.map_range(va(IO_BASE), IO_BASE.into(), LOCAL_BASE - IO_BASE, devices, MemoryKind::Device)?;
+        self.map_range(va(LOCAL_BASE), LOCAL_BASE.into(), BLOCK_SIZE, devices, MemoryKind::Device)
+    }
+
+    /// Returns a copy of this address space with a new ASID, for a forked
+    /// process. Pages that own their frame share it with the copy: writable
+    /// ones become read only copy-on-write pages in both address spaces, and
+    /// are copied by `copy_on_write()` when first written to. Other mappings,
+    /// including those of an upper half of its own, are copied as they are.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Other` if memory or an ASID could not be
+    /// allocated.
+    pub fn fork(&mut self) -> io::Result<AddressSpace> {
+        let mut space = AddressSpace::new()?;
+        if self.kernel {
+            space.upper = Unique::new(alloc_table()?).unwrap();
+            space.kernel = true;
+            unsafe { fork_table(self.upper.as_ptr(), space.upper.as_ptr(), 0)? };
+        }
+        let result = unsafe { fork_table(self.root.as_ptr(), space.root.as_ptr(), 0) };
+        vm::invalidate_asid(self.asid);
+        result.map(|_| space)
+    }
+
+    /// Resolves a write to the copy-on-write page at `va`. If the page's frame
+    /// is still shared, it is copied into a new frame; otherwise the page is
+    /// made writable again. Returns `false` if `va` is not a copy-on-write
+    /// page.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Other` if a frame could not be allocated.
+    pub fn copy_on_write(&mut self, va: VirtualAddr) -> io::Result<bool> {
+        match self.leaf(va.as_usize()) {
+            Some((entry, level)) if level == LEVELS - 1 && entry.is_copy_on_write() => {}
+            _ => return Ok(false),
+        }
+        let va = va.as_usize() & !(PAGE_SIZE - 1);
+        let entry = self.entry(va, LEVELS - 1, false)?.unwrap();
+        unsafe {
+            let frame: PhysicalAddr = ((*entry).address() as usize).into();
+            if FRAMES.count(frame) > 1 {
+                let copy = FRAMES.alloc()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "out of memory"))?;
+                copy.as_mut_ptr().copy_from_nonoverlapping(frame.as_ptr(), PAGE_SIZE);
+                FRAMES.release(frame);
+                *entry = Entry::mapping(copy.as_u64(), false, (*entry).permission(), (*entry).kind(), false).owned();
+            }
+            *entry = (*entry).writable();
+        }
+        vm::invalidate_page(self.asid, va);
+        Ok(true)
+    }
+
+    /// Returns the ASID of the address space.
+    pub fn asid(&self) -> Asid {
+        self.asid
+    }
+
+    /// Returns the physical address of the level 0 table of the lower half.
+    pub fn root(&self) -> PhysicalAddr {
+        self.root.as_ptr().into()
+    }
+
+    /// Returns the physical address of the level 0 table of the upper half.
+    pub fn upper(&self) -> PhysicalAddr {
+        self.upper.as_ptr().into()
+    }
+
+    /// Returns the value of `TTBR0_EL1` that installs the lower half of this
+    /// address space.
+    pub fn ttbr0(&self) -> u64 {
+        self.root().as_u64()
+    }
+
+    /// Returns the value of `TTBR1_EL1` that installs the upper half of this
+    /// address space. It holds the ASID too: `TCR_EL1.A1` is set.
+    pub fn ttbr1(&self) -> u64 {
+        (self.asid as u64) << 48 | self.upper().as_u64()
+    }
+
+    /// Installs this address space in `TTBR0_EL1` and `TTBR1_EL1`, and lets
+    /// EL0 mask IRQs (`SCTLR_EL1.UMA`) only if it runs kernel code: a user
+    /// program that could mask them would never be preempted.
+    ///
+    /// The lower half is unmapped while the ASID changes, so that no
+    /// translation of the previous process's memory is cached with the new
+    /// one.
+    pub fn activate(&self) {
+        #[cfg(not(test))]
+        unsafe {
+            let mut sctlr: u64;
+            asm!("mrs $0, sctlr_el1" : "=r"(sctlr));
+            if self.kernel {
+                sctlr |= SCTLR_UMA;
+            } else {
+                sctlr &= !SCTLR_UMA;
+            }
+            asm!("msr ttbr0_el1, $0
+                  isb
+                  msr ttbr1_el1, $1
+                  isb
+                  msr ttbr0_el1, $2
+                  msr sctlr_el1, $3
+                  isb"
+                 :: "r"(empty_root().as_u64()), "r"(self.ttbr1()), "r"(self.ttbr0()), "r"(sctlr)
+                 :: "volatile");
+        }
+    }
+
+    /// Returns the level 0 table translating `va`, or `None` if `va` is in
+    /// neither half of the address space.
+    fn root_of(&self, va: usize) -> Option<*mut Table> {
+        if va >> VA_BITS == 0 {
+            Some(self.root.as_ptr())
+        } else if va >= KERNEL_BASE {
+            Some(self.upper.as_ptr())
+        } else {
+            None
+        }
+    }
+
+    /// Returns the block or page descriptor mapping `va` and its level, or
+    /// `None` if `va` is not mapped.
+    fn leaf(&self, va: usize) -> Option<(Entry, usize)> {
+        let mut table = self.root_of(va)?;
+        for level in 0..LEVELS {
+            let entry = unsafe { (*table).entries[index(va, level)] };
+            if !entry.is_valid() {
+                return None;
+            }
+            if level == LEVELS - 1 || !entry.is_table() {
+                return Some((entry, level));
+            }
+            table = table_at(entry.address());
+        }
+        unreachable!()
+    }
+
+    /// Returns a pointer to the descriptor for `va` at `level`, which is 2 or
+    /// 3. Missing tables are allocated if `create` is `true`; otherwise
+    /// returns `None` if one is missing. Blocks in the way of a level 3
+    /// descriptor are split into pages. The kernel's upper half, which other
+    /// address spaces share, is never changed.
+    fn entry(&mut self, va: usize, level: usize, create: bool) -> io::Result<Option<*mut Entry>> {
+        let mut table = match self.root_of(va) {
+            Some(_) if va >= KERNEL_BASE && !self.kernel => {
+                return Err(invalid_input("the kernel's mappings cannot be changed"));
+            }
+            Some(table) => table,
+            None => return Err(invalid_input("virtual address is out of range")),
+        };
+        for current in 0..level {
+            let entry = unsafe { &mut (*table).entries[index(va, current)] };
+            if !entry.is_valid() {
+                if !create {
+                    return Ok(None);
+                }
+                *entry = Entry::table(alloc_table()?.into());
+            } else if !entry.is_table() {
+                self.split(entry)?;
+            }
+            table = table_at(entry.address());
+        }
+        Ok(Some(unsafe { &mut (*table).entries[index(va, level)] }))
+    }
+
+    /// Replaces the block descriptor `entry` with a table of pages mapping the
+    /// same memory with the same attributes.
+    fn split(&mut self, entry: &mut Entry) -> io::Result<()> {
+        let table = alloc_table()?;
+        let page = Entry::mapping(entry.address(), false, entry.permission(), entry.kind(), entry.is_global());
+        for (i, slot) in unsafe { (*table).entries.iter_mut().enumerate() } {
+            *slot = Entry::mapping(page.address() + (i * PAGE_SIZE) as u64, false, page.permission(), page.kind(), page.is_global());
+        }
+        *entry = Entry::table(table.into());
+        vm::invalidate_asid(self.asid);
+        Ok(())
+    }
+
+    /// Maps the page at `va` to the page at `pa`, replacing any existing
+    /// mapping.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if either address is not page
+    /// aligned or `va` is out of range, and of kind `Other` if a table could
+    /// not be allocated.
+    pub fn map(&mut self, va: VirtualAddr, pa: PhysicalAddr, perm: Permission, kind: MemoryKind) -> io::Result<()> {
+        if pa.as_usize() % PAGE_SIZE != 0 {
+            return Err(invalid_input("address is not page aligned"));
+        }
+        self.set(va.as_usize(), Entry::mapping(pa.as_u64(), false, perm, kind, false))
+    }
+
+    /// Maps the page at `va` to the frame at `frame` as normal memory,
+    /// replacing any existing mapping. The page takes over the caller's
+    /// reference to the frame, unless an error is returned. See `map()`.
+    pub fn map_frame(&mut self, va: VirtualAddr, frame: PhysicalAddr, perm: Permission) -> io::Result<()> {
+        if frame.as_usize() % PAGE_SIZE != 0 {
+            return Err(invalid_input("address is not page aligned"));
+        }
+        self.set(va.as_usize(), Entry::mapping(frame.as_u64(), false, perm, MemoryKind::Normal, false).owned())
+    }
+
+    /// Replaces the page descriptor for `va` with `page`.
+    fn set(&mut self, va: usize, page: Entry) -> io::Result<()> {
+        if va % PAGE_SIZE != 0 {
+            return Err(invalid_input("address is not page aligned"));
+        }
+        let entry = self.entry(va, LEVELS - 1, true)?.unwrap();
+        unsafe {
+            let replaced = *entry;
+            *entry = page;
+            if replaced.is_valid() {
+                vm::invalidate_page(self.asid, va);
+                release(replaced);
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps the `size` bytes at `va` to the memory at `pa`, using 2MiB blocks
+    /// where both addresses are aligned to them and pages elsewhere. See
+    /// `map()`.
+    pub fn map_range(&mut self, va: VirtualAddr, pa: PhysicalAddr, size: usize, perm: Permission, kind: MemoryKind) -> io::Result<()> {
+        if size % PAGE_SIZE != 0 {
+            return Err(invalid_input("size is not a multiple of the page size"));
+        }
+        let (va, pa) = (va.as_usize(), pa.as_usize());
+        let mut offset = 0;
+        while offset < size {
+            let (va, pa) = (va + offset, pa + offset);
+            if va % BLOCK_SIZE == 0 && pa % BLOCK_SIZE == 0 && size - offset >= BLOCK_SIZE {
+                let entry = self.entry(va, LEVELS - 2, true)?.unwrap();
+                unsafe {
+                    if !(*entry).is_table() {
+                        let replaced = (*entry).is_valid();
+                        *entry = Entry::mapping(pa as u64, true, perm, kind, false);
+                        if replaced {
+                            vm::invalidate_asid(self.asid);
+                        }
+                        offset += BLOCK_SIZE;
+                        continue;
+                    }
+                }
+            }
+            self.map(va.into(), pa.into(), perm, kind)?;
+            offset += PAGE_SIZE;
+        }
+        Ok(())
+    }
+
+    /// Unmaps the page at `va`, returning the physical address it was mapped
+    /// to, or `None` if it was not mapped. A block containing `va` is split
+    /// so that only the page is unmapped. A frame owned by the page is
+    /// released.
+    pub fn unmap(&mut self, va: VirtualAddr) -> Option<PhysicalAddr> {
+        self.translate(va)?;
+        let va = va.as_usize() & !(PAGE_SIZE - 1);
+        let entry = self.entry(va, LEVELS - 1, false).ok()??;
+        unsafe {
+            let page = *entry;
+            *entry = Entry::INVALID;
+            vm::invalidate_page(self.asid, va);
+            release(page);
+            Some((page.address() as usize).into())
+        }
+    }
+
+    /// Unmaps every page of the `size` bytes at `va`. See `unmap()`.
+    pub fn unmap_range(&mut self, va: VirtualAddr, size: usize) {
+        for offset in (0..size).step_by(PAGE_SIZE) {
+            self.unmap((va.as_usize() + offset).into());
+        }
+    }
+
+    /// Changes the permissions of the page at `va` to `perm`. A block
+    /// containing `va` is split so that only the page changes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `NotFound` if `va` is not mapped, or of kind
+    /// `Other` if a block could not be split.
+    pub fn protect(&mut self, va: VirtualAddr, perm: Permission) -> io::Result<()> {
+        if self.translate(va).is_none() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "page is not mapped"));
+        }
+        let va = va.as_usize() & !(PAGE_SIZE - 1);
+        let entry = self.entry(va, LEVELS - 1, false)?.unwrap();
+        unsafe {
+            *entry = (*entry).with_permission(perm);
+        }
+        vm::invalidate_page(self.asid, va);
+        Ok(())
+    }
+
+    /// Changes the permissions of every page of the `size` bytes at `va`. See
+    /// `protect()`.
+    pub fn protect_range(&mut self, va: VirtualAddr, size: usize, perm: Permission) -> io::Result<()> {
+        for offset in (0..size).step_by(PAGE_SIZE) {
+            self.protect((va.as_usize() + offset).into(), perm)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the physical address `va` is mapped to, or `None` if it is not
+    /// mapped.
+    pub fn translate(&self, va: VirtualAddr) -> Option<PhysicalAddr> {
+        let (entry, level) = self.leaf(va.as_usize())?;
+        let size = if level == LEVELS - 1 { PAGE_SIZE } else { BLOCK_SIZE };
+        Some((entry.address() as usize + va.as_usize() % size).into())
+    }
+
+    /// Returns the permissions of the mapping of `va`, or `None` if it is not
+    /// mapped.
+    pub fn permission(&self, va: VirtualAddr) -> Option<Permission> {
+        self.leaf(va.as_usize()).map(|(entry, _)| entry.permission())
+    }
+
+    /// Returns the memory type of the mapping of `va`, or `None` if it is not
+    /// mapped.
+    pub fn kind(&self, va: VirtualAddr) -> Option<MemoryKind> {
+        self.leaf(va.as_usize()).map(|(entry, _)| entry.kind())
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        unsafe {
+            free_table(self.root.as_ptr(), 0);
+            if self.kernel {
+                free_table(self.upper.as_ptr(), 0);
+            }
+        }
+        vm::invalidate_asid(self.asid);
+        ASIDS.free(self.asid);
+    }
+}
+
+impl fmt::Debug for AddressSpace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AddressSpace")
+            .field("asid", &self.asid)
+            .field("root", &self.root())
+            .field("upper", &self.upper())
+            .field("kernel", &self.kernel)
+            .finish()
+    }
+}
@@ -0,0 +1,261 @@
+use std::fmt;
+
+use vm::PhysicalAddr;
+
+/// The size of a page, and of a translation table, in bytes.
+pub const PAGE_SIZE: usize = 4096;
+
+/// The size of the memory mapped by a level 2 block entry.
+pub const BLOCK_SIZE: usize = 2 << 20;
+
+/// The number of entries in a translation table.
+pub const ENTRIES: usize = 512;
+
+const VALID: u64 = 1 << 0;
+const TABLE: u64 = 1 << 1;
+const ATTR_INDEX_SHIFT: u64 = 2;
+const ATTR_INDEX: u64 = 0b111 << ATTR_INDEX_SHIFT;
+const AP_EL0: u64 = 1 << 6;
+const AP_READ_ONLY: u64 = 1 << 7;
+const INNER_SHAREABLE: u64 = 0b11 << 8;
+const ACCESS_FLAG: u64 = 1 << 10;
+const NOT_GLOBAL: u64 = 1 << 11;
+const ADDRESS: u64 = 0x0000_FFFF_FFFF_F000;
+const PXN: u64 = 1 << 53;
+const UXN: u64 = 1 << 54;
+/// Software-defined bits, ignored by the MMU: the mapping holds a reference to
+/// its frame, and the mapping is a read only copy-on-write mapping.
+const OWNED: u64 = 1 << 55;
+const COPY_ON_WRITE: u64 = 1 << 56;
+
+/// The access permissions of a mapping.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Permission {
+    /// Read, write and execute at EL1; no access at EL0.
+    KernelReadWrite,
+    /// Read only at EL1; no access at EL0.
+    KernelReadOnly,
+    /// Read only at EL0 and EL1.
+    UserReadOnly,
+    /// Read and write at EL0 and EL1.
+    UserReadWrite,
+    /// Read and execute at EL0; read only at EL1.
+    UserReadExecute,
+    /// Read, write and execute at EL0; read and write at EL1.
+    UserReadWriteExecute,
+    /// Read and execute at EL0 and EL1: the kernel's code, which processes
+    /// running kernel code execute too.
+    ReadExecute,
+}
+
+impl Permission {
+    /// Returns the permission that allows reading, and writing and executing
+    /// if asked, at EL0.
+    pub fn user(write: bool, execute: bool) -> Permission {
+        match (write, execute) {
+            (false, false) => Permission::UserReadOnly,
+            (true, false) => Permission::UserReadWrite,
+            (false, true) => Permission::UserReadExecute,
+            (true, true) => Permission::UserReadWriteExecute,
+        }
+    }
+
+    /// Returns whether EL0 can access the memory.
+    pub fn is_user(&self) -> bool {
+        match *self {
+            Permission::KernelReadWrite | Permission::KernelReadOnly => false,
+            _ => true,
+        }
+    }
+
+    /// Returns whether the memory can be written to at the privilege level the
+    /// permission is for.
+    pub fn is_writable(&self) -> bool {
+        match *self {
+            Permission::KernelReadWrite
+            | Permission::UserReadWrite
+            | Permission::UserReadWriteExecute => true,
+            _ => false,
+        }
+    }
+
+    /// Returns this permission without write access.
+    pub fn read_only(&self) -> Permission {
+        match *self {
+            Permission::KernelReadWrite => Permission::KernelReadOnly,
+            Permission::UserReadWrite => Permission::UserReadOnly,
+            Permission::UserReadWriteExecute => Permission::UserReadExecute,
+            perm => perm,
+        }
+    }
+
+    /// Returns this permission with write access. `ReadExecute` memory never
+    /// becomes writable.
+    pub fn writable(&self) -> Permission {
+        match *self {
+            Permission::KernelReadOnly => Permission::KernelReadWrite,
+            Permission::UserReadOnly => Permission::UserReadWrite,
+            Permission::UserReadExecute => Permission::UserReadWriteExecute,
+            perm => perm,
+        }
+    }
+
+    fn bits(&self) -> u64 {
+        match *self {
+            Permission::KernelReadWrite => UXN,
+            Permission::KernelReadOnly => AP_READ_ONLY | UXN | PXN,
+            Permission::UserReadOnly => AP_EL0 | AP_READ_ONLY | UXN | PXN,
+            Permission::UserReadWrite => AP_EL0 | UXN | PXN,
+            Permission::UserReadExecute => AP_EL0 | AP_READ_ONLY | PXN,
+            Permission::UserReadWriteExecute => AP_EL0 | PXN,
+            Permission::ReadExecute => AP_EL0 | AP_READ_ONLY,
+        }
+    }
+
+    fn from_bits(bits: u64) -> Permission {
+        match (bits & AP_EL0 != 0, bits & AP_READ_ONLY != 0, bits & UXN == 0, bits & PXN == 0) {
+            (false, false, _, _) => Permission::KernelReadWrite,
+            (false, true, _, _) => Permission::KernelReadOnly,
+            (true, true, false, _) => Permission::UserReadOnly,
+            (true, false, false, _) => Permission::UserReadWrite,
+            (true, true, true, false) => Permission::UserReadExecute,
+            (true, true, true, true) => Permission::ReadExecute,
+            (true, false, true, _) => Permission::UserReadWriteExecute,
+        }
+    }
+}
+
+/// The memory type of a mapping, an index into `MAIR_EL1`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemoryKind {
+    /// Normal, write-back cacheable memory (attribute 0).
+    Normal = 0,
+    /// Device-nGnRE memory, for peripherals (attribute 1).
+    Device = 1,
+}
+
+/// A translation table descriptor.
+///
+/// At levels 0 to 2 a valid descriptor either points to the next level table
+/// or, at level 2 only, maps a 2MiB block. At level 3 a valid descriptor maps
+/// a 4KiB page.
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Entry(u64);
+
+impl Entry {
+    /// An invalid descriptor: translation through it faults.
+    pub const INVALID: Entry = Entry(0);
+
+    /// Returns a descriptor pointing to the next level table at `table`.
+    pub fn table(table: PhysicalAddr) -> Entry {
+        Entry(table.as_u64() & ADDRESS | TABLE | VALID)
+    }
+
+    /// Returns a descriptor mapping the page or block at `address`. Block
+    /// descriptors may only be used at level 2.
+    ///
+    /// Mappings that are not `global` only match translations made with the
+    /// ASID of the address space they are in.
+    pub fn mapping(address: u64, block: bool, perm: Permission, kind: MemoryKind, global: bool) -> Entry {
+        let mut bits = address & ADDRESS | perm.bits() | ACCESS_FLAG | VALID;
+        bits |= (kind as u64) << ATTR_INDEX_SHIFT;
+        if kind == MemoryKind::Normal {
+            bits |= INNER_SHAREABLE;
+        }
+        if !global {
+            bits |= NOT_GLOBAL;
+        }
+        if !block {
+            bits |= TABLE;
+        }
+        Entry(bits)
+    }
+
+    /// Returns the raw bits of the descriptor.
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns whether the descriptor is valid.
+    pub fn is_valid(&self) -> bool {
+        self.0 & VALID != 0
+    }
+
+    /// Returns whether a valid descriptor at level 0 to 2 points to a table.
+    /// At level 3, this is `true` for every valid page descriptor.
+    pub fn is_table(&self) -> bool {
+        self.is_valid() && self.0 & TABLE != 0
+    }
+
+    /// Returns the output address of the descriptor: the next level table,
+    /// block or page.
+    pub fn address(&self) -> u64 {
+        self.0 & ADDRESS
+    }
+
+    /// Returns the access permissions of a block or page descriptor.
+    pub fn permission(&self) -> Permission {
+        Permission::from_bits(self.0)
+    }
+
+    /// Returns the memory type of a block or page descriptor.
+    pub fn kind(&self) -> MemoryKind {
+        match (self.0 & ATTR_INDEX) >> ATTR_INDEX_SHIFT {
+            0 => MemoryKind::Normal,
+            _ => MemoryKind::Device,
+        }
+    }
+
+    /// Returns whether a block or page descriptor matches every ASID.
+    pub fn is_global(&self) -> bool {
+        self.0 & NOT_GLOBAL == 0
+    }
+
+    /// Returns whether a page descriptor holds a reference to its frame.
+    pub fn is_owned(&self) -> bool {
+        self.0 & OWNED != 0
+    }
+
+    /// Returns this page descriptor marked as holding a reference to its
+    /// frame, which is released when the mapping goes away.
+    pub fn owned(&self) -> Entry {
+        Entry(self.0 | OWNED)
+    }
+
+    /// Returns whether a page descriptor is a copy-on-write mapping.
+    pub fn is_copy_on_write(&self) -> bool {
+        self.0 & COPY_ON_WRITE != 0
+    }
+
+    /// Returns this writable page descriptor as a read only copy-on-write
+    /// mapping.
+    pub fn copy_on_write(&self) -> Entry {
+        Entry(self.with_permission(self.permission().read_only()).0 | COPY_ON_WRITE)
+    }
+
+    /// Returns this copy-on-write page descriptor as the writable mapping it
+    /// was before `copy_on_write()`.
+    pub fn writable(&self) -> Entry {
+        Entry(self.with_permission(self.permission().writable()).0 & !COPY_ON_WRITE)
+    }
+
+    /// Returns this block or page descriptor with its permissions replaced by
+    /// `perm`.
+    pub fn with_permission(&self, perm: Permission) -> Entry {
+        Entry(self.0 & !(AP_EL0 | AP_READ_ONLY | PXN | UXN) | perm.bits())
+    }
+}
+
+impl fmt::Debug for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Entry({:#018x})", self.0)
+    }
+}
+
+/// A translation table with 512 descriptors, mapping 512GiB at level 0, 1GiB
+/// at level 1, 2MiB at level 2 and 4KiB at level 3 per descriptor.
+#[repr(C, align(4096))]
+pub struct Table {
+    pub entries: [Entry; ENTRIES],
+}
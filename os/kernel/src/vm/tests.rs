@@ -0,0 +1,192 @@
+use std::io;
+
+use FRAMES;
+use pi::common::IO_BASE;
+use vm::{AddressSpace, AsidAllocator, Entry, MemoryKind, Permission, PhysicalAddr, VirtualAddr};
+use vm::{mmu, BLOCK_SIZE, KERNEL_BASE, PAGE_SIZE};
+
+fn phys(addr: usize) -> PhysicalAddr {
+    addr.into()
+}
+
+#[test]
+fn test_entry() {
+    let entry = Entry::mapping(0x1234_5000, false, Permission::UserReadExecute, MemoryKind::Normal, false);
+    assert!(entry.is_valid() && entry.is_table());
+    assert_eq!(entry.address(), 0x1234_5000);
+    assert_eq!(entry.permission(), Permission::UserReadExecute);
+    assert_eq!(entry.kind(), MemoryKind::Normal);
+    assert!(!entry.is_global());
+    assert_eq!(entry.bits() & (1 << 10), 1 << 10, "access flag is set");
+
+    let block = Entry::mapping(IO_BASE as u64, true, Permission::KernelReadWrite, MemoryKind::Device, true);
+    assert!(block.is_valid() && !block.is_table());
+    assert_eq!(block.kind(), MemoryKind::Device);
+    assert!(block.is_global());
+
+    for &perm in &[
+        Permission::KernelReadWrite,
+        Permission::KernelReadOnly,
+        Permission::UserReadOnly,
+        Permission::UserReadWrite,
+        Permission::UserReadExecute,
+        Permission::UserReadWriteExecute,
+        Permission::ReadExecute,
+    ] {
+        assert_eq!(block.with_permission(perm).permission(), perm);
+        assert_eq!(block.with_permission(perm).address(), IO_BASE as u64);
+    }
+    assert!(!Entry::INVALID.is_valid());
+}
+
+#[test]
+fn test_asid_allocator() {
+    let asids = AsidAllocator::new();
+    assert_eq!(asids.alloc().unwrap(), 1);
+    assert_eq!(asids.alloc().unwrap(), 2);
+    asids.free(1);
+    assert_eq!(asids.used(), 1);
+    assert_eq!(asids.alloc().unwrap(), 1);
+    for expected in 3..256 {
+        assert_eq!(asids.alloc().unwrap() as usize, expected);
+    }
+    assert_eq!(asids.used(), 255);
+    assert_eq!(asids.alloc().unwrap_err().kind(), io::ErrorKind::Other);
+    asids.free(200);
+    assert_eq!(asids.alloc().unwrap(), 200);
+}
+
+#[test]
+fn test_map_unmap() {
+    let mut space = AddressSpace::new().unwrap();
+    assert_eq!(space.ttbr0(), space.root().as_u64());
+    assert_eq!(space.ttbr1(), (space.asid() as u64) << 48 | space.upper().as_u64());
+    assert_eq!(space.root().as_usize() % PAGE_SIZE, 0);
+    assert!(space.translate(0x1000.into()).is_none());
+
+    space.map(0x4000_1000.into(), phys(0x8_0000), Permission::UserReadWrite, MemoryKind::Normal).unwrap();
+    assert_eq!(space.translate(0x4000_1234.into()).unwrap().as_usize(), 0x8_0234);
+    assert_eq!(space.permission(0x4000_1fff.into()), Some(Permission::UserReadWrite));
+    assert!(space.translate(0x4000_0fff.into()).is_none());
+    assert!(space.translate(0x4000_2000.into()).is_none());
+
+    space.map(0x4000_1000.into(), phys(0x9_0000), Permission::UserReadOnly, MemoryKind::Normal).unwrap();
+    assert_eq!(space.translate(0x4000_1000.into()).unwrap().as_usize(), 0x9_0000);
+    space.protect(0x4000_1800.into(), Permission::UserReadExecute).unwrap();
+    assert_eq!(space.permission(0x4000_1000.into()), Some(Permission::UserReadExecute));
+    assert_eq!(space.translate(0x4000_1000.into()).unwrap().as_usize(), 0x9_0000);
+
+    assert_eq!(space.unmap(0x4000_1000.into()).unwrap().as_usize(), 0x9_0000);
+    assert!(space.translate(0x4000_1000.into()).is_none());
+    assert!(space.unmap(0x4000_1000.into()).is_none());
+    let error = space.protect(0x4000_1000.into(), Permission::UserReadOnly).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+    let error = space.map(0x4000_1001.into(), phys(0), Permission::UserReadOnly, MemoryKind::Normal);
+    assert_eq!(error.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    let error = space.map(0x4000_1000.into(), phys(0x10), Permission::UserReadOnly, MemoryKind::Normal);
+    assert_eq!(error.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    let error = space.map((1 << 48).into(), phys(0), Permission::UserReadOnly, MemoryKind::Normal);
+    assert_eq!(error.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_map_range_blocks() {
+    let mut space = AddressSpace::new().unwrap();
+    let va = 0x80_0000_0000 - PAGE_SIZE;
+    let size = 2 * BLOCK_SIZE + 2 * PAGE_SIZE;
+    space.map_range(va.into(), phys(0x1000_0000 - PAGE_SIZE), size, Permission::UserReadOnly, MemoryKind::Device).unwrap();
+    for &offset in &[0, PAGE_SIZE, BLOCK_SIZE + 0x123, 2 * BLOCK_SIZE + PAGE_SIZE + 5] {
+        assert_eq!(space.translate((va + offset).into()).unwrap().as_usize(), 0x1000_0000 - PAGE_SIZE + offset);
+        assert_eq!(space.kind((va + offset).into()), Some(MemoryKind::Device));
+    }
+    assert!(space.translate((va + size).into()).is_none());
+
+    // Changing one page of a block splits it, leaving the rest unchanged.
+    let page = va + PAGE_SIZE + 7 * PAGE_SIZE;
+    space.protect(page.into(), Permission::UserReadWrite).unwrap();
+    assert_eq!(space.permission(page.into()), Some(Permission::UserReadWrite));
+    assert_eq!(space.permission((page - PAGE_SIZE).into()), Some(Permission::UserReadOnly));
+    assert_eq!(space.permission((page + PAGE_SIZE).into()), Some(Permission::UserReadOnly));
+    assert_eq!(space.kind(page.into()), Some(MemoryKind::Device));
+
+    space.unmap_range((page + PAGE_SIZE).into(), 2 * PAGE_SIZE);
+    assert!(space.translate((page + PAGE_SIZE).into()).is_none());
+    assert!(space.translate((page + 2 * PAGE_SIZE).into()).is_none());
+    assert_eq!(space.translate((page + 3 * PAGE_SIZE).into()).unwrap().as_usize(), 0x1000_0000 + 10 * PAGE_SIZE);
+
+    let error = space.map_range(va.into(), phys(0), 100, Permission::UserReadOnly, MemoryKind::Normal);
+    assert_eq!(error.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_upper_half() {
+    let kernel = AddressSpace::kernel().unwrap();
+    let mut user = AddressSpace::user().unwrap();
+    assert_ne!(kernel.asid(), user.asid());
+    assert_eq!(user.upper(), mmu::kernel_root());
+    assert_ne!(kernel.upper(), mmu::kernel_root());
+
+    let va = |pa: usize| VirtualAddr::from(KERNEL_BASE + pa);
+    assert_eq!(kernel.translate(va(0x8_0000)).unwrap().as_usize(), 0x8_0000);
+    assert_eq!(kernel.kind(va(0x8_0000)), Some(MemoryKind::Normal));
+    assert_eq!(kernel.translate(va(IO_BASE + 0x20_1000)).unwrap().as_usize(), IO_BASE + 0x20_1000);
+    assert_eq!(kernel.kind(va(IO_BASE + 0x20_1000)), Some(MemoryKind::Device));
+    assert_eq!(kernel.kind(va(0x4000_0034)), Some(MemoryKind::Device));
+    assert!(kernel.translate(va(0x4020_0000)).is_none());
+    assert_eq!(kernel.permission(va(0x8_0000)), Some(Permission::UserReadWriteExecute));
+    assert_eq!(kernel.permission(va(IO_BASE)), Some(Permission::UserReadWrite));
+
+    // Nothing is mapped in the lower half, and the kernel's upper half, which
+    // every user address space shares, cannot be changed through one.
+    for space in &[&kernel, &user] {
+        assert!(space.translate(0x8_0000.into()).is_none());
+    }
+    let (perm, kind) = (Permission::UserReadWrite, MemoryKind::Normal);
+    let error = user.map(va(0x8_0000), phys(0x8_0000), perm, kind);
+    assert_eq!(error.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    let error = user.map((KERNEL_BASE - PAGE_SIZE).into(), phys(0x8_0000), perm, kind);
+    assert_eq!(error.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_owned_frames() {
+    // The test keeps a reference to each frame, so that they are never
+    // reallocated, and gives one to the address space with each mapping.
+    let mut space = AddressSpace::new().unwrap();
+    let (first, second) = (FRAMES.alloc().unwrap(), FRAMES.alloc().unwrap());
+    FRAMES.retain(first);
+    FRAMES.retain(second);
+    space.map_frame(0x1000.into(), first, Permission::UserReadWrite).unwrap();
+    space.map_frame(0x2000.into(), second, Permission::UserReadOnly).unwrap();
+    assert_eq!(space.translate(0x1000.into()), Some(first));
+    assert_eq!(FRAMES.count(first), 2);
+
+    // Replacing or unmapping an owned page releases its frame.
+    space.map(0x1000.into(), phys(0x8_0000), Permission::UserReadWrite, MemoryKind::Normal).unwrap();
+    assert_eq!(FRAMES.count(first), 1);
+    FRAMES.retain(first);
+    space.map_frame(0x1000.into(), first, Permission::UserReadWrite).unwrap();
+
+    let mut child = space.fork().unwrap();
+    assert_eq!(FRAMES.count(first), 3);
+    assert_eq!(FRAMES.count(second), 3);
+    assert_eq!(child.translate(0x2000.into()), Some(second));
+    assert_eq!(child.permission(0x2000.into()), Some(Permission::UserReadOnly));
+    assert!(!child.copy_on_write(0x2000.into()).unwrap(), "read only pages are not copy-on-write");
+    assert_eq!(space.permission(0x1000.into()), Some(Permission::UserReadOnly));
+
+    assert!(child.copy_on_write(0x1234.into()).unwrap());
+    assert_eq!(FRAMES.count(first), 2);
+    assert_ne!(child.translate(0x1000.into()), Some(first));
+    assert_eq!(child.permission(0x1000.into()), Some(Permission::UserReadWrite));
+
+    assert_eq!(space.unmap(0x2000.into()), Some(second));
+    assert_eq!(FRAMES.count(second), 2);
+    drop(child);
+    assert_eq!(FRAMES.count(second), 1);
+    drop(space);
+    assert_eq!(FRAMES.count(first), 1);
+    FRAMES.release(first);
+    FRAMES.release(second);
+}
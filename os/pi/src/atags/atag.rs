@@ -3,13 +3,15 @@ use std::str;
 use std::ffi::CStr;
 use std::os::raw::c_char;
 
-pub use atags::raw::{Core, Mem};
+pub use atags::raw::{Core, Initrd, Mem, Ramdisk};
 
 /// An ATAG.
 #[derive(Debug, Copy, Clone)]
 pub enum Atag {
     Core(raw::Core),
     Mem(raw::Mem),
+    Ramdisk(raw::Ramdisk),
+    Initrd(raw::Initrd),
     Cmd(&'static str),
     Unknown(u32),
     None,
@@ -32,6 +34,14 @@ impl Atag {
         }
     }
 
+    /// Returns `Some` if this is an `Initrd` ATAG. Otherwise returns `None`.
+    pub fn initrd(self) -> Option<Initrd> {
+        match self {
+            Atag::Initrd(initrd) => Some(initrd),
+            _ => None,
+        }
+    }
+
     /// Returns `Some` with the command line string if this is a `Cmd` ATAG.
     /// Otherwise returns `None`.
     pub fn cmd(self) -> Option<&'static str> {
@@ -54,6 +64,8 @@ impl<'a> From<&'a raw::Atag> for Atag {
             match (atag.tag, &atag.kind) {
                 (raw::Atag::CORE, &raw::Kind { core }) => Atag::Core(core),
                 (raw::Atag::MEM, &raw::Kind { mem }) => Atag::Mem(mem),
+                (raw::Atag::RAMDISK, &raw::Kind { ramdisk }) => Atag::Ramdisk(ramdisk),
+                (raw::Atag::INITRD2, &raw::Kind { initrd }) => Atag::Initrd(initrd),
                 (raw::Atag::CMDLINE, &raw::Kind { ref cmd }) => {
                     let cmdline = CStr::from_ptr((&cmd.cmd as *const u8) as *const c_char);
                     Atag::Cmd(str::from_utf8_unchecked(cmdline.to_bytes()))
@@ -1,10 +1,12 @@
 mod raw;
 mod atag;
 
+use common::VA_OFFSET;
+
 pub use self::atag::*;
 
 /// The address at which the firmware loads the ATAGS.
-const ATAG_BASE: usize = 0x100;
+const ATAG_BASE: usize = VA_OFFSET + 0x100;
 
 /// An iterator over the ATAGS on this system.
 pub struct Atags {
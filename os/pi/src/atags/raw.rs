@@ -37,6 +37,8 @@ impl Atag {
 pub union Kind {
     pub core: Core,
     pub mem: Mem,
+    pub ramdisk: Ramdisk,
+    pub initrd: Initrd,
     pub cmd: Cmd,
 }
 
@@ -57,6 +59,27 @@ pub struct Mem {
     pub start: u32,
 }
 
+/// A `RAMDISK` ATAG.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Ramdisk {
+    pub flags: u32,
+    /// The size of the decompressed RAM disk, in KiB.
+    pub size: u32,
+    /// The block the RAM disk image starts at.
+    pub start: u32,
+}
+
+/// An `INITRD2` ATAG.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Initrd {
+    /// The physical address of the initial RAM disk.
+    pub start: u32,
+    /// The size of the initial RAM disk, in bytes.
+    pub size: u32,
+}
+
 /// A `CMDLINE` ATAG.
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
@@ -1,6 +1,18 @@
 /// The address where I/O peripherals are mapped to.
 pub const IO_BASE: usize = 0x3F000000;
 
+/// The offset from the physical address of a peripheral, or of the ATAGS, to
+/// the address they are accessed at: the kernel, built with the `higher_half`
+/// feature, reaches all of physical memory at this offset in the upper half
+/// of the address space, while the bootloader runs with the MMU off.
+#[cfg(feature = "higher_half")]
+pub const VA_OFFSET: usize = 0xFFFF_0000_0000_0000;
+
+/// The offset from the physical address of a peripheral, or of the ATAGS, to
+/// the address they are accessed at: none, with the MMU off.
+#[cfg(not(feature = "higher_half"))]
+pub const VA_OFFSET: usize = 0;
+
 /// Generates `pub enums` with no variants for each `ident` passed in.
 pub macro states($($name:ident),*) {
     $(
@@ -0,0 +1,478 @@
+#[cfg(test)]
+mod tests;
+
+use std::cmp;
+
+use common::{IO_BASE, VA_OFFSET};
+use gpio::{Function, Gpio};
+use timer::{current_time, spin_sleep_us};
+use volatile::prelude::*;
+use volatile::{Volatile, ReadVolatile, Reserved};
+
+/// The base address for the EMMC (SD host controller) registers.
+const EMMC_REG_BASE: usize = VA_OFFSET + IO_BASE + 0x300000;
+
+/// The size of a block transferred to or from the card, in bytes.
+pub const BLOCK_SIZE: usize = 512;
+
+/// The frequency of the clock fed to the controller, in Hz.
+const BASE_CLOCK: u32 = 41_666_666;
+
+/// The SD clock frequency while the card is being identified, in Hz.
+const IDENTIFICATION_CLOCK: u32 = 400_000;
+
+/// The SD clock frequency for data transfer, in Hz.
+const TRANSFER_CLOCK: u32 = 25_000_000;
+
+/// How long to wait for the controller or the card before giving up, in
+/// microseconds.
+const TIMEOUT: u64 = 1000 * 1000;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    ARG2: Volatile<u32>,
+    BLKSIZECNT: Volatile<u32>,
+    ARG1: Volatile<u32>,
+    CMDTM: Volatile<u32>,
+    RESP: [ReadVolatile<u32>; 4],
+    DATA: Volatile<u32>,
+    STATUS: ReadVolatile<u32>,
+    CONTROL0: Volatile<u32>,
+    CONTROL1: Volatile<u32>,
+    INTERRUPT: Volatile<u32>,
+    IRPT_MASK: Volatile<u32>,
+    IRPT_EN: Volatile<u32>,
+    CONTROL2: Volatile<u32>,
+    __r0: [Reserved<u32>; 47],
+    SLOTISR_VER: ReadVolatile<u32>,
+}
+
+/// Bits of the `CMDTM` register.
+mod cmdtm {
+    pub const BLKCNT_EN: u32 = 1 << 1;
+    pub const AUTO_CMD12: u32 = 1 << 2;
+    pub const READ: u32 = 1 << 4;
+    pub const MULTI_BLOCK: u32 = 1 << 5;
+    pub const RESPONSE_136: u32 = 1 << 16;
+    pub const RESPONSE_48: u32 = 2 << 16;
+    pub const RESPONSE_48_BUSY: u32 = 3 << 16;
+    pub const CRC_CHECK: u32 = 1 << 19;
+    pub const INDEX_CHECK: u32 = 1 << 20;
+    pub const IS_DATA: u32 = 1 << 21;
+}
+
+/// Bits of the `STATUS` register.
+mod status {
+    pub const CMD_INHIBIT: u32 = 1 << 0;
+    pub const DAT_INHIBIT: u32 = 1 << 1;
+    pub const DAT0_LEVEL: u32 = 1 << 20;
+}
+
+/// Bits of the `CONTROL0` register.
+mod control0 {
+    pub const BUS_WIDTH_4: u32 = 1 << 1;
+}
+
+/// Bits of the `CONTROL1` register.
+mod control1 {
+    pub const CLOCK_INTERNAL_EN: u32 = 1 << 0;
+    pub const CLOCK_STABLE: u32 = 1 << 1;
+    pub const CLOCK_EN: u32 = 1 << 2;
+    pub const CLOCK_DIVIDER: u32 = 0xFFC0;
+    pub const DATA_TIMEOUT_MAX: u32 = 0xE << 16;
+    pub const RESET_ALL: u32 = 1 << 24;
+    pub const RESET_CMD: u32 = 1 << 25;
+    pub const RESET_DATA: u32 = 1 << 26;
+}
+
+/// Bits of the `INTERRUPT` register.
+mod interrupt {
+    pub const CMD_DONE: u32 = 1 << 0;
+    pub const DATA_DONE: u32 = 1 << 1;
+    pub const WRITE_READY: u32 = 1 << 4;
+    pub const READ_READY: u32 = 1 << 5;
+    pub const ERROR: u32 = 0xFFFF_8000;
+    pub const CMD_TIMEOUT: u32 = 1 << 16;
+    pub const DATA_TIMEOUT: u32 = 1 << 20;
+}
+
+/// The error bits of an R1 card status response.
+const R1_ERRORS: u32 = 0xFDF9_0008;
+
+/// The error bits of an R6 response, which carries a shortened card status.
+const R6_ERRORS: u32 = 0xE000;
+
+/// The `ACMD41` argument: the 3.2-3.4V voltage window.
+const OCR_VOLTAGE: u32 = 0x00FF_8000;
+
+/// Bits of the OCR, as returned by `ACMD41`.
+const OCR_HIGH_CAPACITY: u32 = 1 << 30;
+const OCR_READY: u32 = 1 << 31;
+
+/// The `CMD8` argument: 2.7-3.6V and a check pattern echoed by the card.
+const IF_COND: u32 = 0x1AA;
+
+/// An error while talking to the SD card.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// The controller or the card did not respond in time.
+    TimedOut,
+    /// The controller reported an error while sending a command, with the
+    /// error bits of its `INTERRUPT` register.
+    Command(u32),
+    /// The controller reported an error while transferring data, with the
+    /// error bits of its `INTERRUPT` register.
+    Data(u32),
+    /// The card reported an error in its status, with the error bits of the
+    /// status.
+    Card(u32),
+    /// The card does not support the voltage or the commands of the
+    /// controller.
+    Unsupported,
+}
+
+/// A card that has been identified and selected for data transfer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Card {
+    /// The relative card address of the card.
+    pub rca: u32,
+    /// Whether the card is addressed in blocks (SDHC and SDXC) rather than in
+    /// bytes (SDSC).
+    pub high_capacity: bool,
+}
+
+/// The shape of the response to a command.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Response {
+    /// No response.
+    None,
+    /// R1: the card status.
+    R1,
+    /// R1b: the card status, after which the card may signal busy.
+    R1b,
+    /// R2: the CID or CSD register.
+    R2,
+    /// R3: the OCR register, without a valid CRC.
+    R3,
+    /// R6: the published relative card address.
+    R6,
+    /// R7: the card interface condition.
+    R7,
+}
+
+impl Response {
+    /// Returns the `CMDTM` bits for a command with this response.
+    fn flags(self) -> u32 {
+        use self::cmdtm::*;
+        match self {
+            Response::None => 0,
+            Response::R1 | Response::R6 | Response::R7 => RESPONSE_48 | CRC_CHECK | INDEX_CHECK,
+            Response::R1b => RESPONSE_48_BUSY | CRC_CHECK | INDEX_CHECK,
+            Response::R2 => RESPONSE_136 | CRC_CHECK,
+            Response::R3 => RESPONSE_48,
+        }
+    }
+
+    /// Returns the error bits of `status`, the first word of a response of
+    /// this shape: the card status of R1 and R1b, and its shortened form in
+    /// R6. Other responses carry no card status.
+    fn errors(self, status: u32) -> u32 {
+        match self {
+            Response::R1 | Response::R1b => status & R1_ERRORS,
+            Response::R6 => status & R6_ERRORS,
+            _ => 0,
+        }
+    }
+}
+
+/// Returns the `CONTROL1` bits selecting the fastest SD clock of at most
+/// `frequency` Hz on a host of version `version`, as read from `SLOTISR_VER`.
+///
+/// The SD clock is `BASE_CLOCK` divided by twice the divider. Hosts before
+/// version 3 of the specification (versions 0 and 1) only take powers of two
+/// up to 0x80, in bits 15:8; later ones take a 10-bit divider, whose upper
+/// two bits are bits 7:6.
+fn clock_divider(frequency: u32, version: u32) -> u32 {
+    let divider = (BASE_CLOCK + 2 * frequency - 1) / (2 * frequency);
+    let divider = if version >= 2 {
+        cmp::min(divider, 0x3FF)
+    } else {
+        cmp::min(divider.next_power_of_two(), 0x80)
+    };
+    ((divider & 0xFF) << 8) | ((divider & 0x300) >> 2)
+}
+
+/// The EMMC controller, which drives the SD card.
+pub struct Emmc {
+    registers: &'static mut Registers
+}
+
+/// Spins until `done` returns true, giving up after `TIMEOUT` microseconds.
+fn wait_until<F: FnMut() -> bool>(mut done: F) -> Result<(), Error> {
+    let start = current_time();
+    while !done() {
+        if current_time() - start > TIMEOUT {
+            return Err(Error::TimedOut);
+        }
+    }
+    Ok(())
+}
+
+impl Emmc {
+    /// Returns a new handle to the EMMC controller.
+    pub fn new() -> Emmc {
+        Emmc {
+            registers: unsafe { &mut *(EMMC_REG_BASE as *mut Registers) },
+        }
+    }
+
+    /// Resets the controller, then identifies, selects and configures the
+    /// card in the slot: high-capacity cards are detected, the bus is
+    /// switched to 4 bits and the clock to the transfer frequency.
+    pub fn initialize(&mut self) -> Result<Card, Error> {
+        for pin in 48..54 {
+            Gpio::new(pin).into_alt(Function::Alt3);
+        }
+
+        self.registers.CONTROL0.write(0);
+        self.registers.CONTROL2.write(0);
+        self.reset(control1::RESET_ALL)?;
+        self.registers.CONTROL1.or_mask(control1::CLOCK_INTERNAL_EN | control1::DATA_TIMEOUT_MAX);
+        self.set_clock(IDENTIFICATION_CLOCK)?;
+        self.registers.IRPT_EN.write(0xFFFF_FFFF);
+        self.registers.IRPT_MASK.write(0xFFFF_FFFF);
+
+        self.command(0, 0, Response::None)?;
+        let version_2 = match self.command(8, IF_COND, Response::R7) {
+            Ok(response) if response[0] & 0xFFF == IF_COND => true,
+            Ok(_) => return Err(Error::Unsupported),
+            Err(Error::TimedOut) => false,
+            Err(error) => return Err(error),
+        };
+
+        let mut arg = OCR_VOLTAGE;
+        if version_2 {
+            arg |= OCR_HIGH_CAPACITY;
+        }
+        let start = current_time();
+        let ocr = loop {
+            self.command(55, 0, Response::R1)?;
+            let ocr = self.command(41, arg, Response::R3)?[0];
+            if ocr & OCR_READY != 0 {
+                break ocr;
+            } else if current_time() - start > TIMEOUT {
+                return Err(Error::TimedOut);
+            }
+            spin_sleep_us(1000);
+        };
+
+        self.command(2, 0, Response::R2)?;
+        let rca = self.command(3, 0, Response::R6)?[0] & 0xFFFF_0000;
+        self.command(7, rca, Response::R1b)?;
+        self.wait_not_busy()?;
+
+        let card = Card { rca, high_capacity: ocr & OCR_HIGH_CAPACITY != 0 };
+        if !card.high_capacity {
+            self.command(16, BLOCK_SIZE as u32, Response::R1)?;
+        }
+
+        // Every SD memory card supports a 4-bit bus.
+        self.command(55, rca, Response::R1)?;
+        self.command(6, 2, Response::R1)?;
+        self.registers.CONTROL0.or_mask(control0::BUS_WIDTH_4);
+        self.set_clock(TRANSFER_CLOCK)?;
+        Ok(card)
+    }
+
+    /// Reads blocks of `card` starting at block `block` into `buf`.
+    /// `buf.len()` must be a non-zero multiple of `BLOCK_SIZE`. A single
+    /// block is read with `READ_SINGLE_BLOCK` (CMD17), several with
+    /// `READ_MULTIPLE_BLOCK` (CMD18) followed by an automatic
+    /// `STOP_TRANSMISSION`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buf` is not made of whole blocks.
+    pub fn read_blocks(&mut self, card: &Card, block: u32, buf: &mut [u8]) -> Result<(), Error> {
+        self.start_transfer(card, block, buf.len(), 17, cmdtm::READ)?;
+        for chunk in buf.chunks_mut(BLOCK_SIZE) {
+            if let Err(error) = self.wait_interrupt(interrupt::READ_READY) {
+                return Err(self.data_error(error));
+            }
+            for bytes in chunk.chunks_mut(4) {
+                let word = self.registers.DATA.read();
+                for (i, byte) in bytes.iter_mut().enumerate() {
+                    *byte = (word >> (8 * i)) as u8;
+                }
+            }
+        }
+        self.finish_transfer()
+    }
+
+    /// Writes `buf` to `card` starting at block `block`. `buf.len()` must be
+    /// a non-zero multiple of `BLOCK_SIZE`. A single block is written with
+    /// `WRITE_BLOCK` (CMD24), several with `WRITE_MULTIPLE_BLOCK` (CMD25)
+    /// followed by an automatic `STOP_TRANSMISSION`. Returns once the card is
+    /// no longer busy programming the data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buf` is not made of whole blocks.
+    pub fn write_blocks(&mut self, card: &Card, block: u32, buf: &[u8]) -> Result<(), Error> {
+        self.start_transfer(card, block, buf.len(), 24, 0)?;
+        for chunk in buf.chunks(BLOCK_SIZE) {
+            if let Err(error) = self.wait_interrupt(interrupt::WRITE_READY) {
+                return Err(self.data_error(error));
+            }
+            for bytes in chunk.chunks(4) {
+                let word = bytes.iter().enumerate().fold(0, |word, (i, &byte)| {
+                    word | (byte as u32) << (8 * i)
+                });
+                self.registers.DATA.write(word);
+            }
+        }
+        self.finish_transfer()?;
+        self.wait_not_busy()
+    }
+
+    /// Sends the command that starts a transfer of `len` bytes at block
+    /// `block`: `single` if `len` is one block, `single + 1` otherwise.
+    /// `direction` is `cmdtm::READ` for a read, 0 for a write.
+    fn start_transfer(
+        &mut self,
+        card: &Card,
+        block: u32,
+        len: usize,
+        single: u32,
+        direction: u32,
+    ) -> Result<(), Error> {
+        assert!(len != 0 && len % BLOCK_SIZE == 0, "partial block transfer");
+        let count = (len / BLOCK_SIZE) as u32;
+        let address = if card.high_capacity { block } else { block * BLOCK_SIZE as u32 };
+
+        self.wait_status(status::DAT_INHIBIT, false)?;
+        self.registers.BLKSIZECNT.write((count << 16) | BLOCK_SIZE as u32);
+        let flags = Response::R1.flags() | cmdtm::IS_DATA | direction;
+        if count == 1 {
+            self.send(single, address, flags)?;
+        } else {
+            let multi = cmdtm::BLKCNT_EN | cmdtm::MULTI_BLOCK | cmdtm::AUTO_CMD12;
+            self.send(single + 1, address, flags | multi)?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the end of a data transfer.
+    fn finish_transfer(&mut self) -> Result<(), Error> {
+        match self.wait_interrupt(interrupt::DATA_DONE) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(self.data_error(error)),
+        }
+    }
+
+    /// Sends command `index` with argument `arg` and returns its response.
+    fn command(&mut self, index: u32, arg: u32, response: Response) -> Result<[u32; 4], Error> {
+        let result = self.send(index, arg, response.flags())?;
+        let errors = response.errors(result[0]);
+        if errors != 0 {
+            return Err(Error::Card(errors));
+        }
+        Ok(result)
+    }
+
+    /// Sends command `index` with argument `arg` and `CMDTM` bits `flags`,
+    /// and returns the raw response. The command line is reset if the
+    /// command fails.
+    fn send(&mut self, index: u32, arg: u32, flags: u32) -> Result<[u32; 4], Error> {
+        self.wait_status(status::CMD_INHIBIT, false)?;
+        let pending = self.registers.INTERRUPT.read();
+        self.registers.INTERRUPT.write(pending);
+        self.registers.ARG1.write(arg);
+        self.registers.CMDTM.write((index << 24) | flags);
+        if let Err(error) = self.wait_interrupt(interrupt::CMD_DONE) {
+            self.reset(control1::RESET_CMD)?;
+            return Err(error);
+        }
+
+        Ok([
+            self.registers.RESP[0].read(),
+            self.registers.RESP[1].read(),
+            self.registers.RESP[2].read(),
+            self.registers.RESP[3].read(),
+        ])
+    }
+
+    /// Waits for the interrupt `mask` and acknowledges it.
+    fn wait_interrupt(&mut self, mask: u32) -> Result<(), Error> {
+        let mut flags = 0;
+        {
+            let registers = &*self.registers;
+            wait_until(|| {
+                flags = registers.INTERRUPT.read();
+                flags & (mask | interrupt::ERROR) != 0
+            })?;
+        }
+
+        self.registers.INTERRUPT.write(flags & (mask | interrupt::ERROR));
+        if flags & (interrupt::CMD_TIMEOUT | interrupt::DATA_TIMEOUT) != 0 {
+            Err(Error::TimedOut)
+        } else if flags & interrupt::ERROR != 0 {
+            Err(Error::Command(flags & interrupt::ERROR))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Resets the data line after `error` during a data transfer and reports
+    /// controller errors as `Error::Data`.
+    fn data_error(&mut self, error: Error) -> Error {
+        if let Err(error) = self.reset(control1::RESET_DATA) {
+            return error;
+        }
+        match error {
+            Error::Command(flags) => Error::Data(flags),
+            error => error,
+        }
+    }
+
+    /// Sets the reset bits `mask` of `CONTROL1` and waits for the controller
+    /// to clear them.
+    fn reset(&mut self, mask: u32) -> Result<(), Error> {
+        self.registers.CONTROL1.or_mask(mask);
+        let registers = &*self.registers;
+        wait_until(|| !registers.CONTROL1.has_mask(mask))
+    }
+
+    /// Switches the SD clock to at most `frequency` Hz.
+    fn set_clock(&mut self, frequency: u32) -> Result<(), Error> {
+        self.wait_status(status::CMD_INHIBIT | status::DAT_INHIBIT, false)?;
+        self.registers.CONTROL1.and_mask(!control1::CLOCK_EN);
+
+        let version = (self.registers.SLOTISR_VER.read() >> 16) & 0xFF;
+        let bits = clock_divider(frequency, version);
+
+        let control1 = self.registers.CONTROL1.read() & !control1::CLOCK_DIVIDER;
+        self.registers.CONTROL1.write(control1 | bits);
+        {
+            let registers = &*self.registers;
+            wait_until(|| registers.CONTROL1.has_mask(control1::CLOCK_STABLE))?;
+        }
+        self.registers.CONTROL1.or_mask(control1::CLOCK_EN);
+        spin_sleep_us(2000);
+        Ok(())
+    }
+
+    /// Spins while the card holds the data line low to signal that it is
+    /// busy.
+    fn wait_not_busy(&self) -> Result<(), Error> {
+        self.wait_status(status::DAT0_LEVEL, true)
+    }
+
+    /// Spins until the bits `mask` of `STATUS` are all set, if `set`, or all
+    /// clear otherwise.
+    fn wait_status(&self, mask: u32, set: bool) -> Result<(), Error> {
+        let registers = &*self.registers;
+        wait_until(|| (registers.STATUS.read() & mask == mask) == set)
+    }
+}
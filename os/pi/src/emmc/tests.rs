@@ -0,0 +1,62 @@
+use emmc::{clock_divider, Response, BASE_CLOCK, IDENTIFICATION_CLOCK, TRANSFER_CLOCK};
+
+/// Returns the SD clock, in Hz, selected by the `CONTROL1` bits `bits`.
+fn sd_clock(bits: u32) -> u32 {
+    let divider = ((bits >> 8) & 0xFF) | ((bits & 0xC0) << 2);
+    BASE_CLOCK / (2 * divider)
+}
+
+#[test]
+fn test_clock_divider_version_3() {
+    // 41.67 MHz / (2 * 53) and / (2 * 1).
+    assert_eq!(clock_divider(IDENTIFICATION_CLOCK, 2), 53 << 8);
+    assert_eq!(sd_clock(clock_divider(IDENTIFICATION_CLOCK, 2)), 393_081);
+    assert_eq!(clock_divider(TRANSFER_CLOCK, 2), 1 << 8);
+    assert_eq!(sd_clock(clock_divider(TRANSFER_CLOCK, 2)), 20_833_333);
+
+    // Dividers past 8 bits keep their upper two bits in bits 7:6.
+    assert_eq!(clock_divider(50_000, 2), 0xA1 << 8 | 0x40);
+    assert_eq!(sd_clock(clock_divider(50_000, 2)), 49_960);
+    assert_eq!(clock_divider(10_000, 2), 0xFFC0);
+}
+
+#[test]
+fn test_clock_divider_version_2() {
+    // Only powers of two: 41.67 MHz / (2 * 64).
+    assert_eq!(clock_divider(IDENTIFICATION_CLOCK, 1), 64 << 8);
+    assert_eq!(sd_clock(clock_divider(IDENTIFICATION_CLOCK, 1)), 325_520);
+    assert_eq!(clock_divider(TRANSFER_CLOCK, 0), 1 << 8);
+    assert_eq!(clock_divider(50_000, 1), 0x80 << 8);
+}
+
+#[test]
+fn test_clock_divider_bounds() {
+    for &version in &[0, 1, 2] {
+        for &frequency in &[IDENTIFICATION_CLOCK, 1_000_000, 10_000_000, TRANSFER_CLOCK] {
+            let clock = sd_clock(clock_divider(frequency, version));
+            assert!(clock <= frequency, "{} Hz above {} Hz", clock, frequency);
+            if version >= 2 {
+                assert!(clock > frequency / 2 || clock == BASE_CLOCK / 2);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_response_errors() {
+    // R1: a card in the transfer state, then OUT_OF_RANGE, AKE_SEQ_ERROR and
+    // APP_CMD, which is not an error.
+    assert_eq!(Response::R1.errors(0x0000_0900), 0);
+    assert_eq!(Response::R1.errors(0x8000_0900), 0x8000_0000);
+    assert_eq!(Response::R1b.errors(0x0000_0908), 0x8);
+    assert_eq!(Response::R1.errors(0x0000_0920), 0);
+
+    // R6: the RCA in the upper half, then COM_CRC_ERROR.
+    assert_eq!(Response::R6.errors(0xABCD_0500), 0);
+    assert_eq!(Response::R6.errors(0xABCD_8500), 0x8000);
+
+    // The OCR and the interface condition are not card statuses.
+    assert_eq!(Response::R3.errors(0xFFFF_FFFF), 0);
+    assert_eq!(Response::R7.errors(0xFFFF_FFFF), 0);
+    assert_eq!(Response::R2.errors(0xFFFF_FFFF), 0);
+}
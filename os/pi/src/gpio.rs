@@ -1,6 +1,6 @@
 use core::marker::PhantomData;
 
-use common::{IO_BASE, states};
+use common::{IO_BASE, VA_OFFSET, states};
 use volatile::prelude::*;
 use volatile::{Volatile, WriteVolatile, ReadVolatile, Reserved};
 
@@ -65,7 +65,7 @@ pub struct Gpio<State> {
 }
 
 /// The base address of the `GPIO` registers.
-const GPIO_BASE: usize = IO_BASE + 0x200000;
+const GPIO_BASE: usize = VA_OFFSET + IO_BASE + 0x200000;
 
 impl<T> Gpio<T> {
     /// Transitions `self` to state `S`, consuming `self` and returning a new
@@ -106,6 +106,12 @@ impl Gpio<Uninitialized> {
         self.transition()
     }
 
+    /// Reads the pin's value without changing its function. Returns `true` if
+    /// the level is high and `false` if the level is low.
+    pub fn level(&mut self) -> bool {
+        self.registers.LEV[(self.pin / 32) as usize].has_mask(1 << (self.pin % 32))
+    }
+
     /// Sets this pin to be an _output_ pin. Consumes self and returns a `Gpio`
     /// structure in the `Output` state.
     pub fn into_output(self) -> Gpio<Output> {
@@ -1,8 +1,8 @@
-use common::IO_BASE;
+use common::{IO_BASE, VA_OFFSET};
 use volatile::prelude::*;
 use volatile::{Volatile, ReadVolatile};
 
-const INT_BASE: usize = IO_BASE + 0xB000 + 0x200;
+const INT_BASE: usize = VA_OFFSET + IO_BASE + 0xB000 + 0x200;
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Interrupt {
@@ -13,3 +13,5 @@ pub mod gpio;
 pub mod common;
 pub mod atags;
 pub mod interrupt;
+pub mod local;
+pub mod emmc;
@@ -0,0 +1,80 @@
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Volatile, WriteVolatile};
+use common::VA_OFFSET;
+
+/// The base address of the ARM local peripherals (QA7).
+const LOCAL_BASE: usize = VA_OFFSET + 0x40000000;
+
+/// The number of cores served by the local interrupt controller.
+pub const CORES: usize = 4;
+
+/// Interrupt sources of a core in the local interrupt controller (ref: QA7
+/// 4.10, page 16).
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LocalInterrupt {
+    /// The non-secure physical timer of the core's ARM generic timer.
+    Timer = 1,
+    /// Mailbox 0 of the core, used for inter-processor interrupts.
+    Mailbox0 = 4,
+    /// An interrupt of the GPU's interrupt controller routed to the core.
+    Gpu = 8,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CONTROL: Volatile<u32>,
+    _unused: [Volatile<u32>; 15],
+    CORE_TIMER_IRQCNTL: [Volatile<u32>; CORES],
+    CORE_MAILBOX_IRQCNTL: [Volatile<u32>; CORES],
+    CORE_IRQ_SRC: [ReadVolatile<u32>; CORES],
+    CORE_FIQ_SRC: [ReadVolatile<u32>; CORES],
+    MAILBOX_SET: [[WriteVolatile<u32>; 4]; CORES],
+    MAILBOX_CLEAR: [[Volatile<u32>; 4]; CORES],
+}
+
+/// The local interrupt controller: routes the interrupts of each core's
+/// generic timer and mailboxes to the core, and lets cores interrupt each
+/// other through the mailboxes.
+pub struct LocalController {
+    registers: &'static mut Registers,
+}
+
+impl LocalController {
+    /// Returns a new handle to the local interrupt controller.
+    pub fn new() -> LocalController {
+        LocalController {
+            registers: unsafe { &mut *(LOCAL_BASE as *mut Registers) },
+        }
+    }
+
+    /// Routes the interrupt of the non-secure physical timer of core `core`
+    /// to its IRQ.
+    pub fn enable_timer(&mut self, core: usize) {
+        self.registers.CORE_TIMER_IRQCNTL[core].or_mask(1 << (LocalInterrupt::Timer as u8));
+    }
+
+    /// Raises an IRQ on core `core` whenever its mailbox 0 is not empty.
+    pub fn enable_mailbox(&mut self, core: usize) {
+        self.registers.CORE_MAILBOX_IRQCNTL[core].or_mask(1);
+    }
+
+    /// Returns `true` if `int` is pending on core `core`. Otherwise, returns
+    /// `false`.
+    pub fn is_pending(&self, core: usize, int: LocalInterrupt) -> bool {
+        self.registers.CORE_IRQ_SRC[core].has_mask(1 << (int as u8))
+    }
+
+    /// Sets `bits` in mailbox 0 of core `core`.
+    pub fn send(&mut self, core: usize, bits: u32) {
+        self.registers.MAILBOX_SET[core][0].write(bits);
+    }
+
+    /// Clears mailbox 0 of core `core` and returns the bits that were set.
+    pub fn take(&mut self, core: usize) -> u32 {
+        let bits = self.registers.MAILBOX_CLEAR[core][0].read();
+        self.registers.MAILBOX_CLEAR[core][0].write(bits);
+        bits
+    }
+}
@@ -1,8 +1,9 @@
 use volatile::prelude::*;
 use volatile::Volatile;
+use common::VA_OFFSET;
 
 /// The base address for the ARM generic timer registers.
-const GEN_TIMER_REG_BASE: usize = 0x40000000;
+const GEN_TIMER_REG_BASE: usize = VA_OFFSET + 0x40000000;
 
 /// Core interrupt sources (ref: QA7 4.10, page 16)
 #[repr(u8)]
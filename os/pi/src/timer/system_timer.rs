@@ -1,9 +1,9 @@
-use common::IO_BASE;
+use common::{IO_BASE, VA_OFFSET};
 use volatile::prelude::*;
 use volatile::{Volatile, ReadVolatile};
 
 /// The base address for the ARM system timer registers.
-const TIMER_REG_BASE: usize = IO_BASE + 0x3000;
+const TIMER_REG_BASE: usize = VA_OFFSET + IO_BASE + 0x3000;
 
 #[repr(C)]
 #[allow(non_snake_case)]
@@ -4,14 +4,14 @@ use volatile::prelude::*;
 use volatile::{Volatile, ReadVolatile, Reserved};
 
 use timer;
-use common::IO_BASE;
+use common::{IO_BASE, VA_OFFSET};
 use gpio::{Gpio, Function};
 
 /// The base address for the `MU` registers.
-const MU_REG_BASE: usize = IO_BASE + 0x215040;
+const MU_REG_BASE: usize = VA_OFFSET + IO_BASE + 0x215040;
 
 /// The `AUXENB` register from page 9 of the BCM2837 documentation.
-const AUX_ENABLES: *mut Volatile<u8> = (IO_BASE + 0x215004) as *mut Volatile<u8>;
+const AUX_ENABLES: *mut Volatile<u8> = (VA_OFFSET + IO_BASE + 0x215004) as *mut Volatile<u8>;
 
 /// Enum representing bit fields of the `AUX_MU_LSR_REG` register.
 #[repr(u8)]
@@ -84,24 +84,19 @@ impl<T> Mutex<T> {
 }
 
 impl<T> Mutex<T> {
-    // Once MMU/cache is enabled, do the right thing here. For now, we don't
-    // need any real synchronization.
     #[stable(feature = "rust1", since = "1.0.0")]
     pub fn try_lock(&self) -> Option<MutexGuard<T>> {
-        if !self.lock.load(Ordering::Relaxed) {
-            self.lock.store(true, Ordering::Relaxed);
+        if self.lock.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok() {
             Some(MutexGuard { lock: &self })
         } else {
             None
         }
     }
 
-    // Once MMU/cache is enabled, do the right thing here. For now, we don't
-    // need any real synchronization.
+    /// Spins until the lock is acquired.
     #[inline(never)]
     #[stable(feature = "rust1", since = "1.0.0")]
     pub fn lock(&self) -> Result<MutexGuard<T>, !> {
-        // Wait until we can "aquire" the lock, then "acquire" it.
         loop {
             match self.try_lock() {
                 Some(guard) => return Ok(guard),
@@ -111,7 +106,7 @@ impl<T> Mutex<T> {
     }
 
     fn unlock(&self) {
-        self.lock.store(false, Ordering::Relaxed);
+        self.lock.store(false, Ordering::Release);
     }
 }
 